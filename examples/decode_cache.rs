@@ -0,0 +1,104 @@
+//! A rough measure of what `DecodeCache` buys on a tight integer loop, with
+//! and without compressed instructions. Run with
+//! `cargo run --release --example decode_cache`.
+//!
+//! This is a microbenchmark, not `embench-iot`; expect real programs to
+//! gain less.
+
+use rrv32::*;
+use std::time::Instant;
+
+const PROGRAM: [u32; 8] = [
+    0x7ff00293, // li x5, 2047
+    0x00130313, // 1: addi x6, x6, 1
+    0x006383b3, // add x7, x7, x6
+    0x10602023, // sw x6, 256(x0)
+    0x10002403, // lw x8, 256(x0)
+    0xfff28293, // addi x5, x5, -1
+    0xfe0296e3, // bnez x5, 1b
+    0xfe5ff06f, // j 0
+];
+/// The same loop, with compressed instructions wherever possible.
+const COMPRESSED: [u32; 6] = [
+    0x7ff00493, // li x9, 2047
+    0x10000613, // li x12, 256
+    0x95aa0505, // 1: c.addi x10, 1; c.add x11, x10
+    0x4214c208, // c.sw x10, 0(x12); c.lw x13, 0(x12)
+    0xf8fd14fd, // c.addi x9, -1; c.bnez x9, 1b
+    0x0000b7f5, // c.j 0
+];
+const STEPS: u64 = 20_000_000;
+const ROUNDS: usize = 10;
+
+struct Ram(Vec<u32>);
+
+impl ExecutionEnvironment for Ram {
+    fn read_word(
+        &mut self,
+        address: u32,
+        _mask: u32,
+    ) -> Result<u32, MemoryAccessFailure> {
+        self.0
+            .get(address as usize / 4)
+            .copied()
+            .ok_or(MemoryAccessFailure::AccessFault)
+    }
+    fn write_word(
+        &mut self,
+        address: u32,
+        data: u32,
+        mask: u32,
+    ) -> Result<(), MemoryAccessFailure> {
+        let word = self
+            .0
+            .get_mut(address as usize / 4)
+            .ok_or(MemoryAccessFailure::AccessFault)?;
+        *word = (*word & !mask) | (data & mask);
+        Ok(())
+    }
+    fn load_reserved_word(
+        &mut self,
+        _address: u32,
+    ) -> Result<u32, MemoryAccessFailure> {
+        Err(MemoryAccessFailure::AccessFault)
+    }
+    fn store_reserved_word(
+        &mut self,
+        _address: u32,
+        _data: u32,
+    ) -> Result<bool, MemoryAccessFailure> {
+        Err(MemoryAccessFailure::AccessFault)
+    }
+}
+
+fn ram(program: &[u32]) -> Ram {
+    let mut words = vec![0; 1024];
+    words[..program.len()].copy_from_slice(program);
+    Ram(words)
+}
+
+/// Returns millions of instructions per second.
+fn run<Env: ExecutionEnvironment>(env: &mut Env) -> f64 {
+    let mut cpu = Cpu::<()>::new();
+    let start = Instant::now();
+    for _ in 0..STEPS {
+        cpu.step(env).unwrap();
+    }
+    STEPS as f64 / start.elapsed().as_secs_f64() / 1e6
+}
+
+fn main() {
+    for (name, program) in [("RV32I", &PROGRAM[..]), ("RV32IC", &COMPRESSED)] {
+        // alternate between the two, and keep the best of each, so that
+        // noise from the rest of the machine doesn't favor either
+        let (mut uncached, mut cached) = (0.0f64, 0.0f64);
+        for _ in 0..ROUNDS {
+            uncached = uncached.max(run(&mut ram(program)));
+            cached = cached.max(run(&mut DecodeCache::new(ram(program))));
+        }
+        println!("{name}:");
+        println!("  uncached: {uncached:.1} MIPS");
+        println!("  cached:   {cached:.1} MIPS");
+        println!("  speedup:  {:.2}x", cached / uncached);
+    }
+}
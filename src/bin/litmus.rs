@@ -0,0 +1,1367 @@
+//! Runs RISC-V litmus tests, in the `.litmus` format used by `herd7` and
+//! the official `litmus-tests-riscv` suite, on a `Machine`. Each test is run
+//! many times with randomized interleavings, and the observed final states
+//! are reported in (roughly) the same format as `herd7`. If you give it the
+//! output of `herd7 -model riscv.cat` for the same tests, it will also flag
+//! every observed state that RVWMO doesn't allow.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    process::ExitCode,
+};
+
+use anyhow::{anyhow, bail, Context};
+use rrv32::{
+    ExecutionEnvironment, Machine, MemoryAccessFailure, SmpEnvironment,
+};
+
+/// Thread N's code starts at `CODE_BASE * (N + 1)`.
+const CODE_BASE: u32 = 0x10000;
+/// Shared locations are allocated upward from here, 16 bytes apart.
+const DATA_BASE: u32 = 0x1000;
+/// If a run takes more than this many steps (across all harts), give up on
+/// it. (Litmus tests with loops in them are rare, but they exist.)
+const STEP_LIMIT: usize = 10000;
+
+fn print_usage_and_exit(fatal: bool) {
+    println!(
+        "Usage: litmus [--runs=N] [--seed=N] [--model=HERD_OUTPUT] \
+         TEST.litmus..."
+    );
+    std::process::exit(if fatal { 1 } else { 0 })
+}
+
+struct Options {
+    runs: u64,
+    seed: u64,
+    model: Option<String>,
+    paths: Vec<String>,
+}
+
+fn parse_args() -> Options {
+    let mut options = Options {
+        runs: 10000,
+        seed: 1,
+        model: None,
+        paths: vec![],
+    };
+    for arg in std::env::args().skip(1) {
+        if let Some((lhs, rhs)) = arg.split_once('=') {
+            match lhs {
+                "--runs" | "--seed" => {
+                    let Ok(value) = rhs.parse() else {
+                        println!("{lhs} requires a number");
+                        print_usage_and_exit(true);
+                        unreachable!()
+                    };
+                    if lhs == "--runs" {
+                        options.runs = value;
+                    } else {
+                        options.seed = value;
+                    }
+                }
+                "--model" => options.model = Some(rhs.to_string()),
+                _ => {
+                    println!("Unknown parameter {lhs:?}");
+                    print_usage_and_exit(true);
+                }
+            }
+        } else {
+            match arg.as_str() {
+                "--runs" | "--seed" | "--model" => {
+                    println!("{arg} requires an equals sign and an argument");
+                    print_usage_and_exit(true);
+                }
+                "help" | "--help" | "-h" | "-?" => {
+                    print_usage_and_exit(false);
+                }
+                _ if arg.starts_with('-') => {
+                    println!("Unknown option {arg:?}");
+                    print_usage_and_exit(true);
+                }
+                _ => options.paths.push(arg),
+            }
+        }
+    }
+    if options.paths.is_empty() {
+        println!("No litmus tests given.");
+        print_usage_and_exit(true);
+    }
+    options
+}
+
+/// Something whose final value we can observe.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Key {
+    /// Thread, register number.
+    Register(usize, u32),
+    /// Location name.
+    Memory(String),
+}
+
+impl std::fmt::Display for Key {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Key::Register(thread, register) => {
+                write!(f, "{thread}:x{register}")
+            }
+            Key::Memory(location) => write!(f, "{location}"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Value {
+    Int(u32),
+    /// The address of a location.
+    Address(String),
+}
+
+/// One allowed final state, according to `herd7`: `(key, value)` pairs,
+/// still as text.
+type ModelState = Vec<(String, String)>;
+
+enum Condition {
+    True,
+    False,
+    Not(Box<Condition>),
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+    Equal(Key, Value),
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Quantifier {
+    Exists,
+    NotExists,
+    Forall,
+}
+
+struct Test {
+    name: String,
+    /// Assembled code for each thread.
+    threads: Vec<Vec<u32>>,
+    /// Shared locations, in order of address.
+    locations: Vec<String>,
+    init_registers: Vec<(usize, u32, Value)>,
+    init_memory: Vec<(String, u32)>,
+    quantifier: Quantifier,
+    condition: Condition,
+    condition_text: String,
+    /// What to report at the end of each run.
+    observed: Vec<Key>,
+}
+
+impl Test {
+    fn address_of(&self, location: &str) -> u32 {
+        let index = self
+            .locations
+            .iter()
+            .position(|x| x == location)
+            .expect("unknown location");
+        DATA_BASE + index as u32 * 16
+    }
+    fn value(&self, value: &Value) -> u32 {
+        match value {
+            Value::Int(x) => *x,
+            Value::Address(location) => self.address_of(location),
+        }
+    }
+    fn eval(&self, condition: &Condition, state: &[u32]) -> bool {
+        match condition {
+            Condition::True => true,
+            Condition::False => false,
+            Condition::Not(x) => !self.eval(x, state),
+            Condition::And(a, b) => self.eval(a, state) && self.eval(b, state),
+            Condition::Or(a, b) => self.eval(a, state) || self.eval(b, state),
+            Condition::Equal(key, value) => {
+                let index = self.observed.iter().position(|x| x == key);
+                state[index.unwrap()] == self.value(value)
+            }
+        }
+    }
+    fn format_state(&self, state: &[u32]) -> String {
+        let mut ret = String::new();
+        for (key, value) in self.observed.iter().zip(state.iter()) {
+            ret.push_str(&format!("{key}={}; ", *value as i32));
+        }
+        ret.pop();
+        ret
+    }
+}
+
+/// Flat little memory shared by every thread of a test.
+#[derive(Clone, Default)]
+struct LitmusEnv {
+    words: HashMap<u32, u32>,
+}
+
+impl ExecutionEnvironment for LitmusEnv {
+    fn read_word(
+        &mut self,
+        address: u32,
+        _mask: u32,
+    ) -> Result<u32, MemoryAccessFailure> {
+        if address & 3 != 0 {
+            return Err(MemoryAccessFailure::Unaligned);
+        }
+        Ok(self.words.get(&address).copied().unwrap_or(0))
+    }
+    fn write_word(
+        &mut self,
+        address: u32,
+        data: u32,
+        mask: u32,
+    ) -> Result<(), MemoryAccessFailure> {
+        if address & 3 != 0 {
+            return Err(MemoryAccessFailure::Unaligned);
+        }
+        let word = self.words.entry(address).or_insert(0);
+        *word = (*word & !mask) | (data & mask);
+        Ok(())
+    }
+    fn load_reserved_word(
+        &mut self,
+        _address: u32,
+    ) -> Result<u32, MemoryAccessFailure> {
+        unreachable!("Machine handles reservations")
+    }
+    fn store_reserved_word(
+        &mut self,
+        _address: u32,
+        _data: u32,
+    ) -> Result<bool, MemoryAccessFailure> {
+        unreachable!("Machine handles reservations")
+    }
+}
+
+impl SmpEnvironment for LitmusEnv {}
+
+/// Tiny xorshift generator. Good enough for picking which hart goes next.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+/// Runs the test once. Returns the observed values, or `None` if the run
+/// didn't finish in time.
+fn run_once(
+    test: &Test,
+    env: &LitmusEnv,
+    rng: &mut Rng,
+) -> anyhow::Result<Option<Vec<u32>>> {
+    let mut machine = Machine::<(), _>::new(env.clone(), test.threads.len());
+    let mut ends = vec![];
+    for (thread, code) in test.threads.iter().enumerate() {
+        let base = CODE_BASE * (thread as u32 + 1);
+        machine.cpu_mut(thread).set_pc(base);
+        ends.push(base + code.len() as u32 * 4);
+    }
+    for (thread, register, value) in test.init_registers.iter() {
+        machine
+            .cpu_mut(*thread)
+            .set_register(*register, test.value(value));
+    }
+    let mut live: Vec<usize> = (0..test.threads.len())
+        .filter(|&thread| !test.threads[thread].is_empty())
+        .collect();
+    for _ in 0..STEP_LIMIT {
+        if live.is_empty() {
+            break;
+        }
+        let index = (rng.next() % live.len() as u64) as usize;
+        let thread = live[index];
+        machine.step_hart(thread).map_err(|x| {
+            anyhow!(
+                "thread {thread} raised {:?} at {:#x} (mtval {:#x})",
+                x.exception.mcause,
+                x.exception.mepc,
+                x.exception.mtval
+            )
+        })?;
+        if machine.cpu(thread).get_pc() == ends[thread] {
+            live.swap_remove(index);
+        }
+    }
+    if !live.is_empty() {
+        return Ok(None);
+    }
+    let words = &machine.env().words;
+    Ok(Some(
+        test.observed
+            .iter()
+            .map(|key| match key {
+                Key::Register(thread, register) => {
+                    machine.cpu(*thread).get_register(*register)
+                }
+                Key::Memory(location) => {
+                    let address = test.address_of(location);
+                    words.get(&address).copied().unwrap_or(0)
+                }
+            })
+            .collect(),
+    ))
+}
+
+/// Memory as it is at the start of every run: each thread's code, and the
+/// initial values of the shared locations.
+fn initial_env(test: &Test) -> LitmusEnv {
+    let mut env = LitmusEnv::default();
+    for (thread, code) in test.threads.iter().enumerate() {
+        let base = CODE_BASE * (thread as u32 + 1);
+        for (n, &word) in code.iter().enumerate() {
+            env.words.insert(base + n as u32 * 4, word);
+        }
+    }
+    for (location, value) in test.init_memory.iter() {
+        env.words.insert(test.address_of(location), *value);
+    }
+    env
+}
+
+/// Returns true if every observed state was allowed by the model (or there
+/// was no model to check against).
+fn run_test(
+    test: &Test,
+    options: &Options,
+    model: Option<&[ModelState]>,
+) -> anyhow::Result<bool> {
+    let env = initial_env(test);
+    let mut rng = Rng(options.seed.max(1));
+    let mut states: BTreeMap<Vec<u32>, u64> = BTreeMap::new();
+    let mut timeouts = 0;
+    for _ in 0..options.runs {
+        match run_once(test, &env, &mut rng)? {
+            Some(state) => *states.entry(state).or_default() += 1,
+            None => timeouts += 1,
+        }
+    }
+    let verdict = match test.quantifier {
+        Quantifier::Exists => "Allowed",
+        Quantifier::NotExists => "Forbidden",
+        Quantifier::Forall => "Required",
+    };
+    println!("Test {} {verdict}", test.name);
+    println!("States {}", states.len());
+    let mut positive = 0;
+    let mut negative = 0;
+    let mut forbidden = 0;
+    for (state, &count) in states.iter() {
+        let text = test.format_state(state);
+        if test.eval(&test.condition, state) {
+            positive += count;
+        } else {
+            negative += count;
+        }
+        let allowed = model.map(|model| {
+            model
+                .iter()
+                .any(|allowed| state_matches(test, state, allowed))
+        });
+        if allowed == Some(false) {
+            forbidden += 1;
+            println!("{count:>8}:> {text} FORBIDDEN BY MODEL");
+        } else {
+            println!("{count:>8}:> {text}");
+        }
+    }
+    let ok = match test.quantifier {
+        Quantifier::Exists => positive > 0,
+        Quantifier::NotExists => positive == 0,
+        Quantifier::Forall => negative == 0,
+    };
+    println!("{}", if ok { "Ok" } else { "No" });
+    println!("Witnesses");
+    println!("Positive: {positive} Negative: {negative}");
+    let quantifier = match test.quantifier {
+        Quantifier::Exists => "exists",
+        Quantifier::NotExists => "~exists",
+        Quantifier::Forall => "forall",
+    };
+    println!("Condition {quantifier} {}", test.condition_text);
+    let observation = if positive == 0 {
+        "Never"
+    } else if negative == 0 {
+        "Always"
+    } else {
+        "Sometimes"
+    };
+    println!(
+        "Observation {} {observation} {positive} {negative}",
+        test.name
+    );
+    if timeouts > 0 {
+        println!("Timeouts {timeouts}");
+    }
+    match model {
+        None => println!("Model (none given)"),
+        Some(model) => {
+            let unobserved = model
+                .iter()
+                .filter(|allowed| {
+                    !states
+                        .keys()
+                        .any(|state| state_matches(test, state, allowed))
+                })
+                .count();
+            println!(
+                "Model {} allowed, {} forbidden observed, {} allowed \
+                 unobserved",
+                model.len(),
+                forbidden,
+                unobserved,
+            );
+        }
+    }
+    println!();
+    Ok(forbidden == 0)
+}
+
+/// Does this observed state agree with this state from the model on every
+/// key they have in common?
+fn state_matches(test: &Test, state: &[u32], allowed: &ModelState) -> bool {
+    allowed.iter().all(|(key, value)| {
+        let Some(index) =
+            test.observed.iter().position(|x| x.to_string() == *key)
+        else {
+            return true;
+        };
+        let expected = match parse::value(value) {
+            Ok(Value::Int(x)) => x,
+            Ok(Value::Address(location))
+                if test.locations.contains(&location) =>
+            {
+                test.address_of(&location)
+            }
+            _ => return false,
+        };
+        state[index] == expected
+    })
+}
+
+fn main() -> ExitCode {
+    let options = parse_args();
+    let model = match options.model.as_ref() {
+        None => HashMap::new(),
+        Some(path) => match std::fs::read_to_string(path)
+            .with_context(|| format!("Unable to read {path:?}"))
+            .and_then(|text| parse::model(&text))
+        {
+            Ok(model) => model,
+            Err(x) => {
+                println!("{x:#}");
+                return ExitCode::FAILURE;
+            }
+        },
+    };
+    let mut success = true;
+    for path in options.paths.iter() {
+        let result = std::fs::read_to_string(path)
+            .with_context(|| format!("Unable to read {path:?}"))
+            .and_then(|text| parse::test(&text))
+            .and_then(|test| {
+                let states = model.get(&test.name);
+                if options.model.is_some() && states.is_none() {
+                    println!("(Model has no states for {})", test.name);
+                }
+                run_test(&test, &options, states.map(Vec::as_slice))
+            })
+            .with_context(|| format!("In {path:?}"));
+        match result {
+            Ok(true) => (),
+            Ok(false) => success = false,
+            Err(x) => {
+                println!("{x:#}\n");
+                success = false;
+            }
+        }
+    }
+    if success {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+mod parse {
+    use super::*;
+
+    /// Parses a `herd7` log into the allowed final states of each test.
+    pub fn model(
+        text: &str,
+    ) -> anyhow::Result<HashMap<String, Vec<ModelState>>> {
+        let mut ret = HashMap::new();
+        let mut lines = text.lines();
+        let mut name = None;
+        while let Some(line) = lines.next() {
+            let line = line.trim();
+            if let Some(rest) = line.strip_prefix("Test ") {
+                name = rest.split_whitespace().next().map(str::to_string);
+            } else if let Some(count) = line.strip_prefix("States ") {
+                let name = name
+                    .take()
+                    .ok_or_else(|| anyhow!("model has States without Test"))?;
+                let count: usize = count.trim().parse()?;
+                let mut states = vec![];
+                for _ in 0..count {
+                    let line = lines
+                        .next()
+                        .ok_or_else(|| anyhow!("model ends mid-States"))?;
+                    // herd7 sometimes prefixes a count, as we do
+                    let line = line.rsplit(":>").next().unwrap();
+                    states.push(
+                        line.split(';')
+                            .filter_map(|item| item.split_once('='))
+                            .map(|(key, value)| {
+                                (
+                                    key.trim()
+                                        .trim_start_matches('[')
+                                        .trim_end_matches(']')
+                                        .to_string(),
+                                    value.trim().to_string(),
+                                )
+                            })
+                            .collect(),
+                    );
+                }
+                ret.insert(name, states);
+            }
+        }
+        Ok(ret)
+    }
+
+    pub fn value(text: &str) -> anyhow::Result<Value> {
+        let text = text.trim();
+        let (negative, digits) = match text.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, text),
+        };
+        let number = if let Some(hex) = digits
+            .strip_prefix("0x")
+            .or_else(|| digits.strip_prefix("0X"))
+        {
+            Some(u32::from_str_radix(hex, 16)?)
+        } else if digits.starts_with(|x: char| x.is_ascii_digit()) {
+            Some(digits.parse::<u32>()?)
+        } else {
+            None
+        };
+        match number {
+            Some(x) if negative => Ok(Value::Int(x.wrapping_neg())),
+            Some(x) => Ok(Value::Int(x)),
+            None if negative => bail!("bad value {text:?}"),
+            None => {
+                let name = text.trim_start_matches('&');
+                if name.is_empty()
+                    || !name.chars().all(|x| x.is_alphanumeric() || x == '_')
+                {
+                    bail!("bad value {text:?}");
+                }
+                Ok(Value::Address(name.to_string()))
+            }
+        }
+    }
+
+    pub fn register(text: &str) -> anyhow::Result<u32> {
+        const ABI_NAMES: [&str; 32] = [
+            "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1",
+            "a0", "a1", "a2", "a3", "a4", "a5", "a6", "a7", "s2", "s3", "s4",
+            "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4", "t5",
+            "t6",
+        ];
+        let text = text.trim();
+        if let Some(number) = text.strip_prefix('x') {
+            if let Ok(number) = number.parse::<u32>() {
+                if number < 32 {
+                    return Ok(number);
+                }
+            }
+        }
+        if text == "fp" {
+            return Ok(8);
+        }
+        ABI_NAMES
+            .iter()
+            .position(|x| *x == text)
+            .map(|x| x as u32)
+            .ok_or_else(|| anyhow!("bad register {text:?}"))
+    }
+
+    fn key(text: &str) -> anyhow::Result<Key> {
+        let text = text.trim();
+        if let Some((thread, register)) = text.split_once(':') {
+            Ok(Key::Register(
+                thread.trim().parse()?,
+                self::register(register)?,
+            ))
+        } else {
+            let name = text.trim_start_matches('[').trim_end_matches(']');
+            if name.is_empty()
+                || !name.chars().all(|x| x.is_alphanumeric() || x == '_')
+            {
+                bail!("bad location {text:?}");
+            }
+            Ok(Key::Memory(name.to_string()))
+        }
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum Token {
+        Open,
+        Close,
+        Not,
+        And,
+        Or,
+        Atom(String),
+    }
+
+    fn tokenize(text: &str) -> anyhow::Result<Vec<Token>> {
+        let mut ret = vec![];
+        let mut rest = text.trim_start();
+        while !rest.is_empty() {
+            let (token, len) = if rest.starts_with("/\\") {
+                (Token::And, 2)
+            } else if rest.starts_with("\\/") {
+                (Token::Or, 2)
+            } else if rest.starts_with('(') {
+                (Token::Open, 1)
+            } else if rest.starts_with(')') {
+                (Token::Close, 1)
+            } else if rest.starts_with('~') {
+                (Token::Not, 1)
+            } else {
+                // an atom: everything up to the next operator, spaces and
+                // all (there may be spaces around the `=`)
+                let len = rest
+                    .find(['(', ')', '~', '/', '\\'])
+                    .unwrap_or(rest.len());
+                (Token::Atom(rest[..len].trim().to_string()), len)
+            };
+            ret.push(token);
+            rest = rest[len..].trim_start();
+        }
+        Ok(ret)
+    }
+
+    /// Recursive descent over the tokens: `or := and ("\/" and)*`, `and :=
+    /// unary ("/\" unary)*`, `unary := "~" unary | "(" or ")" | atom`.
+    fn condition_or(tokens: &[Token]) -> anyhow::Result<(Condition, usize)> {
+        let (mut ret, mut pos) = condition_and(tokens)?;
+        while tokens.get(pos) == Some(&Token::Or) {
+            let (rhs, len) = condition_and(&tokens[pos + 1..])?;
+            ret = Condition::Or(Box::new(ret), Box::new(rhs));
+            pos += 1 + len;
+        }
+        Ok((ret, pos))
+    }
+
+    fn condition_and(tokens: &[Token]) -> anyhow::Result<(Condition, usize)> {
+        let (mut ret, mut pos) = condition_unary(tokens)?;
+        while tokens.get(pos) == Some(&Token::And) {
+            let (rhs, len) = condition_unary(&tokens[pos + 1..])?;
+            ret = Condition::And(Box::new(ret), Box::new(rhs));
+            pos += 1 + len;
+        }
+        Ok((ret, pos))
+    }
+
+    fn condition_unary(
+        tokens: &[Token],
+    ) -> anyhow::Result<(Condition, usize)> {
+        match tokens.first() {
+            Some(Token::Not) => {
+                let (inner, len) = condition_unary(&tokens[1..])?;
+                Ok((Condition::Not(Box::new(inner)), 1 + len))
+            }
+            Some(Token::Open) => {
+                let (inner, len) = condition_or(&tokens[1..])?;
+                if tokens.get(1 + len) != Some(&Token::Close) {
+                    bail!("unbalanced parentheses in condition");
+                }
+                Ok((inner, 2 + len))
+            }
+            Some(Token::Atom(atom)) => {
+                let condition = match atom.as_str() {
+                    "true" => Condition::True,
+                    "false" => Condition::False,
+                    _ => {
+                        let (lhs, rhs) =
+                            atom.split_once('=').ok_or_else(|| {
+                                anyhow!("bad condition term {atom:?}")
+                            })?;
+                        Condition::Equal(key(lhs)?, value(rhs)?)
+                    }
+                };
+                Ok((condition, 1))
+            }
+            x => bail!("unexpected {x:?} in condition"),
+        }
+    }
+
+    fn collect_keys(condition: &Condition, out: &mut Vec<Key>) {
+        match condition {
+            Condition::True | Condition::False => (),
+            Condition::Not(x) => collect_keys(x, out),
+            Condition::And(a, b) | Condition::Or(a, b) => {
+                collect_keys(a, out);
+                collect_keys(b, out);
+            }
+            Condition::Equal(key, _) => {
+                if !out.contains(key) {
+                    out.push(key.clone());
+                }
+            }
+        }
+    }
+
+    pub fn test(text: &str) -> anyhow::Result<Test> {
+        let mut header = text.lines().next().unwrap_or("").split_whitespace();
+        if header.next() != Some("RISCV") {
+            bail!("not a RISCV litmus test");
+        }
+        let name = header
+            .next()
+            .ok_or_else(|| anyhow!("litmus test has no name"))?
+            .to_string();
+        let open = text
+            .find('{')
+            .ok_or_else(|| anyhow!("no initial state section"))?;
+        let close = open
+            + text[open..]
+                .find('}')
+                .ok_or_else(|| anyhow!("unterminated initial state"))?;
+        let mut locations = vec![];
+        let mut init_registers = vec![];
+        let mut init_memory = vec![];
+        let add_location = |locations: &mut Vec<String>, name: &str| {
+            if !locations.iter().any(|x| x == name) {
+                locations.push(name.to_string());
+            }
+        };
+        for item in text[open + 1..close].split(';') {
+            let item = item.trim();
+            if item.is_empty() {
+                continue;
+            }
+            let (lhs, rhs) = item
+                .split_once('=')
+                .ok_or_else(|| anyhow!("bad initial state {item:?}"))?;
+            // throw away a C-style type, if any
+            let lhs = lhs.split_whitespace().last().unwrap_or("");
+            let value = value(rhs)?;
+            if let Value::Address(location) = &value {
+                add_location(&mut locations, location);
+            }
+            match key(lhs)? {
+                Key::Register(thread, register) => {
+                    init_registers.push((thread, register, value))
+                }
+                Key::Memory(location) => {
+                    add_location(&mut locations, &location);
+                    let Value::Int(value) = value else {
+                        bail!("pointers in memory are not supported");
+                    };
+                    init_memory.push((location, value))
+                }
+            }
+        }
+        // the program table, up to the final condition
+        let rest = &text[close + 1..];
+        let mut table = vec![];
+        let mut tail = String::new();
+        for line in rest.lines() {
+            let trimmed = line.trim();
+            if !tail.is_empty()
+                || ["exists", "~exists", "forall", "locations", "filter"]
+                    .iter()
+                    .any(|x| trimmed.starts_with(x))
+            {
+                tail.push_str(trimmed);
+                tail.push(' ');
+            } else if !trimmed.is_empty() {
+                table.push(trimmed.trim_end_matches(';'));
+            }
+        }
+        let (columns, rows) = table
+            .split_first()
+            .ok_or_else(|| anyhow!("no program table"))?;
+        let thread_count = columns.split('|').count();
+        for (n, column) in columns.split('|').enumerate() {
+            if column.trim() != format!("P{n}") {
+                bail!("program table column {n} should be P{n}");
+            }
+        }
+        let mut sources = vec![vec![]; thread_count];
+        for row in rows {
+            for (n, cell) in row.split('|').enumerate() {
+                let cell = cell.trim();
+                if n >= thread_count {
+                    bail!("program table row has too many columns");
+                }
+                if !cell.is_empty() {
+                    sources[n].push(cell);
+                }
+            }
+        }
+        let threads = sources
+            .iter()
+            .enumerate()
+            .map(|(n, source)| {
+                asm::assemble(source).with_context(|| format!("In P{n}"))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        // optional `locations [...]`, then the quantified condition
+        let mut tail = tail.trim();
+        let mut observed = vec![];
+        if let Some(rest) = tail.strip_prefix("locations") {
+            let rest = rest.trim_start();
+            let end = rest
+                .find(']')
+                .ok_or_else(|| anyhow!("unterminated locations"))?;
+            for item in rest[..end].trim_start_matches('[').split(';') {
+                if !item.trim().is_empty() {
+                    observed.push(key(item)?);
+                }
+            }
+            tail = rest[end + 1..].trim();
+        }
+        let (quantifier, condition_text) =
+            if let Some(rest) = tail.strip_prefix("~exists") {
+                (Quantifier::NotExists, rest)
+            } else if let Some(rest) = tail.strip_prefix("exists") {
+                (Quantifier::Exists, rest)
+            } else if let Some(rest) = tail.strip_prefix("forall") {
+                (Quantifier::Forall, rest)
+            } else if tail.starts_with("filter") {
+                bail!("filter clauses are not supported");
+            } else {
+                bail!("no final condition");
+            };
+        let condition_text = condition_text.trim().to_string();
+        let tokens = tokenize(&condition_text)?;
+        let (condition, len) = condition_or(&tokens)?;
+        if len != tokens.len() {
+            bail!("trailing garbage after condition");
+        }
+        collect_keys(&condition, &mut observed);
+        for key in observed.iter() {
+            if let Key::Memory(location) = key {
+                add_location(&mut locations, location);
+            }
+            if let Key::Register(thread, _) = key {
+                if *thread >= thread_count {
+                    bail!("condition refers to nonexistent thread {thread}");
+                }
+            }
+        }
+        Ok(Test {
+            name,
+            threads,
+            locations,
+            init_registers,
+            init_memory,
+            quantifier,
+            condition,
+            condition_text,
+            observed,
+        })
+    }
+}
+
+/// Just enough of an assembler for litmus tests.
+mod asm {
+    use super::*;
+    use parse::register;
+
+    fn r(funct7: u32, rs2: u32, rs1: u32, funct3: u32, rd: u32) -> u32 {
+        (funct7 << 25) | (rs2 << 20) | (rs1 << 15) | (funct3 << 12) | (rd << 7)
+    }
+    fn i(imm: i32, rs1: u32, funct3: u32, rd: u32, opcode: u32) -> u32 {
+        ((imm as u32) << 20)
+            | (rs1 << 15)
+            | (funct3 << 12)
+            | (rd << 7)
+            | opcode
+    }
+    fn s(imm: i32, rs2: u32, rs1: u32, funct3: u32) -> u32 {
+        let imm = imm as u32;
+        ((imm >> 5) << 25)
+            | (rs2 << 20)
+            | (rs1 << 15)
+            | (funct3 << 12)
+            | ((imm & 0x1F) << 7)
+            | 0b0100011
+    }
+    fn b(offset: i32, rs2: u32, rs1: u32, funct3: u32) -> u32 {
+        let imm = offset as u32;
+        (((imm >> 12) & 1) << 31)
+            | (((imm >> 5) & 0x3F) << 25)
+            | (rs2 << 20)
+            | (rs1 << 15)
+            | (funct3 << 12)
+            | (((imm >> 1) & 0xF) << 8)
+            | (((imm >> 11) & 1) << 7)
+            | 0b1100011
+    }
+    fn j(offset: i32, rd: u32) -> u32 {
+        let imm = offset as u32;
+        (((imm >> 20) & 1) << 31)
+            | (((imm >> 1) & 0x3FF) << 21)
+            | (((imm >> 11) & 1) << 20)
+            | (((imm >> 12) & 0xFF) << 12)
+            | (rd << 7)
+            | 0b1101111
+    }
+
+    fn immediate(text: &str) -> anyhow::Result<i32> {
+        match parse::value(text)? {
+            Value::Int(x) => Ok(x as i32),
+            Value::Address(_) => bail!("expected a number, got {text:?}"),
+        }
+    }
+
+    fn imm12(text: &str) -> anyhow::Result<i32> {
+        let imm = immediate(text)?;
+        if !(-2048..2048).contains(&imm) {
+            bail!("immediate {imm} out of range");
+        }
+        Ok(imm)
+    }
+
+    /// `imm(reg)` or `(reg)`
+    fn memory(text: &str) -> anyhow::Result<(i32, u32)> {
+        let text = text.trim();
+        let (imm, rest) = text
+            .split_once('(')
+            .ok_or_else(|| anyhow!("bad memory operand {text:?}"))?;
+        let reg = rest
+            .strip_suffix(')')
+            .ok_or_else(|| anyhow!("bad memory operand {text:?}"))?;
+        let imm = if imm.trim().is_empty() {
+            0
+        } else {
+            imm12(imm)?
+        };
+        Ok((imm, register(reg)?))
+    }
+
+    /// `rw`, `iorw`, etc. to the fence bits
+    fn fence_set(text: &str) -> anyhow::Result<u32> {
+        let mut ret = 0;
+        for x in text.trim().chars() {
+            ret |= match x {
+                'i' => 8,
+                'o' => 4,
+                'r' => 2,
+                'w' => 1,
+                _ => bail!("bad fence operand {text:?}"),
+            };
+        }
+        Ok(ret)
+    }
+
+    /// Assemble one instruction at `pc`. Returns one or two words.
+    fn instruction(
+        line: &str,
+        pc: u32,
+        labels: Option<&HashMap<String, u32>>,
+    ) -> anyhow::Result<Vec<u32>> {
+        let (mnemonic, operands) =
+            line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let mnemonic = mnemonic.to_ascii_lowercase();
+        let ops: Vec<&str> = if operands.trim().is_empty() {
+            vec![]
+        } else {
+            operands.split(',').map(str::trim).collect()
+        };
+        let want = |n: usize| -> anyhow::Result<()> {
+            if ops.len() != n {
+                bail!("{mnemonic} takes {n} operands");
+            }
+            Ok(())
+        };
+        // on the first pass, labels aren't known yet, so anything will do
+        let target = |text: &str| -> anyhow::Result<i32> {
+            let Some(labels) = labels else {
+                return Ok(0);
+            };
+            match labels.get(text) {
+                Some(&address) => Ok(address.wrapping_sub(pc) as i32),
+                None => immediate(text)
+                    .map_err(|_| anyhow!("unknown label {text:?}")),
+            }
+        };
+        const ALU: [(&str, u32, u32); 10] = [
+            ("add", 0, 0),
+            ("sub", 0b0100000, 0),
+            ("sll", 0, 1),
+            ("slt", 0, 2),
+            ("sltu", 0, 3),
+            ("xor", 0, 4),
+            ("srl", 0, 5),
+            ("sra", 0b0100000, 5),
+            ("or", 0, 6),
+            ("and", 0, 7),
+        ];
+        const ALU_IMM: [(&str, u32); 6] = [
+            ("addi", 0),
+            ("slti", 2),
+            ("sltiu", 3),
+            ("xori", 4),
+            ("ori", 6),
+            ("andi", 7),
+        ];
+        const LOADS: [(&str, u32); 5] =
+            [("lb", 0), ("lh", 1), ("lw", 2), ("lbu", 4), ("lhu", 5)];
+        const STORES: [(&str, u32); 3] = [("sb", 0), ("sh", 1), ("sw", 2)];
+        const BRANCHES: [(&str, u32); 6] = [
+            ("beq", 0),
+            ("bne", 1),
+            ("blt", 4),
+            ("bge", 5),
+            ("bltu", 6),
+            ("bgeu", 7),
+        ];
+        const AMOS: [(&str, u32); 11] = [
+            ("lr.w", 0b00010),
+            ("sc.w", 0b00011),
+            ("amoswap.w", 0b00001),
+            ("amoadd.w", 0b00000),
+            ("amoxor.w", 0b00100),
+            ("amoand.w", 0b01100),
+            ("amoor.w", 0b01000),
+            ("amomin.w", 0b10000),
+            ("amomax.w", 0b10100),
+            ("amominu.w", 0b11000),
+            ("amomaxu.w", 0b11100),
+        ];
+        let m = mnemonic.as_str();
+        if let Some(&(_, funct7, funct3)) = ALU.iter().find(|x| x.0 == m) {
+            want(3)?;
+            let (rd, rs1, rs2) =
+                (register(ops[0])?, register(ops[1])?, register(ops[2])?);
+            return Ok(vec![r(funct7, rs2, rs1, funct3, rd) | 0b0110011]);
+        }
+        if let Some(&(_, funct3)) = ALU_IMM.iter().find(|x| x.0 == m) {
+            want(3)?;
+            let (rd, rs1) = (register(ops[0])?, register(ops[1])?);
+            return Ok(vec![i(imm12(ops[2])?, rs1, funct3, rd, 0b0010011)]);
+        }
+        if let Some(&(_, funct3)) = LOADS.iter().find(|x| x.0 == m) {
+            want(2)?;
+            let (imm, rs1) = memory(ops[1])?;
+            return Ok(vec![i(
+                imm,
+                rs1,
+                funct3,
+                register(ops[0])?,
+                0b0000011,
+            )]);
+        }
+        if let Some(&(_, funct3)) = STORES.iter().find(|x| x.0 == m) {
+            want(2)?;
+            let (imm, rs1) = memory(ops[1])?;
+            return Ok(vec![s(imm, register(ops[0])?, rs1, funct3)]);
+        }
+        if let Some(&(_, funct3)) = BRANCHES.iter().find(|x| x.0 == m) {
+            want(3)?;
+            let (rs1, rs2) = (register(ops[0])?, register(ops[1])?);
+            return Ok(vec![b(target(ops[2])?, rs2, rs1, funct3)]);
+        }
+        // AMOs, with optional `.aq`, `.rl`, `.aqrl`, `.aq.rl` suffixes
+        let base = m
+            .trim_end_matches(".aqrl")
+            .trim_end_matches(".rl")
+            .trim_end_matches(".aq");
+        if let Some(&(_, funct5)) = AMOS.iter().find(|x| x.0 == base) {
+            let suffix = &m[base.len()..];
+            let aq = suffix.contains("aq") as u32;
+            let rl = suffix.contains("rl") as u32;
+            let (rd, rs2, address) = if funct5 == 0b00010 {
+                want(2)?;
+                (register(ops[0])?, 0, ops[1])
+            } else {
+                want(3)?;
+                (register(ops[0])?, register(ops[1])?, ops[2])
+            };
+            let (imm, rs1) = memory(address)?;
+            if imm != 0 {
+                bail!("{mnemonic} can't take an offset");
+            }
+            let funct7 = (funct5 << 2) | (aq << 1) | rl;
+            return Ok(vec![r(funct7, rs2, rs1, 2, rd) | 0b0101111]);
+        }
+        Ok(vec![match m {
+            "slli" | "srli" | "srai" => {
+                want(3)?;
+                let (rd, rs1) = (register(ops[0])?, register(ops[1])?);
+                let shamt = immediate(ops[2])?;
+                if !(0..32).contains(&shamt) {
+                    bail!("shift amount {shamt} out of range");
+                }
+                let (funct7, funct3) = match m {
+                    "slli" => (0, 1),
+                    "srli" => (0, 5),
+                    _ => (0b0100000, 5),
+                };
+                r(funct7, shamt as u32, rs1, funct3, rd) | 0b0010011
+            }
+            "li" => {
+                want(2)?;
+                let rd = register(ops[0])?;
+                let imm = immediate(ops[1])?;
+                if (-2048..2048).contains(&imm) {
+                    i(imm, 0, 0, rd, 0b0010011)
+                } else {
+                    let low = (imm << 20) >> 20;
+                    let high = imm.wrapping_sub(low) as u32;
+                    return Ok(vec![
+                        high | (rd << 7) | 0b0110111,
+                        i(low, rd, 0, rd, 0b0010011),
+                    ]);
+                }
+            }
+            "lui" => {
+                want(2)?;
+                let imm = immediate(ops[1])? as u32;
+                (imm << 12) | (register(ops[0])? << 7) | 0b0110111
+            }
+            "mv" => {
+                want(2)?;
+                i(0, register(ops[1])?, 0, register(ops[0])?, 0b0010011)
+            }
+            "nop" => {
+                want(0)?;
+                i(0, 0, 0, 0, 0b0010011)
+            }
+            "beqz" | "bnez" => {
+                want(2)?;
+                let funct3 = if m == "beqz" { 0 } else { 1 };
+                b(target(ops[1])?, 0, register(ops[0])?, funct3)
+            }
+            "j" => {
+                want(1)?;
+                j(target(ops[0])?, 0)
+            }
+            "jal" => match ops.len() {
+                1 => j(target(ops[0])?, 1),
+                _ => {
+                    want(2)?;
+                    j(target(ops[1])?, register(ops[0])?)
+                }
+            },
+            "fence" => {
+                let (pred, succ) = match ops.len() {
+                    0 => (0b1111, 0b1111),
+                    _ => {
+                        want(2)?;
+                        (fence_set(ops[0])?, fence_set(ops[1])?)
+                    }
+                };
+                (pred << 24) | (succ << 20) | 0b0001111
+            }
+            "fence.tso" => {
+                want(0)?;
+                (0b1000 << 28) | (0b0011 << 24) | (0b0011 << 20) | 0b0001111
+            }
+            "fence.i" => {
+                want(0)?;
+                (1 << 12) | 0b0001111
+            }
+            _ => bail!("unknown instruction {mnemonic:?}"),
+        }])
+    }
+
+    /// Assemble one thread's code, with labels (`LC00:`) resolved relative
+    /// to its load address. Since branches are relative, the load address
+    /// doesn't matter, so we pretend it's zero.
+    pub fn assemble(source: &[&str]) -> anyhow::Result<Vec<u32>> {
+        let mut labels = HashMap::new();
+        let mut code = vec![];
+        // first pass finds out where the labels are, second pass fills in
+        // the branches
+        for pass in 0..2 {
+            code.clear();
+            for line in source.iter() {
+                let mut line = *line;
+                while let Some((label, rest)) = line.split_once(':') {
+                    if label.contains(char::is_whitespace) {
+                        break;
+                    }
+                    if pass == 0 {
+                        labels
+                            .insert(label.to_string(), code.len() as u32 * 4);
+                    }
+                    line = rest.trim();
+                }
+                if !line.is_empty() {
+                    let pc = code.len() as u32 * 4;
+                    let labels = (pass == 1).then_some(&labels);
+                    let words = instruction(line, pc, labels)
+                        .with_context(|| format!("In {line:?}"))?;
+                    code.extend(words);
+                }
+            }
+        }
+        Ok(code)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    const SB: &str = include_str!("../../litmus/SB.litmus");
+    const MP: &str = include_str!("../../litmus/MP+fence.rw.rw+ctrl.litmus");
+    const COUNTER: &str = include_str!("../../litmus/LR-SC-counter.litmus");
+    /// What `herd7 -model riscv.cat` has to say about SB.
+    const SB_MODEL: &str = "\
+Test SB Allowed
+States 4
+0:x7=0; 1:x7=0;
+0:x7=0; 1:x7=1;
+0:x7=1; 1:x7=0;
+0:x7=1; 1:x7=1;
+Ok
+";
+    fn options(runs: u64) -> Options {
+        Options {
+            runs,
+            seed: 1,
+            model: None,
+            paths: vec![],
+        }
+    }
+    #[test]
+    fn parse_test() {
+        let test = parse::test(SB).unwrap();
+        assert_eq!(test.name, "SB");
+        assert_eq!(
+            test.threads,
+            [
+                [0x00532023, 0x00042383], // sw x5,0(x6); lw x7,0(x8)
+                [0x00532023, 0x00042383],
+            ]
+        );
+        assert_eq!(test.locations, ["x", "y"]);
+        assert_eq!(test.init_registers.len(), 6);
+        assert!(test.quantifier == Quantifier::Exists);
+        assert_eq!(test.condition_text, "(0:x7=0 /\\ 1:x7=0)");
+        assert_eq!(test.observed, [Key::Register(0, 7), Key::Register(1, 7)]);
+        assert_eq!(test.value(&Value::Address("y".to_string())), 0x1010);
+        // labels resolve to the right place
+        let test = parse::test(MP).unwrap();
+        assert_eq!(
+            test.threads[1],
+            [
+                0x00032283, // lw x5,0(x6)
+                0x00029263, // bne x5,x0,LC00
+                0x0004a383, // lw x7,0(x9)
+            ]
+        );
+    }
+    #[test]
+    fn parse_condition() {
+        let test = parse::test(
+            "RISCV T\n{\nx=1;\n}\n P0 ;\n nop ;\n\
+             locations [y;]\n\
+             exists (~(0:a0=1 \\/ x=2) /\\ y=-1)\n",
+        )
+        .unwrap();
+        assert_eq!(
+            test.observed,
+            [
+                Key::Memory("y".to_string()),
+                Key::Register(0, 10),
+                Key::Memory("x".to_string()),
+            ]
+        );
+        assert_eq!(test.locations, ["x", "y"]);
+        assert!(test.eval(&test.condition, &[!0, 0, 0]));
+        assert!(!test.eval(&test.condition, &[!0, 1, 0]));
+        assert!(!test.eval(&test.condition, &[!0, 0, 2]));
+        assert!(!test.eval(&test.condition, &[0, 0, 0]));
+        assert_eq!(test.format_state(&[!0, 0, 2]), "y=-1; 0:x10=0; x=2;");
+    }
+    #[test]
+    fn malformed() {
+        let good = "RISCV T\n{\n0:x5=1;\n}\n P0 ;\n nop ;\nexists (0:x5=1)\n";
+        assert!(parse::test(good).is_ok());
+        for (from, to, error) in [
+            ("RISCV T", "X86 T", "not a RISCV litmus test"),
+            ("RISCV T", "RISCV", "has no name"),
+            ("{\n0:x5=1;\n}", "", "no initial state section"),
+            ("}", "", "unterminated initial state"),
+            ("0:x5=1;", "0:x5 1;", "bad initial state"),
+            ("0:x5=1;", "x=y;", "pointers in memory"),
+            (" P0 ;", " P1 ;", "should be P0"),
+            (" nop ;", " nop | nop ;", "too many columns"),
+            (" nop ;", " frob x1 ;", "unknown instruction"),
+            (" nop ;", " addi x5,x5,4096 ;", "out of range"),
+            (" nop ;", " beq x0,x0,nowhere ;", "unknown label"),
+            (" nop ;", " lw x5,4(x99) ;", "bad register"),
+            (" nop ;", " lr.w x5,4(x6) ;", "can't take an offset"),
+            ("(0:x5=1)", "(0:x5=1", "unbalanced parentheses"),
+            ("(0:x5=1)", "(0:x5=1) x", "trailing garbage"),
+            ("(0:x5=1)", "(0:x5)", "bad condition term"),
+            ("(0:x5=1)", "(1:x5=1)", "nonexistent thread 1"),
+            ("exists", "filter", "not supported"),
+            ("exists (0:x5=1)", "", "no final condition"),
+        ] {
+            let text = good.replacen(from, to, 1);
+            let Err(e) = parse::test(&text) else {
+                panic!("{text:?} should not parse");
+            };
+            let message = format!("{e:#}");
+            assert!(message.contains(error), "{text:?}: {message}");
+        }
+        assert!(parse::model("States 1\nx=1;\n").is_err());
+        assert!(parse::model("Test T\nStates 2\nx=1;\n").is_err());
+    }
+    #[test]
+    fn outcomes() {
+        // every hart runs each instruction to completion, so the outcomes
+        // RVWMO would allow for these two never happen
+        for text in [SB, MP] {
+            let test = parse::test(text).unwrap();
+            let env = initial_env(&test);
+            let mut rng = Rng(1);
+            let mut states = vec![];
+            for _ in 0..500 {
+                let state = run_once(&test, &env, &mut rng).unwrap().unwrap();
+                assert!(!test.eval(&test.condition, &state), "{}", test.name);
+                if !states.contains(&state) {
+                    states.push(state);
+                }
+            }
+            // but every interleaving is possible
+            assert_eq!(states.len(), 3, "{}", test.name);
+        }
+        // and this one always gets the right answer
+        let test = parse::test(COUNTER).unwrap();
+        let env = initial_env(&test);
+        let mut rng = Rng(1);
+        for _ in 0..500 {
+            let state = run_once(&test, &env, &mut rng).unwrap().unwrap();
+            assert!(test.eval(&test.condition, &state));
+        }
+    }
+    #[test]
+    fn model_check() {
+        let test = parse::test(SB).unwrap();
+        let model = parse::model(SB_MODEL).unwrap();
+        assert_eq!(model["SB"].len(), 4);
+        assert!(state_matches(&test, &[1, 0], &model["SB"][2]));
+        assert!(!state_matches(&test, &[1, 0], &model["SB"][1]));
+        assert!(run_test(&test, &options(200), Some(&model["SB"])).unwrap());
+        // a model that only allows 0:x7=0, 1:x7=1 must flag the others
+        assert!(
+            !run_test(&test, &options(200), Some(&model["SB"][1..2])).unwrap()
+        );
+    }
+}
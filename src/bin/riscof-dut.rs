@@ -299,12 +299,12 @@ fn load_elf(path: &str) -> LoadedElf {
             //assert_eq!(program_header.p_memsz % 4, 0, "Memory size not a multiple of 4.");
             // Being lazy! Round file and memory size up to a multiple of 4. It
             // only needs to be good enough to work in the tests...
-            let disk_size = if program_header.p_filesz % 4 == 0 {
+            let disk_size = if program_header.p_filesz & 3 == 0 {
                 program_header.p_filesz
             } else {
                 (program_header.p_filesz & !3) + 4
             } as usize;
-            let _mem_size = if program_header.p_memsz % 4 == 0 {
+            let _mem_size = if program_header.p_memsz & 3 == 0 {
                 program_header.p_memsz
             } else {
                 (program_header.p_memsz & !3) + 4
@@ -425,7 +425,7 @@ impl<const A: bool, const M: bool, const C: bool> ExecutionEnvironment
         address: u32,
         _mask: u32,
     ) -> Result<u32, rrv32::MemoryAccessFailure> {
-        if address % 4 != 0 {
+        if address & 3 != 0 {
             return Err(MemoryAccessFailure::Unaligned);
         }
         if address == 0xC0000000 {
@@ -445,7 +445,7 @@ impl<const A: bool, const M: bool, const C: bool> ExecutionEnvironment
         data: u32,
         mask: u32,
     ) -> Result<(), rrv32::MemoryAccessFailure> {
-        if address % 4 != 0 {
+        if address & 3 != 0 {
             return Err(MemoryAccessFailure::Unaligned);
         }
         if self.reserved_addr == address {
@@ -469,7 +469,7 @@ impl<const A: bool, const M: bool, const C: bool> ExecutionEnvironment
         &mut self,
         address: u32,
     ) -> Result<u32, rrv32::MemoryAccessFailure> {
-        if address % 4 != 0 {
+        if address & 3 != 0 {
             return Err(MemoryAccessFailure::Unaligned);
         }
         let ret = self.read_word(address, !0);
@@ -483,7 +483,7 @@ impl<const A: bool, const M: bool, const C: bool> ExecutionEnvironment
         address: u32,
         data: u32,
     ) -> Result<bool, rrv32::MemoryAccessFailure> {
-        if address % 4 != 0 {
+        if address & 3 != 0 {
             return Err(MemoryAccessFailure::Unaligned);
         }
         if self.reserved_addr != address {
@@ -549,8 +549,8 @@ fn run_inner<F: FloatBits, const A: bool, const M: bool, const C: bool>(
         .get(b"rvtest_sig_end" as &[u8])
         .expect("missing rvtest_sig_end symbol");
     assert!(sig_end >= sig_begin);
-    assert!(sig_begin % 4 == 0);
-    assert!(sig_end % 4 == 0);
+    assert!(sig_begin & 3 == 0);
+    assert!(sig_end & 3 == 0);
     let mut f = File::create(signature_path).unwrap();
     for sigaddr in (sig_begin..sig_end).step_by(4) {
         writeln!(f, "{:08x}", elfo.read_word(sigaddr, !0).unwrap()).unwrap();
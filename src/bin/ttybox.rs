@@ -8,6 +8,7 @@ use anyhow::Context;
 
 use rrv32::*;
 
+#[derive(Clone)]
 pub struct BoxSpace {
     ram: Vec<u32>,
     reserved_addr: u32,
@@ -103,6 +104,9 @@ impl ExecutionEnvironment for BoxSpace {
         self.reserved_addr = NO_RESERVED_ADDR;
         Ok(true)
     }
+    fn is_io_address(&self, address: u32) -> bool {
+        address == 0xFFFFFFFC
+    }
 }
 
 fn main() {
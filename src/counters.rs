@@ -0,0 +1,857 @@
+//! The Zicntr and Zihpm performance counters, fed by the `account_*` hooks.
+
+use super::*;
+
+/// `mcountinhibit` bit that stops `cycle`.
+const INHIBIT_CY: u32 = 1 << 0;
+/// `mcountinhibit` bit that stops `instret`.
+const INHIBIT_IR: u32 = 1 << 2;
+
+/// An event that the `hpmcounter`s can count. There's one of these for each
+/// accounting hook, except that branches are split four ways.
+///
+/// The value in an `mhpmevent` CSR is a bitmask of these events: bit `n`
+/// set means that every event whose discriminant is `n` increments the
+/// corresponding `mhpmcounter`. For example, writing
+/// `HpmEvent::BranchNotTakenForward.mask() |
+/// HpmEvent::BranchNotTakenBackward.mask()` to `mhpmevent3` makes
+/// `hpmcounter3` count untaken branches.
+///
+/// Events are counted exactly as the CPU reports them. For example, a `LD`
+/// counts one `MemoryDoubleLoad`, not two `MemoryLoad`s, no matter what your
+/// `account_memory_double_load` does.
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum HpmEvent {
+    /// [`account_ifetch`](ExecutionEnvironment::account_ifetch)
+    Ifetch = 0,
+    /// [`account_generic_op`](ExecutionEnvironment::account_generic_op)
+    GenericOp = 1,
+    /// [`account_memory_load`](ExecutionEnvironment::account_memory_load)
+    MemoryLoad = 2,
+    /// [`account_memory_store`](ExecutionEnvironment::account_memory_store)
+    MemoryStore = 3,
+    /// [`account_memory_double_load`](ExecutionEnvironment::account_memory_double_load)
+    MemoryDoubleLoad = 4,
+    /// [`account_memory_double_store`](ExecutionEnvironment::account_memory_double_store)
+    MemoryDoubleStore = 5,
+    /// [`account_memory_quad_load`](ExecutionEnvironment::account_memory_quad_load)
+    MemoryQuadLoad = 6,
+    /// [`account_memory_quad_store`](ExecutionEnvironment::account_memory_quad_store)
+    MemoryQuadStore = 7,
+    /// [`account_memory_op`](ExecutionEnvironment::account_memory_op)
+    MemoryOp = 8,
+    /// [`account_alu_op`](ExecutionEnvironment::account_alu_op)
+    AluOp = 9,
+    /// [`account_mul_op`](ExecutionEnvironment::account_mul_op)
+    MulOp = 10,
+    /// [`account_div_op`](ExecutionEnvironment::account_div_op)
+    DivOp = 11,
+    /// [`account_clmul_op`](ExecutionEnvironment::account_clmul_op)
+    ClmulOp = 12,
+    /// [`account_xperm_op`](ExecutionEnvironment::account_xperm_op)
+    XpermOp = 13,
+    /// [`account_crypto_op`](ExecutionEnvironment::account_crypto_op)
+    CryptoOp = 14,
+    /// [`account_amo_op`](ExecutionEnvironment::account_amo_op)
+    AmoOp = 15,
+    /// [`account_jump_op`](ExecutionEnvironment::account_jump_op)
+    JumpOp = 16,
+    /// [`account_branch_op`](ExecutionEnvironment::account_branch_op), taken,
+    /// forward
+    BranchTakenForward = 17,
+    /// [`account_branch_op`](ExecutionEnvironment::account_branch_op), taken,
+    /// backward
+    BranchTakenBackward = 18,
+    /// [`account_branch_op`](ExecutionEnvironment::account_branch_op), not
+    /// taken, forward
+    BranchNotTakenForward = 19,
+    /// [`account_branch_op`](ExecutionEnvironment::account_branch_op), not
+    /// taken, backward
+    BranchNotTakenBackward = 20,
+    /// [`account_float_op`](ExecutionEnvironment::account_float_op)
+    FloatOp = 21,
+    /// [`account_float_divide`](ExecutionEnvironment::account_float_divide)
+    FloatDivide = 22,
+    /// [`account_float_ternop`](ExecutionEnvironment::account_float_ternop)
+    FloatTernop = 23,
+    /// [`account_fcvt_from_int`](ExecutionEnvironment::account_fcvt_from_int)
+    FcvtFromInt = 24,
+    /// [`account_fcvt_to_int`](ExecutionEnvironment::account_fcvt_to_int)
+    FcvtToInt = 25,
+    /// [`account_sqrt`](ExecutionEnvironment::account_sqrt)
+    Sqrt = 26,
+    /// [`account_vector_op`](ExecutionEnvironment::account_vector_op)
+    VectorOp = 27,
+    /// [`account_custom_op`](ExecutionEnvironment::account_custom_op)
+    CustomOp = 28,
+}
+
+impl HpmEvent {
+    /// The bit to set in an `mhpmevent` CSR to count this event.
+    pub fn mask(self) -> u32 {
+        1 << self as u32
+    }
+}
+
+/// Wraps an [`ExecutionEnvironment`], and implements the counter CSRs on
+/// top of it:
+///
+/// - `cycle`, `instret`, `hpmcounter3` through `hpmcounter31`, and their
+///   `h` halves, read-only
+/// - `time` and `timeh`, read-only, from
+///   [`read_mtime`](ExecutionEnvironment::read_mtime)
+/// - `mcycle`, `minstret`, `mhpmcounter3` through `mhpmcounter31`, and their
+///   `h` halves
+/// - `mcountinhibit`
+/// - `mhpmevent3` through `mhpmevent31`, which select [`HpmEvent`]s
+///
+/// Every other CSR, and everything else, goes to the wrapped environment.
+/// `cycle` follows [`cycle_count`](ExecutionEnvironment::cycle_count), if
+/// your environment has one. `instret` counts instructions retired, so the
+/// ones that raise exceptions don't count.
+///
+/// There's no `mcounteren` or `scounteren`; every counter can be read at
+/// every privilege level. If that matters to you, check the privilege
+/// level in your own `read_csr` and wrap *that* environment in another
+/// layer.
+#[derive(Clone, Debug)]
+pub struct Counters<Env: ExecutionEnvironment> {
+    inner: Env,
+    cycle: u64,
+    instret: u64,
+    hpm: [u64; 29],
+    events: [u32; 29],
+    /// All of `events` ORed together, so that uninteresting events are
+    /// cheap.
+    watched: u32,
+    inhibit: u32,
+    /// What `cycle_count` said last time we asked.
+    last_cycle_count: u64,
+    /// `minstret` was written by the instruction that's about to retire, so
+    /// it shouldn't count itself.
+    instret_written: bool,
+}
+
+impl<Env: ExecutionEnvironment> Counters<Env> {
+    /// Wrap an environment, with every counter at zero.
+    pub fn new(inner: Env) -> Counters<Env> {
+        Counters {
+            last_cycle_count: inner.cycle_count().unwrap_or(0),
+            inner,
+            cycle: 0,
+            instret: 0,
+            hpm: [0; 29],
+            events: [0; 29],
+            watched: 0,
+            inhibit: 0,
+            instret_written: false,
+        }
+    }
+    /// The wrapped environment.
+    pub fn inner(&self) -> &Env {
+        &self.inner
+    }
+    /// The wrapped environment.
+    pub fn inner_mut(&mut self) -> &mut Env {
+        &mut self.inner
+    }
+    /// Take apart the `Counters`, returning the wrapped environment.
+    pub fn into_inner(self) -> Env {
+        self.inner
+    }
+    /// The current value of `mcycle`.
+    pub fn cycle(&mut self) -> u64 {
+        self.sync_cycles(false);
+        self.cycle
+    }
+    /// The current value of `minstret`.
+    pub fn instret(&self) -> u64 {
+        self.instret
+    }
+    /// The current value of `mhpmcounter<n>`, where `n` is 3 through 31.
+    pub fn hpmcounter(&self, n: u32) -> u64 {
+        self.hpm[n as usize - 3]
+    }
+    /// Catch `cycle` up with `cycle_count`, or if there isn't one, add a
+    /// cycle if an instruction was just `fetched`.
+    fn sync_cycles(&mut self, fetched: bool) {
+        let elapsed = match self.inner.cycle_count() {
+            Some(now) => {
+                let elapsed = now.wrapping_sub(self.last_cycle_count);
+                self.last_cycle_count = now;
+                elapsed
+            }
+            None => fetched as u64,
+        };
+        if self.inhibit & INHIBIT_CY == 0 {
+            self.cycle = self.cycle.wrapping_add(elapsed);
+        }
+    }
+    fn count(&mut self, event: HpmEvent) {
+        let mask = event.mask();
+        if self.watched & mask == 0 {
+            return;
+        }
+        for (n, counter) in self.hpm.iter_mut().enumerate() {
+            // mhpmcounter3 is inhibited by bit 3, and so on
+            if self.events[n] & mask != 0 && self.inhibit & (8 << n) == 0 {
+                *counter = counter.wrapping_add(1);
+            }
+        }
+    }
+}
+
+impl<Env: ExecutionEnvironment> ExecutionEnvironment for Counters<Env> {
+    const SUPPORT_A: bool = Env::SUPPORT_A;
+    const SUPPORT_C: bool = Env::SUPPORT_C;
+    const SUPPORT_M: bool = Env::SUPPORT_M;
+    const SUPPORT_ZBA: bool = Env::SUPPORT_ZBA;
+    const SUPPORT_ZBB: bool = Env::SUPPORT_ZBB;
+    const SUPPORT_ZBC: bool = Env::SUPPORT_ZBC;
+    const SUPPORT_ZBS: bool = Env::SUPPORT_ZBS;
+    const SUPPORT_ZBKB: bool = Env::SUPPORT_ZBKB;
+    const SUPPORT_ZBKC: bool = Env::SUPPORT_ZBKC;
+    const SUPPORT_ZBKX: bool = Env::SUPPORT_ZBKX;
+    const SUPPORT_ZKND: bool = Env::SUPPORT_ZKND;
+    const SUPPORT_ZKNE: bool = Env::SUPPORT_ZKNE;
+    const SUPPORT_ZKNH: bool = Env::SUPPORT_ZKNH;
+    const SUPPORT_ZKSED: bool = Env::SUPPORT_ZKSED;
+    const SUPPORT_ZKSH: bool = Env::SUPPORT_ZKSH;
+    const SUPPORT_ZKR: bool = Env::SUPPORT_ZKR;
+    const SUPPORT_ZICOND: bool = Env::SUPPORT_ZICOND;
+    const SUPPORT_ZFHMIN: bool = Env::SUPPORT_ZFHMIN;
+    const SUPPORT_ZFH: bool = Env::SUPPORT_ZFH;
+    const SUPPORT_ZFA: bool = Env::SUPPORT_ZFA;
+    const SUPPORT_ZFINX: bool = Env::SUPPORT_ZFINX;
+    const SUPPORT_ZDINX: bool = Env::SUPPORT_ZDINX;
+    const SUPPORT_ZFBFMIN: bool = Env::SUPPORT_ZFBFMIN;
+    const SUPPORT_ZVE32X: bool = Env::SUPPORT_ZVE32X;
+    const SUPPORT_ZVE32F: bool = Env::SUPPORT_ZVE32F;
+    const SUPPORT_P: bool = Env::SUPPORT_P;
+    const SUPPORT_ZCF: bool = Env::SUPPORT_ZCF;
+    const SUPPORT_ZCD: bool = Env::SUPPORT_ZCD;
+    const SUPPORT_ZCB: bool = Env::SUPPORT_ZCB;
+    const SUPPORT_ZCMP: bool = Env::SUPPORT_ZCMP;
+    const SUPPORT_ZCMT: bool = Env::SUPPORT_ZCMT;
+    const SUPPORT_ZICBOM: bool = Env::SUPPORT_ZICBOM;
+    const SUPPORT_ZICBOZ: bool = Env::SUPPORT_ZICBOZ;
+    const SUPPORT_ZICBOP: bool = Env::SUPPORT_ZICBOP;
+    const SUPPORT_ZAWRS: bool = Env::SUPPORT_ZAWRS;
+    const SUPPORT_ZIHINTPAUSE: bool = Env::SUPPORT_ZIHINTPAUSE;
+    const SUPPORT_ZIHINTNTL: bool = Env::SUPPORT_ZIHINTNTL;
+    const SUPPORT_ZACAS: bool = Env::SUPPORT_ZACAS;
+    const SUPPORT_ZABHA: bool = Env::SUPPORT_ZABHA;
+    const CACHE_BLOCK_SIZE: u32 = Env::CACHE_BLOCK_SIZE;
+    fn enable_a(&self) -> bool {
+        self.inner.enable_a()
+    }
+    fn enable_c(&self) -> bool {
+        self.inner.enable_c()
+    }
+    fn enable_m(&self) -> bool {
+        self.inner.enable_m()
+    }
+    fn enable_f(&self) -> bool {
+        self.inner.enable_f()
+    }
+    fn enable_d(&self) -> bool {
+        self.inner.enable_d()
+    }
+    fn enable_q(&self) -> bool {
+        self.inner.enable_q()
+    }
+    fn enable_zicsr(&self) -> bool {
+        self.inner.enable_zicsr()
+    }
+    fn enable_zifence(&self) -> bool {
+        self.inner.enable_zifence()
+    }
+    fn enable_zba(&self) -> bool {
+        self.inner.enable_zba()
+    }
+    fn enable_zbb(&self) -> bool {
+        self.inner.enable_zbb()
+    }
+    fn enable_zbc(&self) -> bool {
+        self.inner.enable_zbc()
+    }
+    fn enable_zbs(&self) -> bool {
+        self.inner.enable_zbs()
+    }
+    fn enable_zbkb(&self) -> bool {
+        self.inner.enable_zbkb()
+    }
+    fn enable_zbkc(&self) -> bool {
+        self.inner.enable_zbkc()
+    }
+    fn enable_zbkx(&self) -> bool {
+        self.inner.enable_zbkx()
+    }
+    fn enable_zknd(&self) -> bool {
+        self.inner.enable_zknd()
+    }
+    fn enable_zkne(&self) -> bool {
+        self.inner.enable_zkne()
+    }
+    fn enable_zknh(&self) -> bool {
+        self.inner.enable_zknh()
+    }
+    fn enable_zksed(&self) -> bool {
+        self.inner.enable_zksed()
+    }
+    fn enable_zksh(&self) -> bool {
+        self.inner.enable_zksh()
+    }
+    fn enable_zkr(&self) -> bool {
+        self.inner.enable_zkr()
+    }
+    fn enable_zicond(&self) -> bool {
+        self.inner.enable_zicond()
+    }
+    fn enable_zfhmin(&self) -> bool {
+        self.inner.enable_zfhmin()
+    }
+    fn enable_zfh(&self) -> bool {
+        self.inner.enable_zfh()
+    }
+    fn enable_zfa(&self) -> bool {
+        self.inner.enable_zfa()
+    }
+    fn enable_zfinx(&self) -> bool {
+        self.inner.enable_zfinx()
+    }
+    fn enable_zdinx(&self) -> bool {
+        self.inner.enable_zdinx()
+    }
+    fn enable_zfbfmin(&self) -> bool {
+        self.inner.enable_zfbfmin()
+    }
+    fn enable_zve32x(&self) -> bool {
+        self.inner.enable_zve32x()
+    }
+    fn enable_zve32f(&self) -> bool {
+        self.inner.enable_zve32f()
+    }
+    fn enable_p(&self) -> bool {
+        self.inner.enable_p()
+    }
+    fn enable_zcf(&self) -> bool {
+        self.inner.enable_zcf()
+    }
+    fn enable_zcd(&self) -> bool {
+        self.inner.enable_zcd()
+    }
+    fn enable_zcb(&self) -> bool {
+        self.inner.enable_zcb()
+    }
+    fn enable_zcmp(&self) -> bool {
+        self.inner.enable_zcmp()
+    }
+    fn enable_zcmt(&self) -> bool {
+        self.inner.enable_zcmt()
+    }
+    fn enable_zicbom(&self) -> bool {
+        self.inner.enable_zicbom()
+    }
+    fn enable_zicboz(&self) -> bool {
+        self.inner.enable_zicboz()
+    }
+    fn enable_zicbop(&self) -> bool {
+        self.inner.enable_zicbop()
+    }
+    fn enable_zawrs(&self) -> bool {
+        self.inner.enable_zawrs()
+    }
+    fn enable_zacas(&self) -> bool {
+        self.inner.enable_zacas()
+    }
+    fn enable_zabha(&self) -> bool {
+        self.inner.enable_zabha()
+    }
+    fn read_word(
+        &mut self,
+        address: u32,
+        mask: u32,
+    ) -> Result<u32, MemoryAccessFailure> {
+        self.inner.read_word(address, mask)
+    }
+    fn read_instruction(
+        &mut self,
+        address: u32,
+    ) -> Result<u32, MemoryAccessFailure> {
+        self.inner.read_instruction(address)
+    }
+    fn cached_instruction(
+        &mut self,
+        address: u32,
+    ) -> Option<DecodedInstruction> {
+        self.inner.cached_instruction(address)
+    }
+    fn cache_instruction(
+        &mut self,
+        address: u32,
+        decoded: DecodedInstruction,
+    ) {
+        self.inner.cache_instruction(address, decoded)
+    }
+    fn read_half(&mut self, address: u32) -> Result<u16, MemoryAccessFailure> {
+        self.inner.read_half(address)
+    }
+    fn read_byte(&mut self, address: u32) -> Result<u8, MemoryAccessFailure> {
+        self.inner.read_byte(address)
+    }
+    fn write_word(
+        &mut self,
+        address: u32,
+        data: u32,
+        mask: u32,
+    ) -> Result<(), MemoryAccessFailure> {
+        self.inner.write_word(address, data, mask)
+    }
+    fn write_half(
+        &mut self,
+        address: u32,
+        data: u16,
+    ) -> Result<(), MemoryAccessFailure> {
+        self.inner.write_half(address, data)
+    }
+    fn write_byte(
+        &mut self,
+        address: u32,
+        data: u8,
+    ) -> Result<(), MemoryAccessFailure> {
+        self.inner.write_byte(address, data)
+    }
+    fn load_reserved_word(
+        &mut self,
+        address: u32,
+    ) -> Result<u32, MemoryAccessFailure> {
+        self.inner.load_reserved_word(address)
+    }
+    fn store_reserved_word(
+        &mut self,
+        address: u32,
+        data: u32,
+    ) -> Result<bool, MemoryAccessFailure> {
+        self.inner.store_reserved_word(address, data)
+    }
+    fn atomic_modify_word(
+        &mut self,
+        address: u32,
+        operand: u32,
+        op: fn(u32, u32) -> u32,
+    ) -> Result<u32, MemoryAccessFailure> {
+        self.inner.atomic_modify_word(address, operand, op)
+    }
+    fn atomic_modify_half(
+        &mut self,
+        address: u32,
+        operand: u16,
+        op: fn(u16, u16) -> u16,
+    ) -> Result<u16, MemoryAccessFailure> {
+        self.inner.atomic_modify_half(address, operand, op)
+    }
+    fn atomic_modify_byte(
+        &mut self,
+        address: u32,
+        operand: u8,
+        op: fn(u8, u8) -> u8,
+    ) -> Result<u8, MemoryAccessFailure> {
+        self.inner.atomic_modify_byte(address, operand, op)
+    }
+    fn compare_and_swap_word(
+        &mut self,
+        address: u32,
+        expected: u32,
+        new: u32,
+    ) -> Result<u32, MemoryAccessFailure> {
+        self.inner.compare_and_swap_word(address, expected, new)
+    }
+    fn compare_and_swap_double(
+        &mut self,
+        address: u32,
+        expected: u64,
+        new: u64,
+    ) -> Result<u64, MemoryAccessFailure> {
+        self.inner.compare_and_swap_double(address, expected, new)
+    }
+    fn compare_and_swap_half(
+        &mut self,
+        address: u32,
+        expected: u16,
+        new: u16,
+    ) -> Result<u16, MemoryAccessFailure> {
+        self.inner.compare_and_swap_half(address, expected, new)
+    }
+    fn compare_and_swap_byte(
+        &mut self,
+        address: u32,
+        expected: u8,
+        new: u8,
+    ) -> Result<u8, MemoryAccessFailure> {
+        self.inner.compare_and_swap_byte(address, expected, new)
+    }
+    fn is_io_address(&self, address: u32) -> bool {
+        self.inner.is_io_address(address)
+    }
+    fn is_io_csr(&self, csr_number: u32) -> bool {
+        matches!(csr_number, 0xC01 | 0xC81) || self.inner.is_io_csr(csr_number)
+    }
+    fn perform_ecall<F: FloatBits, V: VectorBits, X: IntegerRegisters>(
+        &mut self,
+        cpu: &mut Cpu<F, V, X>,
+    ) -> Result<(), (ExceptionCause, u32)> {
+        self.inner.perform_ecall(cpu)
+    }
+    fn perform_ebreak<F: FloatBits, V: VectorBits, X: IntegerRegisters>(
+        &mut self,
+        cpu: &mut Cpu<F, V, X>,
+    ) -> Result<(), (ExceptionCause, u32)> {
+        self.inner.perform_ebreak(cpu)
+    }
+    fn perform_custom<F: FloatBits, V: VectorBits, X: IntegerRegisters>(
+        &mut self,
+        cpu: &mut Cpu<F, V, X>,
+        instruction: CustomInstruction,
+    ) -> Result<(), (ExceptionCause, u32)> {
+        self.inner.perform_custom(cpu, instruction)
+    }
+    fn read_csr(&mut self, csr_number: u32) -> Result<u32, ExceptionCause> {
+        let value = match csr_number {
+            0xB00 | 0xB80 | 0xC00 | 0xC80 => {
+                self.sync_cycles(false);
+                self.cycle
+            }
+            0xC01 | 0xC81 => self.inner.read_mtime()?,
+            0xB02 | 0xB82 | 0xC02 | 0xC82 => self.instret,
+            0xB03..=0xB1F | 0xB83..=0xB9F | 0xC03..=0xC1F | 0xC83..=0xC9F => {
+                self.hpm[(csr_number & 0x1F) as usize - 3]
+            }
+            0x320 => return Ok(self.inhibit),
+            0x323..=0x33F => {
+                return Ok(self.events[csr_number as usize - 0x323]);
+            }
+            _ => return self.inner.read_csr(csr_number),
+        };
+        if csr_number & 0x80 == 0 {
+            Ok(value as u32)
+        } else {
+            Ok((value >> 32) as u32)
+        }
+    }
+    fn write_csr(
+        &mut self,
+        csr_number: u32,
+        new_value: u32,
+    ) -> Result<(), ExceptionCause> {
+        let counter = match csr_number {
+            // the unprivileged views are read-only
+            0xC00..=0xC1F | 0xC80..=0xC9F => {
+                return Err(ExceptionCause::IllegalInstruction)
+            }
+            0xB00 | 0xB80 => {
+                self.sync_cycles(false);
+                &mut self.cycle
+            }
+            0xB02 | 0xB82 => {
+                self.instret_written = true;
+                &mut self.instret
+            }
+            0xB03..=0xB1F | 0xB83..=0xB9F => {
+                &mut self.hpm[(csr_number & 0x1F) as usize - 3]
+            }
+            0x320 => {
+                // there's no bit to inhibit `time`
+                self.inhibit = new_value & !0b10;
+                return Ok(());
+            }
+            0x323..=0x33F => {
+                self.events[csr_number as usize - 0x323] = new_value;
+                self.watched = self.events.iter().fold(0, |a, &b| a | b);
+                return Ok(());
+            }
+            _ => return self.inner.write_csr(csr_number, new_value),
+        };
+        *counter = if csr_number & 0x80 == 0 {
+            (*counter & !0xFFFF_FFFF) | new_value as u64
+        } else {
+            (*counter & 0xFFFF_FFFF) | (new_value as u64) << 32
+        };
+        Ok(())
+    }
+    fn poll_entropy(&mut self) -> Result<EntropyStatus, ExceptionCause> {
+        self.inner.poll_entropy()
+    }
+    fn fence_i(&mut self) {
+        self.inner.fence_i()
+    }
+    fn cache_block_op(
+        &mut self,
+        address: u32,
+        op: CacheBlockOp,
+    ) -> Result<(), MemoryAccessFailure> {
+        self.inner.cache_block_op(address, op)
+    }
+    fn cache_block_zero(
+        &mut self,
+        address: u32,
+    ) -> Result<(), MemoryAccessFailure> {
+        self.inner.cache_block_zero(address)
+    }
+    fn prefetch(&mut self, address: u32, kind: PrefetchKind) {
+        self.inner.prefetch(address, kind)
+    }
+    fn pause(&mut self) {
+        self.inner.pause()
+    }
+    fn wait_on_reservation(
+        &mut self,
+        short_timeout: bool,
+    ) -> Result<(), ExceptionCause> {
+        self.inner.wait_on_reservation(short_timeout)
+    }
+    fn non_temporal_hint(&mut self, locality: NonTemporalLocality) {
+        self.inner.non_temporal_hint(locality)
+    }
+    fn read_mtime(&mut self) -> Result<u64, ExceptionCause> {
+        self.inner.read_mtime()
+    }
+    fn cycle_count(&self) -> Option<u64> {
+        self.inner.cycle_count()
+    }
+    fn read_vs(&self) -> ExtensionStatus {
+        self.inner.read_vs()
+    }
+    fn write_vs(&mut self, status: ExtensionStatus) {
+        self.inner.write_vs(status)
+    }
+    fn read_fs(&self) -> ExtensionStatus {
+        self.inner.read_fs()
+    }
+    fn write_fs(&mut self, status: ExtensionStatus) {
+        self.inner.write_fs(status)
+    }
+    fn use_accurate_single_sqrt(&self) -> bool {
+        self.inner.use_accurate_single_sqrt()
+    }
+    fn use_accurate_double_sqrt(&self) -> bool {
+        self.inner.use_accurate_double_sqrt()
+    }
+    fn use_accurate_quad_sqrt(&self) -> bool {
+        self.inner.use_accurate_quad_sqrt()
+    }
+    fn account_ifetch(&mut self, pc: u32) {
+        self.sync_cycles(true);
+        self.count(HpmEvent::Ifetch);
+        self.inner.account_ifetch(pc)
+    }
+    fn account_retire(&mut self) {
+        if !std::mem::take(&mut self.instret_written)
+            && self.inhibit & INHIBIT_IR == 0
+        {
+            self.instret = self.instret.wrapping_add(1);
+        }
+        self.inner.account_retire()
+    }
+    fn account_generic_op(&mut self) {
+        self.count(HpmEvent::GenericOp);
+        self.inner.account_generic_op()
+    }
+    fn account_memory_load(&mut self, address: u32) {
+        self.count(HpmEvent::MemoryLoad);
+        self.inner.account_memory_load(address)
+    }
+    fn account_memory_store(&mut self, address: u32) {
+        self.count(HpmEvent::MemoryStore);
+        self.inner.account_memory_store(address)
+    }
+    fn account_memory_double_load(&mut self, address: u32) {
+        self.count(HpmEvent::MemoryDoubleLoad);
+        self.inner.account_memory_double_load(address)
+    }
+    fn account_memory_double_store(&mut self, address: u32) {
+        self.count(HpmEvent::MemoryDoubleStore);
+        self.inner.account_memory_double_store(address)
+    }
+    fn account_memory_quad_load(&mut self, address: u32) {
+        self.count(HpmEvent::MemoryQuadLoad);
+        self.inner.account_memory_quad_load(address)
+    }
+    fn account_memory_quad_store(&mut self, address: u32) {
+        self.count(HpmEvent::MemoryQuadStore);
+        self.inner.account_memory_quad_store(address)
+    }
+    fn account_memory_op(&mut self, address: u32) {
+        self.count(HpmEvent::MemoryOp);
+        self.inner.account_memory_op(address)
+    }
+    fn account_alu_op(&mut self) {
+        self.count(HpmEvent::AluOp);
+        self.inner.account_alu_op()
+    }
+    fn account_mul_op(&mut self) {
+        self.count(HpmEvent::MulOp);
+        self.inner.account_mul_op()
+    }
+    fn account_div_op(&mut self) {
+        self.count(HpmEvent::DivOp);
+        self.inner.account_div_op()
+    }
+    fn account_clmul_op(&mut self) {
+        self.count(HpmEvent::ClmulOp);
+        self.inner.account_clmul_op()
+    }
+    fn account_xperm_op(&mut self) {
+        self.count(HpmEvent::XpermOp);
+        self.inner.account_xperm_op()
+    }
+    fn account_crypto_op(&mut self) {
+        self.count(HpmEvent::CryptoOp);
+        self.inner.account_crypto_op()
+    }
+    fn account_amo_op(&mut self) {
+        self.count(HpmEvent::AmoOp);
+        self.inner.account_amo_op()
+    }
+    fn account_jump_op(&mut self) {
+        self.count(HpmEvent::JumpOp);
+        self.inner.account_jump_op()
+    }
+    fn account_branch_op(&mut self, did_take: bool, was_forward: bool) {
+        self.count(match (did_take, was_forward) {
+            (true, true) => HpmEvent::BranchTakenForward,
+            (true, false) => HpmEvent::BranchTakenBackward,
+            (false, true) => HpmEvent::BranchNotTakenForward,
+            (false, false) => HpmEvent::BranchNotTakenBackward,
+        });
+        self.inner.account_branch_op(did_take, was_forward)
+    }
+    fn account_float_op(&mut self, num_words: u32) {
+        self.count(HpmEvent::FloatOp);
+        self.inner.account_float_op(num_words)
+    }
+    fn account_float_divide(&mut self, num_words: u32) {
+        self.count(HpmEvent::FloatDivide);
+        self.inner.account_float_divide(num_words)
+    }
+    fn account_float_ternop(&mut self, num_words: u32) {
+        self.count(HpmEvent::FloatTernop);
+        self.inner.account_float_ternop(num_words)
+    }
+    fn account_fcvt_from_int(&mut self, num_words: u32) {
+        self.count(HpmEvent::FcvtFromInt);
+        self.inner.account_fcvt_from_int(num_words)
+    }
+    fn account_fcvt_to_int(&mut self, num_words: u32) {
+        self.count(HpmEvent::FcvtToInt);
+        self.inner.account_fcvt_to_int(num_words)
+    }
+    fn account_sqrt(&mut self, num_words: u32, num_iterations: u32) {
+        self.count(HpmEvent::Sqrt);
+        self.inner.account_sqrt(num_words, num_iterations)
+    }
+    fn account_vector_op(&mut self, elements: u32) {
+        self.count(HpmEvent::VectorOp);
+        self.inner.account_vector_op(elements)
+    }
+    fn account_custom_op(&mut self) {
+        self.count(HpmEvent::CustomOp);
+        self.inner.account_custom_op()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    const PROGRAM: [u32; 8] = [
+        0x00300293, // li x5, 3
+        0x32331073, // csrw mhpmevent3, x6
+        0xfff28293, // 1: addi x5, x5, -1
+        0xfe029ee3, // bnez x5, 1b
+        0xc02023f3, // rdinstret x7
+        0xc0302473, // csrr x8, hpmcounter3
+        0xc00024f3, // rdcycle x9
+        0x0000006f, // j .
+    ];
+    struct Rom(&'static [u32]);
+    impl ExecutionEnvironment for Rom {
+        fn read_word(
+            &mut self,
+            address: u32,
+            _mask: u32,
+        ) -> Result<u32, MemoryAccessFailure> {
+            self.0
+                .get(address as usize / 4)
+                .copied()
+                .ok_or(MemoryAccessFailure::AccessFault)
+        }
+        fn write_word(
+            &mut self,
+            _address: u32,
+            _data: u32,
+            _mask: u32,
+        ) -> Result<(), MemoryAccessFailure> {
+            Err(MemoryAccessFailure::AccessFault)
+        }
+        fn load_reserved_word(
+            &mut self,
+            _address: u32,
+        ) -> Result<u32, MemoryAccessFailure> {
+            unreachable!()
+        }
+        fn store_reserved_word(
+            &mut self,
+            _address: u32,
+            _data: u32,
+        ) -> Result<bool, MemoryAccessFailure> {
+            unreachable!()
+        }
+    }
+    #[test]
+    fn untaken_branches() {
+        let mut env = Counters::new(Rom(&PROGRAM));
+        let mut cpu = Cpu::<()>::new();
+        cpu.set_register(
+            6,
+            HpmEvent::BranchNotTakenForward.mask()
+                | HpmEvent::BranchNotTakenBackward.mask(),
+        );
+        while cpu.get_pc() != 28 {
+            cpu.step(&mut env).unwrap();
+        }
+        // rdinstret doesn't count itself
+        assert_eq!(cpu.get_register(7), 8);
+        assert_eq!(cpu.get_register(8), 1);
+        assert_eq!(cpu.get_register(9), 11);
+        assert_eq!(env.instret(), 11);
+        assert_eq!(env.hpmcounter(3), 1);
+    }
+    #[test]
+    fn exceptions_dont_retire() {
+        const PROGRAM: [u32; 3] = [
+            0x0002a303, // lw x6, 0(x5)
+            0xb0239073, // csrw minstret, x7
+            0xc0202473, // rdinstret x8
+        ];
+        let mut env = Counters::new(Rom(&PROGRAM));
+        env.write_csr(0x323, HpmEvent::Ifetch.mask()).unwrap();
+        let mut cpu = Cpu::<()>::new();
+        cpu.set_register(5, 0x1000);
+        let e = cpu.step(&mut env).unwrap_err();
+        assert_eq!(e.mcause, ExceptionCause::LoadAccessFault);
+        // fetched, but not retired
+        assert_eq!(env.hpmcounter(3), 1);
+        assert_eq!(env.instret(), 0);
+        cpu.set_register(5, 0);
+        cpu.step(&mut env).unwrap();
+        assert_eq!(env.instret(), 1);
+        // the value written is what the next instruction sees
+        cpu.set_register(7, 100);
+        cpu.step(&mut env).unwrap();
+        assert_eq!(env.instret(), 100);
+        cpu.step(&mut env).unwrap();
+        assert_eq!(cpu.get_register(8), 100);
+        assert_eq!(env.instret(), 101);
+    }
+}
@@ -7,8 +7,26 @@ mod serde_support;
 
 mod float;
 pub use float::FloatBits;
+#[cfg(feature = "float")]
+pub use float::Zfinx;
 use float::*;
 
+mod bitmanip;
+mod crypto;
+mod fast;
+pub(crate) use fast::FastOp;
+mod vector;
+pub use vector::{VectorBits, VectorCsrs};
+#[cfg(feature = "P")]
+mod packed;
+mod registers;
+pub use registers::IntegerRegisters;
+#[cfg(feature = "C")]
+mod zcm;
+
+#[cfg(test)]
+mod test_env;
+
 /// Register that always contains zero.
 pub const REGISTER_ZERO: u32 = 0;
 /// Register that contains the return address of the most recent call.
@@ -81,7 +99,7 @@ pub const REGISTER_T6: u32 = 31;
 /// Exceptions that can occur during execution of an instruction. Values
 /// correspond to `mcause` values from the RISC-V privileged spec.
 #[repr(u32)]
-#[derive(Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 #[allow(unused)]
 pub enum ExceptionCause {
     MisalignedPC = 0,
@@ -128,10 +146,23 @@ impl Debug for Exception {
 }
 
 #[repr(C)]
-pub struct Cpu<F: FloatBits = ()> {
-    registers: [u32; 32], // pc is stored where x0 would be
+#[derive(Clone)]
+pub struct Cpu<
+    F: FloatBits = (),
+    V: VectorBits = (),
+    X: IntegerRegisters = [u32; 32],
+> {
+    registers: X, // pc is stored where x0 would be
     float_registers: [F; 32],
     fcsr: F::CsrType,
+    vector_registers: [V; 32],
+    vcsr: V::CsrType,
+    /// The P extension's OV flag, if there's no `vxsat` to keep it in.
+    #[cfg(feature = "P")]
+    ov: bool,
+    /// The Zcmt extension's jump table base.
+    #[cfg(feature = "C")]
+    jvt: u32,
 }
 
 fn alu_op(alt: bool, op: u32, a: u32, b: u32) -> Result<u32, ExceptionCause> {
@@ -195,19 +226,25 @@ fn alu_op(alt: bool, op: u32, a: u32, b: u32) -> Result<u32, ExceptionCause> {
     })
 }
 
-impl<F: FloatBits> Cpu<F> {
+impl<F: FloatBits, V: VectorBits, X: IntegerRegisters> Cpu<F, V, X> {
     /// Creates a new CPU, a blank slate. All the registers are zeroed.
-    pub fn new() -> Cpu<F> {
+    pub fn new() -> Cpu<F, V, X> {
         Cpu {
-            registers: [0; 32],
+            registers: X::zero(),
             float_registers: Default::default(),
             fcsr: F::default_csr(),
+            vector_registers: [V::zero(); 32],
+            vcsr: V::default_csr(),
+            #[cfg(feature = "P")]
+            ov: false,
+            #[cfg(feature = "C")]
+            jvt: 0,
         }
     }
     /// Return the current value of the PC, i.e. the instruction that will be
     /// executed by the next `step`.
     pub fn get_pc(&self) -> u32 {
-        self.registers[0]
+        self.registers.as_slice()[0]
     }
     /// Change the current value of the PC. The pointed-to instruction will be
     /// executed by the next `step`.
@@ -220,32 +257,32 @@ impl<F: FloatBits> Cpu<F> {
     /// misaligned value, it will contain the misaligned value instead. This is
     /// a condition that can only be created by a mistake in your code!)
     pub fn set_pc(&mut self, new_pc: u32) {
-        self.registers[0] = new_pc & !1;
+        self.registers.as_mut_slice()[0] = new_pc & !1;
     }
-    /// Get the value of a general purpose register, in the range 0-31.
-    /// Register 0 always returns 0. Index greater than or equal to 32 will
-    /// cause a PANIC!
+    /// Get the value of a general purpose register, in the range 0-31 (or
+    /// 0-15 for RV32E). Register 0 always returns 0. Index greater than or
+    /// equal to the number of registers will cause a PANIC!
     ///
     /// We provide symbolic constants named according to the standard RISC-V
     /// ABI for convenience, see e.g. [`REGISTER_SP`].
     pub fn get_register(&self, index: u32) -> u32 {
-        if (1..32).contains(&index) {
-            self.registers[index as usize]
+        if (1..X::COUNT).contains(&index) {
+            self.registers.as_slice()[index as usize]
         } else if index == 0 {
             0
         } else {
             panic!("register {index} out of range")
         }
     }
-    /// Change the value of a general purpose register, in the range 0-31.
-    /// Setting register 0 is a no-op. Index greater than or equal to 32 will
-    /// cause a PANIC!
+    /// Change the value of a general purpose register, in the range 0-31 (or
+    /// 0-15 for RV32E). Setting register 0 is a no-op. Index greater than or
+    /// equal to the number of registers will cause a PANIC!
     ///
     /// We provide symbolic constants named according to the standard RISC-V
     /// ABI for convenience, see e.g. [`REGISTER_SP`].
     pub fn set_register(&mut self, index: u32, value: u32) {
-        if (1..32).contains(&index) {
-            self.registers[index as usize] = value;
+        if (1..X::COUNT).contains(&index) {
+            self.registers.as_mut_slice()[index as usize] = value;
         } else if index == 0 {
             // do nothing
         } else {
@@ -269,12 +306,23 @@ impl<F: FloatBits> Cpu<F> {
         let amop: fn(u32, u32) -> u32 = match amop {
             0b00000 => |mem, reg| {
                 // AMOADD.W
-                mem + reg
+                mem.wrapping_add(reg)
             },
             0b00001 => |_mem, reg| {
                 // AMOSWAP.W
                 reg
             },
+            0b00101 if Env::SUPPORT_ZACAS && env.enable_zacas() => {
+                // AMOCAS.W from Zacas
+                let expected = self.get_register(rd);
+                let new = self.get_register(rs2);
+                let oldmem = map_store(
+                    addr,
+                    env.compare_and_swap_word(addr, expected, new),
+                )?;
+                self.set_register(rd, oldmem);
+                return Ok(());
+            }
             0b00010 if rs2 == 0 => {
                 // LR.W
                 let result = map_load(addr, env.load_reserved_word(addr))?;
@@ -331,27 +379,136 @@ impl<F: FloatBits> Cpu<F> {
         let src = self.get_register(rs2);
         // yes, map_store, because AMOs produce store exceptions even if it is
         // the first access that faults
-        let oldmem = map_store(addr, env.read_word(addr, !0))?;
-        let newmem = amop(oldmem, src);
-        map_store(addr, env.write_word(addr, newmem, !0))?;
+        let oldmem = map_store(addr, env.atomic_modify_word(addr, src, amop))?;
         self.set_register(rd, oldmem);
         Ok(())
     }
-    /// Error result is `(mcause, mtval)`.
-    #[allow(clippy::unusual_byte_groupings)]
-    fn internal_step<Env: ExecutionEnvironment>(
+    /// The byte and halfword AMOs from Zabha, including `AMOCAS.B` and
+    /// `AMOCAS.H` if Zacas is enabled too. `width` is `funct3`.
+    #[allow(clippy::too_many_arguments)]
+    fn perform_narrow_amo<Env: ExecutionEnvironment>(
         &mut self,
         env: &mut Env,
+        amop: u32,
+        width: u32,
+        rd: u32,
+        rs1: u32,
+        rs2: u32,
+        orig_instruction: u32,
     ) -> Result<(), (ExceptionCause, u32)> {
-        let this_pc = self.get_pc();
-        let orig_instruction =
-            map_ifetch(this_pc, env.read_instruction(this_pc))?;
-        env.account_ifetch(this_pc);
+        let illegal =
+            Err((ExceptionCause::IllegalInstruction, orig_instruction));
+        let addr = self.get_register(rs1);
+        let src = self.get_register(rs2);
+        // |mem, reg| -> mem, for either width
+        macro_rules! amop {
+            ($unsigned:ty, $signed:ty) => {{
+                let amop: fn($unsigned, $unsigned) -> $unsigned = match amop {
+                    0b00000 => |mem, reg| mem.wrapping_add(reg), // AMOADD
+                    0b00001 => |_mem, reg| reg,                  // AMOSWAP
+                    0b00100 => |mem, reg| mem ^ reg,             // AMOXOR
+                    0b01100 => |mem, reg| mem & reg,             // AMOAND
+                    0b01000 => |mem, reg| mem | reg,             // AMOOR
+                    0b10000 => |mem, reg| {
+                        // AMOMIN
+                        (mem as $signed).min(reg as $signed) as $unsigned
+                    },
+                    0b10100 => |mem, reg| {
+                        // AMOMAX
+                        (mem as $signed).max(reg as $signed) as $unsigned
+                    },
+                    0b11000 => |mem, reg| mem.min(reg), // AMOMINU
+                    0b11100 => |mem, reg| mem.max(reg), // AMOMAXU
+                    _ => return illegal,
+                };
+                amop
+            }};
+        }
+        let cas = amop == 0b00101;
+        if cas && (!Env::SUPPORT_ZACAS || !env.enable_zacas()) {
+            return illegal;
+        }
+        // the old value is sign extended, even for the unsigned operations
+        let oldmem = match (width, cas) {
+            (0b000, true) => {
+                let expected = self.get_register(rd) as u8;
+                let result =
+                    env.compare_and_swap_byte(addr, expected, src as u8);
+                map_store(addr, result)? as i8 as u32
+            }
+            (0b000, false) => {
+                let result =
+                    env.atomic_modify_byte(addr, src as u8, amop!(u8, i8));
+                map_store(addr, result)? as i8 as u32
+            }
+            (_, true) => {
+                let expected = self.get_register(rd) as u16;
+                let result =
+                    env.compare_and_swap_half(addr, expected, src as u16);
+                map_store(addr, result)? as i16 as u32
+            }
+            (_, false) => {
+                let result =
+                    env.atomic_modify_half(addr, src as u16, amop!(u16, i16));
+                map_store(addr, result)? as i16 as u32
+            }
+        };
+        self.set_register(rd, oldmem);
+        Ok(())
+    }
+    /// `AMOCAS.D` from Zacas, which on RV32 works on even/odd register pairs.
+    /// The `x0` pair reads as zero and ignores writes.
+    fn perform_amocas_double<Env: ExecutionEnvironment>(
+        &mut self,
+        env: &mut Env,
+        rd: u32,
+        rs1: u32,
+        rs2: u32,
+        orig_instruction: u32,
+    ) -> Result<(), (ExceptionCause, u32)> {
+        if rd & 1 != 0 || rs2 & 1 != 0 {
+            return Err((
+                ExceptionCause::IllegalInstruction,
+                orig_instruction,
+            ));
+        }
+        let pair = |cpu: &Self, register: u32| {
+            if register == 0 {
+                0
+            } else {
+                (cpu.get_register(register + 1) as u64) << 32
+                    | cpu.get_register(register) as u64
+            }
+        };
+        let addr = self.get_register(rs1);
+        let expected = pair(self, rd);
+        let new = pair(self, rs2);
+        let oldmem =
+            map_store(addr, env.compare_and_swap_double(addr, expected, new))?;
+        if rd != 0 {
+            self.set_register(rd, oldmem as u32);
+            self.set_register(rd + 1, (oldmem >> 32) as u32);
+        }
+        Ok(())
+    }
+    /// Expand `orig_instruction`, just fetched from `this_pc`, if it's
+    /// compressed, and check that it doesn't name any missing registers.
+    /// Compressed instructions simple enough not to need expanding are
+    /// executed on the spot instead, and give `None`. Error result is
+    /// `(mcause, mtval)`.
+    #[allow(clippy::unusual_byte_groupings)]
+    #[cfg_attr(not(feature = "C"), allow(unused_variables))]
+    // (inlined because it used to be part of internal_step, and splitting it
+    // out made every uncached step measurably slower)
+    #[inline(always)]
+    fn decode<Env: ExecutionEnvironment>(
+        &mut self,
+        env: &mut Env,
+        this_pc: u32,
+        orig_instruction: u32,
+    ) -> Result<Option<u32>, (ExceptionCause, u32)> {
         #[cfg(feature = "C")]
-        let (orig_instruction, instruction, mut next_pc) = if orig_instruction
-            & 0b11
-            != 0b11
-        {
+        let instruction = if orig_instruction & 0b11 != 0b11 {
             // It's a 16-bit instruction!
             if !Env::SUPPORT_C || !env.enable_c() {
                 return Err((
@@ -404,6 +561,43 @@ impl<F: FloatBits> Cpu<F> {
                     { $start $( | inserted!(extracted!($input) => $output))+ }
                 };
             }
+            macro_rules! zcb {
+                () => {
+                    Env::SUPPORT_ZCB && env.enable_zcb()
+                };
+            }
+            macro_rules! zcf {
+                () => {
+                    Env::SUPPORT_ZCF && env.enable_zcf()
+                };
+            }
+            macro_rules! zcm {
+                () => {
+                    (Env::SUPPORT_ZCMP && env.enable_zcmp())
+                        || (Env::SUPPORT_ZCMT && env.enable_zcmt())
+                };
+            }
+            // Zcmp and Zcmt take over Zcd's encodings
+            macro_rules! zcd {
+                () => {
+                    Env::SUPPORT_ZCD && env.enable_zcd() && !zcm!()
+                };
+            }
+            // most compressed instructions that name x16-x31 expand into
+            // ones that get caught later, but these are executed right here
+            if X::COUNT < 32 {
+                let (rd, rs2) = extract!((11..7), (6..2));
+                let missing = match extract!((15..13), (1..0)) {
+                    (0b000 | 0b010 | 0b011, 0b01) | (0b000, 0b10) => {
+                        rd >= X::COUNT
+                    }
+                    (0b100, 0b10) => rd >= X::COUNT || rs2 >= X::COUNT,
+                    _ => false,
+                };
+                if missing {
+                    illegal!()
+                }
+            }
             let decoded_instruction: u32 = match extract!((15..13), (1..0)) {
                 (0b000, 0b00) => {
                     // ADDI4SPN
@@ -418,10 +612,13 @@ impl<F: FloatBits> Cpu<F> {
                     );
                     env.account_generic_op();
                     self.set_pc(this_pc.wrapping_add(2));
-                    return Ok(());
+                    return Ok(None);
                 }
                 (0b001, 0b00) => {
                     // FLD
+                    if !zcd!() {
+                        illegal!()
+                    }
                     let offset = assemble!(0, (12..10)->3, (6..5)->6);
                     let (rs1, rd) = extract!((9..7), (4..2));
                     assemble!(0b011_00000_0000111, (rs1+8)->15, (rd+8)->7, offset->20)
@@ -435,16 +632,51 @@ impl<F: FloatBits> Cpu<F> {
                 }
                 (0b011, 0b00) => {
                     // FLW
+                    if !zcf!() {
+                        illegal!()
+                    }
                     let offset =
                         assemble!(0, (12..10)->3, (6..6)->2, (5..5)->6);
                     let (rs1, rd) = extract!((9..7), (4..2));
                     assemble!(0b010_00000_0000111, (rs1+8)->15, (rd+8)->7, offset->20)
                 }
+                (0b100, 0b00) if zcb!() => {
+                    let (op, rs1, rd) = extract!((12..10), (9..7), (4..2));
+                    let (rs1, rd) = (rs1 + 8, rd + 8);
+                    let offset = assemble!(0, (6..6)->0, (5..5)->1);
+                    match (op, offset & 1) {
+                        // LBU
+                        (0b000, _) => {
+                            assemble!(0b100_00000_0000011, rs1->15, rd->7, offset->20)
+                        }
+                        // LHU
+                        (0b001, 0) => {
+                            assemble!(0b101_00000_0000011, rs1->15, rd->7, offset->20)
+                        }
+                        // LH
+                        (0b001, _) => {
+                            let offset = offset & 0b10;
+                            assemble!(0b001_00000_0000011, rs1->15, rd->7, offset->20)
+                        }
+                        // SB (rd is really rs2)
+                        (0b010, _) => {
+                            assemble!(0b000_00000_0100011, rs1->15, rd->20, offset->7)
+                        }
+                        // SH
+                        (0b011, 0) => {
+                            assemble!(0b001_00000_0100011, rs1->15, rd->20, offset->7)
+                        }
+                        _ => illegal!(),
+                    }
+                }
                 (0b101, 0b00) => {
                     // FSD
+                    if !zcd!() {
+                        illegal!()
+                    }
                     let offset = assemble!(0, (12..10)->3, (6..5)->6);
                     let (rs1, rs2) = extract!((9..7), (4..2));
-                    assemble!(0b010_00000_0100111, (rs1+8)->15, (rs2+8)->20, (offset>>5)->25, (offset&0b11111)->7)
+                    assemble!(0b011_00000_0100111, (rs1+8)->15, (rs2+8)->20, (offset>>5)->25, (offset&0b11111)->7)
                 }
                 (0b110, 0b00) => {
                     // SW
@@ -455,6 +687,9 @@ impl<F: FloatBits> Cpu<F> {
                 }
                 (0b111, 0b00) => {
                     // FSW
+                    if !zcf!() {
+                        illegal!()
+                    }
                     let offset =
                         assemble!(0, (12..10)->3, (6..6)->2, (5..5)->6);
                     let (rs1, rs2) = extract!((9..7), (4..2));
@@ -470,7 +705,7 @@ impl<F: FloatBits> Cpu<F> {
                     );
                     self.set_pc(self.get_pc().wrapping_add(2));
                     env.account_generic_op();
-                    return Ok(());
+                    return Ok(None);
                 }
                 (0b001, 0b01) => {
                     // JAL
@@ -479,7 +714,7 @@ impl<F: FloatBits> Cpu<F> {
                     self.set_register(1, this_pc.wrapping_add(2));
                     self.set_pc(self.get_pc().wrapping_add(imm) & !1);
                     env.account_jump_op();
-                    return Ok(());
+                    return Ok(None);
                 }
                 (0b010, 0b01) => {
                     // LI
@@ -488,7 +723,7 @@ impl<F: FloatBits> Cpu<F> {
                     self.set_register(rd, imm);
                     self.set_pc(this_pc.wrapping_add(2));
                     env.account_generic_op();
-                    return Ok(());
+                    return Ok(None);
                 }
                 (0b011, 0b01) => {
                     let rd = extract!((11..7));
@@ -501,7 +736,7 @@ impl<F: FloatBits> Cpu<F> {
                         );
                         self.set_pc(this_pc.wrapping_add(2));
                         env.account_generic_op();
-                        return Ok(());
+                        return Ok(None);
                     } else {
                         // LUI
                         let imm = assemble!(0, (~12..12)->17, (6..2)->12);
@@ -511,7 +746,7 @@ impl<F: FloatBits> Cpu<F> {
                         self.set_register(rd, imm);
                         self.set_pc(this_pc.wrapping_add(2));
                         env.account_generic_op();
-                        return Ok(());
+                        return Ok(None);
                     }
                 }
                 (0b100, 0b01) => {
@@ -531,7 +766,7 @@ impl<F: FloatBits> Cpu<F> {
                             );
                             self.set_pc(this_pc.wrapping_add(2));
                             env.account_generic_op();
-                            return Ok(());
+                            return Ok(None);
                         }
                         1 => {
                             // SRAI
@@ -546,7 +781,7 @@ impl<F: FloatBits> Cpu<F> {
                             );
                             self.set_pc(this_pc.wrapping_add(2));
                             env.account_generic_op();
-                            return Ok(());
+                            return Ok(None);
                         }
                         2 => {
                             // ANDI
@@ -554,7 +789,52 @@ impl<F: FloatBits> Cpu<F> {
                             self.set_register(rd, self.get_register(rd) & imm);
                             self.set_pc(this_pc.wrapping_add(2));
                             env.account_generic_op();
-                            return Ok(());
+                            return Ok(None);
+                        }
+                        3 if extract!((12..12)) != 0 => {
+                            // Zcb, where RV64's SUBW and ADDW would be
+                            if !zcb!() {
+                                illegal!()
+                            }
+                            match extract!((6..5), (4..2)) {
+                                (0b10, rs2) => {
+                                    // MUL
+                                    assemble!(0b0000001_00000_00000_000_00000_0110011, rd->7, rd->15, (rs2+8)->20)
+                                }
+                                (0b11, 0b000) => {
+                                    // ZEXT.B
+                                    self.set_register(
+                                        rd,
+                                        self.get_register(rd) & 0xFF,
+                                    );
+                                    self.set_pc(this_pc.wrapping_add(2));
+                                    env.account_generic_op();
+                                    return Ok(None);
+                                }
+                                (0b11, 0b001) => {
+                                    // SEXT.B
+                                    assemble!(0b0110000_00100_00000_001_00000_0010011, rd->7, rd->15)
+                                }
+                                (0b11, 0b010) => {
+                                    // ZEXT.H
+                                    assemble!(0b0000100_00000_00000_100_00000_0110011, rd->7, rd->15)
+                                }
+                                (0b11, 0b011) => {
+                                    // SEXT.H
+                                    assemble!(0b0110000_00101_00000_001_00000_0010011, rd->7, rd->15)
+                                }
+                                (0b11, 0b101) => {
+                                    // NOT
+                                    self.set_register(
+                                        rd,
+                                        !self.get_register(rd),
+                                    );
+                                    self.set_pc(this_pc.wrapping_add(2));
+                                    env.account_generic_op();
+                                    return Ok(None);
+                                }
+                                _ => illegal!(),
+                            }
                         }
                         3 => {
                             // and the rest
@@ -585,7 +865,7 @@ impl<F: FloatBits> Cpu<F> {
                             }
                             self.set_pc(this_pc.wrapping_add(2));
                             env.account_generic_op();
-                            return Ok(());
+                            return Ok(None);
                         }
                         _ => unreachable!(),
                     }
@@ -595,7 +875,7 @@ impl<F: FloatBits> Cpu<F> {
                     let imm = assemble!(0, (~12..12)->11, (11..11)->4, (10..9)->8, (8..8)->10, (7..7)->6, (6..6)->7, (5..3)->1, (2..2)->5);
                     self.set_pc(this_pc.wrapping_add(imm) & !1);
                     env.account_jump_op();
-                    return Ok(());
+                    return Ok(None);
                 }
                 (0b110, 0b01) => {
                     // BEQZ
@@ -608,10 +888,10 @@ impl<F: FloatBits> Cpu<F> {
                         self.set_pc(this_pc.wrapping_add(2));
                         env.account_branch_op(
                             false,
-                            orig_instruction & (1 << 12) != 0,
+                            orig_instruction & (1 << 12) == 0,
                         );
                     }
-                    return Ok(());
+                    return Ok(None);
                 }
                 (0b111, 0b01) => {
                     // BNEZ
@@ -624,10 +904,10 @@ impl<F: FloatBits> Cpu<F> {
                         self.set_pc(this_pc.wrapping_add(2));
                         env.account_branch_op(
                             false,
-                            orig_instruction & (1 << 12) != 0,
+                            orig_instruction & (1 << 12) == 0,
                         );
                     }
-                    return Ok(());
+                    return Ok(None);
                 }
                 (0b000, 0b10) => {
                     // SLLI
@@ -641,10 +921,13 @@ impl<F: FloatBits> Cpu<F> {
                     );
                     self.set_pc(this_pc.wrapping_add(2));
                     env.account_generic_op();
-                    return Ok(());
+                    return Ok(None);
                 }
                 (0b001, 0b10) => {
                     // FLDSP
+                    if !zcd!() {
+                        illegal!()
+                    }
                     let rs = 2;
                     let rd = extract!((11..7));
                     let offset =
@@ -664,6 +947,9 @@ impl<F: FloatBits> Cpu<F> {
                 }
                 (0b011, 0b10) => {
                     // FLWSP
+                    if !zcf!() {
+                        illegal!()
+                    }
                     let rs = 2;
                     let rd = extract!((11..7));
                     let offset =
@@ -681,7 +967,7 @@ impl<F: FloatBits> Cpu<F> {
                             }
                             self.set_pc(self.get_register(rs1) & !1);
                             env.account_jump_op();
-                            return Ok(());
+                            return Ok(None);
                         }
                         (0, rd, rs2) => {
                             // MV
@@ -689,7 +975,7 @@ impl<F: FloatBits> Cpu<F> {
                             self.set_register(rd, self.get_register(rs2));
                             self.set_pc(this_pc.wrapping_add(2));
                             env.account_generic_op();
-                            return Ok(());
+                            return Ok(None);
                         }
                         (1, 0, 0) => {
                             // EBREAK
@@ -702,7 +988,7 @@ impl<F: FloatBits> Cpu<F> {
                             self.set_register(1, this_pc.wrapping_add(2));
                             self.set_pc(dst & !1);
                             env.account_jump_op();
-                            return Ok(());
+                            return Ok(None);
                         }
                         (1, rd, rs2) => {
                             // ADD
@@ -711,8 +997,25 @@ impl<F: FloatBits> Cpu<F> {
                         _ => unreachable!(),
                     }
                 }
+                (0b101, 0b10) if zcm!() => {
+                    if extract!((12..10)) == 0b000 {
+                        if !Env::SUPPORT_ZCMT || !env.enable_zcmt() {
+                            illegal!()
+                        }
+                        return self
+                            .zcmt(env, orig_instruction)
+                            .map(|()| None);
+                    }
+                    if !Env::SUPPORT_ZCMP || !env.enable_zcmp() {
+                        illegal!()
+                    }
+                    return self.zcmp(env, orig_instruction).map(|()| None);
+                }
                 (0b101, 0b10) => {
                     // FSDSP
+                    if !zcd!() {
+                        illegal!()
+                    }
                     let rs = 2;
                     let rd = extract!((6..2));
                     let offset = assemble!(0, (12..10)->3, (9..7)->6);
@@ -727,6 +1030,9 @@ impl<F: FloatBits> Cpu<F> {
                 }
                 (0b111, 0b10) => {
                     // FSWSP
+                    if !zcf!() {
+                        illegal!()
+                    }
                     let rs = 2;
                     let rd = extract!((6..2));
                     let offset = assemble!(0, (12..9)->2, (8..7)->6);
@@ -734,23 +1040,86 @@ impl<F: FloatBits> Cpu<F> {
                 }
                 _ => illegal!(),
             };
-            // 16-bit instructions are 2 bytes long
-            (
-                orig_instruction,
-                decoded_instruction,
-                this_pc.wrapping_add(2),
-            )
-            // 32-bit instructions are 4 bytes long
+            decoded_instruction
         } else {
-            (orig_instruction, orig_instruction, this_pc.wrapping_add(4))
+            orig_instruction
         };
         #[cfg(not(feature = "C"))]
         if Env::SUPPORT_C && env.enable_c() {
             panic!("Your crate skipped compiling RV32C support but then enabled it!")
         }
         #[cfg(not(feature = "C"))]
-        let (instruction, orig_instruction, mut next_pc) =
-            (orig_instruction, orig_instruction, this_pc.wrapping_add(4));
+        let instruction = orig_instruction;
+        if X::COUNT < 32 {
+            let zfinx =
+                F::SUPPORT_FCSR && Env::SUPPORT_ZFINX && env.enable_zfinx();
+            if registers::names_missing_register(instruction, zfinx, X::COUNT)
+            {
+                return Err((
+                    ExceptionCause::IllegalInstruction,
+                    orig_instruction,
+                ));
+            }
+        }
+        Ok(Some(instruction))
+    }
+    /// Everything but a cache hit that can take the shortcut. `cached` is
+    /// whatever the cache had for this address. Error result is
+    /// `(mcause, mtval)`.
+    #[allow(clippy::unusual_byte_groupings)]
+    // (kept out of line, so that a cache hit doesn't pay for setting up the
+    // full decoder's stack frame)
+    #[inline(never)]
+    fn internal_step<Env: ExecutionEnvironment>(
+        &mut self,
+        env: &mut Env,
+        cached: Option<DecodedInstruction>,
+    ) -> Result<(), (ExceptionCause, u32)> {
+        let this_pc = self.get_pc();
+        let (orig_instruction, instruction) = match cached {
+            Some(decoded) if decoded.expanded & 0b11 == 0b11 => {
+                env.account_ifetch(this_pc);
+                (decoded.original, decoded.expanded)
+            }
+            cached => {
+                let orig_instruction = match cached {
+                    // (compressed instructions that don't get expanded
+                    // still have to be decoded every time)
+                    Some(decoded) => decoded.original,
+                    None => {
+                        map_ifetch(this_pc, env.read_instruction(this_pc))?
+                    }
+                };
+                env.account_ifetch(this_pc);
+                let orig_instruction = if cfg!(feature = "C")
+                    && orig_instruction & 0b11 != 0b11
+                {
+                    orig_instruction & 0xFFFF
+                } else {
+                    orig_instruction
+                };
+                let expanded = self.decode(env, this_pc, orig_instruction)?;
+                if cached.is_none() {
+                    let decoded = fast::decoded::<F, X, Env>(
+                        env,
+                        orig_instruction,
+                        expanded.unwrap_or(orig_instruction),
+                    );
+                    env.cache_instruction(this_pc, decoded);
+                }
+                match expanded {
+                    Some(instruction) => (orig_instruction, instruction),
+                    None => return Ok(()),
+                }
+            }
+        };
+        // 16-bit instructions are 2 bytes long, 32-bit instructions are 4
+        let mut next_pc =
+            if cfg!(feature = "C") && orig_instruction & 0b11 != 0b11 {
+                this_pc.wrapping_add(2)
+            } else {
+                this_pc.wrapping_add(4)
+            };
         let opcode = (instruction >> 2) & 0b11111;
         // I don't want to calculate these when they're not used, but I don't
         // want to repeat myself either. Fortunately for me, yesterday I
@@ -849,24 +1218,12 @@ impl<F: FloatBits> Cpu<F> {
             };
         }
         #[allow(unused_macros)]
-        macro_rules! frs1 {
-            () => {
-                self.float_registers[rs1!() as usize]
-            };
-        }
-        #[allow(unused_macros)]
         macro_rules! frs2 {
             () => {
                 self.float_registers[rs2!() as usize]
             };
         }
         #[allow(unused_macros)]
-        macro_rules! frs3 {
-            () => {
-                self.float_registers[rs3!() as usize]
-            };
-        }
-        #[allow(unused_macros)]
         macro_rules! frd {
             ($value:expr) => {
                 let rd = rd!() as usize;
@@ -877,52 +1234,212 @@ impl<F: FloatBits> Cpu<F> {
                 }
             };
         }
+        // true if the float instructions operate on the integer registers
+        #[allow(unused_macros)]
+        macro_rules! zfinx {
+            () => {
+                F::SUPPORT_FCSR && Env::SUPPORT_ZFINX && env.enable_zfinx()
+            };
+        }
+        #[allow(unused_macros)]
+        macro_rules! has_f {
+            () => {
+                if zfinx!() {
+                    true
+                } else {
+                    F::SUPPORT_F && env.enable_f()
+                }
+            };
+        }
+        #[allow(unused_macros)]
+        macro_rules! has_d {
+            () => {
+                if zfinx!() {
+                    Env::SUPPORT_ZDINX && env.enable_zdinx()
+                } else {
+                    F::SUPPORT_D && env.enable_d()
+                }
+            };
+        }
+        #[allow(unused_macros)]
+        macro_rules! has_q {
+            () => {
+                !zfinx!() && F::SUPPORT_Q && env.enable_q()
+            };
+        }
+        // read the bits of a float source register, which under Zfinx is an
+        // integer register (or, for doubles, an even/odd pair)
+        #[allow(unused_macros)]
+        macro_rules! fread {
+            (Half, $reg:expr) => {
+                F::unbox_half(self.float_registers[$reg as usize])
+            };
+            (BFloat, $reg:expr) => {
+                F::unbox_half(self.float_registers[$reg as usize])
+            };
+            (Single, $reg:expr) => {
+                if zfinx!() {
+                    self.get_register($reg)
+                } else {
+                    F::unbox_single(self.float_registers[$reg as usize])
+                }
+            };
+            (Double, $reg:expr) => {{
+                let reg = $reg;
+                if zfinx!() {
+                    if reg & 1 != 0 {
+                        illegal!()
+                    }
+                    // x0 pairs with nothing; the pair reads as zero
+                    if reg == 0 {
+                        0
+                    } else {
+                        (self.get_register(reg + 1) as u64) << 32
+                            | self.get_register(reg) as u64
+                    }
+                } else {
+                    F::unbox_double(self.float_registers[reg as usize])
+                }
+            }};
+            (Quad, $reg:expr) => {
+                F::unbox_quad(self.float_registers[$reg as usize])
+            };
+        }
+        // write the bits of a float result to rd, as with fread
+        #[allow(unused_macros)]
+        macro_rules! fwrite {
+            (Half, $bits:expr) => {
+                frd!(F::box_half($bits))
+            };
+            (BFloat, $bits:expr) => {
+                frd!(F::box_half($bits))
+            };
+            (Single, $bits:expr) => {{
+                let bits = $bits;
+                if zfinx!() {
+                    self.set_register(rd!(), bits);
+                } else {
+                    frd!(F::box_single(bits));
+                }
+            }};
+            (Double, $bits:expr) => {{
+                let bits = $bits;
+                if zfinx!() {
+                    let rd = rd!();
+                    if rd & 1 != 0 {
+                        illegal!()
+                    }
+                    // writes to the x0 pair are discarded
+                    if rd != 0 {
+                        self.set_register(rd, bits as u32);
+                        self.set_register(rd + 1, (bits >> 32) as u32);
+                    }
+                } else {
+                    frd!(F::box_double(bits));
+                }
+            }};
+            (Quad, $bits:expr) => {
+                frd!(F::box_quad($bits))
+            };
+        }
+        #[allow(unused_macros)]
+        macro_rules! zfhmin {
+            () => {
+                !zfinx!()
+                    && F::SUPPORT_F
+                    && env.enable_f()
+                    && ((Env::SUPPORT_ZFHMIN && env.enable_zfhmin())
+                        || (Env::SUPPORT_ZFH && env.enable_zfh()))
+            };
+        }
+        #[allow(unused_macros)]
+        macro_rules! zfh {
+            () => {
+                !zfinx!()
+                    && F::SUPPORT_F
+                    && env.enable_f()
+                    && Env::SUPPORT_ZFH
+                    && env.enable_zfh()
+            };
+        }
+        #[allow(unused_macros)]
+        macro_rules! zfbfmin {
+            () => {
+                !zfinx!()
+                    && F::SUPPORT_F
+                    && env.enable_f()
+                    && Env::SUPPORT_ZFBFMIN
+                    && env.enable_zfbfmin()
+            };
+        }
+        #[allow(unused_macros)]
+        macro_rules! zfa {
+            () => {
+                has_f!() && Env::SUPPORT_ZFA && env.enable_zfa()
+            };
+        }
+        #[allow(unused_macros)]
+        macro_rules! float_inputs_half {
+            () => {};
+            ($a:ident) => {
+                let $a = float::to_half(fread!(Half, rs1!()));
+            };
+            ($a:ident, $b:ident) => {
+                let $a = float::to_half(fread!(Half, rs1!()));
+                let $b = float::to_half(fread!(Half, rs2!()));
+            };
+            ($a:ident, $b:ident, $c:ident) => {
+                let $a = float::to_half(fread!(Half, rs1!()));
+                let $b = float::to_half(fread!(Half, rs2!()));
+                let $c = float::to_half(fread!(Half, rs3!()));
+            };
+        }
         #[allow(unused_macros)]
         macro_rules! float_inputs_single {
             () => {};
             ($a:ident) => {
-                let $a = float::to_single(F::unbox_single(frs1!()));
+                let $a = float::to_single(fread!(Single, rs1!()));
             };
             ($a:ident, $b:ident) => {
-                let $a = float::to_single(F::unbox_single(frs1!()));
-                let $b = float::to_single(F::unbox_single(frs2!()));
+                let $a = float::to_single(fread!(Single, rs1!()));
+                let $b = float::to_single(fread!(Single, rs2!()));
             };
             ($a:ident, $b:ident, $c:ident) => {
-                let $a = float::to_single(F::unbox_single(frs1!()));
-                let $b = float::to_single(F::unbox_single(frs2!()));
-                let $c = float::to_single(F::unbox_single(frs3!()));
+                let $a = float::to_single(fread!(Single, rs1!()));
+                let $b = float::to_single(fread!(Single, rs2!()));
+                let $c = float::to_single(fread!(Single, rs3!()));
             };
         }
         #[allow(unused_macros)]
         macro_rules! float_inputs_double {
             () => {};
             ($a:ident) => {
-                let $a = float::to_double(F::unbox_double(frs1!()));
+                let $a = float::to_double(fread!(Double, rs1!()));
             };
             ($a:ident, $b:ident) => {
-                let $a = float::to_double(F::unbox_double(frs1!()));
-                let $b = float::to_double(F::unbox_double(frs2!()));
+                let $a = float::to_double(fread!(Double, rs1!()));
+                let $b = float::to_double(fread!(Double, rs2!()));
             };
             ($a:ident, $b:ident, $c:ident) => {
-                let $a = float::to_double(F::unbox_double(frs1!()));
-                let $b = float::to_double(F::unbox_double(frs2!()));
-                let $c = float::to_double(F::unbox_double(frs3!()));
+                let $a = float::to_double(fread!(Double, rs1!()));
+                let $b = float::to_double(fread!(Double, rs2!()));
+                let $c = float::to_double(fread!(Double, rs3!()));
             };
         }
         #[allow(unused_macros)]
         macro_rules! float_inputs_quad {
             () => {};
             ($a:ident) => {
-                let $a = float::to_quad(F::unbox_quad(frs1!()));
+                let $a = float::to_quad(fread!(Quad, rs1!()));
             };
             ($a:ident, $b:ident) => {
-                let $a = float::to_quad(F::unbox_quad(frs1!()));
-                let $b = float::to_quad(F::unbox_quad(frs2!()));
+                let $a = float::to_quad(fread!(Quad, rs1!()));
+                let $b = float::to_quad(fread!(Quad, rs2!()));
             };
             ($a:ident, $b:ident, $c:ident) => {
-                let $a = float::to_quad(F::unbox_quad(frs1!()));
-                let $b = float::to_quad(F::unbox_quad(frs2!()));
-                let $c = float::to_quad(F::unbox_quad(frs3!()));
+                let $a = float::to_quad(fread!(Quad, rs1!()));
+                let $b = float::to_quad(fread!(Quad, rs2!()));
+                let $c = float::to_quad(fread!(Quad, rs3!()));
             };
         }
         #[allow(unused_macros)]
@@ -951,7 +1468,7 @@ impl<F: FloatBits> Cpu<F> {
             ($T:ident; $($o:ident),* = $($i:ident),*; $code:block) => {{
                 #[allow(unused)] use float::Float;
                 match fprecision!() {
-                    0b00 if F::SUPPORT_F && env.enable_f() => {
+                    0b00 if has_f!() => {
                         #[allow(unused)] type $T = float::Single;
                         float_inputs_single!($($i),*);
                         $(#[allow(unused_mut)] let mut $o;),*
@@ -959,13 +1476,13 @@ impl<F: FloatBits> Cpu<F> {
                         $(
                             let $o = float::maybe_unstatus(self, $o);
                             if $o.is_nan() {
-                                frd!(F::box_single(float::CANON_NAN_32));
+                                fwrite!(Single, float::CANON_NAN_32);
                             } else {
-                                frd!(F::box_single($o.to_bits() as u32));
+                                fwrite!(Single, $o.to_bits() as u32);
                             }
                         ),*
                     }
-                    0b01 if F::SUPPORT_D && env.enable_d() => {
+                    0b01 if has_d!() => {
                         #[allow(unused)] type $T = float::Double;
                         float_inputs_double!($($i),*);
                         $(#[allow(unused_mut)] let mut $o;),*
@@ -973,13 +1490,27 @@ impl<F: FloatBits> Cpu<F> {
                         $(
                             let $o = float::maybe_unstatus(self, $o);
                             if $o.is_nan() {
-                                frd!(F::box_double(float::CANON_NAN_64));
+                                fwrite!(Double, float::CANON_NAN_64);
+                            } else {
+                                fwrite!(Double, $o.to_bits() as u64);
+                            }
+                        ),*
+                    }
+                    0b10 if zfh!() => {
+                        #[allow(unused)] type $T = float::Half;
+                        float_inputs_half!($($i),*);
+                        $(#[allow(unused_mut)] let mut $o;),*
+                        $code;
+                        $(
+                            let $o = float::maybe_unstatus(self, $o);
+                            if $o.is_nan() {
+                                fwrite!(Half, float::CANON_NAN_16);
                             } else {
-                                frd!(F::box_double($o.to_bits() as u64));
+                                fwrite!(Half, $o.to_bits() as u16);
                             }
                         ),*
                     }
-                    0b11 if F::SUPPORT_Q && env.enable_q() => {
+                    0b11 if has_q!() => {
                         #[allow(unused)] type $T = float::Quad;
                         float_inputs_quad!($($i),*);
                         $(#[allow(unused_mut)] let mut $o;),*
@@ -987,9 +1518,9 @@ impl<F: FloatBits> Cpu<F> {
                         $(
                             let $o = float::maybe_unstatus(self, $o);
                             if $o.is_nan() {
-                                frd!(F::box_quad(float::CANON_NAN_128));
+                                fwrite!(Quad, float::CANON_NAN_128);
                             } else {
-                                frd!(F::box_quad($o.to_bits() as u128));
+                                fwrite!(Quad, $o.to_bits() as u128);
                             }
                         ),*
                     }
@@ -998,31 +1529,13 @@ impl<F: FloatBits> Cpu<F> {
             }};
         }
         #[allow(unused_macros)]
-        macro_rules! fbox {
-            (Single) => {
-                F::box_single
-            };
-            (Double) => {
-                F::box_double
+        macro_rules! fap {
+            (BFloat) => {
+                BFloat
             };
-            (Quad) => {
-                F::box_quad
+            (Half) => {
+                Half
             };
-        }
-        #[allow(unused_macros)]
-        macro_rules! funbox {
-            (Single) => {
-                F::unbox_single
-            };
-            (Double) => {
-                F::unbox_double
-            };
-            (Quad) => {
-                F::unbox_quad
-            };
-        }
-        #[allow(unused_macros)]
-        macro_rules! fap {
             (Single) => {
                 Single
             };
@@ -1035,6 +1548,12 @@ impl<F: FloatBits> Cpu<F> {
         }
         #[allow(unused_macros)]
         macro_rules! ftoap {
+            (BFloat) => {
+                float::to_bfloat
+            };
+            (Half) => {
+                float::to_half
+            };
             (Single) => {
                 float::to_single
             };
@@ -1047,6 +1566,12 @@ impl<F: FloatBits> Cpu<F> {
         }
         #[allow(unused_macros)]
         macro_rules! fbits {
+            (BFloat) => {
+                u16
+            };
+            (Half) => {
+                u16
+            };
             (Single) => {
                 u32
             };
@@ -1058,15 +1583,36 @@ impl<F: FloatBits> Cpu<F> {
             };
         }
         #[allow(unused_macros)]
+        macro_rules! fnan {
+            (BFloat) => {
+                float::CANON_NAN_BF16
+            };
+            (Half) => {
+                float::CANON_NAN_16
+            };
+            (Single) => {
+                float::CANON_NAN_32
+            };
+            (Double) => {
+                float::CANON_NAN_64
+            };
+            (Quad) => {
+                float::CANON_NAN_128
+            };
+        }
+        #[allow(unused_macros)]
         macro_rules! fcvt {
             ($from:ident -> $to:ident) => {
-                let a = funbox!($from)(self.float_registers[rs1!() as usize]);
+                let a = fread!($from, rs1!());
                 let mut entrocrime = false;
                 let o: StatusAnd<fap!($to)> =
                     ftoap!($from)(a).convert_r(round_mode!(), &mut entrocrime);
-                frd!(fbox!($to)(
-                    float::maybe_unstatus(self, o).to_bits() as fbits!($to)
-                ));
+                let o = float::maybe_unstatus(self, o);
+                if o.is_nan() {
+                    fwrite!($to, fnan!($to));
+                } else {
+                    fwrite!($to, o.to_bits() as fbits!($to));
+                }
             };
         }
         match opcode {
@@ -1100,13 +1646,32 @@ impl<F: FloatBits> Cpu<F> {
                 self.set_register(rd!(), result);
                 env.account_memory_load(address);
             }
+            0b00001 | 0b01001
+                if matches!(funct3!(), 0b000 | 0b101 | 0b110 | 0b111)
+                    && self.vector_enabled(env) =>
+            {
+                // vector loads and stores
+                self.vector_memory(env, instruction, opcode == 0b01001)?
+            }
             #[cfg(feature = "float")]
             0b00001 if env.read_fs() == ExtensionStatus::Disabled => {
                 illegal!()
             }
             #[cfg(feature = "float")]
+            0b00001 if (zfhmin!() || zfbfmin!()) && funct3!() == 0b001 => {
+                // FLH
+                let base = self.get_register(rs1!());
+                let address = base.wrapping_add(imm12!());
+                let result = map_load(address, env.read_half(address))?;
+                frd!(F::box_half(result));
+                env.account_memory_load(address);
+            }
+            #[cfg(feature = "float")]
             0b00001
-                if F::SUPPORT_F && env.enable_f() && funct3!() == 0b010 =>
+                if !zfinx!()
+                    && F::SUPPORT_F
+                    && env.enable_f()
+                    && funct3!() == 0b010 =>
             {
                 // FLW
                 let base = self.get_register(rs1!());
@@ -1117,7 +1682,10 @@ impl<F: FloatBits> Cpu<F> {
             }
             #[cfg(feature = "float")]
             0b00001
-                if F::SUPPORT_D && env.enable_d() && funct3!() == 0b011 =>
+                if !zfinx!()
+                    && F::SUPPORT_D
+                    && env.enable_d()
+                    && funct3!() == 0b011 =>
             {
                 // FLD
                 let base = self.get_register(rs1!());
@@ -1134,9 +1702,7 @@ impl<F: FloatBits> Cpu<F> {
                 env.account_memory_double_load(address);
             }
             #[cfg(feature = "float")]
-            0b00001
-                if F::SUPPORT_Q && env.enable_q() && funct3!() == 0b100 =>
-            {
+            0b00001 if has_q!() && funct3!() == 0b100 => {
                 // FLQ?
                 let base = self.get_register(rs1!());
                 let address = base.wrapping_add(imm12!());
@@ -1166,11 +1732,43 @@ impl<F: FloatBits> Cpu<F> {
                 match funct3!() {
                     0b000 => {
                         // FENCE
-                        // (treat as no-op)
+                        // (treat as no-op, unless it's PAUSE from
+                        // Zihintpause)
+                        if instruction == 0x0100000F
+                            && Env::SUPPORT_ZIHINTPAUSE
+                        {
+                            env.pause();
+                        }
                     }
                     0b001 if env.enable_zifence() => {
                         // FENCE.I from Zifence
-                        // (treat as no-op)
+                        // (the environment's problem, if it caches
+                        // instructions)
+                        env.fence_i();
+                    }
+                    0b010 if rd!() == 0 => {
+                        // CBO.* from Zicbom and Zicboz
+                        let address = self.get_register(rs1!());
+                        let block = address & !(Env::CACHE_BLOCK_SIZE - 1);
+                        let zicbom =
+                            Env::SUPPORT_ZICBOM && env.enable_zicbom();
+                        let zicboz =
+                            Env::SUPPORT_ZICBOZ && env.enable_zicboz();
+                        // (None is CBO.ZERO)
+                        let op = match instruction >> 20 {
+                            0b000 if zicbom => Some(CacheBlockOp::Invalidate),
+                            0b001 if zicbom => Some(CacheBlockOp::Clean),
+                            0b010 if zicbom => Some(CacheBlockOp::Flush),
+                            0b100 if zicboz => None,
+                            _ => illegal!(),
+                        };
+                        if let Some(op) = op {
+                            map_store(address, env.cache_block_op(block, op))?;
+                            env.account_memory_op(block);
+                        } else {
+                            map_store(address, env.cache_block_zero(block))?;
+                            env.account_memory_store(block);
+                        }
                     }
                     _ => {
                         illegal!()
@@ -1180,15 +1778,47 @@ impl<F: FloatBits> Cpu<F> {
             0b00100 => {
                 // OP-IMM
                 let op = funct3!();
-                let alt = op == 0b101 && (instruction & (1 << 30)) != 0;
                 let a = self.get_register(rs1!());
-                let b = imm12!();
-                self.set_register(
-                    rd!(),
-                    alu_op(alt, op, a, b)
-                        .map_err(|x| (x, orig_instruction))?,
-                );
-                env.account_alu_op();
+                if op == 0b110
+                    && rd!() == 0
+                    && Env::SUPPORT_ZICBOP
+                    && env.enable_zicbop()
+                {
+                    // an ORI to x0 might be a PREFETCH.* from Zicbop
+                    let kind = match rs2!() {
+                        0b00000 => Some(PrefetchKind::Instruction),
+                        0b00001 => Some(PrefetchKind::Read),
+                        0b00011 => Some(PrefetchKind::Write),
+                        _ => None,
+                    };
+                    if let Some(kind) = kind {
+                        let address = a.wrapping_add(imm12!() & !0b11111);
+                        let block = address & !(Env::CACHE_BLOCK_SIZE - 1);
+                        env.prefetch(block, kind);
+                    }
+                }
+                let result = match (op, funct7!()) {
+                    (0b001, 0b0000000) | (0b101, 0b0000000 | 0b0100000) => {
+                        let alt = (instruction & (1 << 30)) != 0;
+                        env.account_alu_op();
+                        alu_op(alt, op, a, imm12!())
+                            .map_err(|x| (x, orig_instruction))?
+                    }
+                    (0b001 | 0b101, _) => {
+                        match bitmanip::op_imm(env, instruction, a)
+                            .or_else(|| crypto::op_imm(env, instruction, a))
+                        {
+                            Some(x) => x,
+                            None => illegal!(),
+                        }
+                    }
+                    _ => {
+                        env.account_alu_op();
+                        alu_op(false, op, a, imm12!())
+                            .map_err(|x| (x, orig_instruction))?
+                    }
+                };
+                self.set_register(rd!(), result);
             }
             0b00101 => {
                 // AUIPC
@@ -1221,24 +1851,39 @@ impl<F: FloatBits> Cpu<F> {
                 illegal!()
             }
             #[cfg(feature = "float")]
+            0b01001 if (zfhmin!() || zfbfmin!()) && funct3!() == 0b001 => {
+                // FSH
+                let base = self.get_register(rs1!());
+                let address = base.wrapping_add(imm12s!());
+                let half = fread!(Half, rs2!());
+                map_store(address, env.write_half(address, half))?;
+                env.account_memory_store(address);
+            }
+            #[cfg(feature = "float")]
             0b01001
-                if F::SUPPORT_F && env.enable_f() && funct3!() == 0b010 =>
+                if !zfinx!()
+                    && F::SUPPORT_F
+                    && env.enable_f()
+                    && funct3!() == 0b010 =>
             {
                 // FSW
                 let base = self.get_register(rs1!());
                 let address = base.wrapping_add(imm12s!());
-                let word = F::unbox_single(frs2!());
+                let word = fread!(Single, rs2!());
                 map_store(address, env.write_word(address, word, !0))?;
                 env.account_memory_store(address);
             }
             #[cfg(feature = "float")]
             0b01001
-                if F::SUPPORT_D && env.enable_d() && funct3!() == 0b011 =>
+                if !zfinx!()
+                    && F::SUPPORT_D
+                    && env.enable_d()
+                    && funct3!() == 0b011 =>
             {
                 // FSD
                 let base = self.get_register(rs1!());
                 let address = base.wrapping_add(imm12s!());
-                let words = F::unbox_double(frs2!());
+                let words = fread!(Double, rs2!());
                 map_store(address, env.write_word(address, words as u32, !0))?;
                 // we are intentionally not adding 4 to the exception address!
                 map_store(
@@ -1252,13 +1897,11 @@ impl<F: FloatBits> Cpu<F> {
                 env.account_memory_double_store(address);
             }
             #[cfg(feature = "float")]
-            0b01001
-                if F::SUPPORT_Q && env.enable_q() && funct3!() == 0b100 =>
-            {
+            0b01001 if has_q!() && funct3!() == 0b100 => {
                 // FSQ?
                 let base = self.get_register(rs1!());
                 let address = base.wrapping_add(imm12s!());
-                let words = F::unbox_quad(frs2!());
+                let words = fread!(Quad, rs2!());
                 map_store(address, env.write_word(address, words as u32, !0))?;
                 map_store(
                     address,
@@ -1305,8 +1948,60 @@ impl<F: FloatBits> Cpu<F> {
                 )?;
                 env.account_amo_op();
             }
+            0b01011
+                if Env::SUPPORT_A
+                    && env.enable_a()
+                    && Env::SUPPORT_ZABHA
+                    && env.enable_zabha()
+                    && funct3!() & 0b110 == 0b000 =>
+            {
+                // (AMO) bytes and halfwords from Zabha
+                self.perform_narrow_amo(
+                    env,
+                    instruction >> 27,
+                    funct3!(),
+                    rd!(),
+                    rs1!(),
+                    rs2!(),
+                    orig_instruction,
+                )?;
+                env.account_amo_op();
+            }
+            0b01011
+                if Env::SUPPORT_A
+                    && env.enable_a()
+                    && Env::SUPPORT_ZACAS
+                    && env.enable_zacas()
+                    && funct3!() == 0b011
+                    && instruction >> 27 == 0b00101 =>
+            {
+                // AMOCAS.D from Zacas
+                self.perform_amocas_double(
+                    env,
+                    rd!(),
+                    rs1!(),
+                    rs2!(),
+                    orig_instruction,
+                )?;
+                env.account_amo_op();
+            }
             0b01100 => {
                 // (OP)
+                if instruction & 0xFE0FFFFF == 0b0110011
+                    && Env::SUPPORT_ZIHINTNTL
+                {
+                    // an ADD x0, x0, rs2 might be an NTL.* from Zihintntl
+                    let locality = match rs2!() {
+                        2 => Some(NonTemporalLocality::InnermostPrivate),
+                        3 => Some(NonTemporalLocality::AllPrivate),
+                        4 => Some(NonTemporalLocality::InnermostShared),
+                        5 => Some(NonTemporalLocality::All),
+                        _ => None,
+                    };
+                    if let Some(locality) = locality {
+                        env.non_temporal_hint(locality);
+                    }
+                }
                 let a = self.get_register(rs1!());
                 let b = self.get_register(rs2!());
                 let result = match funct7!() {
@@ -1316,7 +2011,7 @@ impl<F: FloatBits> Cpu<F> {
                         env.account_alu_op();
                         x
                     }
-                    0b0100000 => {
+                    0b0100000 if matches!(funct3!(), 0b000 | 0b101) => {
                         let x = alu_op(true, funct3!(), a, b)
                             .map_err(|x| (x, orig_instruction))?;
                         env.account_alu_op();
@@ -1362,11 +2057,7 @@ impl<F: FloatBits> Cpu<F> {
                             0b101 => {
                                 // DIVU
                                 env.account_div_op();
-                                if b == 0 {
-                                    !0
-                                } else {
-                                    a / b
-                                }
+                                a.checked_div(b).unwrap_or(!0)
                             }
                             0b110 => {
                                 // REM
@@ -1389,12 +2080,25 @@ impl<F: FloatBits> Cpu<F> {
                             _ => unreachable!(),
                         }
                     }
-                    _ => {
-                        return Err((
-                            ExceptionCause::IllegalInstruction,
-                            orig_instruction,
-                        ))
+                    0b0000111
+                        if Env::SUPPORT_ZICOND
+                            && env.enable_zicond()
+                            && matches!(funct3!(), 0b101 | 0b111) =>
+                    {
+                        // CZERO.EQZ (101), CZERO.NEZ (111)
+                        env.account_alu_op();
+                        if (b == 0) == (funct3!() == 0b101) {
+                            0
+                        } else {
+                            a
+                        }
                     }
+                    _ => match bitmanip::op(env, instruction, a, b)
+                        .or_else(|| crypto::op(env, instruction, a, b))
+                    {
+                        Some(x) => x,
+                        None => illegal!(),
+                    },
                 };
                 self.set_register(rd!(), result);
             }
@@ -1404,7 +2108,9 @@ impl<F: FloatBits> Cpu<F> {
                 env.account_generic_op();
             }
             #[cfg(feature = "float")]
-            0b10000 if env.read_fs() == ExtensionStatus::Disabled => {
+            0b10000
+                if !zfinx!() && env.read_fs() == ExtensionStatus::Disabled =>
+            {
                 illegal!()
             }
             #[cfg(feature = "float")]
@@ -1416,7 +2122,9 @@ impl<F: FloatBits> Cpu<F> {
                 });
             }
             #[cfg(feature = "float")]
-            0b10001 if env.read_fs() == ExtensionStatus::Disabled => {
+            0b10001
+                if !zfinx!() && env.read_fs() == ExtensionStatus::Disabled =>
+            {
                 illegal!()
             }
             #[cfg(feature = "float")]
@@ -1428,7 +2136,9 @@ impl<F: FloatBits> Cpu<F> {
                 });
             }
             #[cfg(feature = "float")]
-            0b10010 if env.read_fs() == ExtensionStatus::Disabled => {
+            0b10010
+                if !zfinx!() && env.read_fs() == ExtensionStatus::Disabled =>
+            {
                 illegal!()
             }
             #[cfg(feature = "float")]
@@ -1440,7 +2150,9 @@ impl<F: FloatBits> Cpu<F> {
                 });
             }
             #[cfg(feature = "float")]
-            0b10011 if env.read_fs() == ExtensionStatus::Disabled => {
+            0b10011
+                if !zfinx!() && env.read_fs() == ExtensionStatus::Disabled =>
+            {
                 illegal!()
             }
             #[cfg(feature = "float")]
@@ -1452,7 +2164,9 @@ impl<F: FloatBits> Cpu<F> {
                 });
             }
             #[cfg(feature = "float")]
-            0b10100 if env.read_fs() == ExtensionStatus::Disabled => {
+            0b10100
+                if !zfinx!() && env.read_fs() == ExtensionStatus::Disabled =>
+            {
                 illegal!()
             }
             #[cfg(feature = "float")]
@@ -1473,35 +2187,44 @@ impl<F: FloatBits> Cpu<F> {
                     }),
                     0b00011 => float_op!(T; o = a, b; {
                         // FDIV.{S,D,Q}
-                        if a.is_nan() || b.is_nan() {
-                            o = StatusAnd { value: T::NAN, status: if a.is_signaling() || b.is_signaling() { Status::INVALID_OP } else { Status::OK } };
-                        } else if b.is_zero() {
-                            if a.is_zero() {
-                                o = StatusAnd { value: T::NAN, status: Status::INVALID_OP };
-                            } else {
-                                let out_sign = a.is_negative() != b.is_negative();
-                                if out_sign {
-                                    o = StatusAnd { value: -T::INFINITY, status: Status::DIV_BY_ZERO };
-                                } else {
-                                    o = StatusAnd { value: T::INFINITY, status: Status::DIV_BY_ZERO };
-                                }
-                            }
-                        } else {
-                            o = a.div_r(b, round_mode!());
-                        }
-                        // Bug in APFloat?
-                        if a.is_finite() && b.is_finite() && o.value.is_infinite() && !o.status.contains(Status::DIV_BY_ZERO) {
-                            o.status |= Status::OVERFLOW;
-                        }
+                        o = float::divide(a, b, round_mode!());
                         env.account_float_divide(T::get_word_count());
                     }),
                     0b01011 if rs2!() == 0 => {
                         // FSQRT.{S,D,Q}
-                        let a = self.float_registers[rs1!() as usize];
-                        let o = match fprecision!() {
-                            0b00 => {
+                        match fprecision!() {
+                            0b10 if zfh!() => {
+                                // half: an exactly rounded single square root
+                                // has more than twice the bits of a half, so
+                                // rounding it again to half is still exact
+                                let a = float::to_half(fread!(Half, rs1!()));
+                                let mut lost = false;
+                                let a: Single = float::maybe_unstatus(
+                                    self,
+                                    a.convert_r(round_mode!(), &mut lost),
+                                );
+                                let (result, iterations) =
+                                    ieee_apsqrt::sqrt_accurate(
+                                        a.to_bits() as u32,
+                                        round_mode!(),
+                                    );
+                                env.account_sqrt(1, iterations);
+                                let result = float::to_single(
+                                    float::maybe_unstatus(self, result),
+                                );
+                                let result: Half = float::maybe_unstatus(
+                                    self,
+                                    result.convert_r(round_mode!(), &mut lost),
+                                );
+                                if result.is_nan() {
+                                    fwrite!(Half, float::CANON_NAN_16);
+                                } else {
+                                    fwrite!(Half, result.to_bits() as u16);
+                                }
+                            }
+                            0b00 if has_f!() => {
                                 // single
-                                let a = F::unbox_single(a);
+                                let a = fread!(Single, rs1!());
                                 let (result, iterations) = if env
                                     .use_accurate_single_sqrt()
                                 {
@@ -1513,13 +2236,14 @@ impl<F: FloatBits> Cpu<F> {
                                     ieee_apsqrt::sqrt_fast(a, round_mode!())
                                 };
                                 env.account_sqrt(1, iterations);
-                                F::box_single(float::maybe_unstatus(
-                                    self, result,
-                                ))
+                                fwrite!(
+                                    Single,
+                                    float::maybe_unstatus(self, result)
+                                );
                             }
-                            0b01 => {
+                            0b01 if has_d!() => {
                                 // double
-                                let a = F::unbox_double(a);
+                                let a = fread!(Double, rs1!());
                                 let (result, iterations) = if env
                                     .use_accurate_single_sqrt()
                                 {
@@ -1531,13 +2255,14 @@ impl<F: FloatBits> Cpu<F> {
                                     ieee_apsqrt::sqrt_fast(a, round_mode!())
                                 };
                                 env.account_sqrt(2, iterations);
-                                F::box_double(float::maybe_unstatus(
-                                    self, result,
-                                ))
+                                fwrite!(
+                                    Double,
+                                    float::maybe_unstatus(self, result)
+                                );
                             }
-                            0b11 => {
+                            0b11 if has_q!() => {
                                 // quad
-                                let a = F::unbox_quad(a);
+                                let a = fread!(Quad, rs1!());
                                 let (result, iterations) = if env
                                     .use_accurate_single_sqrt()
                                 {
@@ -1546,13 +2271,13 @@ impl<F: FloatBits> Cpu<F> {
                                     ieee_apsqrt::sqrt_fast(a, round_mode!())
                                 };
                                 env.account_sqrt(4, iterations);
-                                F::box_quad(float::maybe_unstatus(
-                                    self, result,
-                                ))
+                                fwrite!(
+                                    Quad,
+                                    float::maybe_unstatus(self, result)
+                                );
                             }
                             _ => illegal!(),
-                        };
-                        frd!(o);
+                        }
                         env.account_generic_op();
                     }
                     0b00100 => {
@@ -1562,41 +2287,50 @@ impl<F: FloatBits> Cpu<F> {
                             0b010 => |a, b| a != b, // FSGNJX
                             _ => illegal!(),
                         };
-                        let a = self.float_registers[rs1!() as usize];
-                        let b = self.float_registers[rs2!() as usize];
                         match fprecision!() {
-                            0b00 => {
+                            0b10 if zfh!() => {
+                                // half
+                                let a = fread!(Half, rs1!());
+                                let b = fread!(Half, rs2!());
+                                let asign = a >> 15 != 0;
+                                let bsign = b >> 15 != 0;
+                                let osign = op(asign, bsign);
+                                let o = (a & (u16::MAX >> 1))
+                                    | ((osign as u16) << 15);
+                                fwrite!(Half, o);
+                            }
+                            0b00 if has_f!() => {
                                 // single
-                                let a = F::unbox_single(a);
-                                let b = F::unbox_single(b);
+                                let a = fread!(Single, rs1!());
+                                let b = fread!(Single, rs2!());
                                 let asign = a >> 31 != 0;
                                 let bsign = b >> 31 != 0;
                                 let osign = op(asign, bsign);
                                 let o = (a & (u32::MAX >> 1))
                                     | ((osign as u32) << 31);
-                                frd!(F::box_single(o));
+                                fwrite!(Single, o);
                             }
-                            0b01 => {
+                            0b01 if has_d!() => {
                                 // double
-                                let a = F::unbox_double(a);
-                                let b = F::unbox_double(b);
+                                let a = fread!(Double, rs1!());
+                                let b = fread!(Double, rs2!());
                                 let asign = a >> 63 != 0;
                                 let bsign = b >> 63 != 0;
                                 let osign = op(asign, bsign);
                                 let o = (a & (u64::MAX >> 1))
                                     | ((osign as u64) << 63);
-                                frd!(F::box_double(o));
+                                fwrite!(Double, o);
                             }
-                            0b11 => {
+                            0b11 if has_q!() => {
                                 // quad
-                                let a = F::unbox_quad(a);
-                                let b = F::unbox_quad(b);
+                                let a = fread!(Quad, rs1!());
+                                let b = fread!(Quad, rs2!());
                                 let asign = a >> 127 != 0;
                                 let bsign = b >> 127 != 0;
                                 let osign = op(asign, bsign);
                                 let o = (a & (u128::MAX >> 1))
                                     | ((osign as u128) << 127);
-                                frd!(F::box_quad(o));
+                                fwrite!(Quad, o);
                             }
                             _ => illegal!(),
                         }
@@ -1606,10 +2340,17 @@ impl<F: FloatBits> Cpu<F> {
                         // FMIN/FMAX
                         match funct3!() {
                             0b000 => float_op!(T; o = a, b; {
-                                if a.is_nan() {
-                                    o = b;
-                                } else if b.is_nan() {
-                                    o = a;
+                                o = float::min_max(a, b, false);
+                                env.account_float_op(T::get_word_count());
+                            }),
+                            0b001 => float_op!(T; o = a, b; {
+                                o = float::min_max(a, b, true);
+                                env.account_float_op(T::get_word_count());
+                            }),
+                            0b010 if zfa!() => float_op!(T; o = a, b; {
+                                // FMINM (Zfa)
+                                if a.is_nan() || b.is_nan() {
+                                    o = T::NAN;
                                 } else if a.is_zero() && b.is_zero() {
                                     if a.is_negative() { o = a }
                                     else { o = b }
@@ -1621,11 +2362,10 @@ impl<F: FloatBits> Cpu<F> {
                                 }
                                 env.account_float_op(T::get_word_count());
                             }),
-                            0b001 => float_op!(T; o = a, b; {
-                                if a.is_nan() {
-                                    o = b;
-                                } else if b.is_nan() {
-                                    o = a;
+                            0b011 if zfa!() => float_op!(T; o = a, b; {
+                                // FMAXM (Zfa)
+                                if a.is_nan() || b.is_nan() {
+                                    o = T::NAN;
                                 } else if a.is_zero() && b.is_zero() {
                                     if b.is_negative() { o = a }
                                     else { o = b }
@@ -1642,31 +2382,78 @@ impl<F: FloatBits> Cpu<F> {
                     }
                     0b01000 => {
                         match (fprecision!(), rs2!()) {
+                            (_, 0b00100 | 0b00101) if zfa!() => {
+                                // FROUND, FROUNDNX (Zfa)
+                                let inexact = rs2!() == 0b00101;
+                                float_op!(T; o = a; {
+                                    if a.is_nan() {
+                                        o = StatusAnd { value: T::NAN, status: if a.is_signaling() { Status::INVALID_OP } else { Status::OK } };
+                                    } else {
+                                        o = a.round_to_integral(round_mode!());
+                                        if !inexact {
+                                            o.status.remove(Status::INEXACT);
+                                        }
+                                    }
+                                    env.account_float_op(T::get_word_count());
+                                });
+                            }
                             // source size -> destination size
-                            (0b01, 0b00) if F::SUPPORT_D && env.enable_d() => {
+                            (0b01, 0b00) if has_d!() => {
                                 // FCVT.D.S
                                 fcvt!(Single -> Double);
                             }
-                            (0b00, 0b01) if F::SUPPORT_D && env.enable_d() => {
+                            (0b00, 0b01) if has_d!() => {
                                 // FCVT.S.D
                                 fcvt!(Double -> Single);
                             }
-                            (0b11, 0b00) if F::SUPPORT_Q && env.enable_q() => {
+                            (0b11, 0b00) if has_q!() => {
                                 // FCVT.Q.S
                                 fcvt!(Single -> Quad);
                             }
-                            (0b00, 0b11) if F::SUPPORT_Q && env.enable_q() => {
+                            (0b00, 0b11) if has_q!() => {
                                 // FCVT.S.Q
                                 fcvt!(Quad -> Single);
                             }
-                            (0b11, 0b01) if F::SUPPORT_Q && env.enable_q() => {
+                            (0b11, 0b01) if has_q!() => {
                                 // FCVT.Q.D
                                 fcvt!(Double -> Quad);
                             }
-                            (0b01, 0b11) if F::SUPPORT_Q && env.enable_q() => {
+                            (0b01, 0b11) if has_q!() => {
                                 // FCVT.D.Q
                                 fcvt!(Quad -> Double);
                             }
+                            (0b00, 0b10) if zfhmin!() => {
+                                // FCVT.S.H
+                                fcvt!(Half -> Single);
+                            }
+                            (0b10, 0b00) if zfhmin!() => {
+                                // FCVT.H.S
+                                fcvt!(Single -> Half);
+                            }
+                            (0b01, 0b10) if zfhmin!() && has_d!() => {
+                                // FCVT.D.H
+                                fcvt!(Half -> Double);
+                            }
+                            (0b10, 0b01) if zfhmin!() && has_d!() => {
+                                // FCVT.H.D
+                                fcvt!(Double -> Half);
+                            }
+                            (0b11, 0b10) if zfhmin!() && has_q!() => {
+                                // FCVT.Q.H
+                                fcvt!(Half -> Quad);
+                            }
+                            (0b10, 0b11) if zfhmin!() && has_q!() => {
+                                // FCVT.H.Q
+                                fcvt!(Quad -> Half);
+                            }
+                            (0b00, 0b00110) if zfbfmin!() => {
+                                // FCVT.S.BF16 (Zfbfmin)
+                                fcvt!(BFloat -> Single);
+                            }
+                            (0b10, 0b01000) if zfbfmin!() => {
+                                // FCVT.BF16.S (Zfbfmin)
+                                fcvt!(Single -> BFloat);
+                            }
                             _ => illegal!(),
                         }
                     }
@@ -1694,6 +2481,22 @@ impl<F: FloatBits> Cpu<F> {
                                 }
                                 env.account_generic_op();
                             }),
+                            0b100 if zfa!() => float_op!(T; = a, b; {
+                                // FLEQ (Zfa)
+                                self.set_register(rd!(), (a <= b) as u32);
+                                if a.is_signaling() || b.is_signaling() {
+                                    self.accrue_float_exceptions(INVALID_FLAG);
+                                }
+                                env.account_generic_op();
+                            }),
+                            0b101 if zfa!() => float_op!(T; = a, b; {
+                                // FLTQ (Zfa)
+                                self.set_register(rd!(), (a < b) as u32);
+                                if a.is_signaling() || b.is_signaling() {
+                                    self.accrue_float_exceptions(INVALID_FLAG);
+                                }
+                                env.account_generic_op();
+                            }),
                             _ => illegal!(),
                         }
                     }
@@ -1739,6 +2542,21 @@ impl<F: FloatBits> Cpu<F> {
                                     self.set_register(rd!(), dst as u32);
                                 }
                             }
+                            0b01000
+                                if zfa!()
+                                    && has_d!()
+                                    && fprecision!() == 0b01
+                                    && funct3!() == 0b001 =>
+                            {
+                                // FCVTMOD.W.D (Zfa)
+                                let a = fread!(Double, rs1!());
+                                let (result, flags) = float::fcvtmod_w_d(a);
+                                if flags != 0 {
+                                    self.accrue_float_exceptions(flags);
+                                }
+                                self.set_register(rd!(), result);
+                                env.account_fcvt_to_int(2);
+                            }
                             _ => illegal!(),
                         }
                     }
@@ -1764,7 +2582,12 @@ impl<F: FloatBits> Cpu<F> {
                         }
                     }
                     0b11100 => match (rs2!(), funct3!()) {
-                        (0b00000, 0b000) => {
+                        (0b00000, 0b000)
+                            if fprecision!() == 0b00
+                                && !zfinx!()
+                                && F::SUPPORT_F
+                                && env.enable_f() =>
+                        {
                             // FMV.X.W
                             let a = F::unbox_single(
                                 self.float_registers[rs1!() as usize],
@@ -1772,24 +2595,35 @@ impl<F: FloatBits> Cpu<F> {
                             self.set_register(rd!(), a);
                             env.account_generic_op();
                         }
+                        (0b00000, 0b000)
+                            if fprecision!() == 0b10
+                                && (zfhmin!() || zfbfmin!()) =>
+                        {
+                            // FMV.X.H
+                            let a = F::unbox_half(
+                                self.float_registers[rs1!() as usize],
+                            );
+                            self.set_register(rd!(), a as i16 as u32);
+                            env.account_generic_op();
+                        }
+                        (0b00001, 0b000)
+                            if fprecision!() == 0b01
+                                && zfa!()
+                                && !zfinx!()
+                                && F::SUPPORT_D
+                                && env.enable_d() =>
+                        {
+                            // FMVH.X.D (Zfa)
+                            let a = F::unbox_double(
+                                self.float_registers[rs1!() as usize],
+                            );
+                            self.set_register(rd!(), (a >> 32) as u32);
+                            env.account_generic_op();
+                        }
                         (0b00000, 0b001) => {
                             // FCLASS.{S,D,Q}
                             float_op!(T; = a; {
-                                let mut o = 0;
-                                if a.is_finite() {
-                                    match (a.is_negative(), a.is_denormal(), a.is_zero()) {
-                                        (true,false,false) => o |= 1<<1,
-                                        (true,true,false) => o |= 1<<2,
-                                        (true,_,true) => o |= 1<<3,
-                                        (false,_,true) => o |= 1<<4,
-                                        (false,true,false) => o |= 1<<5,
-                                        (false,false,false) => o |= 1<<6,
-                                    }
-                                }
-                                else if a.is_neg_infinity() { o |= 1<<0 }
-                                else if a.is_pos_infinity() { o |= 1<<7 }
-                                else if a.is_signaling() { o |= 1<<8 }
-                                else { o |= 1<<9 }
+                                let o = float::classify(a);
                                 self.set_register(rd!(), o);
                                 env.account_generic_op();
                             });
@@ -1797,17 +2631,77 @@ impl<F: FloatBits> Cpu<F> {
                         _ => illegal!(),
                     },
                     0b11110 => match (rs2!(), funct3!()) {
-                        (0b00000, 0b000) => {
+                        (0b00000, 0b000)
+                            if fprecision!() == 0b00
+                                && !zfinx!()
+                                && F::SUPPORT_F
+                                && env.enable_f() =>
+                        {
                             // FMV.W.X
                             let a = self.get_register(rs1!());
                             frd!(F::box_single(a));
                             env.account_generic_op();
                         }
+                        (0b00000, 0b000)
+                            if fprecision!() == 0b10
+                                && (zfhmin!() || zfbfmin!()) =>
+                        {
+                            // FMV.H.X
+                            let a = self.get_register(rs1!());
+                            frd!(F::box_half(a as u16));
+                            env.account_generic_op();
+                        }
+                        (0b00001, 0b000) if zfa!() && !zfinx!() => {
+                            // FLI (Zfa)
+                            float_op!(T; o =; {
+                                o = float::fli::<T>(rs1!());
+                                env.account_generic_op();
+                            });
+                        }
                         _ => illegal!(),
                     },
+                    0b10110
+                        if zfa!()
+                            && !zfinx!()
+                            && F::SUPPORT_D
+                            && env.enable_d()
+                            && fprecision!() == 0b01
+                            && funct3!() == 0b000 =>
+                    {
+                        // FMVP.D.X (Zfa)
+                        let low = self.get_register(rs1!());
+                        let high = self.get_register(rs2!());
+                        frd!(F::box_double((high as u64) << 32 | low as u64));
+                        env.account_generic_op();
+                    }
                     _ => illegal!(),
                 }
             }
+            0b10101 if self.vector_enabled(env) => {
+                // OP-V
+                self.vector_op(env, instruction)?
+            }
+            #[cfg(feature = "P")]
+            0b11101 if Env::SUPPORT_P && env.enable_p() => {
+                // OP-P
+                let a = self.get_register(rs1!());
+                let b = self.get_register(rs2!());
+                let c = self.get_register(rd!());
+                let mut ov = false;
+                let Some(result) =
+                    packed::op(env, instruction, a, b, c, &mut ov)
+                else {
+                    illegal!()
+                };
+                if ov {
+                    self.write_ov(true);
+                }
+                self.set_register(rd!(), result);
+            }
+            #[cfg(not(feature = "P"))]
+            0b11101 if Env::SUPPORT_P && env.enable_p() => {
+                panic!("Your crate skipped compiling P support but then enabled it!")
+            }
             0b11000 => {
                 // (BRANCH)
                 let a = self.get_register(rs1!());
@@ -1867,6 +2761,19 @@ impl<F: FloatBits> Cpu<F> {
                             // EBREAK
                             env.perform_ebreak(self)?;
                         }
+                        0b000000001101_00000_000_00000_1110011
+                        | 0b000000011101_00000_000_00000_1110011
+                            if Env::SUPPORT_ZAWRS
+                                && env.enable_zawrs()
+                                && Env::SUPPORT_A
+                                && env.enable_a() =>
+                        {
+                            // WRS.NTO, WRS.STO from Zawrs
+                            env.wait_on_reservation(
+                                instruction & 1 << 24 != 0,
+                            )
+                            .map_err(|x| (x, orig_instruction))?;
+                        }
                         _ => {
                             illegal!()
                         }
@@ -1920,6 +2827,19 @@ impl<F: FloatBits> Cpu<F> {
                             illegal!()
                         }
                     };
+                    let (read, write) = if csr!() == 0x015
+                        && Env::SUPPORT_ZKR
+                        && env.enable_zkr()
+                    {
+                        // `seed` may only be accessed with a read-write
+                        // instruction, and every access polls the source
+                        if !write {
+                            illegal!()
+                        }
+                        (true, true)
+                    } else {
+                        (read, write)
+                    };
                     let old_value = if !read {
                         0
                     } else {
@@ -1936,6 +2856,14 @@ impl<F: FloatBits> Cpu<F> {
                     illegal!()
                 }
             }
+            0b00010 | 0b01010 | 0b10110 | 0b11110 => {
+                // custom-0 through custom-3
+                env.perform_custom(
+                    self,
+                    CustomInstruction::decode(instruction),
+                )?;
+                env.account_custom_op();
+            }
             _ => {
                 return Err((
                     ExceptionCause::IllegalInstruction,
@@ -1943,6 +2871,14 @@ impl<F: FloatBits> Cpu<F> {
                 ))
             }
         }
+        self.retire(env, next_pc)
+    }
+    /// Finish executing an instruction by moving on to `next_pc`, if we can.
+    fn retire<Env: ExecutionEnvironment>(
+        &mut self,
+        env: &Env,
+        next_pc: u32,
+    ) -> Result<(), (ExceptionCause, u32)> {
         if (next_pc & 2 == 0) || (Env::SUPPORT_C && env.enable_c()) {
             // the lowest bit is supposed to be ignored, and it's hard to get a
             // 1 in there anyway
@@ -1968,12 +2904,30 @@ impl<F: FloatBits> Cpu<F> {
         &mut self,
         env: &mut Env,
     ) -> Result<(), Exception> {
-        self.internal_step(env)
-            .map_err(|(mcause, mtval)| Exception {
+        let this_pc = self.get_pc();
+        // (an instruction cached while different extensions were enabled is
+        // treated as if it weren't cached at all)
+        let cached = env.cached_instruction(this_pc).filter(|decoded| {
+            decoded.gates == fast::decode_gates::<F, X, Env>(env)
+        });
+        let result = match cached {
+            Some(decoded) if decoded.op != FastOp::Slow => {
+                env.account_ifetch(this_pc);
+                self.fast_step(env, this_pc, &decoded)
+            }
+            cached => self.internal_step(env, cached),
+        };
+        match result {
+            Ok(()) => {
+                env.account_retire();
+                Ok(())
+            }
+            Err((mcause, mtval)) => Err(Exception {
                 mcause,
                 mepc: self.get_pc(),
                 mtval,
-            })
+            }),
+        }
     }
     /// Perform a read of a given CSR, as from a `CSRR` instruction.
     ///
@@ -1985,22 +2939,28 @@ impl<F: FloatBits> Cpu<F> {
         env: &mut Env,
         csr_number: u32,
     ) -> Result<u32, ExceptionCause> {
-        if F::SUPPORT_F && env.enable_f() {
+        if let Some(result) = self.read_vector_csr(env, csr_number) {
+            return result;
+        }
+        // under Zfinx, fcsr is there but mstatus.FS doesn't gate it
+        let zfinx =
+            F::SUPPORT_FCSR && Env::SUPPORT_ZFINX && env.enable_zfinx();
+        if zfinx || (F::SUPPORT_F && env.enable_f()) {
             match csr_number {
                 0x001 => {
-                    if env.read_fs() == ExtensionStatus::Disabled {
+                    if !zfinx && env.read_fs() == ExtensionStatus::Disabled {
                         return Err(ExceptionCause::IllegalInstruction);
                     }
                     return Ok(self.read_fflags());
                 }
                 0x002 => {
-                    if env.read_fs() == ExtensionStatus::Disabled {
+                    if !zfinx && env.read_fs() == ExtensionStatus::Disabled {
                         return Err(ExceptionCause::IllegalInstruction);
                     }
                     return Ok(self.read_frm());
                 }
                 0x003 => {
-                    if env.read_fs() == ExtensionStatus::Disabled {
+                    if !zfinx && env.read_fs() == ExtensionStatus::Disabled {
                         return Err(ExceptionCause::IllegalInstruction);
                     }
                     return Ok(self.read_fcsr());
@@ -2008,6 +2968,17 @@ impl<F: FloatBits> Cpu<F> {
                 _ => (),
             }
         }
+        if csr_number == 0x015 && Env::SUPPORT_ZKR && env.enable_zkr() {
+            return env.poll_entropy().map(|status| status.to_seed());
+        }
+        #[cfg(feature = "P")]
+        if csr_number == 0x009 && Env::SUPPORT_P && env.enable_p() {
+            return Ok(self.read_ov() as u32);
+        }
+        #[cfg(feature = "C")]
+        if csr_number == 0x017 && Env::SUPPORT_ZCMT && env.enable_zcmt() {
+            return Ok(self.jvt);
+        }
         env.read_csr(csr_number)
     }
     /// Perform a write to a given CSR, as from a `CSRW` instruction.
@@ -2021,23 +2992,30 @@ impl<F: FloatBits> Cpu<F> {
         csr_number: u32,
         new_value: u32,
     ) -> Result<(), ExceptionCause> {
-        if F::SUPPORT_F && env.enable_f() {
+        if let Some(result) = self.write_vector_csr(env, csr_number, new_value)
+        {
+            return result;
+        }
+        // under Zfinx, fcsr is there but mstatus.FS doesn't gate it
+        let zfinx =
+            F::SUPPORT_FCSR && Env::SUPPORT_ZFINX && env.enable_zfinx();
+        if zfinx || (F::SUPPORT_F && env.enable_f()) {
             #[allow(clippy::unit_arg)]
             match csr_number {
                 0x001 => {
-                    if env.read_fs() == ExtensionStatus::Disabled {
+                    if !zfinx && env.read_fs() == ExtensionStatus::Disabled {
                         return Err(ExceptionCause::IllegalInstruction);
                     }
                     return Ok(self.write_fflags(new_value));
                 }
                 0x002 => {
-                    if env.read_fs() == ExtensionStatus::Disabled {
+                    if !zfinx && env.read_fs() == ExtensionStatus::Disabled {
                         return Err(ExceptionCause::IllegalInstruction);
                     }
                     return Ok(self.write_frm(new_value));
                 }
                 0x003 => {
-                    if env.read_fs() == ExtensionStatus::Disabled {
+                    if !zfinx && env.read_fs() == ExtensionStatus::Disabled {
                         return Err(ExceptionCause::IllegalInstruction);
                     }
                     return Ok(self.write_fcsr(new_value));
@@ -2045,6 +3023,21 @@ impl<F: FloatBits> Cpu<F> {
                 _ => (),
             }
         }
+        if csr_number == 0x015 && Env::SUPPORT_ZKR && env.enable_zkr() {
+            // writes to `seed` are ignored
+            return Ok(());
+        }
+        #[cfg(feature = "P")]
+        if csr_number == 0x009 && Env::SUPPORT_P && env.enable_p() {
+            self.write_ov(new_value & 1 != 0);
+            return Ok(());
+        }
+        #[cfg(feature = "C")]
+        if csr_number == 0x017 && Env::SUPPORT_ZCMT && env.enable_zcmt() {
+            // only jump table mode zero exists so far
+            self.jvt = new_value & !0b111111;
+            return Ok(());
+        }
         env.write_csr(csr_number, new_value)
     }
     /// Read the `fflags` CSR, which contains all of the accumulated exception
@@ -2083,7 +3076,9 @@ impl<F: FloatBits> Cpu<F> {
     }
 }
 
-impl<F: FloatBits> Default for Cpu<F> {
+impl<F: FloatBits, V: VectorBits, X: IntegerRegisters> Default
+    for Cpu<F, V, X>
+{
     fn default() -> Self {
         Cpu::new()
     }
@@ -2148,16 +3143,30 @@ mod test {
     #[test]
     fn cpu_base_size() {
         let size = std::mem::size_of::<Cpu<()>>();
-        if size != 128 {
-            panic!("Cpu<()> should be 128 bytes, was {size}!")
+        // the P extension's OV flag and Zcmt's jvt have no padding to hide in
+        let expected = 128
+            + if cfg!(feature = "C") { 4 } else { 0 }
+            + if cfg!(feature = "P") { 4 } else { 0 };
+        if size != expected {
+            panic!("Cpu<()> should be {expected} bytes, was {size}!")
+        }
+    }
+    #[test]
+    fn cpu_e_size() {
+        let size = std::mem::size_of::<Cpu<(), (), [u32; 16]>>();
+        let expected = 64
+            + if cfg!(feature = "C") { 4 } else { 0 }
+            + if cfg!(feature = "P") { 4 } else { 0 };
+        if size != expected {
+            panic!("Cpu<(), (), [u32; 16]> should be {expected} bytes, was {size}!")
         }
     }
     #[cfg(feature = "float")]
     #[test]
     fn cpu_f_size() {
         let size = std::mem::size_of::<Cpu<u32>>();
-        if size != 260 {
-            panic!("Cpu<u32> should be 260 bytes, was {size}!")
+        if size != 264 {
+            panic!("Cpu<u32> should be 264 bytes, was {size}!")
         }
     }
     #[cfg(feature = "float")]
@@ -2176,4 +3185,436 @@ mod test {
             panic!("Cpu<u128> should be 644 or 648 or 656 bytes, was {size}!")
         }
     }
+    #[cfg(feature = "float")]
+    #[test]
+    fn cpu_zfinx_size() {
+        let size = std::mem::size_of::<Cpu<Zfinx>>();
+        if size != 136 {
+            panic!("Cpu<Zfinx> should be 136 bytes, was {size}!")
+        }
+    }
+    #[cfg(feature = "float")]
+    #[test]
+    fn cpu_zve32f_size() {
+        let size = std::mem::size_of::<Cpu<u64, [u8; 16]>>();
+        let (a, b) = if cfg!(feature = "P") {
+            (924, 928)
+        } else {
+            (916, 920)
+        };
+        if size != a && size != b {
+            panic!(
+                "Cpu<u64, [u8; 16]> should be {a} or {b} bytes, was {size}!"
+            )
+        }
+    }
+    #[cfg(feature = "float")]
+    #[test]
+    fn zfh() {
+        test_env::check::<u64>(
+            &[
+                0xf40080d3, // fmv.h.x f1, x1
+                0xf4010153, // fmv.h.x f2, x2
+                0x0420f1d3, // fadd.h f3, f1, f2
+                0x1c30f253, // fdiv.h f4, f1, f3
+                0x5c0172d3, // fsqrt.h f5, f2
+                0x40227353, // fcvt.s.h f6, f4
+                0xe40201d3, // fmv.x.h x3, f4
+                0xe0030253, // fmv.x.w x4, f6
+                0xc40192d3, // fcvt.w.h x5, f3, rtz
+                0x20401027, // fsh f4, 0x200(x0)
+                0x20001387, // flh f7, 0x200(x0)
+                0xe4038353, // fmv.x.h x6, f7
+                0xe00203d3, // fmv.x.w x7, f4
+                0xe4028453, // fmv.x.h x8, f5
+            ],
+            &[(1, 0x3C00), (2, 0x4000)],
+            &[
+                0x0000_3555,
+                0x3EAA_A000,
+                3,
+                0x0000_3555,
+                0xFFFF_3555,
+                0x0000_3DA8,
+            ],
+        );
+    }
+    #[cfg(feature = "float")]
+    #[test]
+    fn zfa() {
+        test_env::check::<u64>(
+            &[
+                0xf21a80d3, // fli.d f1, 2.5
+                0xf0108153, // fli.s f2, min
+                0x424081d3, // fround.d f3, f1, rne
+                0x4250b253, // froundnx.d f4, f1, rup
+                0xe21081d3, // fmvh.x.d x3, f1
+                0xe0010253, // fmv.x.w x4, f2
+                0xb22082d3, // fmvp.d.x f5, x1, x2
+                0xa242c2d3, // fleq.d x5, f5, f4
+                0xa242d353, // fltq.d x6, f5, f4
+                0xe21183d3, // fmvh.x.d x7, f3
+                0xb2a48353, // fmvp.d.x f6, x9, x10
+                0xc2831453, // fcvtmod.w.d x8, f6, rtz
+                0xf21f83d3, // fli.d f7, nan
+                0x2a72b453, // fmaxm.d f8, f5, f7
+                0xe21404d3, // fmvh.x.d x9, f8
+                0x00102573, // frflags x10
+            ],
+            // f5 = 3.0, f6 = 2^32 + 5.75
+            &[
+                (1, 0),
+                (2, 0x4008_0000),
+                (9, 0x005C_0000),
+                (10, 0x41F0_0000),
+            ],
+            &[
+                0x4004_0000,
+                0x0080_0000,
+                1,
+                0,
+                0x4000_0000,
+                5,
+                0x7FF8_0000,
+                (INVALID_FLAG | INEXACT_FLAG) as u32,
+            ],
+        );
+    }
+    #[cfg(feature = "float")]
+    #[test]
+    fn zfbfmin() {
+        test_env::check::<u32>(
+            &[
+                0xf00080d3, // fmv.w.x f1, x1
+                0x4480f153, // fcvt.bf16.s f2, f1
+                0xe40101d3, // fmv.x.h x3, f2
+                0xf00101d3, // fmv.w.x f3, x2
+                0x4481f253, // fcvt.bf16.s f4, f3
+                0xe4020253, // fmv.x.h x4, f4
+                0x20201027, // fsh f2, 0x200(x0)
+                0x20001307, // flh f6, 0x200(x0)
+                0x406373d3, // fcvt.s.bf16 f7, f6
+                0xe00382d3, // fmv.x.w x5, f7
+                0xf0038453, // fmv.w.x f8, x7
+                0x448474d3, // fcvt.bf16.s f9, f8
+                0xe4048353, // fmv.x.h x6, f9
+                0x001023f3, // frflags x7
+            ],
+            // 1 + 2^-23, a signaling NaN, and a tie
+            &[(1, 0x3F80_0001), (2, 0x7F80_0001), (7, 0x3F81_8000)],
+            &[
+                0x3F80,
+                0x7FC0,
+                0x3F80_0000,
+                0x3F82,
+                (INVALID_FLAG | INEXACT_FLAG) as u32,
+            ],
+        );
+    }
+    #[test]
+    fn zicond() {
+        let cpu = test_env::run::<()>(
+            &[
+                0x0e20d1b3, // czero.eqz x3, x1, x2
+                0x0e20f233, // czero.nez x4, x1, x2
+                0x0e00d2b3, // czero.eqz x5, x1, x0
+                0x0e00f333, // czero.nez x6, x1, x0
+            ],
+            &[(1, 0x1234_5678), (2, 7)],
+        )
+        .unwrap();
+        assert_eq!(cpu.get_register(3), 0x1234_5678);
+        assert_eq!(cpu.get_register(4), 0);
+        assert_eq!(cpu.get_register(5), 0);
+        assert_eq!(cpu.get_register(6), 0x1234_5678);
+    }
+    #[cfg(feature = "float")]
+    #[test]
+    fn zfinx() {
+        let program = [
+            0x0020f1d3, // fadd.s x3, x1, x2
+            0x1020f253, // fmul.s x4, x1, x2
+            0x42018353, // fcvt.d.s x6, x3
+            0x02637453, // fadd.d x8, x6, x6
+            0xc2041553, // fcvt.w.d x10, x8, rtz
+            0x401475d3, // fcvt.s.d x11, x8
+            0x02607653, // fadd.d x12, x0, x6
+        ];
+        let mut env = test_env::TestEnv::new(&program);
+        env.zfinx = true;
+        let cpu = test_env::run_in::<Zfinx, (), [u32; 32]>(
+            env,
+            &program,
+            &[(1, 0x3FC0_0000), (2, 0x4010_0000)],
+        )
+        .unwrap();
+        test_env::assert_registers(
+            &cpu,
+            &[
+                0x4070_0000,
+                0x4058_0000,
+                0,
+                0,
+                0x400E_0000,
+                0,
+                0x401E_0000,
+                7,
+                0x40F0_0000,
+                0,
+                0x400E_0000,
+            ],
+        );
+        // no float moves, and no odd register pairs
+        for instruction in [
+            0xe00082d3, // fmv.x.w x5, x1
+            0x026372d3, // fadd.d x5, x6, x6
+        ] {
+            let mut env = test_env::TestEnv::new(&[instruction]);
+            env.zfinx = true;
+            let Err(e) = test_env::run_in::<Zfinx, (), [u32; 32]>(
+                env,
+                &[instruction],
+                &[],
+            ) else {
+                panic!("{instruction:08x} should be illegal");
+            };
+            assert_eq!(e.mcause, ExceptionCause::IllegalInstruction);
+        }
+    }
+    #[test]
+    fn rv32e() {
+        let program = [
+            0x00508193, // addi x3, x1, 5
+            0x00118213, // addi x4, x3, 1
+        ];
+        let cpu = test_env::run_in::<(), (), [u32; 16]>(
+            test_env::TestEnv::new(&program),
+            &program,
+            &[(1, 10)],
+        )
+        .unwrap();
+        assert_eq!(cpu.get_register(3), 15);
+        assert_eq!(cpu.get_register(4), 16);
+        // x16 and up don't exist
+        for instruction in [
+            0x00108813, // addi x16, x1, 1
+            0x011081b3, // add x3, x1, x17
+            0x01f02023, // sw x31, 0(x0)
+        ] {
+            let env = test_env::TestEnv::new(&[instruction]);
+            let Err(e) = test_env::run_in::<(), (), [u32; 16]>(
+                env,
+                &[instruction],
+                &[],
+            ) else {
+                panic!("{instruction:08x} should be illegal");
+            };
+            assert_eq!(e.mcause, ExceptionCause::IllegalInstruction);
+        }
+    }
+    #[test]
+    fn rv32e_compressed() {
+        // these are executed in place, not expanded first
+        let program = [
+            0x8192_479d, // c.li x15, 7; c.mv x3, x4
+            0x018a_9192, // c.add x3, x4; c.slli x3, 2
+        ];
+        let cpu = test_env::run_in::<(), (), [u32; 16]>(
+            test_env::TestEnv::new(&program),
+            &program,
+            &[(4, 5)],
+        )
+        .unwrap();
+        assert_eq!(cpu.get_register(15), 7);
+        assert_eq!(cpu.get_register(3), 40);
+        for instruction in [
+            0x8846, // c.mv x16, x17
+            0x81c2, // c.mv x3, x16
+            0x980e, // c.add x16, x3
+            0x91c6, // c.add x3, x17
+            0x0806, // c.slli x16, 1
+            0x0841, // c.addi x16, 16
+            0x4805, // c.li x16, 1
+            0x6805, // c.lui x16, 1
+        ] {
+            let env = test_env::TestEnv::new(&[instruction]);
+            let Err(e) = test_env::run_in::<(), (), [u32; 16]>(
+                env,
+                &[instruction],
+                &[],
+            ) else {
+                panic!("{instruction:04x} should be illegal");
+            };
+            assert_eq!(e.mcause, ExceptionCause::IllegalInstruction);
+            assert_eq!(e.mtval, instruction);
+        }
+    }
+    #[cfg(feature = "C")]
+    #[test]
+    fn zcb() {
+        let program = [
+            0x8c24_8844, // c.sb x9, 1(x8); c.sh x9, 2(x8)
+            0x842c_8048, // c.lbu x10, 1(x8); c.lhu x11, 2(x8)
+            0x9ee1_8470, // c.lh x12, 2(x8); c.zext.b x13
+            0x9fe9_9f65, // c.sext.b x14; c.zext.h x15
+            0x9d75_9ced, // c.sext.h x9; c.not x10
+            0x0001_9dd1, // c.mul x11, x12; c.nop
+        ];
+        let cpu = test_env::run::<()>(
+            &program,
+            &[
+                (8, 0x80),
+                (9, 0x0001_8085),
+                (13, 0x1234_5678),
+                (14, 0x80),
+                (15, 0xFFFF_1234),
+            ],
+        )
+        .unwrap();
+        assert_eq!(cpu.get_register(9), 0xFFFF_8085);
+        assert_eq!(cpu.get_register(10), !0x85);
+        assert_eq!(cpu.get_register(11), 0x8085u32.wrapping_mul(0xFFFF_8085));
+        assert_eq!(cpu.get_register(12), 0xFFFF_8085);
+        assert_eq!(cpu.get_register(13), 0x78);
+        assert_eq!(cpu.get_register(14), 0xFFFF_FF80);
+        assert_eq!(cpu.get_register(15), 0x1234);
+        // C.ZEXT.W only exists on RV64
+        let Err(e) = test_env::run::<()>(&[0x0001_9ef1], &[]) else {
+            panic!("c.zext.w should be illegal");
+        };
+        assert_eq!(e.mcause, ExceptionCause::IllegalInstruction);
+    }
+    #[cfg(feature = "C")]
+    #[test]
+    fn zcmp_zcmt() {
+        let program = [
+            0xac26_b876, // cm.push {ra, s0-s2}, -32; cm.mvsa01 s0, s1
+            0xbe76_ad62, // cm.mva01s s2, s0; cm.popret {ra, s0-s2}, 32
+        ];
+        let cpu = test_env::run::<()>(
+            &program,
+            &[(1, 0x100), (2, 0x200), (8, 8), (9, 9), (10, 10), (11, 11)],
+        )
+        .unwrap();
+        assert_eq!(cpu.get_pc(), 0x100);
+        assert_eq!(cpu.get_register(2), 0x200);
+        assert_eq!(cpu.get_register(8), 8);
+        assert_eq!(cpu.get_register(9), 9);
+        assert_eq!(cpu.get_register(18), 0);
+        assert_eq!(cpu.get_register(10), 0);
+        assert_eq!(cpu.get_register(11), 10);
+        // the jump table lives at 0xC0, and entry 32 points at 0x300
+        let program = [
+            0x01729073,  // csrw jvt, x5
+            0x0001_a082, // cm.jalt 32; c.nop
+        ];
+        let mut env = test_env::TestEnv::new(&program);
+        env.ram[(0xC0 + 32 * 4) / 4] = 0x301;
+        let cpu =
+            test_env::run_in::<(), (), [u32; 32]>(env, &program, &[(5, 0xC0)])
+                .unwrap();
+        assert_eq!(cpu.get_pc(), 0x300);
+        assert_eq!(cpu.get_register(1), 6);
+        for instruction in [
+            0x0001_ac22, // cm.mvsa01 s0, s0
+            0x0001_b832, // cm.push with an rlist of 3
+            0x0001_a402, // reserved
+        ] {
+            let Err(e) = test_env::run::<()>(&[instruction], &[]) else {
+                panic!("{instruction:08x} should be illegal");
+            };
+            assert_eq!(e.mcause, ExceptionCause::IllegalInstruction);
+        }
+    }
+    #[test]
+    fn zicbo() {
+        let program = [
+            0x0040a00f, // cbo.zero (x1)
+            0x0020a00f, // cbo.flush (x1)
+            0x0430e013, // prefetch.w 64(x1)
+            0x0bc02103, // lw x2, 0xbc(x0)
+            0x0c002183, // lw x3, 0xc0(x0)
+        ];
+        let mut env = test_env::TestEnv::new(&program);
+        env.ram[32..64].fill(!0);
+        // x1 is in the middle of the 64-byte block at 0x80
+        let cpu =
+            test_env::run_in::<(), (), [u32; 32]>(env, &program, &[(1, 0x84)])
+                .unwrap();
+        assert_eq!(cpu.get_register(2), 0);
+        assert_eq!(cpu.get_register(3), !0);
+        // rd must be x0
+        let Err(e) = test_env::run::<()>(&[0x0010a28f], &[]) else {
+            panic!("cbo.clean with an rd should be illegal");
+        };
+        assert_eq!(e.mcause, ExceptionCause::IllegalInstruction);
+    }
+    #[test]
+    fn zacas_zabha() {
+        let program = [
+            0x086502af, // amoswap.b x5, x6, (x10)
+            0x808593af, // amomin.h x7, x8, (x11)
+            0x28d5262f, // amocas.w x12, x13, (x10)
+            0x28f5072f, // amocas.b x14, x15, (x10)
+            0x2924b82f, // amocas.d x16, x18, (x9)
+            0x2804ba2f, // amocas.d x20, x0, (x9)
+            0x00052b03, // lw x22, 0(x10)
+            0x08c02b83, // lw x23, 0x8c(x0)
+        ];
+        let mut env = test_env::TestEnv::new(&program);
+        env.ram[32] = 0x1234_80FF;
+        env.ram[34] = 1;
+        env.ram[35] = 2;
+        let cpu = test_env::run_in::<(), (), [u32; 32]>(
+            env,
+            &program,
+            &[
+                (6, 0x11),
+                (8, 0xFFFF_8000),
+                (9, 0x88),
+                (10, 0x80),
+                (11, 0x82),
+                (12, 0x8000_8011),
+                (13, 0xCAFE),
+                (16, 1),
+                (17, 2),
+                (18, 3),
+                (19, 4),
+                (20, 3),
+                (21, 4),
+            ],
+        )
+        .unwrap();
+        // byte and halfword results are sign extended
+        assert_eq!(cpu.get_register(5), 0xFFFF_FFFF);
+        assert_eq!(cpu.get_register(7), 0x1234);
+        assert_eq!(cpu.get_register(12), 0x8000_8011);
+        // a failed comparison leaves memory alone
+        assert_eq!(cpu.get_register(14), 0xFFFF_FFFE);
+        assert_eq!(cpu.get_register(22), 0xCAFE);
+        assert_eq!((cpu.get_register(16), cpu.get_register(17)), (1, 2));
+        assert_eq!((cpu.get_register(20), cpu.get_register(21)), (3, 4));
+        assert_eq!(cpu.get_register(23), 0);
+        // register pairs must start at an even register
+        let Err(e) = test_env::run::<()>(&[0x2924b8af], &[]) else {
+            panic!("amocas.d x17, x18, (x9) should be illegal");
+        };
+        assert_eq!(e.mcause, ExceptionCause::IllegalInstruction);
+    }
+    #[test]
+    fn custom() {
+        let program = [
+            0x0073028b, // (custom-0 multiply-accumulate) x5, x6, x7
+            0x00128413, // addi x8, x5, 1
+        ];
+        let cpu =
+            test_env::run::<()>(&program, &[(5, 1), (6, 3), (7, 4)]).unwrap();
+        assert_eq!(cpu.get_register(5), 13);
+        assert_eq!(cpu.get_register(8), 14);
+        // the test environment doesn't do anything in custom-1
+        let Err(e) = test_env::run::<()>(&[0x0073022b], &[]) else {
+            panic!("custom-1 should be illegal");
+        };
+        assert_eq!(e.mcause, ExceptionCause::IllegalInstruction);
+    }
 }
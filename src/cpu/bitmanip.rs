@@ -0,0 +1,293 @@
+//! The bit manipulation extensions: Zba, Zbb, Zbc, and Zbs, plus the
+//! scalar crypto bit manipulation extensions: Zbkb, Zbkc, and Zbkx.
+
+use super::*;
+
+/// Carry-less multiply, all 64 bits of it.
+fn clmul(a: u32, b: u32) -> u64 {
+    let mut ret = 0;
+    for bit in 0..32 {
+        if b & (1 << bit) != 0 {
+            ret ^= (a as u64) << bit;
+        }
+    }
+    ret
+}
+
+/// Bit `2n` of the result is bit `n` of `a`, and bit `2n+1` is bit `n+16`.
+fn zip(a: u32) -> u32 {
+    let mut ret = 0;
+    for bit in 0..16 {
+        ret |= ((a >> bit) & 1) << (bit * 2);
+        ret |= ((a >> (bit + 16)) & 1) << (bit * 2 + 1);
+    }
+    ret
+}
+
+/// The inverse of `zip`.
+fn unzip(a: u32) -> u32 {
+    let mut ret = 0;
+    for bit in 0..16 {
+        ret |= ((a >> (bit * 2)) & 1) << bit;
+        ret |= ((a >> (bit * 2 + 1)) & 1) << (bit + 16);
+    }
+    ret
+}
+
+/// Replace each `width`-bit lane of `b` with the lane of `a` it indexes, or
+/// zero if the index is out of range.
+fn xperm(a: u32, b: u32, width: u32) -> u32 {
+    let mask = (1 << width) - 1;
+    let mut ret = 0;
+    for lane in (0..32).step_by(width as usize) {
+        let index = (b >> lane) & mask;
+        if index < 32 / width {
+            ret |= ((a >> (index * width)) & mask) << lane;
+        }
+    }
+    ret
+}
+
+/// Try to execute an OP instruction that isn't in the base ISA or M.
+/// Returns `None` if it's not a bit manipulation instruction, or not one
+/// that's currently enabled.
+pub(super) fn op<Env: ExecutionEnvironment>(
+    env: &mut Env,
+    instruction: u32,
+    a: u32,
+    b: u32,
+) -> Option<u32> {
+    let zba = Env::SUPPORT_ZBA && env.enable_zba();
+    let zbb = Env::SUPPORT_ZBB && env.enable_zbb();
+    let zbc = Env::SUPPORT_ZBC && env.enable_zbc();
+    let zbs = Env::SUPPORT_ZBS && env.enable_zbs();
+    let zbkb = Env::SUPPORT_ZBKB && env.enable_zbkb();
+    let zbkc = Env::SUPPORT_ZBKC && env.enable_zbkc();
+    let zbkx = Env::SUPPORT_ZBKX && env.enable_zbkx();
+    let funct3 = (instruction >> 12) & 0b111;
+    let funct7 = instruction >> 25;
+    let rs2 = (instruction >> 20) & 0b11111;
+    let result = match (funct7, funct3) {
+        (0b0000101, 0b001) if zbc || zbkc => {
+            // CLMUL
+            env.account_clmul_op();
+            return Some(clmul(a, b) as u32);
+        }
+        (0b0000101, 0b010) if zbc => {
+            // CLMULR
+            env.account_clmul_op();
+            return Some((clmul(a, b) >> 31) as u32);
+        }
+        (0b0000101, 0b011) if zbc || zbkc => {
+            // CLMULH
+            env.account_clmul_op();
+            return Some((clmul(a, b) >> 32) as u32);
+        }
+        (0b0010100, 0b010) if zbkx => {
+            // XPERM4
+            env.account_xperm_op();
+            return Some(xperm(a, b, 4));
+        }
+        (0b0010100, 0b100) if zbkx => {
+            // XPERM8
+            env.account_xperm_op();
+            return Some(xperm(a, b, 8));
+        }
+        (0b0010000, 0b010) if zba => (a << 1).wrapping_add(b), // SH1ADD
+        (0b0010000, 0b100) if zba => (a << 2).wrapping_add(b), // SH2ADD
+        (0b0010000, 0b110) if zba => (a << 3).wrapping_add(b), // SH3ADD
+        (0b0100000, 0b111) if zbb || zbkb => a & !b,           // ANDN
+        (0b0100000, 0b110) if zbb || zbkb => a | !b,           // ORN
+        (0b0100000, 0b100) if zbb || zbkb => !(a ^ b),         // XNOR
+        (0b0000101, 0b100) if zbb => (a as i32).min(b as i32) as u32, // MIN
+        (0b0000101, 0b101) if zbb => a.min(b),                 // MINU
+        (0b0000101, 0b110) if zbb => (a as i32).max(b as i32) as u32, // MAX
+        (0b0000101, 0b111) if zbb => a.max(b),                 // MAXU
+        (0b0110000, 0b001) if zbb || zbkb => a.rotate_left(b & 31), // ROL
+        (0b0110000, 0b101) if zbb || zbkb => a.rotate_right(b & 31), // ROR
+        (0b0000100, 0b100) if zbkb => (a & 0xFFFF) | (b << 16), // PACK
+        (0b0000100, 0b100) if zbb && rs2 == 0 => a & 0xFFFF,   // ZEXT.H
+        (0b0000100, 0b111) if zbkb => (a & 0xFF) | ((b & 0xFF) << 8), // PACKH
+        (0b0100100, 0b001) if zbs => a & !(1 << (b & 31)),     // BCLR
+        (0b0100100, 0b101) if zbs => (a >> (b & 31)) & 1,      // BEXT
+        (0b0110100, 0b001) if zbs => a ^ (1 << (b & 31)),      // BINV
+        (0b0010100, 0b001) if zbs => a | (1 << (b & 31)),      // BSET
+        _ => return None,
+    };
+    env.account_alu_op();
+    Some(result)
+}
+
+/// Try to execute an OP-IMM instruction whose upper immediate bits make it
+/// something other than a base ISA shift. Returns `None` if it's not a bit
+/// manipulation instruction, or not one that's currently enabled.
+pub(super) fn op_imm<Env: ExecutionEnvironment>(
+    env: &mut Env,
+    instruction: u32,
+    a: u32,
+) -> Option<u32> {
+    let zbb = Env::SUPPORT_ZBB && env.enable_zbb();
+    let zbs = Env::SUPPORT_ZBS && env.enable_zbs();
+    let zbkb = Env::SUPPORT_ZBKB && env.enable_zbkb();
+    let funct3 = (instruction >> 12) & 0b111;
+    let funct7 = instruction >> 25;
+    // shamt, or the sub-opcode for the unary instructions
+    let shamt = (instruction >> 20) & 0b11111;
+    let result = match (funct7, funct3, shamt) {
+        (0b0110000, 0b001, 0b00000) if zbb => a.leading_zeros(), // CLZ
+        (0b0110000, 0b001, 0b00001) if zbb => a.trailing_zeros(), // CTZ
+        (0b0110000, 0b001, 0b00010) if zbb => a.count_ones(),    // CPOP
+        (0b0110000, 0b001, 0b00100) if zbb => a as i8 as u32,    // SEXT.B
+        (0b0110000, 0b001, 0b00101) if zbb => a as i16 as u32,   // SEXT.H
+        (0b0110000, 0b101, _) if zbb || zbkb => a.rotate_right(shamt), // RORI
+        (0b0010100, 0b101, 0b00111) if zbb => {
+            // ORC.B
+            u32::from_le_bytes(a.to_le_bytes().map(|x| {
+                if x == 0 {
+                    0
+                } else {
+                    0xFF
+                }
+            }))
+        }
+        (0b0110100, 0b101, 0b11000) if zbb || zbkb => a.swap_bytes(), // REV8
+        (0b0110100, 0b101, 0b00111) if zbkb => {
+            // BREV8
+            u32::from_le_bytes(a.to_le_bytes().map(u8::reverse_bits))
+        }
+        (0b0000100, 0b001, 0b01111) if zbkb => zip(a), // ZIP
+        (0b0000100, 0b101, 0b01111) if zbkb => unzip(a), // UNZIP
+        (0b0100100, 0b001, _) if zbs => a & !(1 << shamt), // BCLRI
+        (0b0100100, 0b101, _) if zbs => (a >> shamt) & 1, // BEXTI
+        (0b0110100, 0b001, _) if zbs => a ^ (1 << shamt), // BINVI
+        (0b0010100, 0b001, _) if zbs => a | (1 << shamt), // BSETI
+        _ => return None,
+    };
+    env.account_alu_op();
+    Some(result)
+}
+
+#[cfg(test)]
+mod test {
+    use super::test_env::check;
+    #[test]
+    fn bitmanip() {
+        check::<()>(
+            &[
+                0x2020a1b3, // sh1add x3, x1, x2
+                0x4020f233, // andn x4, x1, x2
+                0x0a20c2b3, // min x5, x1, x2
+                0x60011313, // clz x6, x2
+                0x60209393, // cpop x7, x1
+                0x6040d413, // rori x8, x1, 4
+                0x2870d493, // orc.b x9, x1
+                0x6980d513, // rev8 x10, x1
+                0x4820d593, // bexti x11, x1, 2
+                0x68209633, // binv x12, x1, x2
+                0x0800c6b3, // zext.h x13, x1
+            ],
+            &[(1, 0x8000_0104), (2, 0x10)],
+            &[
+                0x0000_0218,
+                0x8000_0104,
+                0x8000_0104,
+                27,
+                3,
+                0x4800_0010,
+                0xFF00_FFFF,
+                0x0401_0080,
+                1,
+                0x8001_0104,
+                0x0000_0104,
+            ],
+        );
+    }
+    #[test]
+    fn carryless_and_crypto() {
+        check::<()>(
+            &[
+                0x0a2091b3, // clmul x3, x1, x2
+                0x0a20b233, // clmulh x4, x1, x2
+                0x0a20a2b3, // clmulr x5, x1, x2
+                0x0820c333, // pack x6, x1, x2
+                0x0820f3b3, // packh x7, x1, x2
+                0x6870d413, // brev8 x8, x1
+                0x08f09493, // zip x9, x1
+                0x08f4d513, // unzip x10, x9
+                0x2820c5b3, // xperm8 x11, x1, x2
+                0x2820a633, // xperm4 x12, x1, x2
+            ],
+            &[(1, 0x1234_5678), (2, 0x0001_0203)],
+            &[
+                0x0888_0A88,
+                0x0000_1210,
+                0x0000_2420,
+                0x0203_5678,
+                0x0000_0378,
+                0x482C_6A1E,
+                0x131C_1F60,
+                0x1234_5678,
+                0x7856_3412,
+                0x8887_8685,
+            ],
+        );
+    }
+    #[test]
+    fn bitmanip_edges() {
+        check::<()>(
+            &[
+                0x60001193, // clz x3, x0
+                0x60101213, // ctz x4, x0
+                0x60211293, // cpop x5, x2
+                0x61f0d313, // rori x6, x1, 31
+                0x614093b3, // rol x7, x1, x20
+                0x616ad433, // ror x8, x21, x22
+                0x297014b3, // bset x9, x0, x23
+                0x49f0d513, // bexti x10, x1, 31
+                0x0a20c5b3, // min x11, x1, x2
+                0x0a20f633, // maxu x12, x1, x2
+                0x604c1693, // sext.b x13, x24
+                0x605c9713, // sext.h x14, x25
+                0x201167b3, // sh3add x15, x2, x1
+                0x0a213833, // clmulh x16, x2, x2
+                0x29aac8b3, // xperm8 x17, x21, x26
+                0x29baa933, // xperm4 x18, x21, x27
+                0x287e5993, // orc.b x19, x28
+            ],
+            // shift amounts only use their low five bits, and out of range
+            // xperm indices give zero
+            &[
+                (1, 0x8000_0000),
+                (2, !0),
+                (20, 33),
+                (21, 0x1234_5678),
+                (22, 32),
+                (23, 63),
+                (24, 0x80),
+                (25, 0x8000),
+                (26, 0x0403_02FF),
+                (27, 0x0000_0F08),
+                (28, 0x0001_0100),
+            ],
+            &[
+                32,
+                32,
+                32,
+                0x0000_0001,
+                0x0000_0001,
+                0x1234_5678,
+                0x8000_0000,
+                1,
+                0x8000_0000,
+                0xFFFF_FFFF,
+                0xFFFF_FF80,
+                0xFFFF_8000,
+                0x7FFF_FFF8,
+                0x5555_5555,
+                0x0012_3400,
+                0x8888_8080,
+                0x00FF_FF00,
+            ],
+        );
+    }
+}
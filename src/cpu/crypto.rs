@@ -0,0 +1,329 @@
+//! The scalar cryptography extensions: Zknd and Zkne (AES), Zknh (SHA-2),
+//! Zksed (SM4), and Zksh (SM3). RV32 encodings only, of course.
+
+use super::*;
+
+/// The AES S-box.
+const AES_SBOX: [u8; 256] = [
+    0x63, 0x7C, 0x77, 0x7B, 0xF2, 0x6B, 0x6F, 0xC5, 0x30, 0x01, 0x67, 0x2B,
+    0xFE, 0xD7, 0xAB, 0x76, 0xCA, 0x82, 0xC9, 0x7D, 0xFA, 0x59, 0x47, 0xF0,
+    0xAD, 0xD4, 0xA2, 0xAF, 0x9C, 0xA4, 0x72, 0xC0, 0xB7, 0xFD, 0x93, 0x26,
+    0x36, 0x3F, 0xF7, 0xCC, 0x34, 0xA5, 0xE5, 0xF1, 0x71, 0xD8, 0x31, 0x15,
+    0x04, 0xC7, 0x23, 0xC3, 0x18, 0x96, 0x05, 0x9A, 0x07, 0x12, 0x80, 0xE2,
+    0xEB, 0x27, 0xB2, 0x75, 0x09, 0x83, 0x2C, 0x1A, 0x1B, 0x6E, 0x5A, 0xA0,
+    0x52, 0x3B, 0xD6, 0xB3, 0x29, 0xE3, 0x2F, 0x84, 0x53, 0xD1, 0x00, 0xED,
+    0x20, 0xFC, 0xB1, 0x5B, 0x6A, 0xCB, 0xBE, 0x39, 0x4A, 0x4C, 0x58, 0xCF,
+    0xD0, 0xEF, 0xAA, 0xFB, 0x43, 0x4D, 0x33, 0x85, 0x45, 0xF9, 0x02, 0x7F,
+    0x50, 0x3C, 0x9F, 0xA8, 0x51, 0xA3, 0x40, 0x8F, 0x92, 0x9D, 0x38, 0xF5,
+    0xBC, 0xB6, 0xDA, 0x21, 0x10, 0xFF, 0xF3, 0xD2, 0xCD, 0x0C, 0x13, 0xEC,
+    0x5F, 0x97, 0x44, 0x17, 0xC4, 0xA7, 0x7E, 0x3D, 0x64, 0x5D, 0x19, 0x73,
+    0x60, 0x81, 0x4F, 0xDC, 0x22, 0x2A, 0x90, 0x88, 0x46, 0xEE, 0xB8, 0x14,
+    0xDE, 0x5E, 0x0B, 0xDB, 0xE0, 0x32, 0x3A, 0x0A, 0x49, 0x06, 0x24, 0x5C,
+    0xC2, 0xD3, 0xAC, 0x62, 0x91, 0x95, 0xE4, 0x79, 0xE7, 0xC8, 0x37, 0x6D,
+    0x8D, 0xD5, 0x4E, 0xA9, 0x6C, 0x56, 0xF4, 0xEA, 0x65, 0x7A, 0xAE, 0x08,
+    0xBA, 0x78, 0x25, 0x2E, 0x1C, 0xA6, 0xB4, 0xC6, 0xE8, 0xDD, 0x74, 0x1F,
+    0x4B, 0xBD, 0x8B, 0x8A, 0x70, 0x3E, 0xB5, 0x66, 0x48, 0x03, 0xF6, 0x0E,
+    0x61, 0x35, 0x57, 0xB9, 0x86, 0xC1, 0x1D, 0x9E, 0xE1, 0xF8, 0x98, 0x11,
+    0x69, 0xD9, 0x8E, 0x94, 0x9B, 0x1E, 0x87, 0xE9, 0xCE, 0x55, 0x28, 0xDF,
+    0x8C, 0xA1, 0x89, 0x0D, 0xBF, 0xE6, 0x42, 0x68, 0x41, 0x99, 0x2D, 0x0F,
+    0xB0, 0x54, 0xBB, 0x16,
+];
+
+/// The inverse of the AES S-box.
+const AES_INVERSE_SBOX: [u8; 256] = [
+    0x52, 0x09, 0x6A, 0xD5, 0x30, 0x36, 0xA5, 0x38, 0xBF, 0x40, 0xA3, 0x9E,
+    0x81, 0xF3, 0xD7, 0xFB, 0x7C, 0xE3, 0x39, 0x82, 0x9B, 0x2F, 0xFF, 0x87,
+    0x34, 0x8E, 0x43, 0x44, 0xC4, 0xDE, 0xE9, 0xCB, 0x54, 0x7B, 0x94, 0x32,
+    0xA6, 0xC2, 0x23, 0x3D, 0xEE, 0x4C, 0x95, 0x0B, 0x42, 0xFA, 0xC3, 0x4E,
+    0x08, 0x2E, 0xA1, 0x66, 0x28, 0xD9, 0x24, 0xB2, 0x76, 0x5B, 0xA2, 0x49,
+    0x6D, 0x8B, 0xD1, 0x25, 0x72, 0xF8, 0xF6, 0x64, 0x86, 0x68, 0x98, 0x16,
+    0xD4, 0xA4, 0x5C, 0xCC, 0x5D, 0x65, 0xB6, 0x92, 0x6C, 0x70, 0x48, 0x50,
+    0xFD, 0xED, 0xB9, 0xDA, 0x5E, 0x15, 0x46, 0x57, 0xA7, 0x8D, 0x9D, 0x84,
+    0x90, 0xD8, 0xAB, 0x00, 0x8C, 0xBC, 0xD3, 0x0A, 0xF7, 0xE4, 0x58, 0x05,
+    0xB8, 0xB3, 0x45, 0x06, 0xD0, 0x2C, 0x1E, 0x8F, 0xCA, 0x3F, 0x0F, 0x02,
+    0xC1, 0xAF, 0xBD, 0x03, 0x01, 0x13, 0x8A, 0x6B, 0x3A, 0x91, 0x11, 0x41,
+    0x4F, 0x67, 0xDC, 0xEA, 0x97, 0xF2, 0xCF, 0xCE, 0xF0, 0xB4, 0xE6, 0x73,
+    0x96, 0xAC, 0x74, 0x22, 0xE7, 0xAD, 0x35, 0x85, 0xE2, 0xF9, 0x37, 0xE8,
+    0x1C, 0x75, 0xDF, 0x6E, 0x47, 0xF1, 0x1A, 0x71, 0x1D, 0x29, 0xC5, 0x89,
+    0x6F, 0xB7, 0x62, 0x0E, 0xAA, 0x18, 0xBE, 0x1B, 0xFC, 0x56, 0x3E, 0x4B,
+    0xC6, 0xD2, 0x79, 0x20, 0x9A, 0xDB, 0xC0, 0xFE, 0x78, 0xCD, 0x5A, 0xF4,
+    0x1F, 0xDD, 0xA8, 0x33, 0x88, 0x07, 0xC7, 0x31, 0xB1, 0x12, 0x10, 0x59,
+    0x27, 0x80, 0xEC, 0x5F, 0x60, 0x51, 0x7F, 0xA9, 0x19, 0xB5, 0x4A, 0x0D,
+    0x2D, 0xE5, 0x7A, 0x9F, 0x93, 0xC9, 0x9C, 0xEF, 0xA0, 0xE0, 0x3B, 0x4D,
+    0xAE, 0x2A, 0xF5, 0xB0, 0xC8, 0xEB, 0xBB, 0x3C, 0x83, 0x53, 0x99, 0x61,
+    0x17, 0x2B, 0x04, 0x7E, 0xBA, 0x77, 0xD6, 0x26, 0xE1, 0x69, 0x14, 0x63,
+    0x55, 0x21, 0x0C, 0x7D,
+];
+
+/// The SM4 S-box.
+const SM4_SBOX: [u8; 256] = [
+    0xD6, 0x90, 0xE9, 0xFE, 0xCC, 0xE1, 0x3D, 0xB7, 0x16, 0xB6, 0x14, 0xC2,
+    0x28, 0xFB, 0x2C, 0x05, 0x2B, 0x67, 0x9A, 0x76, 0x2A, 0xBE, 0x04, 0xC3,
+    0xAA, 0x44, 0x13, 0x26, 0x49, 0x86, 0x06, 0x99, 0x9C, 0x42, 0x50, 0xF4,
+    0x91, 0xEF, 0x98, 0x7A, 0x33, 0x54, 0x0B, 0x43, 0xED, 0xCF, 0xAC, 0x62,
+    0xE4, 0xB3, 0x1C, 0xA9, 0xC9, 0x08, 0xE8, 0x95, 0x80, 0xDF, 0x94, 0xFA,
+    0x75, 0x8F, 0x3F, 0xA6, 0x47, 0x07, 0xA7, 0xFC, 0xF3, 0x73, 0x17, 0xBA,
+    0x83, 0x59, 0x3C, 0x19, 0xE6, 0x85, 0x4F, 0xA8, 0x68, 0x6B, 0x81, 0xB2,
+    0x71, 0x64, 0xDA, 0x8B, 0xF8, 0xEB, 0x0F, 0x4B, 0x70, 0x56, 0x9D, 0x35,
+    0x1E, 0x24, 0x0E, 0x5E, 0x63, 0x58, 0xD1, 0xA2, 0x25, 0x22, 0x7C, 0x3B,
+    0x01, 0x21, 0x78, 0x87, 0xD4, 0x00, 0x46, 0x57, 0x9F, 0xD3, 0x27, 0x52,
+    0x4C, 0x36, 0x02, 0xE7, 0xA0, 0xC4, 0xC8, 0x9E, 0xEA, 0xBF, 0x8A, 0xD2,
+    0x40, 0xC7, 0x38, 0xB5, 0xA3, 0xF7, 0xF2, 0xCE, 0xF9, 0x61, 0x15, 0xA1,
+    0xE0, 0xAE, 0x5D, 0xA4, 0x9B, 0x34, 0x1A, 0x55, 0xAD, 0x93, 0x32, 0x30,
+    0xF5, 0x8C, 0xB1, 0xE3, 0x1D, 0xF6, 0xE2, 0x2E, 0x82, 0x66, 0xCA, 0x60,
+    0xC0, 0x29, 0x23, 0xAB, 0x0D, 0x53, 0x4E, 0x6F, 0xD5, 0xDB, 0x37, 0x45,
+    0xDE, 0xFD, 0x8E, 0x2F, 0x03, 0xFF, 0x6A, 0x72, 0x6D, 0x6C, 0x5B, 0x51,
+    0x8D, 0x1B, 0xAF, 0x92, 0xBB, 0xDD, 0xBC, 0x7F, 0x11, 0xD9, 0x5C, 0x41,
+    0x1F, 0x10, 0x5A, 0xD8, 0x0A, 0xC1, 0x31, 0x88, 0xA5, 0xCD, 0x7B, 0xBD,
+    0x2D, 0x74, 0xD0, 0x12, 0xB8, 0xE5, 0xB4, 0xB0, 0x89, 0x69, 0x97, 0x4A,
+    0x0C, 0x96, 0x77, 0x7E, 0x65, 0xB9, 0xF1, 0x09, 0xC5, 0x6E, 0xC6, 0x84,
+    0x18, 0xF0, 0x7D, 0xEC, 0x3A, 0xDC, 0x4D, 0x20, 0x79, 0xEE, 0x5F, 0x3E,
+    0xD7, 0xCB, 0x39, 0x48,
+];
+
+/// Multiply by x in GF(2^8), modulo the AES polynomial.
+fn xtime(x: u8) -> u8 {
+    (x << 1) ^ if x & 0x80 != 0 { 0x1B } else { 0 }
+}
+
+/// Multiply in GF(2^8), modulo the AES polynomial.
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut ret = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            ret ^= a;
+        }
+        a = xtime(a);
+        b >>= 1;
+    }
+    ret
+}
+
+/// The column of `MixColumns` (or `InvMixColumns`, with different
+/// coefficients) that a single byte contributes.
+fn mix_column(x: u8, coefficients: [u8; 4]) -> u32 {
+    u32::from_le_bytes(coefficients.map(|c| gf_mul(x, c)))
+}
+
+/// Try to execute an OP instruction from one of the scalar crypto
+/// extensions. Returns `None` if it isn't one, or not one that's currently
+/// enabled.
+pub(super) fn op<Env: ExecutionEnvironment>(
+    env: &mut Env,
+    instruction: u32,
+    a: u32,
+    b: u32,
+) -> Option<u32> {
+    if (instruction >> 12) & 0b111 != 0b000 {
+        return None;
+    }
+    let zknd = Env::SUPPORT_ZKND && env.enable_zknd();
+    let zkne = Env::SUPPORT_ZKNE && env.enable_zkne();
+    let zknh = Env::SUPPORT_ZKNH && env.enable_zknh();
+    let zksed = Env::SUPPORT_ZKSED && env.enable_zksed();
+    // the AES and SM4 instructions select a byte of rs2 with the top two
+    // bits of funct7
+    let shamt = (instruction >> 30) * 8;
+    let byte = (b >> shamt) as u8;
+    let result = match (instruction >> 25) & 0b11111 {
+        0b10001 if zkne => {
+            // AES32ESI
+            a ^ (AES_SBOX[byte as usize] as u32).rotate_left(shamt)
+        }
+        0b10011 if zkne => {
+            // AES32ESMI
+            let x = AES_SBOX[byte as usize];
+            a ^ mix_column(x, [2, 1, 1, 3]).rotate_left(shamt)
+        }
+        0b10101 if zknd => {
+            // AES32DSI
+            a ^ (AES_INVERSE_SBOX[byte as usize] as u32).rotate_left(shamt)
+        }
+        0b10111 if zknd => {
+            // AES32DSMI
+            let x = AES_INVERSE_SBOX[byte as usize];
+            a ^ mix_column(x, [14, 9, 13, 11]).rotate_left(shamt)
+        }
+        0b11000 if zksed => {
+            // SM4ED
+            let x = SM4_SBOX[byte as usize] as u32;
+            let y = x
+                ^ (x << 8)
+                ^ (x << 2)
+                ^ (x << 18)
+                ^ ((x & 0x3F) << 26)
+                ^ ((x & 0xC0) << 10);
+            a ^ y.rotate_left(shamt)
+        }
+        0b11010 if zksed => {
+            // SM4KS
+            let x = SM4_SBOX[byte as usize] as u32;
+            let y = x
+                ^ ((x & 0x07) << 29)
+                ^ ((x & 0xFE) << 7)
+                ^ ((x & 0x01) << 23)
+                ^ ((x & 0xF8) << 13);
+            a ^ y.rotate_left(shamt)
+        }
+        // the rest are all SHA-512 halves, and must have bs = 0b01
+        _ if instruction >> 30 != 0b01 || !zknh => return None,
+        0b01000 => {
+            // SHA512SUM0R
+            (a << 25) ^ (a << 30) ^ (a >> 28) ^ (b >> 7) ^ (b >> 2) ^ (b << 4)
+        }
+        0b01001 => {
+            // SHA512SUM1R
+            (a << 23)
+                ^ (a >> 14)
+                ^ (a >> 18)
+                ^ (b >> 9)
+                ^ (b << 18)
+                ^ (b << 14)
+        }
+        0b01010 => {
+            // SHA512SIG0L
+            (a >> 1) ^ (a >> 7) ^ (a >> 8) ^ (b << 31) ^ (b << 25) ^ (b << 24)
+        }
+        0b01110 => {
+            // SHA512SIG0H
+            (a >> 1) ^ (a >> 7) ^ (a >> 8) ^ (b << 31) ^ (b << 24)
+        }
+        0b01011 => {
+            // SHA512SIG1L
+            (a << 3) ^ (a >> 6) ^ (a >> 19) ^ (b >> 29) ^ (b << 26) ^ (b << 13)
+        }
+        0b01111 => {
+            // SHA512SIG1H
+            (a << 3) ^ (a >> 6) ^ (a >> 19) ^ (b >> 29) ^ (b << 13)
+        }
+        _ => return None,
+    };
+    env.account_crypto_op();
+    Some(result)
+}
+
+/// Try to execute an OP-IMM instruction from one of the scalar crypto
+/// extensions. Returns `None` if it isn't one, or not one that's currently
+/// enabled.
+pub(super) fn op_imm<Env: ExecutionEnvironment>(
+    env: &mut Env,
+    instruction: u32,
+    a: u32,
+) -> Option<u32> {
+    if (instruction >> 12) & 0b111 != 0b001 {
+        return None;
+    }
+    let zknh = Env::SUPPORT_ZKNH && env.enable_zknh();
+    let zksh = Env::SUPPORT_ZKSH && env.enable_zksh();
+    let result = match instruction >> 20 {
+        0x100 if zknh => {
+            // SHA256SUM0
+            a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22)
+        }
+        0x101 if zknh => {
+            // SHA256SUM1
+            a.rotate_right(6) ^ a.rotate_right(11) ^ a.rotate_right(25)
+        }
+        0x102 if zknh => {
+            // SHA256SIG0
+            a.rotate_right(7) ^ a.rotate_right(18) ^ (a >> 3)
+        }
+        0x103 if zknh => {
+            // SHA256SIG1
+            a.rotate_right(17) ^ a.rotate_right(19) ^ (a >> 10)
+        }
+        0x108 if zksh => {
+            // SM3P0
+            a ^ a.rotate_left(9) ^ a.rotate_left(17)
+        }
+        0x109 if zksh => {
+            // SM3P1
+            a ^ a.rotate_left(15) ^ a.rotate_left(23)
+        }
+        _ => return None,
+    };
+    env.account_crypto_op();
+    Some(result)
+}
+
+#[cfg(test)]
+mod test {
+    use super::test_env::{check, run};
+    use crate::ExceptionCause;
+    #[test]
+    fn scalar_crypto() {
+        check::<()>(
+            &[
+                0x662081b3, // aes32esmi x3, x1, x2, 1
+                0xaa208233, // aes32dsi x4, x1, x2, 2
+                0x10209293, // sha256sig0 x5, x1
+                0x50208333, // sha512sum0r x6, x1, x2
+                0x10909393, // sm3p1 x7, x1
+                0xf0208433, // sm4ed x8, x1, x2, 3
+            ],
+            &[(1, 0x1234_5678), (2, 0xA1B2_C3D4)],
+            &[
+                0x3C1A_0A0A,
+                0x120A_5678,
+                0xE7FC_E6EE,
+                0xC203_E833,
+                0x0501_4549,
+                0x3CEF_8D8D,
+            ],
+        );
+    }
+    #[test]
+    fn seed() {
+        let cpu = run::<()>(
+            &[
+                0x015011f3, // csrrw x3, seed, x0
+                0x01501073, // csrrw x0, seed, x0
+                0x01529273, // csrrw x4, seed, x5
+            ],
+            &[(5, 0xFFFF_FFFF)],
+        )
+        .unwrap();
+        assert_eq!(cpu.get_register(3), 0x8000_0001);
+        assert_eq!(cpu.get_register(4), 0x8000_0003);
+        let Err(exception) = run::<()>(
+            &[
+                0x015022f3, // csrrs x5, seed, x0
+            ],
+            &[],
+        ) else {
+            panic!("reading seed without writing it should be illegal");
+        };
+        assert_eq!(exception.mcause, ExceptionCause::IllegalInstruction);
+    }
+    #[test]
+    fn scalar_crypto_edges() {
+        check::<()>(
+            &[
+                0xe22081b3, // aes32esi x3, x1, x2, 3
+                0xe6208233, // aes32esmi x4, x1, x2, 3
+                0x2a2082b3, // aes32dsi x5, x1, x2, 0
+                0x2e208333, // aes32dsmi x6, x1, x2, 0
+                0x102a1393, // sha256sig0 x7, x20
+                0x103a1413, // sha256sig1 x8, x20
+                0x101a1493, // sha256sum1 x9, x20
+                0x108a1513, // sm3p0 x10, x20
+                0x302085b3, // sm4ed x11, x1, x2, 0
+                0xf4208633, // sm4ks x12, x1, x2, 3
+            ],
+            // the S-boxes of zero, in the lowest and highest bytes
+            &[(20, !0)],
+            &[
+                0x6300_0000,
+                0xC6A5_6363,
+                0x0000_0052,
+                0x50A7_F451,
+                0x1FFF_FFFF,
+                0x003F_FFFF,
+                0xFFFF_FFFF,
+                0xFFFF_FFFF,
+                0x5B5B_D58E,
+                0xD6C0_1A6B,
+            ],
+        );
+    }
+}
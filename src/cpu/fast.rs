@@ -0,0 +1,452 @@
+//! A shortcut through the most common RV32I (and M, and C) instructions, for
+//! instructions that come out of a decoded-instruction cache. Everything the
+//! full decoder would have to work out from the instruction bits is worked
+//! out once, when the instruction is cached.
+
+use super::*;
+
+/// What a cached instruction does, if it's simple enough to take the
+/// shortcut. Anything else is `Slow`, and goes through the full decoder.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum FastOp {
+    Slow,
+    Lui,
+    Auipc,
+    Jal,
+    Jalr,
+    Beq,
+    Bne,
+    Blt,
+    Bge,
+    Bltu,
+    Bgeu,
+    Lb,
+    Lh,
+    Lw,
+    Lbu,
+    Lhu,
+    Sb,
+    Sh,
+    Sw,
+    Addi,
+    Slti,
+    Sltiu,
+    Xori,
+    Ori,
+    Andi,
+    Slli,
+    Srli,
+    Srai,
+    Add,
+    Sub,
+    Sll,
+    Slt,
+    Sltu,
+    Xor,
+    Srl,
+    Sra,
+    Or,
+    And,
+    Mul,
+    // compressed instructions that the full decoder executes on the spot
+    // instead of expanding, which are accounted as generic ops
+    CAddi,
+    CXori,
+    CAndi,
+    CSlli,
+    CSrli,
+    CSrai,
+    CSub,
+    CXor,
+    COr,
+    CAnd,
+}
+
+/// Decide whether `instruction`, the expanded form of `original`, can take
+/// the shortcut, and pull out its operands if so.
+// (inlined so that it compiles away entirely if the environment doesn't
+// cache instructions)
+#[inline(always)]
+pub(super) fn decoded<
+    F: FloatBits,
+    X: IntegerRegisters,
+    Env: ExecutionEnvironment,
+>(
+    env: &Env,
+    original: u32,
+    instruction: u32,
+) -> DecodedInstruction {
+    let funct3 = (instruction >> 12) & 0b111;
+    let funct7 = instruction >> 25;
+    let rd = (instruction >> 7) & 0b11111;
+    let imm_i = ((instruction as i32) >> 20) as u32;
+    let imm_s =
+        (imm_i & !0b11111) | (((instruction as i32) >> 7) as u32 & 0b11111);
+    let imm_b = (((instruction >> 8) & 0b1111) << 1)
+        | (((instruction >> 25) & 0b111111) << 5)
+        | (((instruction >> 7) & 0b1) << 11)
+        | ((((instruction as i32) >> 31) << 12) as u32);
+    let imm_j = (((instruction >> 21) & 0b1111111111) << 1)
+        | (((instruction >> 20) & 0b1) << 11)
+        | (((instruction >> 12) & 0b11111111) << 12)
+        | ((((instruction as i32) >> 31) << 20) as u32);
+    let gates = decode_gates::<F, X, Env>(env);
+    if instruction & 0b11 != 0b11 {
+        // a compressed instruction that didn't need expanding
+        let (op, rd, rs1, rs2, imm) = compressed(instruction);
+        return DecodedInstruction {
+            original,
+            expanded: instruction,
+            op,
+            rd: rd as u8,
+            rs1: rs1 as u8,
+            rs2: rs2 as u8,
+            imm,
+            gates,
+        };
+    }
+    // OP-IMM and OP instructions that write x0 are hints, which might mean
+    // something to the full decoder
+    let major = (instruction >> 2) & 0b11111;
+    let (op, imm) = match major {
+        0b01101 => (FastOp::Lui, instruction & 0xFFFFF000),
+        0b00101 => (FastOp::Auipc, instruction & 0xFFFFF000),
+        0b11011 => (FastOp::Jal, imm_j),
+        0b11001 if funct3 == 0 => (FastOp::Jalr, imm_i),
+        0b11000 => (
+            match funct3 {
+                0b000 => FastOp::Beq,
+                0b001 => FastOp::Bne,
+                0b100 => FastOp::Blt,
+                0b101 => FastOp::Bge,
+                0b110 => FastOp::Bltu,
+                0b111 => FastOp::Bgeu,
+                _ => FastOp::Slow,
+            },
+            imm_b,
+        ),
+        0b00000 => (
+            match funct3 {
+                0b000 => FastOp::Lb,
+                0b001 => FastOp::Lh,
+                0b010 => FastOp::Lw,
+                0b100 => FastOp::Lbu,
+                0b101 => FastOp::Lhu,
+                _ => FastOp::Slow,
+            },
+            imm_i,
+        ),
+        0b01000 => (
+            match funct3 {
+                0b000 => FastOp::Sb,
+                0b001 => FastOp::Sh,
+                0b010 => FastOp::Sw,
+                _ => FastOp::Slow,
+            },
+            imm_s,
+        ),
+        0b00100 if rd != 0 => (
+            match (funct3, funct7) {
+                (0b000, _) => FastOp::Addi,
+                (0b010, _) => FastOp::Slti,
+                (0b011, _) => FastOp::Sltiu,
+                (0b100, _) => FastOp::Xori,
+                (0b110, _) => FastOp::Ori,
+                (0b111, _) => FastOp::Andi,
+                (0b001, 0b0000000) => FastOp::Slli,
+                (0b101, 0b0000000) => FastOp::Srli,
+                (0b101, 0b0100000) => FastOp::Srai,
+                _ => FastOp::Slow,
+            },
+            imm_i,
+        ),
+        0b01100 if rd != 0 => (
+            match (funct3, funct7) {
+                (0b000, 0b0000000) => FastOp::Add,
+                (0b000, 0b0100000) => FastOp::Sub,
+                (0b001, 0b0000000) => FastOp::Sll,
+                (0b010, 0b0000000) => FastOp::Slt,
+                (0b011, 0b0000000) => FastOp::Sltu,
+                (0b100, 0b0000000) => FastOp::Xor,
+                (0b101, 0b0000000) => FastOp::Srl,
+                (0b101, 0b0100000) => FastOp::Sra,
+                (0b110, 0b0000000) => FastOp::Or,
+                (0b111, 0b0000000) => FastOp::And,
+                (0b000, 0b0000001) if Env::SUPPORT_M && env.enable_m() => {
+                    FastOp::Mul
+                }
+                _ => FastOp::Slow,
+            },
+            0,
+        ),
+        _ => (FastOp::Slow, 0),
+    };
+    // fields that aren't registers read as x0, so that RV32E doesn't see
+    // them
+    let rs1 = (instruction >> 15) & 0b11111;
+    let rs2 = (instruction >> 20) & 0b11111;
+    let (rs1, rs2) = match major {
+        0b11000 | 0b01000 | 0b01100 => (rs1, rs2),
+        0b11001 | 0b00000 | 0b00100 => (rs1, 0),
+        _ => (0, 0),
+    };
+    DecodedInstruction {
+        original,
+        expanded: instruction,
+        op,
+        rd: rd as u8,
+        rs1: rs1 as u8,
+        rs2: rs2 as u8,
+        imm,
+        gates,
+    }
+}
+
+/// The shortcut for a compressed instruction that the full decoder has
+/// already accepted, and would have executed on the spot. Gives the op, rd,
+/// rs1, rs2, and immediate.
+// (inlinable, or other crates can't tell that `decoded` compiles away)
+#[inline]
+fn compressed(c: u32) -> (FastOp, u32, u32, u32, u32) {
+    let bits = |hi: u32, lo: u32| (c >> lo) & ((1 << (hi + 1 - lo)) - 1);
+    // the sign, from bit 12, extended up from bit `to`
+    let sign = |to: u32| (((c as i32) << 19 >> 31) as u32) << to;
+    let rd = bits(11, 7);
+    let rs2 = bits(6, 2);
+    let rd_prime = bits(9, 7) + 8;
+    let rs2_prime = bits(4, 2) + 8;
+    let imm6 = sign(5) | bits(6, 2);
+    let imm_j = sign(11)
+        | bits(11, 11) << 4
+        | bits(10, 9) << 8
+        | bits(8, 8) << 10
+        | bits(7, 7) << 6
+        | bits(6, 6) << 7
+        | bits(5, 3) << 1
+        | bits(2, 2) << 5;
+    let imm_b = sign(8)
+        | bits(11, 10) << 3
+        | bits(6, 5) << 6
+        | bits(4, 3) << 1
+        | bits(2, 2) << 5;
+    match (bits(15, 13), c & 0b11) {
+        // ADDI4SPN
+        (0b000, 0b00) => {
+            let offset = bits(12, 11) << 4
+                | bits(10, 7) << 6
+                | bits(6, 6) << 2
+                | bits(5, 5) << 3;
+            (FastOp::CAddi, bits(4, 2) + 8, 2, 0, offset)
+        }
+        // ADDI
+        (0b000, 0b01) => (FastOp::CAddi, rd, rd, 0, imm6),
+        // JAL
+        (0b001, 0b01) => (FastOp::Jal, 1, 0, 0, imm_j),
+        // LI
+        (0b010, 0b01) => (FastOp::CAddi, rd, 0, 0, imm6),
+        // ADDI16SP
+        (0b011, 0b01) if rd == 2 => {
+            let imm = sign(9)
+                | bits(6, 6) << 4
+                | bits(5, 5) << 6
+                | bits(4, 3) << 7
+                | bits(2, 2) << 5;
+            (FastOp::CAddi, 2, 2, 0, imm)
+        }
+        // LUI
+        (0b011, 0b01) => (FastOp::CAddi, rd, 0, 0, imm6 << 12),
+        (0b100, 0b01) => {
+            let rd = rd_prime;
+            match (bits(11, 10), bits(12, 12), bits(6, 5)) {
+                (0b00, _, _) => (FastOp::CSrli, rd, rd, 0, rs2),
+                (0b01, _, _) => (FastOp::CSrai, rd, rd, 0, rs2),
+                (0b10, _, _) => (FastOp::CAndi, rd, rd, 0, imm6),
+                // ZEXT.B and NOT, from Zcb
+                (0b11, 1, _) => match bits(4, 2) {
+                    0b000 => (FastOp::CAndi, rd, rd, 0, 0xFF),
+                    0b101 => (FastOp::CXori, rd, rd, 0, !0),
+                    _ => (FastOp::Slow, 0, 0, 0, 0),
+                },
+                (_, _, 0b00) => (FastOp::CSub, rd, rd, rs2_prime, 0),
+                (_, _, 0b01) => (FastOp::CXor, rd, rd, rs2_prime, 0),
+                (_, _, 0b10) => (FastOp::COr, rd, rd, rs2_prime, 0),
+                (_, _, _) => (FastOp::CAnd, rd, rd, rs2_prime, 0),
+            }
+        }
+        // J
+        (0b101, 0b01) => (FastOp::Jal, 0, 0, 0, imm_j),
+        // BEQZ
+        (0b110, 0b01) => (FastOp::Beq, 0, rd_prime, 0, imm_b),
+        // BNEZ
+        (0b111, 0b01) => (FastOp::Bne, 0, rd_prime, 0, imm_b),
+        // SLLI
+        (0b000, 0b10) => (FastOp::CSlli, rd, rd, 0, rs2),
+        (0b100, 0b10) => match (bits(12, 12), rs2) {
+            // JR
+            (0, 0) => (FastOp::Jalr, 0, rd, 0, 0),
+            // MV
+            (0, _) => (FastOp::CAddi, rd, rs2, 0, 0),
+            // JALR
+            (_, 0) => (FastOp::Jalr, 1, rd, 0, 0),
+            _ => (FastOp::Slow, 0, 0, 0, 0),
+        },
+        _ => (FastOp::Slow, 0, 0, 0, 0),
+    }
+}
+
+/// The extensions the decoder (and the shortcut) depend on, one bit each,
+/// that are enabled right now. A cached instruction is only used as is if
+/// this hasn't changed since it was cached.
+#[inline(always)]
+pub(super) fn decode_gates<
+    F: FloatBits,
+    X: IntegerRegisters,
+    Env: ExecutionEnvironment,
+>(
+    env: &Env,
+) -> u8 {
+    (Env::SUPPORT_C && env.enable_c()) as u8
+        | ((Env::SUPPORT_M && env.enable_m()) as u8) << 1
+        | ((Env::SUPPORT_ZCB && env.enable_zcb()) as u8) << 2
+        | ((Env::SUPPORT_ZCF && env.enable_zcf()) as u8) << 3
+        | ((Env::SUPPORT_ZCD && env.enable_zcd()) as u8) << 4
+        | ((Env::SUPPORT_ZCMP && env.enable_zcmp()) as u8) << 5
+        | ((Env::SUPPORT_ZCMT && env.enable_zcmt()) as u8) << 6
+        // (only RV32E's missing register check cares about this one)
+        | ((X::COUNT < 32
+            && F::SUPPORT_FCSR
+            && Env::SUPPORT_ZFINX
+            && env.enable_zfinx()) as u8)
+            << 7
+}
+
+impl<F: FloatBits, V: VectorBits, X: IntegerRegisters> Cpu<F, V, X> {
+    /// Execute a cached instruction whose `op` isn't `Slow`. Accounts for
+    /// everything exactly as the full decoder would have, except for the
+    /// fetch, which has already been accounted.
+    pub(super) fn fast_step<Env: ExecutionEnvironment>(
+        &mut self,
+        env: &mut Env,
+        this_pc: u32,
+        decoded: &DecodedInstruction,
+    ) -> Result<(), (ExceptionCause, u32)> {
+        let mut next_pc = if decoded.original & 0b11 == 0b11 {
+            this_pc.wrapping_add(4)
+        } else {
+            this_pc.wrapping_add(2)
+        };
+        let rd = decoded.rd as u32;
+        let imm = decoded.imm;
+        let a = self.get_register(decoded.rs1 as u32);
+        let b = self.get_register(decoded.rs2 as u32);
+        macro_rules! branch {
+            ($condition:expr) => {{
+                let should_branch = $condition;
+                if should_branch {
+                    next_pc = this_pc.wrapping_add(imm);
+                }
+                env.account_branch_op(should_branch, (imm as i32) >= 0);
+            }};
+        }
+        macro_rules! load {
+            ($read:ident, $convert:ty) => {{
+                let address = a.wrapping_add(imm);
+                let value = map_load(address, env.$read(address))?;
+                self.set_register(rd, value as $convert as u32);
+                env.account_memory_load(address);
+            }};
+        }
+        macro_rules! store {
+            ($result:expr, $address:ident) => {{
+                let $address = a.wrapping_add(imm);
+                map_store($address, $result)?;
+                env.account_memory_store($address);
+            }};
+        }
+        macro_rules! alu {
+            ($value:expr) => {{
+                let value = $value;
+                self.set_register(rd, value);
+                env.account_alu_op();
+            }};
+        }
+        macro_rules! generic {
+            ($value:expr) => {{
+                let value = $value;
+                self.set_register(rd, value);
+                env.account_generic_op();
+            }};
+        }
+        match decoded.op {
+            FastOp::Slow => unreachable!(),
+            FastOp::Lui => {
+                self.set_register(rd, imm);
+                env.account_generic_op();
+            }
+            FastOp::Auipc => alu!(this_pc.wrapping_add(imm)),
+            FastOp::Jal => {
+                self.set_register(rd, next_pc);
+                next_pc = this_pc.wrapping_add(imm);
+                env.account_jump_op();
+            }
+            FastOp::Jalr => {
+                self.set_register(rd, next_pc);
+                next_pc = a.wrapping_add(imm) & !1;
+                env.account_jump_op();
+            }
+            FastOp::Beq => branch!(a == b),
+            FastOp::Bne => branch!(a != b),
+            FastOp::Blt => branch!((a as i32) < (b as i32)),
+            FastOp::Bge => branch!((a as i32) >= (b as i32)),
+            FastOp::Bltu => branch!(a < b),
+            FastOp::Bgeu => branch!(a >= b),
+            FastOp::Lb => load!(read_byte, i8),
+            FastOp::Lh => load!(read_half, i16),
+            FastOp::Lw => {
+                let address = a.wrapping_add(imm);
+                let value = map_load(address, env.read_word(address, !0))?;
+                self.set_register(rd, value);
+                env.account_memory_load(address);
+            }
+            FastOp::Lbu => load!(read_byte, u8),
+            FastOp::Lhu => load!(read_half, u16),
+            FastOp::Sb => store!(env.write_byte(address, b as u8), address),
+            FastOp::Sh => store!(env.write_half(address, b as u16), address),
+            FastOp::Sw => store!(env.write_word(address, b, !0), address),
+            FastOp::Addi => alu!(a.wrapping_add(imm)),
+            FastOp::Slti => alu!(((a as i32) < (imm as i32)) as u32),
+            FastOp::Sltiu => alu!((a < imm) as u32),
+            FastOp::Xori => alu!(a ^ imm),
+            FastOp::Ori => alu!(a | imm),
+            FastOp::Andi => alu!(a & imm),
+            FastOp::Slli => alu!(a << (imm & 0b11111)),
+            FastOp::Srli => alu!(a >> (imm & 0b11111)),
+            FastOp::Srai => alu!(((a as i32) >> (imm & 0b11111)) as u32),
+            FastOp::Add => alu!(a.wrapping_add(b)),
+            FastOp::Sub => alu!(a.wrapping_sub(b)),
+            FastOp::Sll => alu!(a << (b & 0b11111)),
+            FastOp::Slt => alu!(((a as i32) < (b as i32)) as u32),
+            FastOp::Sltu => alu!((a < b) as u32),
+            FastOp::Xor => alu!(a ^ b),
+            FastOp::Srl => alu!(a >> (b & 0b11111)),
+            FastOp::Sra => alu!(((a as i32) >> (b & 0b11111)) as u32),
+            FastOp::Or => alu!(a | b),
+            FastOp::And => alu!(a & b),
+            FastOp::Mul => {
+                self.set_register(rd, a.wrapping_mul(b));
+                env.account_mul_op();
+            }
+            FastOp::CAddi => generic!(a.wrapping_add(imm)),
+            FastOp::CXori => generic!(a ^ imm),
+            FastOp::CAndi => generic!(a & imm),
+            FastOp::CSlli => generic!(a << imm),
+            FastOp::CSrli => generic!(a >> imm),
+            FastOp::CSrai => generic!(((a as i32) >> imm) as u32),
+            FastOp::CSub => generic!(a.wrapping_sub(b)),
+            FastOp::CXor => generic!(a ^ b),
+            FastOp::COr => generic!(a | b),
+            FastOp::CAnd => generic!(a & b),
+        }
+        self.retire(env, next_pc)
+    }
+}
@@ -2,13 +2,17 @@
 
 #[cfg(feature = "float")]
 pub use rustc_apfloat::{
-    ieee::{Double, Quad, Single},
-    Float, FloatConvert, Status, StatusAnd,
+    ieee::{BFloat, Double, Half, Quad, Single},
+    Float, FloatConvert, Round, Status, StatusAnd,
 };
 
 // The RISC-V specification decrees that all NaNs generated by the CPU shall
 // have these exact bit values.
 #[cfg(feature = "float")]
+pub const CANON_NAN_16: u16 = 0x7e00;
+#[cfg(feature = "float")]
+pub const CANON_NAN_BF16: u16 = 0x7fc0;
+#[cfg(feature = "float")]
 pub const CANON_NAN_32: u32 = 0x7fc00000;
 #[cfg(feature = "float")]
 pub const CANON_NAN_64: u64 = 0x7ff80000_00000000;
@@ -30,14 +34,16 @@ pub const UNDERFLOW_FLAG: u8 = 0b00010;
 #[cfg(feature = "float")]
 pub const INEXACT_FLAG: u8 = 0b00001;
 
-/// This trait is implemented by all four possible type parameters of
+/// This trait is implemented by all five possible type parameters of
 /// [`Cpu<F>`](super::Cpu). You should not try to implement it yourself.
-/// Instead, you should use one of the four provided implementations.
+/// Instead, you should use one of the five provided implementations.
 ///
 /// - `()`: no floating point support
 /// - `u32`: 32-bit floating point support only
 /// - `u64`: 32- and 64-bit floating point support
 /// - `u128`: 32-, 64-, and 128-bit floating point support
+/// - [`Zfinx`]: no float registers at all, only `fcsr`, for floats that live
+///   in the integer registers (Zfinx/Zdinx)
 ///
 /// Nothing inside this trait is considered part of the public-facing API.
 /// Non-compatible changes may be made even on patch releases. Depend on this
@@ -51,6 +57,17 @@ pub trait FloatBits: Default + Copy + PartialEq + Eq {
     const SUPPORT_D: bool = false;
     /// True if this `FloatBits` can fit a 128-bit float.
     const SUPPORT_Q: bool = false;
+    /// True if this `FloatBits` comes with an `fcsr`, which is needed for any
+    /// float support at all, including Zfinx.
+    const SUPPORT_FCSR: bool = false;
+    /// Cram a 16-bit float into this `FloatBits`.
+    fn box_half(_input: u16) -> Self {
+        unreachable!()
+    }
+    /// Extract a 16-bit float from this `FloatBits`.
+    fn unbox_half(self) -> u16 {
+        unreachable!()
+    }
     /// Cram a 32-bit float into this `FloatBits`.
     fn box_single(_input: u32) -> Self {
         unreachable!()
@@ -77,7 +94,7 @@ pub trait FloatBits: Default + Copy + PartialEq + Eq {
     }
     /// Type that should be used to store the `fcsr` value; `u8` if floats are
     /// supported, `()` if not.
-    type CsrType;
+    type CsrType: Copy;
     /// Default value of `fcsr`.
     fn default_csr() -> Self::CsrType;
     /// Extract the current value of `fcsr`.
@@ -106,10 +123,49 @@ impl FloatBits for () {
     fn from_bytes(_b: &[u8]) -> Self {}
 }
 
+/// A [`FloatBits`] for CPUs whose floats live in the integer registers, as
+/// in the Zfinx and Zdinx extensions. There are no float registers at all,
+/// so `Cpu<Zfinx>` is only a few bytes bigger than `Cpu<()>`.
+///
+/// Float instructions only work if the `ExecutionEnvironment` enables
+/// [`SUPPORT_ZFINX`](crate::ExecutionEnvironment::SUPPORT_ZFINX).
+#[cfg(feature = "float")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Zfinx;
+
+#[cfg(feature = "float")]
+impl FloatBits for Zfinx {
+    const BYTES_PER_FLOAT: usize = 0;
+    const SUPPORT_FCSR: bool = true;
+    type CsrType = u8;
+    fn default_csr() -> u8 {
+        DEFAULT_CSR
+    }
+    fn read_csr(csr: &u8) -> u8 {
+        *csr
+    }
+    fn write_csr(csr: &mut u8, value: u8) {
+        *csr = value
+    }
+    fn to_bytes(&self) -> [u8; 16] {
+        [0; 16]
+    }
+    fn from_bytes(_b: &[u8]) -> Self {
+        Zfinx
+    }
+}
+
 #[cfg(feature = "float")]
 impl FloatBits for u32 {
     const BYTES_PER_FLOAT: usize = 4;
+    const SUPPORT_FCSR: bool = true;
     const SUPPORT_F: bool = true;
+    fn box_half(input: u16) -> Self {
+        input as u32 | !(u16::MAX as u32)
+    }
+    fn unbox_half(self) -> u16 {
+        self as u16
+    }
     fn box_single(input: u32) -> Self {
         input
     }
@@ -138,8 +194,15 @@ impl FloatBits for u32 {
 #[cfg(feature = "float")]
 impl FloatBits for u64 {
     const BYTES_PER_FLOAT: usize = 8;
+    const SUPPORT_FCSR: bool = true;
     const SUPPORT_F: bool = true;
     const SUPPORT_D: bool = true;
+    fn box_half(input: u16) -> Self {
+        input as u64 | !(u16::MAX as u64)
+    }
+    fn unbox_half(self) -> u16 {
+        self as u16
+    }
     fn box_single(input: u32) -> Self {
         input as u64 | !(u32::MAX as u64)
     }
@@ -177,9 +240,16 @@ impl FloatBits for u64 {
 #[cfg(feature = "float")]
 impl FloatBits for u128 {
     const BYTES_PER_FLOAT: usize = 16;
+    const SUPPORT_FCSR: bool = true;
     const SUPPORT_F: bool = true;
     const SUPPORT_D: bool = true;
     const SUPPORT_Q: bool = true;
+    fn box_half(input: u16) -> Self {
+        input as u128 | !(u16::MAX as u128)
+    }
+    fn unbox_half(self) -> u16 {
+        self as u16
+    }
     fn box_single(input: u32) -> Self {
         input as u128 | !(u32::MAX as u128)
     }
@@ -219,6 +289,16 @@ impl FloatBits for u128 {
     }
 }
 
+#[cfg(feature = "float")]
+pub fn to_half(bits: u16) -> Half {
+    Half::from_bits(bits as u128)
+}
+
+#[cfg(feature = "float")]
+pub fn to_bfloat(bits: u16) -> BFloat {
+    BFloat::from_bits(bits as u128)
+}
+
 #[cfg(feature = "float")]
 pub fn to_single(bits: u32) -> Single {
     Single::from_bits(bits as u128)
@@ -252,6 +332,15 @@ impl<T> MaybeUnstatus for StatusAnd<T> {
     }
 }
 
+#[cfg(feature = "float")]
+impl MaybeUnstatus for Half {
+    type Inner = Self;
+    fn with_status(&self, _: impl FnOnce(&Status)) {}
+    fn to_inner(self) -> Self {
+        self
+    }
+}
+
 #[cfg(feature = "float")]
 impl MaybeUnstatus for Single {
     type Inner = Self;
@@ -280,29 +369,17 @@ impl MaybeUnstatus for Quad {
 }
 
 #[cfg(feature = "float")]
-pub fn maybe_unstatus<T: MaybeUnstatus, F: FloatBits>(
-    cpu: &mut super::Cpu<F>,
+pub fn maybe_unstatus<
+    T: MaybeUnstatus,
+    F: FloatBits,
+    V: super::VectorBits,
+    X: super::IntegerRegisters,
+>(
+    cpu: &mut super::Cpu<F, V, X>,
     x: T,
 ) -> T::Inner {
     x.with_status(|status| {
-        // unlucky us, rustc_apfloat defines these in the opposite order as the
-        // RISC-V spec does
-        let mut q = 0;
-        if status.contains(Status::INVALID_OP) {
-            q |= INVALID_FLAG;
-        }
-        if status.contains(Status::DIV_BY_ZERO) {
-            q |= ZERO_DIV_FLAG;
-        }
-        if status.contains(Status::OVERFLOW) {
-            q |= OVERFLOW_FLAG;
-        }
-        if status.contains(Status::UNDERFLOW) {
-            q |= UNDERFLOW_FLAG;
-        }
-        if status.contains(Status::INEXACT) {
-            q |= INEXACT_FLAG;
-        }
+        let q = status_flags(*status);
         if q != 0 {
             cpu.accrue_float_exceptions(q)
         }
@@ -310,11 +387,135 @@ pub fn maybe_unstatus<T: MaybeUnstatus, F: FloatBits>(
     x.to_inner()
 }
 
+/// Convert an apfloat status into RISC-V exception flags.
+#[cfg(feature = "float")]
+pub fn status_flags(status: Status) -> u8 {
+    // unlucky us, rustc_apfloat defines these in the opposite order as the
+    // RISC-V spec does
+    let mut q = 0;
+    if status.contains(Status::INVALID_OP) {
+        q |= INVALID_FLAG;
+    }
+    if status.contains(Status::DIV_BY_ZERO) {
+        q |= ZERO_DIV_FLAG;
+    }
+    if status.contains(Status::OVERFLOW) {
+        q |= OVERFLOW_FLAG;
+    }
+    if status.contains(Status::UNDERFLOW) {
+        q |= UNDERFLOW_FLAG;
+    }
+    if status.contains(Status::INEXACT) {
+        q |= INEXACT_FLAG;
+    }
+    q
+}
+
+/// Divide, the RISC-V way.
+#[cfg(feature = "float")]
+pub fn divide<T: Float>(a: T, b: T, round: Round) -> StatusAnd<T> {
+    let mut o = if a.is_nan() || b.is_nan() {
+        let status = if a.is_signaling() || b.is_signaling() {
+            Status::INVALID_OP
+        } else {
+            Status::OK
+        };
+        StatusAnd {
+            value: T::NAN,
+            status,
+        }
+    } else if b.is_zero() {
+        if a.is_zero() {
+            StatusAnd {
+                value: T::NAN,
+                status: Status::INVALID_OP,
+            }
+        } else if a.is_negative() != b.is_negative() {
+            StatusAnd {
+                value: -T::INFINITY,
+                status: Status::DIV_BY_ZERO,
+            }
+        } else {
+            StatusAnd {
+                value: T::INFINITY,
+                status: Status::DIV_BY_ZERO,
+            }
+        }
+    } else {
+        a.div_r(b, round)
+    };
+    // Bug in APFloat?
+    if a.is_finite()
+        && b.is_finite()
+        && o.value.is_infinite()
+        && !o.status.contains(Status::DIV_BY_ZERO)
+    {
+        o.status |= Status::OVERFLOW;
+    }
+    o
+}
+
+/// `FMIN` or `FMAX`: a NaN input loses to a number, and -0 is less than +0.
+#[cfg(feature = "float")]
+pub fn min_max<T: Float>(a: T, b: T, max: bool) -> StatusAnd<T> {
+    let value = if a.is_nan() {
+        b
+    } else if b.is_nan() {
+        a
+    } else if a.is_zero() && b.is_zero() {
+        if a.is_negative() != max {
+            a
+        } else {
+            b
+        }
+    } else if max {
+        a.max(b)
+    } else {
+        a.min(b)
+    };
+    let status = if a.is_signaling() || b.is_signaling() {
+        Status::INVALID_OP
+    } else {
+        Status::OK
+    };
+    StatusAnd { value, status }
+}
+
+/// The `FCLASS` mask for a value.
+#[cfg(feature = "float")]
+pub fn classify<T: Float>(a: T) -> u32 {
+    if a.is_finite() {
+        match (a.is_negative(), a.is_denormal(), a.is_zero()) {
+            (true, false, false) => 1 << 1,
+            (true, true, false) => 1 << 2,
+            (true, _, true) => 1 << 3,
+            (false, _, true) => 1 << 4,
+            (false, true, false) => 1 << 5,
+            (false, false, false) => 1 << 6,
+        }
+    } else if a.is_neg_infinity() {
+        1 << 0
+    } else if a.is_pos_infinity() {
+        1 << 7
+    } else if a.is_signaling() {
+        1 << 8
+    } else {
+        1 << 9
+    }
+}
+
 #[cfg(feature = "float")]
 pub trait WordyFloat {
     fn get_word_count() -> u32;
 }
 
+#[cfg(feature = "float")]
+impl WordyFloat for Half {
+    fn get_word_count() -> u32 {
+        1
+    }
+}
+
 #[cfg(feature = "float")]
 impl WordyFloat for Single {
     fn get_word_count() -> u32 {
@@ -335,3 +536,82 @@ impl WordyFloat for Quad {
         4
     }
 }
+
+/// The constants that `FLI` can load (Zfa), as single-precision bits. Entry 1
+/// is the smallest normal number, entry 30 is infinity, and entry 31 is the
+/// canonical NaN; those depend on the precision, so they're handled in
+/// [`fli`].
+#[cfg(feature = "float")]
+const FLI_TABLE: [u32; 32] = [
+    0xBF800000, 0, 0x37800000, 0x38000000, 0x3B800000, 0x3C000000, 0x3D800000,
+    0x3E000000, 0x3E800000, 0x3EA00000, 0x3EC00000, 0x3EE00000, 0x3F000000,
+    0x3F200000, 0x3F400000, 0x3F600000, 0x3F800000, 0x3FA00000, 0x3FC00000,
+    0x3FE00000, 0x40000000, 0x40200000, 0x40400000, 0x40800000, 0x41000000,
+    0x41800000, 0x43000000, 0x43800000, 0x47000000, 0x47800000, 0, 0,
+];
+
+/// The value `FLI` loads for the given entry, in the given precision. (2^16
+/// doesn't fit in a half, so it becomes infinity.)
+#[cfg(feature = "float")]
+pub fn fli<T: Float>(index: u32) -> T
+where
+    Single: FloatConvert<T>,
+{
+    match index {
+        1 => T::smallest_normalized(),
+        30 => T::INFINITY,
+        31 => T::NAN,
+        _ => {
+            let mut lost = false;
+            to_single(FLI_TABLE[index as usize])
+                .convert(&mut lost)
+                .value
+        }
+    }
+}
+
+/// `FCVTMOD.W.D` (Zfa): truncate a double to an integer, and return the low
+/// 32 bits of that integer, along with the exception flags. Infinities and
+/// NaNs become zero.
+#[cfg(feature = "float")]
+pub fn fcvtmod_w_d(bits: u64) -> (u32, u8) {
+    let negative = bits >> 63 != 0;
+    let exponent = ((bits >> 52) & 0x7FF) as i32;
+    let fraction = bits & ((1 << 52) - 1);
+    if exponent == 0x7FF {
+        return (0, INVALID_FLAG);
+    }
+    let (mantissa, shift) = if exponent == 0 {
+        (fraction, -1074)
+    } else {
+        (fraction | (1 << 52), exponent - 1075)
+    };
+    let mantissa = mantissa as u128;
+    // the magnitude of the truncated value (or zero, if it's a multiple of
+    // 2^64 and we only care about its low bits)
+    let (magnitude, inexact) = if shift >= 64 {
+        (0, false)
+    } else if shift >= 0 {
+        (mantissa << shift, false)
+    } else if shift > -64 {
+        let magnitude = mantissa >> -shift;
+        (magnitude, magnitude << -shift != mantissa)
+    } else {
+        (0, mantissa != 0)
+    };
+    let limit = if negative { 1 << 31 } else { (1 << 31) - 1 };
+    let out_of_range = shift >= 64 || magnitude > limit;
+    let flags = if out_of_range {
+        INVALID_FLAG
+    } else if inexact {
+        INEXACT_FLAG
+    } else {
+        0
+    };
+    let result = if negative {
+        magnitude.wrapping_neg()
+    } else {
+        magnitude
+    };
+    (result as u32, flags)
+}
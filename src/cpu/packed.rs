@@ -0,0 +1,518 @@
+//! The packed SIMD extension (P), as of the 0.9.x draft: 8- and 16-bit SIMD
+//! arithmetic, shifts, and comparisons, plus the 32-bit saturating and
+//! multiply-accumulate instructions. The instructions that write a register
+//! pair, or only exist on RV64, are not implemented.
+
+use super::*;
+
+/// All ones, `bits` wide.
+fn mask(bits: u32) -> u32 {
+    u32::MAX >> (32 - bits)
+}
+
+/// Sign extend a lane `bits` wide.
+fn sx(x: u32, bits: u32) -> i64 {
+    (((x << (32 - bits)) as i32) >> (32 - bits)) as i64
+}
+
+/// Signed halfword `index` of `x`.
+fn half(x: u32, index: u32) -> i64 {
+    sx(x >> (index * 16), 16)
+}
+
+/// Clamp `v` to a lane `bits` wide, setting `ov` if it didn't fit.
+fn saturate(v: i64, bits: u32, signed: bool, ov: &mut bool) -> u32 {
+    let (min, max) = if signed {
+        (-(1 << (bits - 1)), (1 << (bits - 1)) - 1)
+    } else {
+        (0, mask(bits) as i64)
+    };
+    if v < min || v > max {
+        *ov = true;
+    }
+    v.clamp(min, max) as u32
+}
+
+/// Shift right by `amount`, rounding to nearest if `round` is set.
+fn shift_right(v: i64, amount: u32, round: bool) -> i64 {
+    if round && amount > 0 {
+        ((v >> (amount - 1)) + 1) >> 1
+    } else {
+        v >> amount
+    }
+}
+
+/// Apply `op(lane, a, b)` to each `bits`-wide lane. If `cross` is set, each
+/// lane of `a` is paired with its neighbour in `b` instead.
+fn simd(
+    a: u32,
+    b: u32,
+    bits: u32,
+    cross: bool,
+    mut op: impl FnMut(u32, u32, u32) -> u32,
+) -> u32 {
+    let mut ret = 0;
+    for lane in 0..32 / bits {
+        let x = (a >> (lane * bits)) & mask(bits);
+        let y = (b >> ((lane ^ cross as u32) * bits)) & mask(bits);
+        ret |= (op(lane, x, y) & mask(bits)) << (lane * bits);
+    }
+    ret
+}
+
+/// One lane of an add or subtract. `variant` is the top four bits of
+/// `funct7`: wrapping, halving (signed or unsigned), or saturating (signed
+/// or unsigned).
+fn add_sub(
+    variant: u32,
+    x: u32,
+    y: u32,
+    sub: bool,
+    bits: u32,
+    ov: &mut bool,
+) -> u32 {
+    let signed = variant & 0b0010 == 0;
+    let (x, y) = if signed {
+        (sx(x, bits), sx(y, bits))
+    } else {
+        (x as i64, y as i64)
+    };
+    let v = if sub { x - y } else { x + y };
+    match variant {
+        0b0100 => v as u32,
+        0b0000 | 0b0010 => (v >> 1) as u32,
+        _ => saturate(v, bits, signed, ov),
+    }
+}
+
+/// One lane of a shift. `op` is the bottom two bits of `funct7`: SRA, SRL,
+/// SLL, or KSLRA. `alt` picks rounding for the right shifts, and saturation
+/// for SLL.
+fn shift(
+    op: u32,
+    alt: bool,
+    x: u32,
+    amount: u32,
+    bits: u32,
+    ov: &mut bool,
+) -> u32 {
+    match op {
+        0b00 => shift_right(sx(x, bits), amount, alt) as u32,
+        0b01 => shift_right(x as i64, amount, alt) as u32,
+        0b10 if alt => saturate(sx(x, bits) << amount, bits, true, ov),
+        0b10 => x << amount,
+        _ => {
+            // `amount` is signed, and one bit wider than usual
+            let amount = sx(amount, bits.trailing_zeros() + 1);
+            if amount < 0 {
+                let amount = (-amount).min(bits as i64 - 1) as u32;
+                shift_right(sx(x, bits), amount, alt) as u32
+            } else {
+                saturate(sx(x, bits) << amount, bits, true, ov)
+            }
+        }
+    }
+}
+
+/// The 8- and 16-bit SIMD instructions, and the 8-bit multiply-accumulates:
+/// `OP-P` with `funct3` 000.
+fn simd_op<Env: ExecutionEnvironment>(
+    env: &mut Env,
+    instruction: u32,
+    a: u32,
+    b: u32,
+    c: u32,
+    ov: &mut bool,
+) -> Option<u32> {
+    let funct7 = instruction >> 25;
+    let rs2 = (instruction >> 20) & 0b11111;
+    let bits = if funct7 & 0b100 != 0 { 8 } else { 16 };
+    let variant = funct7 >> 3;
+    let result = match funct7 {
+        0b0000000..=0b0100111 => {
+            match funct7 & 0b111 {
+                // ADD16, SUB16, ADD8, SUB8, and their halving and saturating
+                // versions
+                0b000 | 0b001 | 0b100 | 0b101 => {
+                    let sub = funct7 & 1 != 0;
+                    simd(a, b, bits, false, |_, x, y| {
+                        add_sub(variant, x, y, sub, bits, ov)
+                    })
+                }
+                // CRAS16, CRSA16, and their halving and saturating versions
+                0b010 | 0b011 => {
+                    let sub_lane = funct7 & 1;
+                    simd(a, b, 16, true, |lane, x, y| {
+                        add_sub(variant, x, y, lane == sub_lane, 16, ov)
+                    })
+                }
+                // CMPEQ16, SCMPLT16, SCMPLE16, UCMPLT16, UCMPLE16, and the 8-bit
+                // versions
+                _ => {
+                    let bits = if funct7 & 1 != 0 { 8 } else { 16 };
+                    simd(a, b, bits, false, |_, x, y| {
+                        let (sx, sy) = (sx(x, bits), sx(y, bits));
+                        let hit = match variant {
+                            0b0100 => x == y,
+                            0b0000 => sx < sy,
+                            0b0001 => sx <= sy,
+                            0b0010 => x < y,
+                            _ => x <= y,
+                        };
+                        if hit {
+                            mask(bits)
+                        } else {
+                            0
+                        }
+                    })
+                }
+            }
+        }
+        0b0101000..=0b0111111 => {
+            // SRA16, SRL16, SLL16, KSLRA16, and their rounding, saturating,
+            // immediate, and 8-bit versions
+            let op = funct7 & 0b11;
+            let (alt, amount) = match variant & 0b11 {
+                0b01 => (false, b),
+                0b10 => (true, b),
+                _ if op == 0b11 => return None,
+                _ if bits == 16 => (rs2 >> 4 != 0, rs2),
+                _ if rs2 >> 4 != 0 => return None,
+                _ => (rs2 >> 3 != 0, rs2),
+            };
+            // KSLRA takes one more bit, for the sign
+            let amount = if op == 0b11 {
+                amount & (bits * 2 - 1)
+            } else {
+                amount & (bits - 1)
+            };
+            simd(a, 0, bits, false, |_, x, _| {
+                shift(op, alt, x, amount, bits, ov)
+            })
+        }
+        0b1000000 | 0b1000001 | 0b1001000 | 0b1001001 | 0b1000100
+        | 0b1000101 | 0b1001100 | 0b1001101 => {
+            // SMIN16, SMAX16, UMIN16, UMAX16, and the 8-bit versions
+            let unsigned = funct7 & 0b1000 != 0;
+            let max = funct7 & 1 != 0;
+            simd(a, b, bits, false, |_, x, y| {
+                let (kx, ky) = if unsigned {
+                    (x as i64, y as i64)
+                } else {
+                    (sx(x, bits), sx(y, bits))
+                };
+                if (kx < ky) != max {
+                    x
+                } else {
+                    y
+                }
+            })
+        }
+        0b1000011 | 0b1001011 | 0b1000111 | 0b1001111 => {
+            // KHM16, KHMX16, KHM8, KHMX8
+            let cross = funct7 & 0b1000 != 0;
+            env.account_mul_op();
+            return Some(simd(a, b, bits, cross, |_, x, y| {
+                let product = sx(x, bits) * sx(y, bits);
+                saturate(product >> (bits - 1), bits, true, ov)
+            }));
+        }
+        0b1010110 if rs2 == 0b10001 || rs2 == 0b10000 => {
+            // KABS16, KABS8
+            let bits = if rs2 & 1 != 0 { 16 } else { 8 };
+            simd(a, 0, bits, false, |_, x, _| {
+                saturate(sx(x, bits).abs(), bits, true, ov)
+            })
+        }
+        0b1010110 if rs2 == 0b10100 => {
+            // KABSW
+            saturate((a as i32 as i64).abs(), 32, true, ov)
+        }
+        0b1100100..=0b1100110 => {
+            // SMAQA, SMAQA.SU, UMAQA
+            let mut sum = c;
+            for byte in 0..4 {
+                let x = (a >> (byte * 8)) & 0xFF;
+                let y = (b >> (byte * 8)) & 0xFF;
+                let product = match funct7 {
+                    0b1100100 => sx(x, 8) * sx(y, 8),
+                    0b1100101 => sx(x, 8) * y as i64,
+                    _ => (x * y) as i64,
+                };
+                sum = sum.wrapping_add(product as u32);
+            }
+            env.account_mul_op();
+            return Some(sum);
+        }
+        _ => return None,
+    };
+    env.account_alu_op();
+    Some(result)
+}
+
+/// The 32-bit instructions: `OP-P` with `funct3` 001.
+fn word_op<Env: ExecutionEnvironment>(
+    env: &mut Env,
+    instruction: u32,
+    a: u32,
+    b: u32,
+    c: u32,
+    ov: &mut bool,
+) -> Option<u32> {
+    let funct7 = instruction >> 25;
+    let rs2 = (instruction >> 20) & 0b11111;
+    let (sa, sb, sc) = (a as i32 as i64, b as i32 as i64, c as i32 as i64);
+    let (ua, ub) = (a as i64, b as i64);
+    let sat32 = |v, ov: &mut bool| saturate(v, 32, true, ov);
+    let sat16 = |v, ov: &mut bool| saturate(v, 16, true, ov) as i16 as u32;
+    let result = match funct7 {
+        0b0000000 => sat32(sa + sb, ov), // KADDW
+        0b0000001 => sat32(sa - sb, ov), // KSUBW
+        0b0000010 => sat16(sa + sb, ov), // KADDH
+        0b0000011 => sat16(sa - sb, ov), // KSUBH
+        0b0001000 => saturate(ua + ub, 32, false, ov), // UKADDW
+        0b0001001 => saturate(ua - ub, 32, false, ov), // UKSUBW
+        0b0001010 => saturate(ua + ub, 16, false, ov), // UKADDH
+        0b0001011 => saturate(ua - ub, 16, false, ov), // UKSUBH
+        0b0010000 => ((sa + sb) >> 1) as u32, // RADDW
+        0b0010001 => ((sa - sb) >> 1) as u32, // RSUBW
+        0b0011000 => ((ua + ub) >> 1) as u32, // URADDW
+        0b0011001 => ((ua - ub) >> 1) as u32, // URSUBW
+        0b0010011 => sat32(sa << (b & 31), ov), // KSLLW
+        0b0011011 => sat32(sa << rs2, ov), // KSLLIW
+        _ => {
+            let result = match funct7 {
+                0b0100000..=0b0111011 if funct7 & 0b100 == 0 => {
+                    // SMMUL, KMMAC, KMMSB, KWMMUL, SMMWB, SMMWT, KMMAWB,
+                    // KMMAWT, and their rounding versions
+                    let round = funct7 & 0b1000 != 0;
+                    let alt = funct7 & 0b10000 != 0;
+                    match funct7 & 0b11 {
+                        0b00 if !alt => shift_right(sa * sb, 32, round) as u32,
+                        0b00 => {
+                            sat32(sc + shift_right(sa * sb, 32, round), ov)
+                        }
+                        0b01 if !alt => {
+                            sat32(sc - shift_right(sa * sb, 32, round), ov)
+                        }
+                        0b01 => sat32(shift_right(sa * sb, 31, round), ov),
+                        0b10 => {
+                            let b = half(b, alt as u32);
+                            shift_right(sa * b, 16, round) as u32
+                        }
+                        _ => {
+                            let b = half(b, alt as u32);
+                            sat32(sc + shift_right(sa * b, 16, round), ov)
+                        }
+                    }
+                }
+                0b0000100 => (half(a, 0) * half(b, 0)) as u32, // SMBB16
+                0b0001100 => (half(a, 0) * half(b, 1)) as u32, // SMBT16
+                0b0010100 => (half(a, 1) * half(b, 1)) as u32, // SMTT16
+                0b0000101 => sat32(2 * half(a, 0) * half(b, 0), ov), // KDMBB
+                0b0001101 => sat32(2 * half(a, 0) * half(b, 1), ov), // KDMBT
+                0b0010101 => sat32(2 * half(a, 1) * half(b, 1), ov), // KDMTT
+                0b0000110 => sat16((half(a, 0) * half(b, 0)) >> 15, ov), // KHMBB
+                0b0001110 => sat16((half(a, 0) * half(b, 1)) >> 15, ov), // KHMBT
+                0b0010110 => sat16((half(a, 1) * half(b, 1)) >> 15, ov), // KHMTT
+                0b0011100 => {
+                    // KMDA
+                    let v = half(a, 1) * half(b, 1) + half(a, 0) * half(b, 0);
+                    sat32(v, ov)
+                }
+                0b0011101 => {
+                    // KMXDA
+                    let v = half(a, 1) * half(b, 0) + half(a, 0) * half(b, 1);
+                    sat32(v, ov)
+                }
+                0b0101100 => {
+                    // SMDS
+                    (half(a, 1) * half(b, 1) - half(a, 0) * half(b, 0)) as u32
+                }
+                0b0110100 => {
+                    // SMDRS
+                    (half(a, 0) * half(b, 0) - half(a, 1) * half(b, 1)) as u32
+                }
+                0b0111100 => {
+                    // SMXDS
+                    (half(a, 1) * half(b, 0) - half(a, 0) * half(b, 1)) as u32
+                }
+                0b0101101 => sat32(sc + half(a, 0) * half(b, 0), ov), // KMABB
+                0b0110101 => sat32(sc + half(a, 0) * half(b, 1), ov), // KMABT
+                0b0111101 => sat32(sc + half(a, 1) * half(b, 1), ov), // KMATT
+                0b0100100 => {
+                    // KMADA
+                    let v = half(a, 1) * half(b, 1) + half(a, 0) * half(b, 0);
+                    sat32(sc + v, ov)
+                }
+                0b0100101 => {
+                    // KMAXDA
+                    let v = half(a, 1) * half(b, 0) + half(a, 0) * half(b, 1);
+                    sat32(sc + v, ov)
+                }
+                0b0101110 => {
+                    // KMADS
+                    let v = half(a, 1) * half(b, 1) - half(a, 0) * half(b, 0);
+                    sat32(sc + v, ov)
+                }
+                0b0110110 => {
+                    // KMADRS
+                    let v = half(a, 0) * half(b, 0) - half(a, 1) * half(b, 1);
+                    sat32(sc + v, ov)
+                }
+                0b0111110 => {
+                    // KMAXDS
+                    let v = half(a, 1) * half(b, 0) - half(a, 0) * half(b, 1);
+                    sat32(sc + v, ov)
+                }
+                0b0100110 => {
+                    // KMSDA
+                    let v = half(a, 1) * half(b, 1) + half(a, 0) * half(b, 0);
+                    sat32(sc - v, ov)
+                }
+                0b0100111 => {
+                    // KMSXDA
+                    let v = half(a, 1) * half(b, 0) + half(a, 0) * half(b, 1);
+                    sat32(sc - v, ov)
+                }
+                _ => return None,
+            };
+            env.account_mul_op();
+            return Some(result);
+        }
+    };
+    env.account_alu_op();
+    Some(result)
+}
+
+/// Try to execute an `OP-P` instruction. `c` is the current value of `rd`,
+/// which the multiply-accumulate instructions add to. Returns `None` if it's
+/// not an instruction we implement. Sets `ov` if the result saturated.
+pub(super) fn op<Env: ExecutionEnvironment>(
+    env: &mut Env,
+    instruction: u32,
+    a: u32,
+    b: u32,
+    c: u32,
+    ov: &mut bool,
+) -> Option<u32> {
+    match (instruction >> 12) & 0b111 {
+        0b000 => simd_op(env, instruction, a, b, c, ov),
+        0b001 => word_op(env, instruction, a, b, c, ov),
+        _ => None,
+    }
+}
+
+impl<F: FloatBits, V: VectorBits, X: IntegerRegisters> Cpu<F, V, X> {
+    /// Read the OV flag, which P shares with the vector extensions' `vxsat`.
+    pub(super) fn read_ov(&self) -> bool {
+        if V::VLENB != 0 {
+            V::read_csr(&self.vcsr).vxsat
+        } else {
+            self.ov
+        }
+    }
+    /// Write the OV flag.
+    pub(super) fn write_ov(&mut self, value: bool) {
+        if V::VLENB != 0 {
+            let mut csrs = V::read_csr(&self.vcsr);
+            csrs.vxsat = value;
+            V::write_csr(&mut self.vcsr, csrs);
+        } else {
+            self.ov = value;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::test_env::check;
+    #[test]
+    fn packed() {
+        check::<()>(
+            &[
+                0x402081f7, // add16 x3, x1, x2
+                0x10208277, // kadd16 x4, x1, x2
+                0x4a2082f7, // sub8 x5, x1, x2
+                0x71108377, // srai16.u x6, x1, 1
+                0xc82083f7, // smaqa x7, x1, x2
+                0x38209477, // kmda x8, x1, x2
+                0x009024f3, // csrr x9, vxsat
+            ],
+            &[(1, 0x7FFF_0001), (2, 0x0001_FFFF), (7, 10)],
+            &[
+                0x8000_0000,
+                0x7FFF_0000,
+                0x7FFE_0102,
+                0x4000_0001,
+                8,
+                32766,
+                1,
+            ],
+        );
+    }
+    #[test]
+    fn packed_saturation() {
+        check::<()>(
+            &[
+                0x102081f7, // kadd16 x3, x1, x2
+                0x12208277, // ksub16 x4, x1, x2
+                0x302082f7, // ukadd16 x5, x1, x2
+                0x32110377, // uksub16 x6, x2, x1
+                0xad1083f7, // kabs16 x7, x1
+                0x65410477, // ksll16 x8, x2, x20
+                0x555104f7, // sll16 x9, x2, x21
+                0x71f08577, // srai16.u x10, x1, 15
+                0x576085f7, // kslra16 x11, x1, x22
+                0x018b9677, // kaddw x12, x23, x24
+                0x038d16f7, // ksubw x13, x26, x24
+                0x0b9c9777, // kdmbb x14, x25, x25
+                0x0d9c97f7, // khmbb x15, x25, x25
+                0x399c9877, // kmda x16, x25, x25
+                0x37fc18f7, // kslliw x17, x24, 31
+                0x38208977, // ukadd8 x18, x1, x2
+                0x009029f3, // csrr x19, vxsat
+            ],
+            // shifts by 15, 31 (so 15), and -16
+            &[
+                (1, 0x7FFF_8000),
+                (2, 0x0001_FFFF),
+                (20, 15),
+                (21, 31),
+                (22, 0x10),
+                (23, 0x7FFF_FFFF),
+                (24, 1),
+                (25, 0x8000_8000),
+                (26, 0x8000_0000),
+            ],
+            &[
+                0x7FFF_8000,
+                0x7FFE_8001,
+                0x8000_FFFF,
+                0x0000_7FFF,
+                0x7FFF_7FFF,
+                0x7FFF_8000,
+                0x8000_8000,
+                0x0001_FFFF,
+                0x0000_FFFF,
+                0x7FFF_FFFF,
+                0x8000_0000,
+                0x7FFF_FFFF,
+                0x0000_7FFF,
+                0x7FFF_FFFF,
+                0x7FFF_FFFF,
+                0x7FFF_FFFF,
+                1,
+            ],
+        );
+        // nothing saturated, so OV stays clear
+        check::<()>(
+            &[
+                0x122081f7, // ksub16 x3, x1, x2
+                0x00902273, // csrr x4, vxsat
+            ],
+            &[(1, 0x7FFF_8000), (2, 0x0001_FFFF)],
+            &[0x7FFE_8001, 0],
+        );
+    }
+}
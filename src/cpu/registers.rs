@@ -0,0 +1,135 @@
+//! The integer register file, which is smaller in RV32E.
+
+/// This trait is implemented by all possible `X` type parameters of
+/// [`Cpu<F, V, X>`](super::Cpu). You should not try to implement it yourself.
+/// Instead, you should use one of the provided implementations.
+///
+/// - `[u32; 32]`: Default. The 32 registers of RV32I.
+/// - `[u32; 16]`: The 16 registers of RV32E.
+///
+/// (The slot for `x0` holds the PC.)
+///
+/// Nothing inside this trait is considered part of the public-facing API.
+/// Non-compatible changes may be made even on patch releases. Depend on this
+/// trait's innards at your own risk.
+pub trait IntegerRegisters: Copy {
+    /// The number of integer registers, including `x0`.
+    const COUNT: u32;
+    /// A register file full of zeroes.
+    fn zero() -> Self;
+    fn as_slice(&self) -> &[u32];
+    fn as_mut_slice(&mut self) -> &mut [u32];
+}
+
+impl IntegerRegisters for [u32; 32] {
+    const COUNT: u32 = 32;
+    fn zero() -> Self {
+        [0; 32]
+    }
+    fn as_slice(&self) -> &[u32] {
+        self
+    }
+    fn as_mut_slice(&mut self) -> &mut [u32] {
+        self
+    }
+}
+
+impl IntegerRegisters for [u32; 16] {
+    const COUNT: u32 = 16;
+    fn zero() -> Self {
+        [0; 16]
+    }
+    fn as_slice(&self) -> &[u32] {
+        self
+    }
+    fn as_mut_slice(&mut self) -> &mut [u32] {
+        self
+    }
+}
+
+const RD: u32 = 1 << 7;
+const RS1: u32 = 1 << 15;
+const RS2: u32 = 1 << 20;
+const RS3: u32 = 1 << 27;
+
+/// Return true if any of the integer registers `instruction` names are
+/// missing from a register file of `count` registers. Fields that hold
+/// float registers, vector registers, immediates, or sub-opcodes don't
+/// count. (Compressed instructions must be expanded first.)
+pub(super) fn names_missing_register(
+    instruction: u32,
+    zfinx: bool,
+    count: u32,
+) -> bool {
+    let funct3 = (instruction >> 12) & 0b111;
+    let funct7 = instruction >> 25;
+    let rs2 = (instruction >> 20) & 0b11111;
+    // vector loads and stores, whose rs2 is a register only if they're
+    // strided
+    let vector_memory = matches!(funct3, 0b000 | 0b101 | 0b110 | 0b111);
+    let fields = match (instruction >> 2) & 0b11111 {
+        0b00000 => RD | RS1,                 // LOAD
+        0b00011 => RD | RS1,                 // MISC-MEM
+        0b00100 => RD | RS1,                 // OP-IMM
+        0b00101 | 0b01101 | 0b11011 => RD,   // AUIPC, LUI, JAL
+        0b01000 => RS1 | RS2,                // STORE
+        0b01011 | 0b01100 => RD | RS1 | RS2, // AMO, OP
+        0b11000 => RS1 | RS2,                // BRANCH
+        0b11001 => RD | RS1,                 // JALR
+        0b00001 | 0b01001 => {
+            // LOAD-FP, STORE-FP
+            if vector_memory && (instruction >> 26) & 0b11 == 0b10 {
+                RS1 | RS2
+            } else {
+                RS1
+            }
+        }
+        0b10000..=0b10011 if zfinx => RD | RS1 | RS2 | RS3, // FMADD etc.
+        // OP-FP, whose unary instructions' rs2 is a sub-opcode below 16
+        0b10100 if zfinx => RD | RS1 | RS2,
+        0b10100 => match funct7 >> 2 {
+            // FMV.X.W, FCLASS, FMVH.X.D, FCVT.W.S, FEQ, FLT, FLE
+            0b11100 | 0b11000 | 0b10100 => RD,
+            // FMV.W.X, but not FLI
+            0b11110 if rs2 == 0 => RS1,
+            // FCVT.S.W
+            0b11010 => RS1,
+            // FMVP.D.X
+            0b10110 => RS1 | RS2,
+            _ => 0,
+        },
+        0b10101 => match funct3 {
+            // VSETVLI, VSETIVLI, VSETVL
+            0b111 if instruction >> 31 == 0 => RD | RS1,
+            0b111 if instruction >> 30 == 0b11 => RD,
+            0b111 => RD | RS1 | RS2,
+            // OPIVX, OPMVX
+            0b100 | 0b110 => RS1,
+            // VMV.X.S, VCPOP, VFIRST
+            0b010 if funct7 >> 1 == 0b010000 => RD,
+            _ => 0,
+        },
+        0b11100 => match funct3 {
+            0b001..=0b011 => RD | RS1, // CSRRW, CSRRS, CSRRC
+            0b101..=0b111 => RD,       // CSRRWI, CSRRSI, CSRRCI
+            _ => 0,
+        },
+        0b11101 => {
+            // OP-P, whose immediate shifts and unary instructions don't
+            // have an rs2
+            let imm = (funct3 == 0b000
+                && (funct7 >> 3 == 0b0111 || funct7 == 0b1010110))
+                || (funct3 == 0b001 && funct7 == 0b0011011);
+            if imm {
+                RD | RS1
+            } else {
+                RD | RS1 | RS2
+            }
+        }
+        _ => 0,
+    };
+    [RD, RS1, RS2, RS3].into_iter().any(|field| {
+        fields & field != 0
+            && (instruction >> field.trailing_zeros()) & 0b11111 >= count
+    })
+}
@@ -0,0 +1,190 @@
+//! A bare-bones environment with every optional extension turned on, for
+//! testing individual instructions.
+
+use super::*;
+
+pub(super) struct TestEnv {
+    pub ram: Vec<u32>,
+    reservation: Option<u32>,
+    entropy: u16,
+    /// Whether the float instructions use the integer registers.
+    pub zfinx: bool,
+    /// Whether Zcmp and Zcmt are enabled, rather than Zcd.
+    pub zcm: bool,
+}
+
+impl TestEnv {
+    /// A kilobyte of RAM, with `program` at address 0.
+    pub fn new(program: &[u32]) -> TestEnv {
+        let mut ram = vec![0; 256];
+        ram[..program.len()].copy_from_slice(program);
+        TestEnv {
+            ram,
+            reservation: None,
+            entropy: 0,
+            zfinx: false,
+            zcm: true,
+        }
+    }
+}
+
+impl ExecutionEnvironment for TestEnv {
+    const SUPPORT_ZBA: bool = true;
+    const SUPPORT_ZBB: bool = true;
+    const SUPPORT_ZBC: bool = true;
+    const SUPPORT_ZBS: bool = true;
+    const SUPPORT_ZBKB: bool = true;
+    const SUPPORT_ZBKC: bool = true;
+    const SUPPORT_ZBKX: bool = true;
+    const SUPPORT_ZKND: bool = true;
+    const SUPPORT_ZKNE: bool = true;
+    const SUPPORT_ZKNH: bool = true;
+    const SUPPORT_ZKSED: bool = true;
+    const SUPPORT_ZKSH: bool = true;
+    const SUPPORT_ZKR: bool = true;
+    const SUPPORT_ZICOND: bool = true;
+    const SUPPORT_ZFH: bool = true;
+    const SUPPORT_ZFA: bool = true;
+    const SUPPORT_ZFINX: bool = true;
+    const SUPPORT_ZDINX: bool = true;
+    const SUPPORT_ZFBFMIN: bool = true;
+    const SUPPORT_ZVE32X: bool = true;
+    const SUPPORT_ZVE32F: bool = true;
+    const SUPPORT_P: bool = true;
+    const SUPPORT_ZCB: bool = true;
+    const SUPPORT_ZCMP: bool = true;
+    const SUPPORT_ZCMT: bool = true;
+    const SUPPORT_ZICBOM: bool = true;
+    const SUPPORT_ZICBOZ: bool = true;
+    const SUPPORT_ZICBOP: bool = true;
+    const SUPPORT_ZACAS: bool = true;
+    const SUPPORT_ZABHA: bool = true;
+    fn read_word(
+        &mut self,
+        address: u32,
+        _mask: u32,
+    ) -> Result<u32, MemoryAccessFailure> {
+        self.ram
+            .get(address as usize / 4)
+            .copied()
+            .ok_or(MemoryAccessFailure::AccessFault)
+    }
+    fn write_word(
+        &mut self,
+        address: u32,
+        data: u32,
+        mask: u32,
+    ) -> Result<(), MemoryAccessFailure> {
+        if self.reservation == Some(address) {
+            self.reservation = None;
+        }
+        let word = self
+            .ram
+            .get_mut(address as usize / 4)
+            .ok_or(MemoryAccessFailure::AccessFault)?;
+        *word = (*word & !mask) | (data & mask);
+        Ok(())
+    }
+    fn load_reserved_word(
+        &mut self,
+        address: u32,
+    ) -> Result<u32, MemoryAccessFailure> {
+        self.reservation = Some(address);
+        self.read_word(address, !0)
+    }
+    fn store_reserved_word(
+        &mut self,
+        address: u32,
+        data: u32,
+    ) -> Result<bool, MemoryAccessFailure> {
+        if self.reservation.take() != Some(address) {
+            return Ok(false);
+        }
+        self.write_word(address, data, !0)?;
+        Ok(true)
+    }
+    fn poll_entropy(&mut self) -> Result<EntropyStatus, ExceptionCause> {
+        // not very random, but very easy to test
+        self.entropy = self.entropy.wrapping_add(1);
+        Ok(EntropyStatus::Es16(self.entropy))
+    }
+    fn perform_custom<F: FloatBits, V: VectorBits, X: IntegerRegisters>(
+        &mut self,
+        cpu: &mut Cpu<F, V, X>,
+        instruction: CustomInstruction,
+    ) -> Result<(), (ExceptionCause, u32)> {
+        // a multiply-accumulate in custom-0, and nothing else
+        if instruction.opcode != 0
+            || instruction.funct3 != 0
+            || instruction.funct7 != 0
+        {
+            return Err((
+                ExceptionCause::IllegalInstruction,
+                instruction.instruction,
+            ));
+        }
+        let product = cpu
+            .get_register(instruction.rs1)
+            .wrapping_mul(cpu.get_register(instruction.rs2));
+        let sum = cpu.get_register(instruction.rd).wrapping_add(product);
+        cpu.set_register(instruction.rd, sum);
+        Ok(())
+    }
+    fn enable_zfinx(&self) -> bool {
+        self.zfinx
+    }
+    fn enable_zcmp(&self) -> bool {
+        self.zcm
+    }
+    fn enable_zcmt(&self) -> bool {
+        self.zcm
+    }
+}
+
+/// Run `program` from address 0 until the PC falls off the end of it,
+/// starting with the given register values.
+pub(super) fn run<F: FloatBits>(
+    program: &[u32],
+    registers: &[(u32, u32)],
+) -> Result<Cpu<F>, Exception> {
+    run_in(TestEnv::new(program), program, registers)
+}
+
+/// As [`run`], but in an environment you've already set up.
+pub(super) fn run_in<F: FloatBits, V: VectorBits, X: IntegerRegisters>(
+    mut env: TestEnv,
+    program: &[u32],
+    registers: &[(u32, u32)],
+) -> Result<Cpu<F, V, X>, Exception> {
+    let mut cpu = Cpu::new();
+    for &(register, value) in registers {
+        cpu.set_register(register, value);
+    }
+    while cpu.get_pc() < program.len() as u32 * 4 {
+        cpu.step(&mut env)?;
+    }
+    Ok(cpu)
+}
+
+/// Run `program` with the given register values, and check that x3, x4,
+/// and so on end up holding `expected`.
+pub(super) fn check<F: FloatBits>(
+    program: &[u32],
+    registers: &[(u32, u32)],
+    expected: &[u32],
+) {
+    let cpu = run::<F>(program, registers).unwrap();
+    assert_registers(&cpu, expected);
+}
+
+/// Check that x3, x4, and so on hold `expected`.
+pub(super) fn assert_registers<F, V, X>(cpu: &Cpu<F, V, X>, expected: &[u32])
+where
+    F: FloatBits,
+    V: VectorBits,
+    X: IntegerRegisters,
+{
+    for (register, &value) in (3..).zip(expected) {
+        assert_eq!(cpu.get_register(register), value, "x{register}");
+    }
+}
@@ -0,0 +1,2320 @@
+//! The embedded vector extensions: Zve32x, and Zve32f on top of it.
+
+use super::*;
+
+#[cfg(feature = "float")]
+use rustc_apfloat::Round;
+
+/// The `vill` bit of `vtype`, which is all that's set after an unsupported
+/// configuration was requested.
+const VILL: u32 = 1 << 31;
+
+/// The mantissas `vfrec7.v` gives, indexed by the top seven bits of the
+/// (normalized) input mantissa.
+#[cfg(feature = "float")]
+const REC7_TABLE: [u8; 128] = [
+    127, 125, 123, 121, 119, 117, 116, 114, 112, 110, 109, 107, 105, 104, 102,
+    100, 99, 97, 96, 94, 93, 91, 90, 88, 87, 85, 84, 83, 81, 80, 79, 77, 76,
+    75, 74, 72, 71, 70, 69, 68, 66, 65, 64, 63, 62, 61, 60, 59, 58, 57, 56,
+    55, 54, 53, 52, 51, 50, 49, 48, 47, 46, 45, 44, 43, 42, 41, 40, 40, 39,
+    38, 37, 36, 35, 35, 34, 33, 32, 31, 31, 30, 29, 28, 28, 27, 26, 25, 25,
+    24, 23, 23, 22, 21, 21, 20, 19, 19, 18, 17, 17, 16, 15, 15, 14, 14, 13,
+    12, 12, 11, 11, 10, 9, 9, 8, 8, 7, 7, 6, 5, 5, 4, 4, 3, 3, 2, 2, 1, 1, 0,
+];
+
+/// The mantissas `vfrsqrt7.v` gives, indexed by the low bit of the
+/// (normalized) input exponent and the top six bits of its mantissa.
+#[cfg(feature = "float")]
+const RSQRT7_TABLE: [u8; 128] = [
+    52, 51, 50, 48, 47, 46, 44, 43, 42, 41, 40, 39, 38, 36, 35, 34, 33, 32,
+    31, 30, 30, 29, 28, 27, 26, 25, 24, 23, 23, 22, 21, 20, 19, 19, 18, 17,
+    16, 16, 15, 14, 14, 13, 12, 12, 11, 10, 10, 9, 9, 8, 7, 7, 6, 6, 5, 4, 4,
+    3, 3, 2, 2, 1, 1, 0, 127, 125, 123, 121, 119, 118, 116, 114, 113, 111,
+    109, 108, 106, 105, 103, 102, 100, 99, 97, 96, 95, 93, 92, 91, 90, 88, 87,
+    86, 85, 84, 83, 82, 80, 79, 78, 77, 76, 75, 74, 73, 72, 71, 70, 70, 69,
+    68, 67, 66, 65, 64, 63, 63, 62, 61, 60, 59, 59, 58, 57, 56, 56, 55, 54,
+    53,
+];
+
+/// This trait is implemented by all possible `V` type parameters of
+/// [`Cpu<F, V, X>`](super::Cpu). You should not try to implement it yourself.
+/// Instead, you should use one of the provided implementations.
+///
+/// - `()`: no vector support
+/// - `[u8; N]`: 32 vector registers of `N` bytes each, i.e. `VLEN` is `8 * N`
+///   bits. `N` must be a power of two, and at least 4.
+///
+/// Nothing inside this trait is considered part of the public-facing API.
+/// Non-compatible changes may be made even on patch releases. Depend on this
+/// trait's innards at your own risk.
+pub trait VectorBits: Copy {
+    /// The size of one vector register in bytes, as read from `vlenb`. Zero
+    /// if there are no vector registers.
+    const VLENB: u32;
+    /// Type that should be used to store the vector CSRs; [`VectorCsrs`] if
+    /// vectors are supported, `()` if not.
+    type CsrType: Copy;
+    /// A vector register full of zeroes.
+    fn zero() -> Self;
+    /// Default value of the vector CSRs.
+    fn default_csr() -> Self::CsrType;
+    /// Extract the current value of the vector CSRs.
+    fn read_csr(csr: &Self::CsrType) -> VectorCsrs;
+    /// Overwrite the current value of the vector CSRs.
+    fn write_csr(csr: &mut Self::CsrType, value: VectorCsrs);
+    /// The bytes of this register, element 0 first.
+    fn bytes(&self) -> &[u8];
+    /// The bytes of this register, element 0 first.
+    fn bytes_mut(&mut self) -> &mut [u8];
+}
+
+/// The vector CSRs: `vstart`, `vl`, `vtype`, and the fixed point `vxrm` and
+/// `vxsat`. (`vlenb` is a constant, and `vcsr` is just `vxrm` and `vxsat`.)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VectorCsrs {
+    vstart: u32,
+    vl: u32,
+    vtype: u32,
+    vxrm: u8,
+    /// Shared with the P extension's OV flag.
+    pub(super) vxsat: bool,
+}
+
+impl Default for VectorCsrs {
+    fn default() -> Self {
+        VectorCsrs {
+            vstart: 0,
+            vl: 0,
+            vtype: VILL,
+            vxrm: 0,
+            vxsat: false,
+        }
+    }
+}
+
+impl VectorBits for () {
+    const VLENB: u32 = 0;
+    type CsrType = ();
+    fn zero() {}
+    fn default_csr() {}
+    fn read_csr(_: &()) -> VectorCsrs {
+        VectorCsrs::default()
+    }
+    fn write_csr(_: &mut (), _value: VectorCsrs) {}
+    fn bytes(&self) -> &[u8] {
+        &[]
+    }
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        &mut []
+    }
+}
+
+impl<const N: usize> VectorBits for [u8; N] {
+    const VLENB: u32 = {
+        assert!(
+            N >= 4 && N.is_power_of_two(),
+            "VLEN must be a power of two, and at least 32 bits"
+        );
+        N as u32
+    };
+    type CsrType = VectorCsrs;
+    fn zero() -> Self {
+        [0; N]
+    }
+    fn default_csr() -> VectorCsrs {
+        VectorCsrs::default()
+    }
+    fn read_csr(csr: &VectorCsrs) -> VectorCsrs {
+        *csr
+    }
+    fn write_csr(csr: &mut VectorCsrs, value: VectorCsrs) {
+        *csr = value
+    }
+    fn bytes(&self) -> &[u8] {
+        self
+    }
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        self
+    }
+}
+
+/// A valid `vtype`, decoded.
+#[derive(Clone, Copy)]
+struct VType {
+    /// SEW, in bytes.
+    sew: u32,
+    /// log2(LMUL), from -3 to 3.
+    lmul: i32,
+}
+
+impl VType {
+    /// Decode a `vtype` value, or return `None` if it's one we don't support.
+    /// (The agnostic bits are accepted, and treated as undisturbed.)
+    fn decode(vtype: u32) -> Option<VType> {
+        // catches `vill` and the reserved bits
+        if vtype & !0xFF != 0 {
+            return None;
+        }
+        let vsew = (vtype >> 3) & 0b111;
+        // ELEN is 32
+        if vsew > 0b010 {
+            return None;
+        }
+        let lmul = match vtype & 0b111 {
+            0b100 => return None,
+            x => ((x as i32) << 29) >> 29,
+        };
+        let sew = 1 << vsew;
+        // a fractional LMUL must still fit one element of SEW at ELEN
+        if lmul < 0 && sew * 8 > 32 >> -lmul {
+            return None;
+        }
+        Some(VType { sew, lmul })
+    }
+    /// The most elements a register group can hold in this configuration.
+    fn vlmax<V: VectorBits>(self) -> u32 {
+        scale(V::VLENB / self.sew, self.lmul)
+    }
+    /// log2(EMUL) for an operand of `eew` bytes, or `None` if that's out of
+    /// range.
+    fn emul(self, eew: u32) -> Option<i32> {
+        let emul = self.lmul + eew.trailing_zeros() as i32
+            - self.sew.trailing_zeros() as i32;
+        (-3..=3).contains(&emul).then_some(emul)
+    }
+}
+
+/// `x` times 2^`log2`.
+fn scale(x: u32, log2: i32) -> u32 {
+    if log2 >= 0 {
+        x << log2
+    } else {
+        x >> -log2
+    }
+}
+
+/// The number of registers in a group with the given log2(EMUL).
+fn regs(emul: i32) -> u32 {
+    1 << emul.max(0)
+}
+
+/// True if a group with the given log2(EMUL) may start at `reg`.
+fn aligned(reg: u32, emul: i32) -> bool {
+    reg.is_multiple_of(regs(emul))
+}
+
+fn overlaps(a: u32, a_regs: u32, b: u32, b_regs: u32) -> bool {
+    a < b + b_regs && b < a + a_regs
+}
+
+/// True if a destination group may share registers with a narrower source
+/// group: only if the source is at least one whole register, and sits in the
+/// highest-numbered part of the destination.
+fn widen_ok(vd: u32, dst_emul: i32, vs: u32, src_emul: i32) -> bool {
+    !overlaps(vd, regs(dst_emul), vs, regs(src_emul))
+        || (src_emul >= 0 && vs == vd + regs(dst_emul) - regs(src_emul))
+}
+
+/// True if a destination group may share registers with a wider source
+/// group: only if it sits in the lowest-numbered part of the source.
+fn narrow_ok(vd: u32, dst_emul: i32, vs: u32, src_emul: i32) -> bool {
+    !overlaps(vd, regs(dst_emul), vs, regs(src_emul)) || vd == vs
+}
+
+/// All ones, `eew` bytes wide.
+fn ones(eew: u32) -> u32 {
+    u32::MAX >> (32 - eew * 8)
+}
+
+/// Sign extend an element of `eew` bytes.
+fn sext(value: u32, eew: u32) -> i32 {
+    let shift = 32 - eew * 8;
+    ((value << shift) as i32) >> shift
+}
+
+/// Shift right by `d` bits, rounding according to `vxrm`. (Unsigned values
+/// are passed as non-negative.)
+fn roundoff(v: i64, d: u32, vxrm: u8) -> i64 {
+    if d == 0 {
+        return v;
+    }
+    let bit = |n: u32| (v >> n) & 1;
+    let below = |n: u32| v & ((1 << n) - 1) != 0;
+    let r = match vxrm {
+        // round to nearest, ties up
+        0 => bit(d - 1),
+        // round to nearest, ties to even
+        1 => bit(d - 1) & (below(d - 1) || bit(d) != 0) as i64,
+        // round down
+        2 => 0,
+        // round to odd
+        _ => (bit(d) == 0 && below(d)) as i64,
+    };
+    (v >> d) + r
+}
+
+/// Clamp a signed value to `eew` bytes, noting whether it saturated.
+fn clamp_signed(v: i64, eew: u32, sat: &mut bool) -> u32 {
+    let max = (ones(eew) >> 1) as i64;
+    let min = -max - 1;
+    if v > max {
+        *sat = true;
+        max as u32
+    } else if v < min {
+        *sat = true;
+        min as u32 & ones(eew)
+    } else {
+        v as u32 & ones(eew)
+    }
+}
+
+/// Clamp an unsigned value to `eew` bytes, noting whether it saturated.
+fn clamp_unsigned(v: i64, eew: u32, sat: &mut bool) -> u32 {
+    if v > ones(eew) as i64 {
+        *sat = true;
+        ones(eew)
+    } else if v < 0 {
+        *sat = true;
+        0
+    } else {
+        v as u32
+    }
+}
+
+#[cfg(feature = "float")]
+fn round_mode(frm: u32) -> Option<Round> {
+    match frm {
+        0b000 => Some(Round::NearestTiesToEven),
+        0b001 => Some(Round::TowardZero),
+        0b010 => Some(Round::TowardNegative),
+        0b011 => Some(Round::TowardPositive),
+        0b100 => Some(Round::NearestTiesToAway),
+        _ => None,
+    }
+}
+
+/// The bits of a single precision result, with any NaN made canonical, and
+/// its exception flags added to `flags`.
+#[cfg(feature = "float")]
+fn single_bits(x: StatusAnd<Single>, flags: &mut u8) -> u32 {
+    *flags |= float::status_flags(x.status);
+    if x.value.is_nan() {
+        float::CANON_NAN_32
+    } else {
+        x.value.to_bits() as u32
+    }
+}
+
+/// Convert a single to an integer of `eew` bytes, saturating.
+#[cfg(feature = "float")]
+fn single_to_int(
+    a: Single,
+    signed: bool,
+    eew: u32,
+    round: Round,
+    flags: &mut u8,
+) -> u32 {
+    let bits = eew as usize * 8;
+    let mut exact = false;
+    if signed {
+        let max = (ones(eew) >> 1) as i128;
+        let v = if a.is_nan() {
+            *flags |= INVALID_FLAG;
+            max
+        } else {
+            let v = a.to_i128_r(bits, round, &mut exact);
+            *flags |= float::status_flags(v.status);
+            v.value
+        };
+        v.clamp(-max - 1, max) as u32 & ones(eew)
+    } else {
+        let v = if a.is_nan() {
+            *flags |= INVALID_FLAG;
+            u128::MAX
+        } else {
+            let v = a.to_u128_r(bits, round, &mut exact);
+            *flags |= float::status_flags(v.status);
+            v.value
+        };
+        v.min(ones(eew) as u128) as u32
+    }
+}
+
+/// Split a positive, finite, nonzero single into its exponent and 23-bit
+/// mantissa, normalizing subnormals. (The exponent of a subnormal comes out
+/// zero or negative.)
+#[cfg(feature = "float")]
+fn normalize(bits: u32) -> (i32, u32) {
+    let exp = ((bits >> 23) & 0xFF) as i32;
+    let sig = bits & 0x7FFFFF;
+    if exp != 0 {
+        (exp, sig)
+    } else {
+        let shift = sig.leading_zeros() - 8;
+        (1 - shift as i32, (sig << shift) & 0x7FFFFF)
+    }
+}
+
+/// `vfrec7.v`: an estimate of 1/x, good to seven bits.
+#[cfg(feature = "float")]
+fn rec7(bits: u32, round: Round, flags: &mut u8) -> u32 {
+    let a = float::to_single(bits);
+    let sign = bits & (1 << 31);
+    if a.is_nan() {
+        if a.is_signaling() {
+            *flags |= INVALID_FLAG;
+        }
+        return float::CANON_NAN_32;
+    } else if a.is_infinite() {
+        return sign;
+    } else if a.is_zero() {
+        *flags |= ZERO_DIV_FLAG;
+        return sign | 0x7F800000;
+    }
+    let (exp, sig) = normalize(bits);
+    if exp < -1 {
+        // too small to have a finite reciprocal
+        *flags |= OVERFLOW_FLAG | INEXACT_FLAG;
+        let to_infinity = match round {
+            Round::TowardZero => false,
+            Round::TowardNegative => sign != 0,
+            Round::TowardPositive => sign == 0,
+            _ => true,
+        };
+        return if to_infinity {
+            sign | 0x7F800000
+        } else {
+            sign | 0x7F7FFFFF
+        };
+    }
+    let mut out_exp = 253 - exp;
+    let mut out_sig = (REC7_TABLE[(sig >> 16) as usize] as u32) << 16;
+    if out_exp <= 0 {
+        // the result is subnormal
+        out_sig = ((1 << 23) | out_sig) >> (1 - out_exp);
+        out_exp = 0;
+    }
+    sign | (out_exp as u32) << 23 | out_sig
+}
+
+/// `vfrsqrt7.v`: an estimate of 1/sqrt(x), good to seven bits.
+#[cfg(feature = "float")]
+fn rsqrt7(bits: u32, flags: &mut u8) -> u32 {
+    let a = float::to_single(bits);
+    if a.is_zero() {
+        *flags |= ZERO_DIV_FLAG;
+        return bits | 0x7F800000;
+    } else if a.is_nan() || a.is_negative() {
+        if !a.is_nan() || a.is_signaling() {
+            *flags |= INVALID_FLAG;
+        }
+        return float::CANON_NAN_32;
+    } else if a.is_infinite() {
+        return 0;
+    }
+    let (exp, sig) = normalize(bits);
+    let index = ((exp & 1) << 6) as u32 | sig >> 17;
+    let out_exp = ((380 - exp) / 2) as u32;
+    out_exp << 23 | (RSQRT7_TABLE[index as usize] as u32) << 16
+}
+
+/// The second operand of an arithmetic instruction.
+#[derive(Clone, Copy)]
+enum Operand {
+    /// A vector register group, `vs1`.
+    Vector(u32),
+    /// A scalar (or immediate), the same for every element.
+    Scalar(u32),
+}
+
+/// The fields of an arithmetic instruction, and the configuration it runs
+/// under.
+#[derive(Clone, Copy)]
+struct Args {
+    funct6: u32,
+    /// False if the instruction is masked by `v0`.
+    vm: bool,
+    vs2: u32,
+    /// The raw `vs1`/`rs1`/immediate field.
+    rs1: u32,
+    b: Operand,
+    vd: u32,
+    vt: VType,
+    vl: u32,
+}
+
+impl Args {
+    /// True if every operand of a single-width instruction is an aligned
+    /// group, and the destination doesn't clobber the mask.
+    fn single_width_ok(&self) -> bool {
+        let lmul = self.vt.lmul;
+        aligned(self.vd, lmul)
+            && aligned(self.vs2, lmul)
+            && match self.b {
+                Operand::Vector(vs1) => aligned(vs1, lmul),
+                Operand::Scalar(_) => true,
+            }
+            && (self.vm || self.vd != 0)
+    }
+    /// True if the sources are aligned groups, and the mask destination
+    /// `vd` only overlaps them where it's allowed to.
+    fn mask_dest_ok(&self) -> bool {
+        let lmul = self.vt.lmul;
+        aligned(self.vs2, lmul)
+            && narrow_ok(self.vd, 0, self.vs2, lmul)
+            && match self.b {
+                Operand::Vector(vs1) => {
+                    aligned(vs1, lmul) && narrow_ok(self.vd, 0, vs1, lmul)
+                }
+                Operand::Scalar(_) => true,
+            }
+    }
+    /// True if the operands of a widening instruction are valid. `wide_vs2`
+    /// is true if `vs2` is as wide as the destination.
+    fn widening_ok(&self, wide_vs2: bool) -> bool {
+        let lmul = self.vt.lmul;
+        let wide = lmul + 1;
+        self.vt.sew <= 2
+            && wide <= 3
+            && aligned(self.vd, wide)
+            && if wide_vs2 {
+                aligned(self.vs2, wide)
+            } else {
+                aligned(self.vs2, lmul)
+                    && widen_ok(self.vd, wide, self.vs2, lmul)
+            }
+            && match self.b {
+                Operand::Vector(vs1) => {
+                    aligned(vs1, lmul) && widen_ok(self.vd, wide, vs1, lmul)
+                }
+                Operand::Scalar(_) => true,
+            }
+            && (self.vm || self.vd != 0)
+    }
+    /// True if the operands of a narrowing instruction (whose `vs2` is twice
+    /// as wide as everything else) are valid.
+    fn narrowing_ok(&self) -> bool {
+        let lmul = self.vt.lmul;
+        let wide = lmul + 1;
+        self.vt.sew <= 2
+            && wide <= 3
+            && aligned(self.vd, lmul)
+            && aligned(self.vs2, wide)
+            && narrow_ok(self.vd, lmul, self.vs2, wide)
+            && match self.b {
+                Operand::Vector(vs1) => aligned(vs1, lmul),
+                Operand::Scalar(_) => true,
+            }
+            && (self.vm || self.vd != 0)
+    }
+    /// True if `vd` doesn't overlap `vs2` (or `vs1`) at all, as permutations
+    /// require.
+    fn disjoint(&self, vs1_emul: i32) -> bool {
+        let group = regs(self.vt.lmul);
+        !overlaps(self.vd, group, self.vs2, group)
+            && match self.b {
+                Operand::Vector(vs1) => {
+                    !overlaps(self.vd, group, vs1, regs(vs1_emul))
+                }
+                Operand::Scalar(_) => true,
+            }
+    }
+}
+
+/// True if vector instructions are allowed at all.
+fn zve32x<Env: ExecutionEnvironment, V: VectorBits>(env: &Env) -> bool {
+    V::VLENB != 0 && Env::SUPPORT_ZVE32X && env.enable_zve32x()
+}
+
+impl<F: FloatBits, V: VectorBits, X: IntegerRegisters> Cpu<F, V, X> {
+    /// Element `index` of the group starting at `reg`, `eew` bytes wide.
+    fn velement(&self, reg: u32, index: u32, eew: u32) -> u32 {
+        let offset = index * eew;
+        let bytes =
+            self.vector_registers[(reg + offset / V::VLENB) as usize].bytes();
+        let at = (offset % V::VLENB) as usize;
+        let mut buf = [0; 4];
+        buf[..eew as usize].copy_from_slice(&bytes[at..at + eew as usize]);
+        u32::from_le_bytes(buf)
+    }
+    fn set_velement(&mut self, reg: u32, index: u32, eew: u32, value: u32) {
+        let offset = index * eew;
+        let bytes = self.vector_registers[(reg + offset / V::VLENB) as usize]
+            .bytes_mut();
+        let at = (offset % V::VLENB) as usize;
+        bytes[at..at + eew as usize]
+            .copy_from_slice(&value.to_le_bytes()[..eew as usize]);
+    }
+    /// Bit `index` of the mask register `reg`.
+    fn vmask(&self, reg: u32, index: u32) -> bool {
+        let bytes = self.vector_registers[reg as usize].bytes();
+        bytes[(index / 8) as usize] & (1 << (index % 8)) != 0
+    }
+    fn set_vmask(&mut self, reg: u32, index: u32, value: bool) {
+        let bytes = self.vector_registers[reg as usize].bytes_mut();
+        let byte = &mut bytes[(index / 8) as usize];
+        if value {
+            *byte |= 1 << (index % 8);
+        } else {
+            *byte &= !(1 << (index % 8));
+        }
+    }
+    /// True if element `index` isn't masked off.
+    fn vactive(&self, vm: bool, index: u32) -> bool {
+        vm || self.vmask(0, index)
+    }
+    /// Element `index` of the second operand.
+    fn vb(&self, b: Operand, index: u32, eew: u32) -> u32 {
+        match b {
+            Operand::Vector(vs1) => self.velement(vs1, index, eew),
+            Operand::Scalar(x) => x & ones(eew),
+        }
+    }
+    /// Set every active element of `vd` below `vl` to `op(element index)`.
+    fn vmap(
+        &mut self,
+        a: &Args,
+        eew: u32,
+        mut op: impl FnMut(&Self, u32) -> u32,
+    ) {
+        for i in 0..a.vl {
+            if self.vactive(a.vm, i) {
+                let value = op(self, i);
+                self.set_velement(a.vd, i, eew, value);
+            }
+        }
+    }
+    /// As `vmap`, but `vd` is a mask register.
+    fn vmap_mask(&mut self, a: &Args, mut op: impl FnMut(&Self, u32) -> bool) {
+        for i in 0..a.vl {
+            if self.vactive(a.vm, i) {
+                let value = op(self, i);
+                self.set_vmask(a.vd, i, value);
+            }
+        }
+    }
+    /// `vd[i] = op(vs2[i], b[i])`, all SEW wide.
+    fn binary(
+        &mut self,
+        a: &Args,
+        mut op: impl FnMut(u32, u32) -> u32,
+    ) -> Option<()> {
+        if !a.single_width_ok() {
+            return None;
+        }
+        let sew = a.vt.sew;
+        self.vmap(a, sew, |cpu, i| {
+            op(cpu.velement(a.vs2, i, sew), cpu.vb(a.b, i, sew)) & ones(sew)
+        });
+        Some(())
+    }
+    /// `vd[i] = op(vs2[i], b[i], vd[i])`, all SEW wide.
+    fn ternary(
+        &mut self,
+        a: &Args,
+        mut op: impl FnMut(u32, u32, u32) -> u32,
+    ) -> Option<()> {
+        if !a.single_width_ok() {
+            return None;
+        }
+        let sew = a.vt.sew;
+        self.vmap(a, sew, |cpu, i| {
+            let d = cpu.velement(a.vd, i, sew);
+            op(cpu.velement(a.vs2, i, sew), cpu.vb(a.b, i, sew), d) & ones(sew)
+        });
+        Some(())
+    }
+    /// Mask bit `vd[i] = op(vs2[i], b[i])`.
+    fn compare(
+        &mut self,
+        a: &Args,
+        mut op: impl FnMut(u32, u32) -> bool,
+    ) -> Option<()> {
+        if !a.mask_dest_ok() {
+            return None;
+        }
+        let sew = a.vt.sew;
+        self.vmap_mask(a, |cpu, i| {
+            op(cpu.velement(a.vs2, i, sew), cpu.vb(a.b, i, sew))
+        });
+        Some(())
+    }
+    /// `vd[i] = op(vs2[i], b[i], vd[i])`, where `vd` is 2*SEW wide, and so is
+    /// `vs2` if `wide_vs2`.
+    fn widening(
+        &mut self,
+        a: &Args,
+        wide_vs2: bool,
+        mut op: impl FnMut(u32, u32, u32) -> u32,
+    ) -> Option<()> {
+        if !a.widening_ok(wide_vs2) {
+            return None;
+        }
+        let sew = a.vt.sew;
+        let src_eew = if wide_vs2 { sew * 2 } else { sew };
+        self.vmap(a, sew * 2, |cpu, i| {
+            let d = cpu.velement(a.vd, i, sew * 2);
+            op(cpu.velement(a.vs2, i, src_eew), cpu.vb(a.b, i, sew), d)
+                & ones(sew * 2)
+        });
+        Some(())
+    }
+    /// `vd[i] = op(vs2[i], b[i])`, where `vs2` is 2*SEW wide.
+    fn narrowing(
+        &mut self,
+        a: &Args,
+        mut op: impl FnMut(u32, u32) -> u32,
+    ) -> Option<()> {
+        if !a.narrowing_ok() {
+            return None;
+        }
+        let sew = a.vt.sew;
+        self.vmap(a, sew, |cpu, i| {
+            op(cpu.velement(a.vs2, i, sew * 2), cpu.vb(a.b, i, sew))
+                & ones(sew)
+        });
+        Some(())
+    }
+    /// `vd[0] = op(...op(op(vs1[0], vs2[0]), vs2[1])..., vs2[vl-1])`, over
+    /// the active elements of `vs2`. The accumulator is `dst_eew` bytes wide,
+    /// and is left alone if `vl` is zero.
+    fn reduce(
+        &mut self,
+        a: &Args,
+        dst_eew: u32,
+        mut op: impl FnMut(u32, u32) -> u32,
+    ) -> Option<()> {
+        let Operand::Vector(vs1) = a.b else {
+            return None;
+        };
+        if !aligned(a.vs2, a.vt.lmul) {
+            return None;
+        }
+        let mut acc = self.velement(vs1, 0, dst_eew);
+        for i in 0..a.vl {
+            if self.vactive(a.vm, i) {
+                acc =
+                    op(acc, self.velement(a.vs2, i, a.vt.sew)) & ones(dst_eew);
+            }
+        }
+        if a.vl > 0 {
+            self.set_velement(a.vd, 0, dst_eew, acc);
+        }
+        Some(())
+    }
+    /// `vmerge`, or `vmv.v` if unmasked.
+    fn merge(&mut self, a: &Args) -> Option<()> {
+        if !a.single_width_ok() || (a.vm && a.vs2 != 0) {
+            return None;
+        }
+        let sew = a.vt.sew;
+        for i in 0..a.vl {
+            let value = if a.vm || self.vmask(0, i) {
+                self.vb(a.b, i, sew)
+            } else {
+                self.velement(a.vs2, i, sew)
+            };
+            self.set_velement(a.vd, i, sew, value);
+        }
+        Some(())
+    }
+    /// `vslideup`/`vslidedown`, by `offset` elements.
+    fn slide(&mut self, a: &Args, up: bool, offset: u32) -> Option<()> {
+        if !a.single_width_ok() || (up && !a.disjoint(a.vt.lmul)) {
+            return None;
+        }
+        let sew = a.vt.sew;
+        let vlmax = a.vt.vlmax::<V>();
+        for i in 0..a.vl {
+            if (up && i < offset) || !self.vactive(a.vm, i) {
+                continue;
+            }
+            let value = if up {
+                self.velement(a.vs2, i - offset, sew)
+            } else if (i as u64 + offset as u64) < vlmax as u64 {
+                self.velement(a.vs2, i + offset, sew)
+            } else {
+                0
+            };
+            self.set_velement(a.vd, i, sew, value);
+        }
+        Some(())
+    }
+    /// `vslide1up`/`vslide1down`, shifting in `value`.
+    fn slide1(&mut self, a: &Args, up: bool, value: u32) -> Option<()> {
+        if !a.single_width_ok() || (up && !a.disjoint(a.vt.lmul)) {
+            return None;
+        }
+        let sew = a.vt.sew;
+        let vl = a.vl;
+        self.vmap(a, sew, |cpu, i| {
+            if up && i == 0 || !up && i + 1 == vl {
+                value & ones(sew)
+            } else if up {
+                cpu.velement(a.vs2, i - 1, sew)
+            } else {
+                cpu.velement(a.vs2, i + 1, sew)
+            }
+        });
+        Some(())
+    }
+    /// `vrgather`, with indices `index_eew` bytes wide if they come from
+    /// `vs1`.
+    fn gather(&mut self, a: &Args, index_eew: u32) -> Option<()> {
+        let index_emul = a.vt.emul(index_eew)?;
+        if let Operand::Vector(vs1) = a.b {
+            if !aligned(vs1, index_emul) {
+                return None;
+            }
+        }
+        if !a.single_width_ok() || !a.disjoint(index_emul) {
+            return None;
+        }
+        let sew = a.vt.sew;
+        let vlmax = a.vt.vlmax::<V>();
+        self.vmap(a, sew, |cpu, i| {
+            let index = match a.b {
+                Operand::Vector(vs1) => cpu.velement(vs1, i, index_eew),
+                Operand::Scalar(x) => x,
+            };
+            if index < vlmax {
+                cpu.velement(a.vs2, index, sew)
+            } else {
+                0
+            }
+        });
+        Some(())
+    }
+    /// Decode the fields of an arithmetic instruction. `scalar` is the
+    /// operand used by the `.vx`, `.vi`, and `.vf` forms. Returns `None` if
+    /// `vtype` is invalid, or if the instruction would have to resume from a
+    /// nonzero `vstart`, which we don't support.
+    fn vargs(&self, instruction: u32, scalar: u32) -> Option<Args> {
+        let csrs = V::read_csr(&self.vcsr);
+        let vt = VType::decode(csrs.vtype)?;
+        if csrs.vstart != 0 {
+            return None;
+        }
+        let rs1 = (instruction >> 15) & 0b11111;
+        let funct3 = (instruction >> 12) & 0b111;
+        Some(Args {
+            funct6: instruction >> 26,
+            vm: instruction & (1 << 25) != 0,
+            vs2: (instruction >> 20) & 0b11111,
+            rs1,
+            b: if funct3 < 0b011 {
+                Operand::Vector(rs1)
+            } else {
+                Operand::Scalar(scalar)
+            },
+            vd: (instruction >> 7) & 0b11111,
+            vt,
+            vl: csrs.vl,
+        })
+    }
+    /// Execute a vector load (`LOAD-FP`) or store (`STORE-FP`).
+    pub(super) fn vector_memory<Env: ExecutionEnvironment>(
+        &mut self,
+        env: &mut Env,
+        instruction: u32,
+        store: bool,
+    ) -> Result<(), (ExceptionCause, u32)> {
+        let illegal = (ExceptionCause::IllegalInstruction, instruction);
+        if env.read_vs() == ExtensionStatus::Disabled {
+            return Err(illegal);
+        }
+        let vd = (instruction >> 7) & 0b11111;
+        let rs1 = (instruction >> 15) & 0b11111;
+        // rs2, vs2, or the unit-stride sub-opcode
+        let rs2 = (instruction >> 20) & 0b11111;
+        let vm = instruction & (1 << 25) != 0;
+        let mop = (instruction >> 26) & 0b11;
+        let mew = instruction & (1 << 28) != 0;
+        let nf = (instruction >> 29) + 1;
+        let eew = match (instruction >> 12) & 0b111 {
+            0b000 => 1,
+            0b101 => 2,
+            0b110 => 4,
+            _ => return Err(illegal),
+        };
+        if mew {
+            return Err(illegal);
+        }
+        let mut csrs = V::read_csr(&self.vcsr);
+        let vt = VType::decode(csrs.vtype);
+        // (data EEW, log2(data EMUL), fields, elements, fault-only-first)
+        let (data_eew, emul, fields, evl, first_only) = match (mop, rs2) {
+            (0b00, 0b01000) => {
+                // whole register: nf is the number of registers
+                if !vm || !nf.is_power_of_two() || (store && eew != 1) {
+                    return Err(illegal);
+                }
+                let emul = nf.trailing_zeros() as i32;
+                (eew, emul, 1, nf * V::VLENB / eew, false)
+            }
+            (0b00, 0b01011) => {
+                // mask
+                if !vm || nf != 1 || eew != 1 || vt.is_none() {
+                    return Err(illegal);
+                }
+                (1, 0, 1, csrs.vl.div_ceil(8), false)
+            }
+            (0b00, 0b00000) | (0b10, _) => {
+                // unit stride, or strided
+                let emul = vt.and_then(|vt| vt.emul(eew)).ok_or(illegal)?;
+                (eew, emul, nf, csrs.vl, false)
+            }
+            (0b00, 0b10000) if !store => {
+                // fault-only-first
+                let emul = vt.and_then(|vt| vt.emul(eew)).ok_or(illegal)?;
+                (eew, emul, nf, csrs.vl, true)
+            }
+            (0b01 | 0b11, _) => {
+                // indexed: the data is SEW wide, the indices EEW
+                let vt = vt.ok_or(illegal)?;
+                let index_emul = vt.emul(eew).ok_or(illegal)?;
+                if !aligned(rs2, index_emul) {
+                    return Err(illegal);
+                }
+                if !store {
+                    let group = regs(vt.lmul) * nf;
+                    let index_regs = regs(index_emul);
+                    let ok = !overlaps(vd, group, rs2, index_regs)
+                        || (nf == 1
+                            && if vt.sew < eew {
+                                narrow_ok(vd, vt.lmul, rs2, index_emul)
+                            } else if vt.sew > eew {
+                                widen_ok(vd, vt.lmul, rs2, index_emul)
+                            } else {
+                                true
+                            });
+                    if !ok {
+                        return Err(illegal);
+                    }
+                }
+                (vt.sew, vt.lmul, nf, csrs.vl, false)
+            }
+            _ => return Err(illegal),
+        };
+        let field_regs = regs(emul);
+        if !aligned(vd, emul)
+            || field_regs * fields > 8
+            || vd + field_regs * fields > 32
+            || (!store && !vm && vd == 0)
+        {
+            return Err(illegal);
+        }
+        let base = self.get_register(rs1);
+        let stride = self.get_register(rs2);
+        'elements: for i in csrs.vstart..evl {
+            if !self.vactive(vm, i) {
+                continue;
+            }
+            for field in 0..fields {
+                let offset = match mop {
+                    0b00 => (i * fields + field) * data_eew,
+                    0b10 => i.wrapping_mul(stride) + field * data_eew,
+                    _ => self
+                        .velement(rs2, i, eew)
+                        .wrapping_add(field * data_eew),
+                };
+                let address = base.wrapping_add(offset);
+                let reg = vd + field * field_regs;
+                if store {
+                    let value = self.velement(reg, i, data_eew);
+                    let result = match data_eew {
+                        1 => env.write_byte(address, value as u8),
+                        2 => env.write_half(address, value as u16),
+                        _ => env.write_word(address, value, !0),
+                    };
+                    if let Err(failure) = result {
+                        csrs.vstart = i;
+                        V::write_csr(&mut self.vcsr, csrs);
+                        return map_store(address, Err(failure));
+                    }
+                    env.account_memory_store(address);
+                } else {
+                    let result = match data_eew {
+                        1 => env.read_byte(address).map(|x| x as u32),
+                        2 => env.read_half(address).map(|x| x as u32),
+                        _ => env.read_word(address, !0),
+                    };
+                    match result {
+                        Ok(value) => {
+                            self.set_velement(reg, i, data_eew, value);
+                            env.account_memory_load(address);
+                        }
+                        Err(_) if first_only && i > 0 => {
+                            // only the first element may trap; the rest
+                            // just shorten vl
+                            csrs.vl = i;
+                            break 'elements;
+                        }
+                        Err(failure) => {
+                            csrs.vstart = i;
+                            V::write_csr(&mut self.vcsr, csrs);
+                            env.write_vs(ExtensionStatus::Dirty);
+                            return map_load(address, Err(failure));
+                        }
+                    }
+                }
+            }
+        }
+        csrs.vstart = 0;
+        V::write_csr(&mut self.vcsr, csrs);
+        env.write_vs(ExtensionStatus::Dirty);
+        Ok(())
+    }
+    /// Execute an `OP-V` instruction: configuration, or arithmetic.
+    pub(super) fn vector_op<Env: ExecutionEnvironment>(
+        &mut self,
+        env: &mut Env,
+        instruction: u32,
+    ) -> Result<(), (ExceptionCause, u32)> {
+        let ok = if env.read_vs() == ExtensionStatus::Disabled {
+            None
+        } else {
+            match (instruction >> 12) & 0b111 {
+                0b111 => self.vset(env, instruction),
+                0b000 | 0b011 | 0b100 => self.opi(instruction),
+                0b010 | 0b110 => self.opm(instruction),
+                #[cfg(feature = "float")]
+                0b001 | 0b101 => self.opf(env, instruction),
+                _ => None,
+            }
+            .map(|()| {
+                if (instruction >> 12) & 0b111 != 0b111 {
+                    env.write_vs(ExtensionStatus::Dirty);
+                    env.account_vector_op(V::read_csr(&self.vcsr).vl);
+                }
+            })
+        };
+        ok.ok_or((ExceptionCause::IllegalInstruction, instruction))
+    }
+    /// `vsetvli`, `vsetivli`, and `vsetvl`.
+    fn vset<Env: ExecutionEnvironment>(
+        &mut self,
+        env: &mut Env,
+        instruction: u32,
+    ) -> Option<()> {
+        let rd = (instruction >> 7) & 0b11111;
+        let rs1 = (instruction >> 15) & 0b11111;
+        let (vtype, immediate_avl) = if instruction >> 31 == 0 {
+            // VSETVLI
+            ((instruction >> 20) & 0x7FF, None)
+        } else if instruction >> 30 == 0b11 {
+            // VSETIVLI
+            ((instruction >> 20) & 0x3FF, Some(rs1))
+        } else if instruction >> 25 == 0b1000000 {
+            // VSETVL
+            (self.get_register((instruction >> 20) & 0b11111), None)
+        } else {
+            return None;
+        };
+        let mut csrs = V::read_csr(&self.vcsr);
+        let avl = match immediate_avl {
+            Some(avl) => avl,
+            None if rs1 != 0 => self.get_register(rs1),
+            None if rd != 0 => u32::MAX,
+            // keep the current vl
+            None => csrs.vl,
+        };
+        match VType::decode(vtype) {
+            Some(vt) => {
+                csrs.vtype = vtype;
+                csrs.vl = avl.min(vt.vlmax::<V>());
+            }
+            None => {
+                csrs.vtype = VILL;
+                csrs.vl = 0;
+            }
+        }
+        csrs.vstart = 0;
+        V::write_csr(&mut self.vcsr, csrs);
+        self.set_register(rd, csrs.vl);
+        env.write_vs(ExtensionStatus::Dirty);
+        env.account_generic_op();
+        Some(())
+    }
+    /// `vmv<nr>r.v`, which doesn't care about `vtype`.
+    fn whole_move(&mut self, instruction: u32) -> Option<()> {
+        let vd = (instruction >> 7) & 0b11111;
+        let vs2 = (instruction >> 20) & 0b11111;
+        let nr = ((instruction >> 15) & 0b11111) + 1;
+        let vm = instruction & (1 << 25) != 0;
+        if !vm || !nr.is_power_of_two() || nr > 8 || vd % nr + vs2 % nr != 0 {
+            return None;
+        }
+        let mut csrs = V::read_csr(&self.vcsr);
+        for i in csrs.vstart..nr * V::VLENB {
+            let value = self.velement(vs2, i, 1);
+            self.set_velement(vd, i, 1, value);
+        }
+        csrs.vstart = 0;
+        V::write_csr(&mut self.vcsr, csrs);
+        Some(())
+    }
+    /// The integer instructions: `OPIVV`, `OPIVX`, and `OPIVI`.
+    fn opi(&mut self, instruction: u32) -> Option<()> {
+        let funct3 = (instruction >> 12) & 0b111;
+        let rs1 = (instruction >> 15) & 0b11111;
+        if instruction >> 26 == 0b100111 && funct3 == 0b011 {
+            return self.whole_move(instruction);
+        }
+        let scalar = match funct3 {
+            // sign extended immediate
+            0b011 => ((rs1 as i32) << 27 >> 27) as u32,
+            _ => self.get_register(rs1),
+        };
+        let a = self.vargs(instruction, scalar)?;
+        let vv = funct3 == 0b000;
+        let vi = funct3 == 0b011;
+        let sew = a.vt.sew;
+        let bits = sew * 8;
+        let csrs = V::read_csr(&self.vcsr);
+        let vxrm = csrs.vxrm;
+        let mut sat = false;
+        let s = |x| sext(x, sew) as i64;
+        let u = |x| x as i64;
+        // the unsigned immediate, or the scalar, for slides and gathers
+        let offset = if vi { rs1 } else { scalar };
+        match a.funct6 {
+            0b000000 => self.binary(&a, u32::wrapping_add)?, // VADD
+            0b000010 if !vi => self.binary(&a, u32::wrapping_sub)?, // VSUB
+            0b000011 if !vv => self.binary(&a, |x, y| y.wrapping_sub(x))?, // VRSUB
+            0b000100 if !vi => self.binary(&a, u32::min)?, // VMINU
+            0b000101 if !vi => {
+                // VMIN
+                self.binary(&a, |x, y| if s(x) < s(y) { x } else { y })?
+            }
+            0b000110 if !vi => self.binary(&a, u32::max)?, // VMAXU
+            0b000111 if !vi => {
+                // VMAX
+                self.binary(&a, |x, y| if s(x) > s(y) { x } else { y })?
+            }
+            0b001001 => self.binary(&a, |x, y| x & y)?, // VAND
+            0b001010 => self.binary(&a, |x, y| x | y)?, // VOR
+            0b001011 => self.binary(&a, |x, y| x ^ y)?, // VXOR
+            0b001100 => {
+                // VRGATHER
+                let a = Args {
+                    b: match a.b {
+                        Operand::Scalar(_) => Operand::Scalar(offset),
+                        b => b,
+                    },
+                    ..a
+                };
+                self.gather(&a, sew)?
+            }
+            0b001110 if vv => self.gather(&a, 2)?, // VRGATHEREI16
+            0b001110 => self.slide(&a, true, offset)?, // VSLIDEUP
+            0b001111 if !vv => self.slide(&a, false, offset)?, // VSLIDEDOWN
+            0b010000 | 0b010010 if !(vi && a.funct6 == 0b010010) => {
+                // VADC, VSBC
+                if a.vm || !a.single_width_ok() {
+                    return None;
+                }
+                let sub = a.funct6 == 0b010010;
+                let all = Args { vm: true, ..a };
+                self.vmap(&all, sew, |cpu, i| {
+                    let x = cpu.velement(a.vs2, i, sew);
+                    let y = cpu.vb(a.b, i, sew);
+                    let carry = cpu.vmask(0, i) as u32;
+                    let v = if sub {
+                        x.wrapping_sub(y).wrapping_sub(carry)
+                    } else {
+                        x.wrapping_add(y).wrapping_add(carry)
+                    };
+                    v & ones(sew)
+                });
+            }
+            0b010001 | 0b010011 if !(vi && a.funct6 == 0b010011) => {
+                // VMADC, VMSBC
+                if !a.mask_dest_ok() {
+                    return None;
+                }
+                let sub = a.funct6 == 0b010011;
+                let all = Args { vm: true, ..a };
+                self.vmap_mask(&all, |cpu, i| {
+                    let x = cpu.velement(a.vs2, i, sew) as u64;
+                    let y = cpu.vb(a.b, i, sew) as u64;
+                    let carry = (!a.vm && cpu.vmask(0, i)) as u64;
+                    if sub {
+                        x < y + carry
+                    } else {
+                        (x + y + carry) >> bits != 0
+                    }
+                });
+            }
+            0b010111 => self.merge(&a)?, // VMERGE, VMV.V
+            0b011000 => self.compare(&a, |x, y| x == y)?, // VMSEQ
+            0b011001 => self.compare(&a, |x, y| x != y)?, // VMSNE
+            0b011010 if !vi => self.compare(&a, |x, y| x < y)?, // VMSLTU
+            0b011011 if !vi => self.compare(&a, |x, y| s(x) < s(y))?, // VMSLT
+            0b011100 => self.compare(&a, |x, y| x <= y)?, // VMSLEU
+            0b011101 => self.compare(&a, |x, y| s(x) <= s(y))?, // VMSLE
+            0b011110 if !vv => self.compare(&a, |x, y| x > y)?, // VMSGTU
+            0b011111 if !vv => self.compare(&a, |x, y| s(x) > s(y))?, // VMSGT
+            0b100000 => {
+                // VSADDU
+                self.binary(&a, |x, y| {
+                    clamp_unsigned(u(x) + u(y), sew, &mut sat)
+                })?
+            }
+            0b100001 => {
+                // VSADD
+                self.binary(&a, |x, y| {
+                    clamp_signed(s(x) + s(y), sew, &mut sat)
+                })?
+            }
+            0b100010 if !vi => {
+                // VSSUBU
+                self.binary(&a, |x, y| {
+                    clamp_unsigned(u(x) - u(y), sew, &mut sat)
+                })?
+            }
+            0b100011 if !vi => {
+                // VSSUB
+                self.binary(&a, |x, y| {
+                    clamp_signed(s(x) - s(y), sew, &mut sat)
+                })?
+            }
+            0b100101 => self.binary(&a, |x, y| x << (y & (bits - 1)))?, // VSLL
+            0b100111 => {
+                // VSMUL
+                self.binary(&a, |x, y| {
+                    let v = roundoff(s(x) * s(y), bits - 1, vxrm);
+                    clamp_signed(v, sew, &mut sat)
+                })?
+            }
+            0b101000 => self.binary(&a, |x, y| x >> (y & (bits - 1)))?, // VSRL
+            0b101001 => {
+                // VSRA
+                self.binary(&a, |x, y| (s(x) >> (y & (bits - 1))) as u32)?
+            }
+            0b101010 => {
+                // VSSRL
+                self.binary(&a, |x, y| {
+                    roundoff(u(x), y & (bits - 1), vxrm) as u32
+                })?
+            }
+            0b101011 => {
+                // VSSRA
+                self.binary(&a, |x, y| {
+                    roundoff(s(x), y & (bits - 1), vxrm) as u32
+                })?
+            }
+            0b101100 => {
+                // VNSRL
+                self.narrowing(&a, |x, y| x >> (y & (bits * 2 - 1)))?
+            }
+            0b101101 => {
+                // VNSRA
+                self.narrowing(&a, |x, y| {
+                    (sext(x, sew * 2) >> (y & (bits * 2 - 1))) as u32
+                })?
+            }
+            0b101110 => {
+                // VNCLIPU
+                self.narrowing(&a, |x, y| {
+                    let v = roundoff(u(x), y & (bits * 2 - 1), vxrm);
+                    clamp_unsigned(v, sew, &mut sat)
+                })?
+            }
+            0b101111 => {
+                // VNCLIP
+                self.narrowing(&a, |x, y| {
+                    let x = sext(x, sew * 2) as i64;
+                    let v = roundoff(x, y & (bits * 2 - 1), vxrm);
+                    clamp_signed(v, sew, &mut sat)
+                })?
+            }
+            0b110000 | 0b110001 if vv && sew <= 2 => {
+                // VWREDSUMU, VWREDSUM
+                let signed = a.funct6 == 0b110001;
+                self.reduce(&a, sew * 2, |acc, x| {
+                    let x = if signed { sext(x, sew) as u32 } else { x };
+                    acc.wrapping_add(x)
+                })?
+            }
+            _ => return None,
+        }
+        if sat {
+            let mut csrs = V::read_csr(&self.vcsr);
+            csrs.vxsat = true;
+            V::write_csr(&mut self.vcsr, csrs);
+        }
+        Some(())
+    }
+    /// The "multiply" instructions, which include most of the odd ones:
+    /// `OPMVV` and `OPMVX`.
+    fn opm(&mut self, instruction: u32) -> Option<()> {
+        let funct3 = (instruction >> 12) & 0b111;
+        let rs1 = (instruction >> 15) & 0b11111;
+        let scalar = self.get_register(rs1);
+        let a = self.vargs(instruction, scalar)?;
+        let vv = funct3 == 0b010;
+        let sew = a.vt.sew;
+        let bits = sew * 8;
+        let vxrm = V::read_csr(&self.vcsr).vxrm;
+        let s = |x| sext(x, sew) as i64;
+        let u = |x| x as i64;
+        match a.funct6 {
+            0b000000..=0b000111 if vv => {
+                // VREDSUM, VREDAND, VREDOR, VREDXOR, VREDMINU, VREDMIN,
+                // VREDMAXU, VREDMAX
+                let op: fn(u32, u32, u32) -> u32 = match a.funct6 {
+                    0b000000 => |x, y, _| x.wrapping_add(y),
+                    0b000001 => |x, y, _| x & y,
+                    0b000010 => |x, y, _| x | y,
+                    0b000011 => |x, y, _| x ^ y,
+                    0b000100 => |x, y, _| x.min(y),
+                    0b000101 => |x, y, sew| {
+                        if sext(x, sew) < sext(y, sew) {
+                            x
+                        } else {
+                            y
+                        }
+                    },
+                    0b000110 => |x, y, _| x.max(y),
+                    _ => |x, y, sew| {
+                        if sext(x, sew) > sext(y, sew) {
+                            x
+                        } else {
+                            y
+                        }
+                    },
+                };
+                self.reduce(&a, sew, |acc, x| op(acc, x, sew))?
+            }
+            0b001000 => {
+                // VAADDU
+                self.binary(&a, |x, y| roundoff(u(x) + u(y), 1, vxrm) as u32)?
+            }
+            0b001001 => {
+                // VAADD
+                self.binary(&a, |x, y| roundoff(s(x) + s(y), 1, vxrm) as u32)?
+            }
+            0b001010 => {
+                // VASUBU
+                self.binary(&a, |x, y| roundoff(u(x) - u(y), 1, vxrm) as u32)?
+            }
+            0b001011 => {
+                // VASUB
+                self.binary(&a, |x, y| roundoff(s(x) - s(y), 1, vxrm) as u32)?
+            }
+            0b001110 if !vv => self.slide1(&a, true, scalar)?, // VSLIDE1UP
+            0b001111 if !vv => self.slide1(&a, false, scalar)?, // VSLIDE1DOWN
+            0b010000 if vv => {
+                if !a.vm && rs1 == 0b00000 {
+                    return None;
+                }
+                match rs1 {
+                    0b00000 => {
+                        // VMV.X.S
+                        let value = self.velement(a.vs2, 0, sew);
+                        self.set_register(a.vd, sext(value, sew) as u32);
+                    }
+                    0b10000 => {
+                        // VCPOP.M
+                        let count = (0..a.vl)
+                            .filter(|&i| {
+                                self.vactive(a.vm, i) && self.vmask(a.vs2, i)
+                            })
+                            .count();
+                        self.set_register(a.vd, count as u32);
+                    }
+                    0b10001 => {
+                        // VFIRST.M
+                        let first = (0..a.vl).find(|&i| {
+                            self.vactive(a.vm, i) && self.vmask(a.vs2, i)
+                        });
+                        self.set_register(a.vd, first.unwrap_or(u32::MAX));
+                    }
+                    _ => return None,
+                }
+            }
+            0b010000 if a.vs2 == 0 && a.vm => {
+                // VMV.S.X
+                if a.vl > 0 {
+                    self.set_velement(a.vd, 0, sew, scalar & ones(sew));
+                }
+            }
+            0b010010 if vv => {
+                // VZEXT, VSEXT
+                let (factor, signed) = match rs1 {
+                    0b00100 => (4, false),
+                    0b00101 => (4, true),
+                    0b00110 => (2, false),
+                    0b00111 => (2, true),
+                    _ => return None,
+                };
+                let src_eew = sew / factor;
+                if src_eew == 0 {
+                    return None;
+                }
+                let src_emul = a.vt.emul(src_eew)?;
+                if !aligned(a.vd, a.vt.lmul)
+                    || !aligned(a.vs2, src_emul)
+                    || !widen_ok(a.vd, a.vt.lmul, a.vs2, src_emul)
+                    || (!a.vm && a.vd == 0)
+                {
+                    return None;
+                }
+                self.vmap(&a, sew, |cpu, i| {
+                    let x = cpu.velement(a.vs2, i, src_eew);
+                    if signed {
+                        sext(x, src_eew) as u32 & ones(sew)
+                    } else {
+                        x
+                    }
+                });
+            }
+            0b010100 if vv => {
+                let group = regs(a.vt.lmul);
+                if overlaps(a.vd, group, a.vs2, 1) || (!a.vm && a.vd == 0) {
+                    return None;
+                }
+                match rs1 {
+                    0b00001..=0b00011 => {
+                        // VMSBF, VMSOF, VMSIF
+                        let mut found = false;
+                        self.vmap_mask(&a, |cpu, i| {
+                            let bit = cpu.vmask(a.vs2, i);
+                            let out = match rs1 {
+                                0b00001 => !found && !bit,
+                                0b00010 => !found && bit,
+                                _ => !found,
+                            };
+                            found |= bit;
+                            out
+                        });
+                    }
+                    0b10000 => {
+                        // VIOTA
+                        if !aligned(a.vd, a.vt.lmul) {
+                            return None;
+                        }
+                        let mut count = 0;
+                        self.vmap(&a, sew, |cpu, i| {
+                            let out = count;
+                            count += cpu.vmask(a.vs2, i) as u32;
+                            out & ones(sew)
+                        });
+                    }
+                    0b10001 if a.vs2 == 0 => {
+                        // VID
+                        if !aligned(a.vd, a.vt.lmul) {
+                            return None;
+                        }
+                        self.vmap(&a, sew, |_, i| i & ones(sew));
+                    }
+                    _ => return None,
+                }
+            }
+            0b010111 if vv => {
+                // VCOMPRESS
+                if !a.vm || !a.single_width_ok() || !a.disjoint(0) {
+                    return None;
+                }
+                let mut j = 0;
+                for i in 0..a.vl {
+                    if self.vmask(a.rs1, i) {
+                        let value = self.velement(a.vs2, i, sew);
+                        self.set_velement(a.vd, j, sew, value);
+                        j += 1;
+                    }
+                }
+            }
+            0b011000..=0b011111 if vv => {
+                // VMANDN, VMAND, VMOR, VMXOR, VMORN, VMNAND, VMNOR, VMXNOR
+                if !a.vm {
+                    return None;
+                }
+                for i in 0..a.vl {
+                    let x = self.vmask(a.vs2, i);
+                    let y = self.vmask(a.rs1, i);
+                    let value = match a.funct6 {
+                        0b011000 => x & !y,
+                        0b011001 => x & y,
+                        0b011010 => x | y,
+                        0b011011 => x ^ y,
+                        0b011100 => x | !y,
+                        0b011101 => !(x & y),
+                        0b011110 => !(x | y),
+                        _ => !(x ^ y),
+                    };
+                    self.set_vmask(a.vd, i, value);
+                }
+            }
+            0b100000 => {
+                // VDIVU
+                self.binary(&a, |x, y| x.checked_div(y).unwrap_or(u32::MAX))?
+            }
+            0b100001 => {
+                // VDIV
+                self.binary(&a, |x, y| match sext(y, sew) {
+                    0 => u32::MAX,
+                    y => sext(x, sew).wrapping_div(y) as u32,
+                })?
+            }
+            0b100010 => {
+                // VREMU
+                self.binary(&a, |x, y| x.checked_rem(y).unwrap_or(x))?
+            }
+            0b100011 => {
+                // VREM
+                self.binary(&a, |x, y| match sext(y, sew) {
+                    0 => x,
+                    y => sext(x, sew).wrapping_rem(y) as u32,
+                })?
+            }
+            0b100100 => {
+                // VMULHU (the product doesn't fit in an i64 at SEW=32)
+                self.binary(&a, |x, y| ((x as u64 * y as u64) >> bits) as u32)?
+            }
+            0b100101 => self.binary(&a, u32::wrapping_mul)?, // VMUL
+            0b100110 => {
+                // VMULHSU
+                self.binary(&a, |x, y| ((s(x) * u(y)) >> bits) as u32)?
+            }
+            0b100111 => {
+                // VMULH
+                self.binary(&a, |x, y| ((s(x) * s(y)) >> bits) as u32)?
+            }
+            0b101001 => {
+                // VMADD
+                self.ternary(&a, |x, y, d| y.wrapping_mul(d).wrapping_add(x))?
+            }
+            0b101011 => {
+                // VNMSUB
+                self.ternary(&a, |x, y, d| x.wrapping_sub(y.wrapping_mul(d)))?
+            }
+            0b101101 => {
+                // VMACC
+                self.ternary(&a, |x, y, d| y.wrapping_mul(x).wrapping_add(d))?
+            }
+            0b101111 => {
+                // VNMSAC
+                self.ternary(&a, |x, y, d| d.wrapping_sub(y.wrapping_mul(x)))?
+            }
+            0b110000..=0b110111 => {
+                // VWADDU, VWADD, VWSUBU, VWSUB, and their .W forms
+                let wide_vs2 = a.funct6 & 0b100 != 0;
+                let signed = a.funct6 & 0b001 != 0;
+                let sub = a.funct6 & 0b010 != 0;
+                let src_eew = if wide_vs2 { sew * 2 } else { sew };
+                let ext = move |x, eew| {
+                    if signed {
+                        sext(x, eew) as u32
+                    } else {
+                        x
+                    }
+                };
+                self.widening(&a, wide_vs2, |x, y, _| {
+                    let (x, y) = (ext(x, src_eew), ext(y, sew));
+                    if sub {
+                        x.wrapping_sub(y)
+                    } else {
+                        x.wrapping_add(y)
+                    }
+                })?
+            }
+            0b111000 => self.widening(&a, false, |x, y, _| x * y)?, // VWMULU
+            0b111010 => {
+                // VWMULSU
+                self.widening(&a, false, |x, y, _| (s(x) * u(y)) as u32)?
+            }
+            0b111011 => {
+                // VWMUL
+                self.widening(&a, false, |x, y, _| (s(x) * s(y)) as u32)?
+            }
+            0b111100 => {
+                // VWMACCU
+                self.widening(&a, false, |x, y, d| d.wrapping_add(x * y))?
+            }
+            0b111101 => {
+                // VWMACC
+                self.widening(&a, false, |x, y, d| {
+                    d.wrapping_add((s(x) * s(y)) as u32)
+                })?
+            }
+            0b111110 if !vv => {
+                // VWMACCUS
+                self.widening(&a, false, |x, y, d| {
+                    d.wrapping_add((s(x) * u(y)) as u32)
+                })?
+            }
+            0b111111 => {
+                // VWMACCSU
+                self.widening(&a, false, |x, y, d| {
+                    d.wrapping_add((u(x) * s(y)) as u32)
+                })?
+            }
+            _ => return None,
+        }
+        Some(())
+    }
+    /// The single precision float instructions (Zve32f): `OPFVV` and
+    /// `OPFVF`.
+    #[cfg(feature = "float")]
+    fn opf<Env: ExecutionEnvironment>(
+        &mut self,
+        env: &mut Env,
+        instruction: u32,
+    ) -> Option<()> {
+        let zfinx =
+            F::SUPPORT_FCSR && Env::SUPPORT_ZFINX && env.enable_zfinx();
+        if zfinx
+            || !F::SUPPORT_F
+            || !env.enable_f()
+            || !Env::SUPPORT_ZVE32F
+            || !env.enable_zve32f()
+            || env.read_fs() == ExtensionStatus::Disabled
+        {
+            return None;
+        }
+        let funct3 = (instruction >> 12) & 0b111;
+        let rs1 = (instruction >> 15) & 0b11111;
+        let scalar = F::unbox_single(self.float_registers[rs1 as usize]);
+        let a = self.vargs(instruction, scalar)?;
+        let vv = funct3 == 0b001;
+        let sew = a.vt.sew;
+        let rm = round_mode(self.read_frm());
+        let mut flags = 0;
+        let f = float::to_single;
+        // conversions to and from 16-bit integers are the only things that
+        // don't work on singles
+        let conversion = vv && a.funct6 == 0b010010;
+        if sew != 4 && !(conversion && sew == 2) {
+            return None;
+        }
+        match a.funct6 {
+            0b000000 => {
+                // VFADD
+                let rm = rm?;
+                self.binary(&a, |x, y| {
+                    single_bits(f(x).add_r(f(y), rm), &mut flags)
+                })?
+            }
+            0b000001 | 0b000011 if vv => {
+                // VFREDUSUM, VFREDOSUM (both ordered)
+                let rm = rm?;
+                self.reduce(&a, 4, |acc, x| {
+                    single_bits(f(acc).add_r(f(x), rm), &mut flags)
+                })?
+            }
+            0b000010 => {
+                // VFSUB
+                let rm = rm?;
+                self.binary(&a, |x, y| {
+                    single_bits(f(x).sub_r(f(y), rm), &mut flags)
+                })?
+            }
+            0b000100 | 0b000110 => {
+                // VFMIN, VFMAX
+                let max = a.funct6 == 0b000110;
+                self.binary(&a, |x, y| {
+                    single_bits(float::min_max(f(x), f(y), max), &mut flags)
+                })?
+            }
+            0b000101 | 0b000111 if vv => {
+                // VFREDMIN, VFREDMAX
+                let max = a.funct6 == 0b000111;
+                self.reduce(&a, 4, |acc, x| {
+                    single_bits(float::min_max(f(acc), f(x), max), &mut flags)
+                })?
+            }
+            0b001000 => {
+                // VFSGNJ
+                self.binary(&a, |x, y| (x & !(1 << 31)) | (y & (1 << 31)))?
+            }
+            0b001001 => {
+                // VFSGNJN
+                self.binary(&a, |x, y| (x & !(1 << 31)) | (!y & (1 << 31)))?
+            }
+            0b001010 => self.binary(&a, |x, y| x ^ (y & (1 << 31)))?, // VFSGNJX
+            0b001110 if !vv => self.slide1(&a, true, scalar)?, // VFSLIDE1UP
+            0b001111 if !vv => self.slide1(&a, false, scalar)?, // VFSLIDE1DOWN
+            0b010000 if vv && rs1 == 0 && a.vm => {
+                // VFMV.F.S
+                let value = F::box_single(self.velement(a.vs2, 0, 4));
+                if self.float_registers[a.vd as usize] != value {
+                    env.write_fs(ExtensionStatus::Dirty);
+                    self.float_registers[a.vd as usize] = value;
+                }
+            }
+            0b010000 if !vv && a.vs2 == 0 && a.vm => {
+                // VFMV.S.F
+                if a.vl > 0 {
+                    self.set_velement(a.vd, 0, 4, scalar);
+                }
+            }
+            0b010010 if vv => {
+                let rm = rm?;
+                let rtz = Round::TowardZero;
+                match (rs1, sew) {
+                    (0b00000 | 0b00001 | 0b00110 | 0b00111, 4) => {
+                        // VFCVT.XU.F.V, VFCVT.X.F.V, and the RTZ forms
+                        let signed = rs1 & 1 != 0;
+                        let rm = if rs1 & 0b100 != 0 { rtz } else { rm };
+                        self.binary(&a, |x, _| {
+                            single_to_int(f(x), signed, 4, rm, &mut flags)
+                        })?
+                    }
+                    (0b00010, 4) => {
+                        // VFCVT.F.XU.V
+                        self.binary(&a, |x, _| {
+                            let o = Single::from_u128_r(x as u128, rm);
+                            single_bits(o, &mut flags)
+                        })?
+                    }
+                    (0b00011, 4) => {
+                        // VFCVT.F.X.V
+                        self.binary(&a, |x, _| {
+                            let o = Single::from_i128_r(x as i32 as i128, rm);
+                            single_bits(o, &mut flags)
+                        })?
+                    }
+                    (0b01010 | 0b01011, 2) => {
+                        // VFWCVT.F.XU.V, VFWCVT.F.X.V
+                        let signed = rs1 & 1 != 0;
+                        self.widening(&a, false, |x, _, _| {
+                            let x = if signed {
+                                sext(x, 2) as i128
+                            } else {
+                                x as i128
+                            };
+                            single_bits(Single::from_i128_r(x, rm), &mut flags)
+                        })?
+                    }
+                    (0b10000 | 0b10001 | 0b10110 | 0b10111, 2) => {
+                        // VFNCVT.XU.F.W, VFNCVT.X.F.W, and the RTZ forms
+                        let signed = rs1 & 1 != 0;
+                        let rm = if rs1 & 0b100 != 0 { rtz } else { rm };
+                        self.narrowing(&a, |x, _| {
+                            single_to_int(f(x), signed, 2, rm, &mut flags)
+                        })?
+                    }
+                    _ => return None,
+                }
+            }
+            0b010011 if vv => match rs1 {
+                0b00000 => {
+                    // VFSQRT
+                    let rm = rm?;
+                    let accurate = env.use_accurate_single_sqrt();
+                    let mut total_iterations = 0;
+                    self.binary(&a, |x, _| {
+                        let (result, iterations) = if accurate {
+                            ieee_apsqrt::sqrt_accurate(x, rm)
+                        } else {
+                            ieee_apsqrt::sqrt_fast(x, rm)
+                        };
+                        total_iterations += iterations;
+                        flags |= float::status_flags(result.status);
+                        result.value
+                    })?;
+                    env.account_sqrt(1, total_iterations);
+                }
+                0b00100 => self.binary(&a, |x, _| rsqrt7(x, &mut flags))?, // VFRSQRT7
+                0b00101 => {
+                    // VFREC7
+                    let rm = rm?;
+                    self.binary(&a, |x, _| rec7(x, rm, &mut flags))?
+                }
+                0b10000 => self.binary(&a, |x, _| float::classify(f(x)))?, // VFCLASS
+                _ => return None,
+            },
+            0b010111 if !vv => self.merge(&a)?, // VFMERGE, VFMV.V.F
+            0b011000 => {
+                // VMFEQ
+                self.compare(&a, |x, y| {
+                    if f(x).is_signaling() || f(y).is_signaling() {
+                        flags |= INVALID_FLAG;
+                    }
+                    f(x) == f(y)
+                })?
+            }
+            0b011100 => {
+                // VMFNE
+                self.compare(&a, |x, y| {
+                    if f(x).is_signaling() || f(y).is_signaling() {
+                        flags |= INVALID_FLAG;
+                    }
+                    f(x) != f(y)
+                })?
+            }
+            0b011001 | 0b011011 | 0b011101 | 0b011111
+                if !vv || a.funct6 & 0b100 == 0 =>
+            {
+                // VMFLE, VMFLT, VMFGT, VMFGE
+                let funct6 = a.funct6;
+                self.compare(&a, |x, y| {
+                    let (x, y) = (f(x), f(y));
+                    if x.is_nan() || y.is_nan() {
+                        flags |= INVALID_FLAG;
+                    }
+                    match funct6 {
+                        0b011001 => x <= y,
+                        0b011011 => x < y,
+                        0b011101 => x > y,
+                        _ => x >= y,
+                    }
+                })?
+            }
+            0b100000 => {
+                // VFDIV
+                let rm = rm?;
+                self.binary(&a, |x, y| {
+                    single_bits(float::divide(f(x), f(y), rm), &mut flags)
+                })?
+            }
+            0b100001 if !vv => {
+                // VFRDIV
+                let rm = rm?;
+                self.binary(&a, |x, y| {
+                    single_bits(float::divide(f(y), f(x), rm), &mut flags)
+                })?
+            }
+            0b100100 => {
+                // VFMUL
+                let rm = rm?;
+                self.binary(&a, |x, y| {
+                    single_bits(f(x).mul_r(f(y), rm), &mut flags)
+                })?
+            }
+            0b100111 if !vv => {
+                // VFRSUB
+                let rm = rm?;
+                self.binary(&a, |x, y| {
+                    single_bits(f(y).sub_r(f(x), rm), &mut flags)
+                })?
+            }
+            0b101000..=0b101111 => {
+                // VFMADD, VFNMADD, VFMSUB, VFNMSUB, VFMACC, VFNMACC, VFMSAC,
+                // VFNMSAC
+                let rm = rm?;
+                let funct6 = a.funct6;
+                self.ternary(&a, |x, y, d| {
+                    let (a, b, d) = (f(x), f(y), f(d));
+                    let o = match funct6 {
+                        0b101000 => b.mul_add_r(d, a, rm),
+                        0b101001 => (-b).mul_add_r(d, -a, rm),
+                        0b101010 => b.mul_add_r(d, -a, rm),
+                        0b101011 => (-b).mul_add_r(d, a, rm),
+                        0b101100 => b.mul_add_r(a, d, rm),
+                        0b101101 => (-b).mul_add_r(a, -d, rm),
+                        0b101110 => b.mul_add_r(a, -d, rm),
+                        _ => (-b).mul_add_r(a, d, rm),
+                    };
+                    single_bits(o, &mut flags)
+                })?
+            }
+            _ => return None,
+        }
+        if flags != 0 {
+            self.accrue_float_exceptions(flags);
+        }
+        Some(())
+    }
+    /// Read a vector CSR, if `csr_number` is one and vectors are enabled.
+    pub(super) fn read_vector_csr<Env: ExecutionEnvironment>(
+        &self,
+        env: &Env,
+        csr_number: u32,
+    ) -> Option<Result<u32, ExceptionCause>> {
+        if !zve32x::<Env, V>(env)
+            || !matches!(csr_number, 0x008..=0x00A | 0x00F | 0xC20..=0xC22)
+        {
+            return None;
+        }
+        if env.read_vs() == ExtensionStatus::Disabled {
+            return Some(Err(ExceptionCause::IllegalInstruction));
+        }
+        let csrs = V::read_csr(&self.vcsr);
+        Some(Ok(match csr_number {
+            0x008 => csrs.vstart,
+            0x009 => csrs.vxsat as u32,
+            0x00A => csrs.vxrm as u32,
+            0x00F => (csrs.vxrm as u32) << 1 | csrs.vxsat as u32,
+            0xC20 => csrs.vl,
+            0xC21 => csrs.vtype,
+            _ => V::VLENB,
+        }))
+    }
+    /// Write a vector CSR, if `csr_number` is one and vectors are enabled.
+    pub(super) fn write_vector_csr<Env: ExecutionEnvironment>(
+        &mut self,
+        env: &mut Env,
+        csr_number: u32,
+        new_value: u32,
+    ) -> Option<Result<(), ExceptionCause>> {
+        if !zve32x::<Env, V>(env)
+            || !matches!(csr_number, 0x008..=0x00A | 0x00F | 0xC20..=0xC22)
+        {
+            return None;
+        }
+        if env.read_vs() == ExtensionStatus::Disabled || csr_number >= 0xC20 {
+            return Some(Err(ExceptionCause::IllegalInstruction));
+        }
+        let mut csrs = V::read_csr(&self.vcsr);
+        match csr_number {
+            0x008 => csrs.vstart = new_value & (V::VLENB * 8 - 1),
+            0x009 => csrs.vxsat = new_value & 1 != 0,
+            0x00A => csrs.vxrm = new_value as u8 & 0b11,
+            _ => {
+                csrs.vxrm = (new_value >> 1) as u8 & 0b11;
+                csrs.vxsat = new_value & 1 != 0;
+            }
+        }
+        V::write_csr(&mut self.vcsr, csrs);
+        env.write_vs(ExtensionStatus::Dirty);
+        Some(Ok(()))
+    }
+    /// True if the vector instructions are supported and enabled right now.
+    pub(super) fn vector_enabled<Env: ExecutionEnvironment>(
+        &self,
+        env: &Env,
+    ) -> bool {
+        zve32x::<Env, V>(env)
+    }
+    /// Get the contents of a vector register, in the range 0-31, element 0
+    /// first. Empty if there are no vector registers. Index greater than or
+    /// equal to 32 will cause a PANIC!
+    pub fn get_vector_register(&self, index: u32) -> &[u8] {
+        self.vector_registers[index as usize].bytes()
+    }
+    /// Change the contents of a vector register, in the range 0-31. `value`
+    /// must be exactly `VLEN / 8` bytes long, or this will PANIC! So will an
+    /// index greater than or equal to 32.
+    pub fn set_vector_register(&mut self, index: u32, value: &[u8]) {
+        self.vector_registers[index as usize]
+            .bytes_mut()
+            .copy_from_slice(value)
+    }
+    /// Read the `vl` CSR, the number of elements vector instructions operate
+    /// on.
+    pub fn read_vl(&self) -> u32 {
+        V::read_csr(&self.vcsr).vl
+    }
+    /// Read the `vtype` CSR, the current element width and grouping.
+    pub fn read_vtype(&self) -> u32 {
+        V::read_csr(&self.vcsr).vtype
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::test_env::TestEnv;
+    use super::*;
+    /// VLEN is 128, so VLMAX is 4 at e32 and m1.
+    type VCpu = Cpu<(), [u8; 16]>;
+    /// Run `program` after `setup`, until it runs off the end or raises an
+    /// exception.
+    fn run(
+        program: &[u32],
+        setup: impl FnOnce(&mut VCpu, &mut TestEnv),
+    ) -> (VCpu, TestEnv, Result<(), Exception>) {
+        let mut env = TestEnv::new(program);
+        let mut cpu = VCpu::new();
+        setup(&mut cpu, &mut env);
+        let mut result = Ok(());
+        while result.is_ok() && cpu.get_pc() < program.len() as u32 * 4 {
+            result = cpu.step(&mut env);
+        }
+        (cpu, env, result)
+    }
+    fn words(cpu: &VCpu, reg: u32) -> [u32; 4] {
+        let bytes = cpu.get_vector_register(reg);
+        std::array::from_fn(|n| {
+            u32::from_le_bytes(bytes[n * 4..n * 4 + 4].try_into().unwrap())
+        })
+    }
+    fn set_words(cpu: &mut VCpu, reg: u32, words: [u32; 4]) {
+        let bytes: Vec<u8> =
+            words.iter().flat_map(|x| x.to_le_bytes()).collect();
+        cpu.set_vector_register(reg, &bytes);
+    }
+    /// Run each instruction after `vsetivli x0, 4, e32, m1`, and check that
+    /// it's illegal.
+    fn assert_illegal(instructions: &[u32]) {
+        for &instruction in instructions {
+            let (_, _, result) = run(&[0xc1027057, instruction], |_, _| ());
+            let e = result.expect_err(&format!("{instruction:08x}"));
+            assert_eq!(e.mcause, ExceptionCause::IllegalInstruction);
+            assert_eq!(e.mepc, 4);
+        }
+    }
+    #[test]
+    fn masking() {
+        let program = [
+            0xc1027057, // vsetivli x0, 4, e32, m1, tu, mu
+            0x0012b157, // vadd.vi v2, v1, 5, v0.t
+            0xcd027057, // vsetivli x0, 4, e32, m1, ta, ma
+            0x0012b1d7, // vadd.vi v3, v1, 5, v0.t
+            0xc1017057, // vsetivli x0, 2, e32, m1, tu, mu
+            0x0210b257, // vadd.vi v4, v1, 1
+            0x601532d7, // vmseq.vi v5, v1, 10, v0.t
+            0x5c1fb357, // vmerge.vim v6, v1, -1, v0
+        ];
+        let (cpu, _, result) = run(&program, |cpu, _| {
+            set_words(cpu, 0, [0b1010, 0, 0, 0]);
+            set_words(cpu, 1, [10, 20, 30, 40]);
+            set_words(cpu, 2, [1, 2, 3, 4]);
+            set_words(cpu, 3, [7; 4]);
+            set_words(cpu, 4, [9; 4]);
+            set_words(cpu, 5, [!0; 4]);
+            set_words(cpu, 6, [8; 4]);
+        });
+        result.unwrap();
+        // masked off elements are undisturbed
+        assert_eq!(words(&cpu, 2), [1, 25, 3, 45]);
+        // and so are agnostic ones, which is one of the allowed behaviors
+        assert_eq!(words(&cpu, 3), [7, 25, 7, 45]);
+        // so is the tail
+        assert_eq!(words(&cpu, 4), [11, 21, 9, 9]);
+        assert_eq!(words(&cpu, 5), [!0b10, !0, !0, !0]);
+        assert_eq!(words(&cpu, 6), [10, !0, 8, 8]);
+        // a masked instruction can't write v0
+        assert_illegal(&[
+            0x0012b057, // vadd.vi v0, v1, 5, v0.t
+            0x0000e007, // vle32.v v0, (x1), v0.t
+        ]);
+    }
+    #[test]
+    fn strided_and_indexed() {
+        let program = [
+            0xc1027057, // vsetivli x0, 4, e32, m1, tu, mu
+            0x0a20e087, // vlse32.v v1, (x1), x2
+            0x0a326107, // vlse32.v v2, (x4), x3
+            0x06308207, // vluxei8.v v4, (x1), v3
+            0x0e50e307, // vloxei32.v v6, (x1), v5
+            0x0a22e0a7, // vsse32.v v1, (x5), x2
+            0x063300a7, // vsuxei8.v v1, (x6), v3
+        ];
+        let (cpu, env, result) = run(&program, |cpu, env| {
+            for i in 0..16 {
+                env.ram[64 + i] = 100 + i as u32;
+            }
+            for (reg, value) in
+                [(1, 0x100), (2, 8), (3, -4i32 as u32), (4, 0x10C)]
+            {
+                cpu.set_register(reg, value);
+            }
+            cpu.set_register(5, 0x200);
+            cpu.set_register(6, 0x280);
+            // byte offsets
+            set_words(cpu, 3, [0x04_28_00_0C, 0, 0, 0]);
+            set_words(cpu, 5, [4, 8, 0, 60]);
+        });
+        result.unwrap();
+        assert_eq!(words(&cpu, 1), [100, 102, 104, 106]);
+        // negative strides go backward
+        assert_eq!(words(&cpu, 2), [103, 102, 101, 100]);
+        assert_eq!(words(&cpu, 4), [103, 100, 110, 101]);
+        assert_eq!(words(&cpu, 6), [101, 102, 100, 115]);
+        assert_eq!(env.ram[128..135], [100, 0, 102, 0, 104, 0, 106]);
+        assert_eq!(env.ram[160..164], [102, 106, 0, 100]);
+        assert_eq!(env.ram[170], 104);
+    }
+    #[test]
+    fn segments() {
+        let program = [
+            0xc1027057, // vsetivli x0, 4, e32, m1, tu, mu
+            0x2200e107, // vlseg2e32.v v2, (x1)
+            0x22016127, // vsseg2e32.v v2, (x2)
+            0xc0827057, // vsetivli x0, 4, e16, m1, tu, mu
+            0x6200d207, // vlseg4e16.v v4, (x1)
+        ];
+        let (cpu, env, result) = run(&program, |cpu, env| {
+            env.ram[64..72].copy_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+            cpu.set_register(1, 0x100);
+            cpu.set_register(2, 0x200);
+        });
+        result.unwrap();
+        assert_eq!(words(&cpu, 2), [1, 3, 5, 7]);
+        assert_eq!(words(&cpu, 3), [2, 4, 6, 8]);
+        assert_eq!(env.ram[128..136], [1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(words(&cpu, 4), [0x0003_0001, 0x0007_0005, 0, 0]);
+        assert_eq!(words(&cpu, 5), [0; 4]);
+        assert_eq!(words(&cpu, 6), [0x0004_0002, 0x0008_0006, 0, 0]);
+        assert_eq!(words(&cpu, 7), [0; 4]);
+        // EMUL * NF is more than 8 registers
+        let (_, _, result) = run(
+            &[
+                0xc1327057, // vsetivli x0, 4, e32, m8, tu, mu
+                0x2200e007, // vlseg2e32.v v0, (x1)
+            ],
+            |_, _| (),
+        );
+        assert_eq!(
+            result.unwrap_err().mcause,
+            ExceptionCause::IllegalInstruction
+        );
+        // runs off the end of the register file
+        assert_illegal(&[
+            0xe200ef07, // vlseg8e32.v v30, (x1)
+        ]);
+    }
+    #[test]
+    fn fault_only_first() {
+        let program = [
+            0xc1027057, // vsetivli x0, 4, e32, m1, tu, mu
+            0x0300e087, // vle32ff.v v1, (x1)
+            0xc2002173, // csrr x2, vl
+        ];
+        // RAM ends at 0x400
+        let setup = |address| {
+            move |cpu: &mut VCpu, env: &mut TestEnv| {
+                env.ram[254] = 11;
+                env.ram[255] = 22;
+                cpu.set_register(1, address);
+            }
+        };
+        let (cpu, _, result) = run(&program, setup(0x3F8));
+        result.unwrap();
+        // the fault after the first element just cut vl short
+        assert_eq!(cpu.get_register(2), 2);
+        assert_eq!(words(&cpu, 1), [11, 22, 0, 0]);
+        // but a fault on the first element traps
+        let (mut cpu, mut env, result) = run(&program, setup(0x400));
+        let e = result.unwrap_err();
+        assert_eq!(e.mcause, ExceptionCause::LoadAccessFault);
+        assert_eq!(e.mtval, 0x400);
+        assert_eq!(cpu.read_vl(), 4);
+        assert_eq!(cpu.read_csr(&mut env, 0x008), Ok(0));
+        // and so does any fault in a normal load, leaving vstart pointing
+        // at the element that faulted
+        let program = [
+            0xc1027057, // vsetivli x0, 4, e32, m1, tu, mu
+            0x0200e087, // vle32.v v1, (x1)
+        ];
+        let (mut cpu, mut env, result) = run(&program, setup(0x3F8));
+        let e = result.unwrap_err();
+        assert_eq!(e.mcause, ExceptionCause::LoadAccessFault);
+        assert_eq!(e.mtval, 0x400);
+        assert_eq!(cpu.read_vl(), 4);
+        assert_eq!(cpu.read_csr(&mut env, 0x008), Ok(2));
+        assert_eq!(words(&cpu, 1), [11, 22, 0, 0]);
+    }
+    #[test]
+    fn multiply_high() {
+        let program = [
+            0xc1027057, // vsetivli x0, 4, e32, m1, tu, mu
+            0x921121d7, // vmulhu.vv v3, v1, v2
+            0x9e112257, // vmulh.vv v4, v1, v2
+            0x9a1122d7, // vmulhsu.vv v5, v1, v2
+            0x9212e357, // vmulhu.vx v6, v1, x5
+            0xc0847057, // vsetivli x0, 8, e16, m1, tu, mu
+            0x9a1123d7, // vmulhsu.vv v7, v1, v2
+        ];
+        let (cpu, _, result) = run(&program, |cpu, _| {
+            set_words(cpu, 1, [!0, 0x8000_0000, 7, !0]);
+            set_words(cpu, 2, [!0, 0x8000_0000, 3, 2]);
+            cpu.set_register(5, !0);
+        });
+        result.unwrap();
+        assert_eq!(words(&cpu, 3), [0xFFFF_FFFE, 0x4000_0000, 0, 1]);
+        assert_eq!(words(&cpu, 4), [0, 0x4000_0000, 0, !0]);
+        // vs2 is the signed one
+        assert_eq!(words(&cpu, 5), [!0, 0xC000_0000, 0, !0]);
+        assert_eq!(words(&cpu, 6), [0xFFFF_FFFE, 0x7FFF_FFFF, 6, 0xFFFF_FFFE]);
+        assert_eq!(words(&cpu, 7), [!0, 0xC000_0000, 0, 0xFFFF]);
+    }
+    #[test]
+    fn reductions() {
+        let program = [
+            0xc1027057, // vsetivli x0, 4, e32, m1, tu, mu
+            0x021121d7, // vredsum.vs v3, v1, v2
+            0x1a112257, // vredmaxu.vs v4, v1, v2
+            0x1e1122d7, // vredmax.vs v5, v1, v2
+            0x16112357, // vredmin.vs v6, v1, v2
+            0x001123d7, // vredsum.vs v7, v1, v2, v0.t
+            0xc0827057, // vsetivli x0, 4, e16, m1, tu, mu
+            0xc2958557, // vwredsumu.vs v10, v9, v11
+            0xc6958657, // vwredsum.vs v12, v9, v11
+            0xc1007057, // vsetivli x0, 0, e32, m1, tu, mu
+            0x02112457, // vredsum.vs v8, v1, v2
+        ];
+        let (cpu, _, result) = run(&program, |cpu, _| {
+            set_words(cpu, 0, [0b0101, 0, 0, 0]);
+            set_words(cpu, 1, [5, !0, 7, 3]);
+            set_words(cpu, 2, [100, 0, 0, 0]);
+            set_words(cpu, 8, [9; 4]);
+            // the last two words are past vl
+            set_words(cpu, 9, [!0, 0x0001_0002, 0x1234_5678, !0]);
+            set_words(cpu, 10, [0x55; 4]);
+            set_words(cpu, 11, [10, 0, 0, 0]);
+            set_words(cpu, 12, [0x55; 4]);
+        });
+        result.unwrap();
+        assert_eq!(words(&cpu, 3), [114, 0, 0, 0]);
+        assert_eq!(words(&cpu, 4)[0], !0);
+        assert_eq!(words(&cpu, 5)[0], 100);
+        assert_eq!(words(&cpu, 6)[0], !0);
+        assert_eq!(words(&cpu, 7)[0], 112);
+        // only element 0 of the destination is written
+        assert_eq!(words(&cpu, 10), [0x2_000B, 0x55, 0x55, 0x55]);
+        assert_eq!(words(&cpu, 12), [11, 0x55, 0x55, 0x55]);
+        // and not even that if vl is zero
+        assert_eq!(words(&cpu, 8), [9; 4]);
+    }
+    #[test]
+    fn permutations() {
+        let program = [
+            0xc1027057, // vsetivli x0, 4, e32, m1, tu, mu
+            0x3a10b157, // vslideup.vi v2, v1, 1
+            0x3e10b1d7, // vslidedown.vi v3, v1, 1
+            0x3e10c257, // vslidedown.vx v4, v1, x1
+            0x3a1162d7, // vslide1up.vx v5, v1, x2
+            0x3e116357, // vslide1down.vx v6, v1, x2
+            0x32138457, // vrgather.vv v8, v1, v7
+            0x3211c4d7, // vrgather.vx v9, v1, x3
+            0x3212b557, // vrgather.vi v10, v1, 5
+            0x3a1605d7, // vrgatherei16.vv v11, v1, v12
+        ];
+        let (cpu, _, result) = run(&program, |cpu, _| {
+            cpu.set_register(1, 100);
+            cpu.set_register(2, 99);
+            cpu.set_register(3, 2);
+            set_words(cpu, 1, [10, 20, 30, 40]);
+            set_words(cpu, 2, [7; 4]);
+            set_words(cpu, 4, [1; 4]);
+            set_words(cpu, 7, [3, 0, 9, 1]);
+            set_words(cpu, 10, [1; 4]);
+            set_words(cpu, 12, [0x0001_0001, 0x0000_0003, 0, 0]);
+        });
+        result.unwrap();
+        // slideup leaves the elements below the offset alone
+        assert_eq!(words(&cpu, 2), [7, 10, 20, 30]);
+        // slidedown reads zeros past VLMAX
+        assert_eq!(words(&cpu, 3), [20, 30, 40, 0]);
+        assert_eq!(words(&cpu, 4), [0; 4]);
+        assert_eq!(words(&cpu, 5), [99, 10, 20, 30]);
+        assert_eq!(words(&cpu, 6), [20, 30, 40, 99]);
+        // so do out of range gathers
+        assert_eq!(words(&cpu, 8), [40, 10, 0, 20]);
+        assert_eq!(words(&cpu, 9), [30; 4]);
+        assert_eq!(words(&cpu, 10), [0; 4]);
+        assert_eq!(words(&cpu, 11), [20, 20, 40, 10]);
+        // the destination can't overlap the sources
+        assert_illegal(&[
+            0x3a10b0d7, // vslideup.vi v1, v1, 1
+            0x321380d7, // vrgather.vv v1, v1, v7
+        ]);
+    }
+    #[test]
+    fn vset() {
+        let program = [
+            0x003170d7, // vsetvli x1, x2, e8, m8, tu, mu
+            0x011071d7, // vsetvli x3, x0, e32, m2, tu, mu
+            0x00a07057, // vsetvli x0, x0, e16, m4, tu, mu
+            0xc2002273, // csrr x4, vl
+            0xc06ff2d7, // vsetivli x5, 31, e8, mf4, tu, mu
+            0xc2102373, // csrr x6, vtype
+            0x0d03f3d7, // vsetvli x7, x7, e32, m1, ta, ma
+            0xc2102473, // csrr x8, vtype
+            0x015174d7, // vsetvli x9, x2, e32, mf8, tu, mu
+            0xc2102573, // csrr x10, vtype
+            0x80c175d7, // vsetvl x11, x2, x12
+            0x80e176d7, // vsetvl x13, x2, x14
+            0x810177d7, // vsetvl x15, x2, x16
+            0x812178d7, // vsetvl x17, x2, x18
+            0xc21029f3, // csrr x19, vtype
+        ];
+        let (cpu, _, result) = run(&program, |cpu, _| {
+            for (reg, value) in [
+                (2, 1000),
+                (7, 6),
+                (12, 0b100),         // reserved LMUL
+                (14, 0b011_000),     // e64
+                (16, 0b1_0001_0000), // a reserved bit
+                (18, 0b010_000),     // e32, m1
+            ] {
+                cpu.set_register(reg, value);
+            }
+            for reg in [9, 11, 13, 15] {
+                cpu.set_register(reg, 0xFF);
+            }
+        });
+        result.unwrap();
+        // AVL above VLMAX gets VLMAX
+        assert_eq!(cpu.get_register(1), 128);
+        // and so does rs1 = x0, unless rd is x0 too, which keeps vl
+        assert_eq!(cpu.get_register(3), 8);
+        assert_eq!(cpu.get_register(4), 8);
+        assert_eq!(cpu.get_register(5), 4);
+        assert_eq!(cpu.get_register(6), 0b000_110);
+        assert_eq!(cpu.get_register(7), 4);
+        // the agnostic bits are remembered
+        assert_eq!(cpu.get_register(8), 0b1101_0000);
+        // SEW is too big for LMUL = 1/8
+        assert_eq!(cpu.get_register(9), 0);
+        assert_eq!(cpu.get_register(10), VILL);
+        assert_eq!(cpu.get_register(11), 0);
+        assert_eq!(cpu.get_register(13), 0);
+        assert_eq!(cpu.get_register(15), 0);
+        assert_eq!(cpu.get_register(17), 4);
+        assert_eq!(cpu.get_register(19), 0b010_000);
+        // nothing but another vset works while vill is set
+        let program = [
+            0x803170d7, // vsetvl x1, x2, x3
+            0x022180d7, // vadd.vv v1, v2, v3
+        ];
+        let (cpu, _, result) = run(&program, |cpu, _| {
+            cpu.set_register(1, 0xFF);
+            cpu.set_register(2, 4);
+            cpu.set_register(3, VILL);
+        });
+        let e = result.unwrap_err();
+        assert_eq!(e.mcause, ExceptionCause::IllegalInstruction);
+        assert_eq!(e.mepc, 4);
+        assert_eq!(cpu.get_register(1), 0);
+        assert_eq!(cpu.read_vtype(), VILL);
+    }
+    #[cfg(feature = "float")]
+    #[test]
+    fn zve32f() {
+        use super::test_env::run_in;
+        let program = [
+            0xc1027157, // vsetivli x2, 4, e32, m1
+            0x0200e087, // vle32.v v1, (x1)
+            0x0212b157, // vadd.vi v2, v1, 5
+            0x021021d7, // vredsum.vs v3, v1, v0
+            0x423021d7, // vmv.x.s x3, v3
+            0x01008213, // addi x4, x1, 16
+            0x02026207, // vle32.v v4, (x4)
+            0x924212d7, // vfmul.vv v5, v4, v4
+            0x0200e2a7, // vse32.v v5, (x1)
+            0x0040a283, // lw x5, 4(x1)
+            0xc0847057, // vsetivli x0, 8, e16, m1
+            0xc210a357, // vwaddu.vv v6, v1, v1
+        ];
+        let mut env = TestEnv::new(&program);
+        env.ram[64..72].copy_from_slice(&[
+            1, 2, 3, 4, 0x3FC00000, 0x40200000, 0xBF800000, 0x3E800000,
+        ]);
+        let cpu =
+            run_in::<u32, [u8; 16], [u32; 32]>(env, &program, &[(1, 0x100)])
+                .unwrap();
+        let words = |reg| {
+            let bytes = cpu.get_vector_register(reg);
+            (0..4)
+                .map(|n| {
+                    u32::from_le_bytes(
+                        bytes[n * 4..n * 4 + 4].try_into().unwrap(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(cpu.get_register(2), 4);
+        assert_eq!(words(2), [6, 7, 8, 9]);
+        assert_eq!(cpu.get_register(3), 10);
+        assert_eq!(words(5), [0x40100000, 0x40C80000, 0x3F800000, 0x3D800000]);
+        assert_eq!(cpu.get_register(5), 0x40C80000);
+        assert_eq!(cpu.read_vl(), 8);
+        assert_eq!(words(6), [2, 0, 4, 0]);
+        assert_eq!(words(7), [6, 0, 8, 0]);
+    }
+}
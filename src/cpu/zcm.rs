@@ -0,0 +1,127 @@
+//! The Zcmp (push, pop, and register pair moves) and Zcmt (table jump)
+//! code-size extensions. Both live in the encoding space of `C.FSDSP`.
+
+use super::*;
+
+/// The registers `CM.PUSH` and `CM.POP` can save and restore, in the order
+/// `rlist` adds them: `ra`, then `s0` through `s11`.
+const SAVED: [u32; 13] = [1, 8, 9, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27];
+
+/// The register named by a three-bit `sreg` field, `s0` through `s7`.
+fn sreg(field: u32) -> u32 {
+    if field < 2 {
+        field + 8
+    } else {
+        field + 16
+    }
+}
+
+impl<F: FloatBits, V: VectorBits, X: IntegerRegisters> Cpu<F, V, X> {
+    /// Execute a Zcmp instruction. `instruction` is the 16-bit instruction,
+    /// already known to be in the `C.FSDSP` encoding space.
+    pub(super) fn zcmp<Env: ExecutionEnvironment>(
+        &mut self,
+        env: &mut Env,
+        instruction: u32,
+    ) -> Result<(), (ExceptionCause, u32)> {
+        let this_pc = self.get_pc();
+        let illegal = Err((ExceptionCause::IllegalInstruction, instruction));
+        match (instruction >> 8) & 0b11111 {
+            op @ (0b11000 | 0b11010 | 0b11100 | 0b11110) => {
+                // CM.PUSH, CM.POP, CM.POPRETZ, CM.POPRET
+                let rlist = (instruction >> 4) & 0b1111;
+                // RV32E only has ra, s0, and s1 to save
+                if rlist < 4 || (X::COUNT < 32 && rlist > 6) {
+                    return illegal;
+                }
+                let count = if rlist == 15 { 13 } else { rlist - 3 };
+                let saved = &SAVED[..count as usize];
+                let base = match rlist {
+                    4..=7 => 16,
+                    8..=11 => 32,
+                    12..=14 => 48,
+                    _ => 64,
+                };
+                let stack_adj = base + ((instruction >> 2) & 0b11) * 16;
+                let sp = self.get_register(2);
+                if op == 0b11000 {
+                    // the last register in the list goes just below sp
+                    let mut address = sp;
+                    for &reg in saved.iter().rev() {
+                        address = address.wrapping_sub(4);
+                        let word = self.get_register(reg);
+                        map_store(address, env.write_word(address, word, !0))?;
+                        env.account_memory_store(address);
+                    }
+                    self.set_register(2, sp.wrapping_sub(stack_adj));
+                } else {
+                    let top = sp.wrapping_add(stack_adj);
+                    let mut address = top;
+                    for &reg in saved.iter().rev() {
+                        address = address.wrapping_sub(4);
+                        let word =
+                            map_load(address, env.read_word(address, !0))?;
+                        self.set_register(reg, word);
+                        env.account_memory_load(address);
+                    }
+                    self.set_register(2, top);
+                    if op == 0b11100 {
+                        self.set_register(10, 0);
+                    }
+                    if op != 0b11010 {
+                        self.set_pc(self.get_register(1) & !1);
+                        env.account_jump_op();
+                        return Ok(());
+                    }
+                }
+            }
+            _ if (instruction >> 10) & 0b111 == 0b011 => {
+                let r1s = sreg((instruction >> 7) & 0b111);
+                let r2s = sreg((instruction >> 2) & 0b111);
+                if r1s == r2s || r1s >= X::COUNT || r2s >= X::COUNT {
+                    return illegal;
+                }
+                match (instruction >> 5) & 0b11 {
+                    0b01 => {
+                        // CM.MVSA01
+                        self.set_register(r1s, self.get_register(10));
+                        self.set_register(r2s, self.get_register(11));
+                    }
+                    0b11 => {
+                        // CM.MVA01S
+                        let (a0, a1) =
+                            (self.get_register(r1s), self.get_register(r2s));
+                        self.set_register(10, a0);
+                        self.set_register(11, a1);
+                    }
+                    _ => return illegal,
+                }
+                env.account_generic_op();
+            }
+            _ => return illegal,
+        }
+        self.set_pc(this_pc.wrapping_add(2));
+        Ok(())
+    }
+    /// Execute a Zcmt instruction, `CM.JT` or `CM.JALT`. `instruction` is the
+    /// 16-bit instruction, already known to be one of them.
+    pub(super) fn zcmt<Env: ExecutionEnvironment>(
+        &mut self,
+        env: &mut Env,
+        instruction: u32,
+    ) -> Result<(), (ExceptionCause, u32)> {
+        let this_pc = self.get_pc();
+        let index = (instruction >> 2) & 0xFF;
+        // the table fetch counts as an instruction fetch, and the mode bits
+        // of jvt are always zero
+        let address = self.jvt.wrapping_add(index * 4);
+        let target = map_ifetch(address, env.read_instruction(address))?;
+        if index >= 32 {
+            // CM.JALT
+            self.set_register(1, this_pc.wrapping_add(2));
+        }
+        self.set_pc(target & !1);
+        env.account_jump_op();
+        Ok(())
+    }
+}
@@ -0,0 +1,891 @@
+//! A cache of decoded instructions, so that hot code doesn't have to be
+//! fetched and decoded over and over.
+
+use super::*;
+
+use std::fmt::{self, Debug, Formatter};
+
+/// How many instructions a [`DecodeCache`] holds, unless you ask for
+/// something else.
+pub const DEFAULT_DECODE_CACHE_SIZE: usize = 8192;
+
+#[derive(Copy, Clone, Debug)]
+struct Slot {
+    /// The address the instruction was fetched from, or an odd number
+    /// (which no instruction can be fetched from) if the slot is empty.
+    address: u32,
+    decoded: DecodedInstruction,
+}
+
+const EMPTY: Slot = Slot {
+    address: 1,
+    decoded: DecodedInstruction {
+        original: 0,
+        expanded: 0,
+        op: FastOp::Slow,
+        rd: 0,
+        rs1: 0,
+        rs2: 0,
+        imm: 0,
+        gates: 0,
+    },
+};
+
+/// Wraps an [`ExecutionEnvironment`], and remembers the instructions the
+/// CPU fetches and decodes, so that executing them again doesn't have to go
+/// through [`read_instruction`](ExecutionEnvironment::read_instruction) or
+/// the compressed instruction decoder. Everything else goes to the wrapped
+/// environment.
+///
+/// The cache is direct mapped: each instruction has exactly one slot it can
+/// go in, chosen by its address, and it evicts whatever was there. Cached
+/// instructions are forgotten when:
+///
+/// - `FENCE.I` is executed
+/// - a store through this environment touches any of their bytes
+/// - you call [`flush`](Self::flush) or [`flush_range`](Self::flush_range)
+///
+/// So you **must** flush the cache yourself if memory changes behind its
+/// back (e.g. through [`inner_mut`](Self::inner_mut), another hart's
+/// environment, or DMA), or if the same address can start fetching from
+/// different memory (e.g. because you changed an address translation).
+/// Instructions fetched from I/O addresses are never cached. Enabling or
+/// disabling extensions doesn't need a flush; cached instructions are
+/// decoded again if that changes what they decode to.
+///
+/// To get any use out of this, the `Cpu` has to see the cache, so wrap it
+/// *inside* anything that might intercept stores, like
+/// [`Recorder`](crate::Recorder).
+#[derive(Clone)]
+pub struct DecodeCache<Env: ExecutionEnvironment> {
+    inner: Env,
+    slots: Box<[Slot]>,
+}
+
+impl<Env: ExecutionEnvironment> DecodeCache<Env> {
+    /// Wrap an environment, with an empty cache of
+    /// [`DEFAULT_DECODE_CACHE_SIZE`] instructions.
+    pub fn new(inner: Env) -> DecodeCache<Env> {
+        DecodeCache::with_size(inner, DEFAULT_DECODE_CACHE_SIZE)
+    }
+    /// Wrap an environment, with an empty cache of `size` instructions,
+    /// rounded up to a power of two.
+    pub fn with_size(inner: Env, size: usize) -> DecodeCache<Env> {
+        DecodeCache {
+            inner,
+            slots: vec![EMPTY; size.max(1).next_power_of_two()]
+                .into_boxed_slice(),
+        }
+    }
+    /// The wrapped environment.
+    pub fn inner(&self) -> &Env {
+        &self.inner
+    }
+    /// The wrapped environment. If you change any memory that might contain
+    /// code through this, call [`flush`](Self::flush) or
+    /// [`flush_range`](Self::flush_range) afterward.
+    pub fn inner_mut(&mut self) -> &mut Env {
+        &mut self.inner
+    }
+    /// Take apart the `DecodeCache`, returning the wrapped environment.
+    pub fn into_inner(self) -> Env {
+        self.inner
+    }
+    /// Forget every cached instruction.
+    pub fn flush(&mut self) {
+        self.slots.fill(EMPTY);
+    }
+    /// Forget every cached instruction that overlaps the `length` bytes
+    /// starting at `address`.
+    pub fn flush_range(&mut self, address: u32, length: u32) {
+        if length / 2 >= self.slots.len() as u32 {
+            // every slot is in range, so don't bother checking
+            return self.flush();
+        }
+        // an instruction that starts up to two bytes early might overlap
+        let first = (address & !1).wrapping_sub(2);
+        for offset in (0..length.saturating_add(2)).step_by(2) {
+            let address = first.wrapping_add(offset);
+            let index = self.index(address);
+            if self.slots[index].address == address {
+                self.slots[index] = EMPTY;
+            }
+        }
+    }
+    fn index(&self, address: u32) -> usize {
+        (address >> 1) as usize & (self.slots.len() - 1)
+    }
+}
+
+impl<Env: ExecutionEnvironment + Debug> Debug for DecodeCache<Env> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        // (the slots themselves would be thousands of lines of noise)
+        f.debug_struct("DecodeCache")
+            .field("inner", &self.inner)
+            .field("size", &self.slots.len())
+            .finish()
+    }
+}
+
+impl<Env: ExecutionEnvironment> ExecutionEnvironment for DecodeCache<Env> {
+    const SUPPORT_A: bool = Env::SUPPORT_A;
+    const SUPPORT_C: bool = Env::SUPPORT_C;
+    const SUPPORT_M: bool = Env::SUPPORT_M;
+    const SUPPORT_ZBA: bool = Env::SUPPORT_ZBA;
+    const SUPPORT_ZBB: bool = Env::SUPPORT_ZBB;
+    const SUPPORT_ZBC: bool = Env::SUPPORT_ZBC;
+    const SUPPORT_ZBS: bool = Env::SUPPORT_ZBS;
+    const SUPPORT_ZBKB: bool = Env::SUPPORT_ZBKB;
+    const SUPPORT_ZBKC: bool = Env::SUPPORT_ZBKC;
+    const SUPPORT_ZBKX: bool = Env::SUPPORT_ZBKX;
+    const SUPPORT_ZKND: bool = Env::SUPPORT_ZKND;
+    const SUPPORT_ZKNE: bool = Env::SUPPORT_ZKNE;
+    const SUPPORT_ZKNH: bool = Env::SUPPORT_ZKNH;
+    const SUPPORT_ZKSED: bool = Env::SUPPORT_ZKSED;
+    const SUPPORT_ZKSH: bool = Env::SUPPORT_ZKSH;
+    const SUPPORT_ZKR: bool = Env::SUPPORT_ZKR;
+    const SUPPORT_ZICOND: bool = Env::SUPPORT_ZICOND;
+    const SUPPORT_ZFHMIN: bool = Env::SUPPORT_ZFHMIN;
+    const SUPPORT_ZFH: bool = Env::SUPPORT_ZFH;
+    const SUPPORT_ZFA: bool = Env::SUPPORT_ZFA;
+    const SUPPORT_ZFINX: bool = Env::SUPPORT_ZFINX;
+    const SUPPORT_ZDINX: bool = Env::SUPPORT_ZDINX;
+    const SUPPORT_ZFBFMIN: bool = Env::SUPPORT_ZFBFMIN;
+    const SUPPORT_ZVE32X: bool = Env::SUPPORT_ZVE32X;
+    const SUPPORT_ZVE32F: bool = Env::SUPPORT_ZVE32F;
+    const SUPPORT_P: bool = Env::SUPPORT_P;
+    const SUPPORT_ZCF: bool = Env::SUPPORT_ZCF;
+    const SUPPORT_ZCD: bool = Env::SUPPORT_ZCD;
+    const SUPPORT_ZCB: bool = Env::SUPPORT_ZCB;
+    const SUPPORT_ZCMP: bool = Env::SUPPORT_ZCMP;
+    const SUPPORT_ZCMT: bool = Env::SUPPORT_ZCMT;
+    const SUPPORT_ZICBOM: bool = Env::SUPPORT_ZICBOM;
+    const SUPPORT_ZICBOZ: bool = Env::SUPPORT_ZICBOZ;
+    const SUPPORT_ZICBOP: bool = Env::SUPPORT_ZICBOP;
+    const SUPPORT_ZAWRS: bool = Env::SUPPORT_ZAWRS;
+    const SUPPORT_ZIHINTPAUSE: bool = Env::SUPPORT_ZIHINTPAUSE;
+    const SUPPORT_ZIHINTNTL: bool = Env::SUPPORT_ZIHINTNTL;
+    const SUPPORT_ZACAS: bool = Env::SUPPORT_ZACAS;
+    const SUPPORT_ZABHA: bool = Env::SUPPORT_ZABHA;
+    const CACHE_BLOCK_SIZE: u32 = Env::CACHE_BLOCK_SIZE;
+    fn enable_a(&self) -> bool {
+        self.inner.enable_a()
+    }
+    fn enable_c(&self) -> bool {
+        self.inner.enable_c()
+    }
+    fn enable_m(&self) -> bool {
+        self.inner.enable_m()
+    }
+    fn enable_f(&self) -> bool {
+        self.inner.enable_f()
+    }
+    fn enable_d(&self) -> bool {
+        self.inner.enable_d()
+    }
+    fn enable_q(&self) -> bool {
+        self.inner.enable_q()
+    }
+    fn enable_zicsr(&self) -> bool {
+        self.inner.enable_zicsr()
+    }
+    fn enable_zifence(&self) -> bool {
+        self.inner.enable_zifence()
+    }
+    fn enable_zba(&self) -> bool {
+        self.inner.enable_zba()
+    }
+    fn enable_zbb(&self) -> bool {
+        self.inner.enable_zbb()
+    }
+    fn enable_zbc(&self) -> bool {
+        self.inner.enable_zbc()
+    }
+    fn enable_zbs(&self) -> bool {
+        self.inner.enable_zbs()
+    }
+    fn enable_zbkb(&self) -> bool {
+        self.inner.enable_zbkb()
+    }
+    fn enable_zbkc(&self) -> bool {
+        self.inner.enable_zbkc()
+    }
+    fn enable_zbkx(&self) -> bool {
+        self.inner.enable_zbkx()
+    }
+    fn enable_zknd(&self) -> bool {
+        self.inner.enable_zknd()
+    }
+    fn enable_zkne(&self) -> bool {
+        self.inner.enable_zkne()
+    }
+    fn enable_zknh(&self) -> bool {
+        self.inner.enable_zknh()
+    }
+    fn enable_zksed(&self) -> bool {
+        self.inner.enable_zksed()
+    }
+    fn enable_zksh(&self) -> bool {
+        self.inner.enable_zksh()
+    }
+    fn enable_zkr(&self) -> bool {
+        self.inner.enable_zkr()
+    }
+    fn enable_zicond(&self) -> bool {
+        self.inner.enable_zicond()
+    }
+    fn enable_zfhmin(&self) -> bool {
+        self.inner.enable_zfhmin()
+    }
+    fn enable_zfh(&self) -> bool {
+        self.inner.enable_zfh()
+    }
+    fn enable_zfa(&self) -> bool {
+        self.inner.enable_zfa()
+    }
+    fn enable_zfinx(&self) -> bool {
+        self.inner.enable_zfinx()
+    }
+    fn enable_zdinx(&self) -> bool {
+        self.inner.enable_zdinx()
+    }
+    fn enable_zfbfmin(&self) -> bool {
+        self.inner.enable_zfbfmin()
+    }
+    fn enable_zve32x(&self) -> bool {
+        self.inner.enable_zve32x()
+    }
+    fn enable_zve32f(&self) -> bool {
+        self.inner.enable_zve32f()
+    }
+    fn enable_p(&self) -> bool {
+        self.inner.enable_p()
+    }
+    fn enable_zcf(&self) -> bool {
+        self.inner.enable_zcf()
+    }
+    fn enable_zcd(&self) -> bool {
+        self.inner.enable_zcd()
+    }
+    fn enable_zcb(&self) -> bool {
+        self.inner.enable_zcb()
+    }
+    fn enable_zcmp(&self) -> bool {
+        self.inner.enable_zcmp()
+    }
+    fn enable_zcmt(&self) -> bool {
+        self.inner.enable_zcmt()
+    }
+    fn enable_zicbom(&self) -> bool {
+        self.inner.enable_zicbom()
+    }
+    fn enable_zicboz(&self) -> bool {
+        self.inner.enable_zicboz()
+    }
+    fn enable_zicbop(&self) -> bool {
+        self.inner.enable_zicbop()
+    }
+    fn enable_zawrs(&self) -> bool {
+        self.inner.enable_zawrs()
+    }
+    fn enable_zacas(&self) -> bool {
+        self.inner.enable_zacas()
+    }
+    fn enable_zabha(&self) -> bool {
+        self.inner.enable_zabha()
+    }
+    fn read_word(
+        &mut self,
+        address: u32,
+        mask: u32,
+    ) -> Result<u32, MemoryAccessFailure> {
+        self.inner.read_word(address, mask)
+    }
+    fn read_instruction(
+        &mut self,
+        address: u32,
+    ) -> Result<u32, MemoryAccessFailure> {
+        self.inner.read_instruction(address)
+    }
+    fn cached_instruction(
+        &mut self,
+        address: u32,
+    ) -> Option<DecodedInstruction> {
+        let slot = &self.slots[self.index(address)];
+        if slot.address == address {
+            Some(slot.decoded)
+        } else {
+            None
+        }
+    }
+    fn cache_instruction(
+        &mut self,
+        address: u32,
+        decoded: DecodedInstruction,
+    ) {
+        // instructions fetched from I/O addresses might be different next
+        // time
+        if !self.inner.is_io_address(address) {
+            let index = self.index(address);
+            self.slots[index] = Slot { address, decoded };
+        }
+    }
+    fn read_half(&mut self, address: u32) -> Result<u16, MemoryAccessFailure> {
+        self.inner.read_half(address)
+    }
+    fn read_byte(&mut self, address: u32) -> Result<u8, MemoryAccessFailure> {
+        self.inner.read_byte(address)
+    }
+    fn write_word(
+        &mut self,
+        address: u32,
+        data: u32,
+        mask: u32,
+    ) -> Result<(), MemoryAccessFailure> {
+        self.flush_range(address, 4);
+        self.inner.write_word(address, data, mask)
+    }
+    fn write_half(
+        &mut self,
+        address: u32,
+        data: u16,
+    ) -> Result<(), MemoryAccessFailure> {
+        self.flush_range(address, 2);
+        self.inner.write_half(address, data)
+    }
+    fn write_byte(
+        &mut self,
+        address: u32,
+        data: u8,
+    ) -> Result<(), MemoryAccessFailure> {
+        self.flush_range(address, 1);
+        self.inner.write_byte(address, data)
+    }
+    fn load_reserved_word(
+        &mut self,
+        address: u32,
+    ) -> Result<u32, MemoryAccessFailure> {
+        self.inner.load_reserved_word(address)
+    }
+    fn store_reserved_word(
+        &mut self,
+        address: u32,
+        data: u32,
+    ) -> Result<bool, MemoryAccessFailure> {
+        self.flush_range(address, 4);
+        self.inner.store_reserved_word(address, data)
+    }
+    fn atomic_modify_word(
+        &mut self,
+        address: u32,
+        operand: u32,
+        op: fn(u32, u32) -> u32,
+    ) -> Result<u32, MemoryAccessFailure> {
+        self.flush_range(address, 4);
+        self.inner.atomic_modify_word(address, operand, op)
+    }
+    fn compare_and_swap_word(
+        &mut self,
+        address: u32,
+        expected: u32,
+        new: u32,
+    ) -> Result<u32, MemoryAccessFailure> {
+        self.flush_range(address, 4);
+        self.inner.compare_and_swap_word(address, expected, new)
+    }
+    fn atomic_modify_half(
+        &mut self,
+        address: u32,
+        operand: u16,
+        op: fn(u16, u16) -> u16,
+    ) -> Result<u16, MemoryAccessFailure> {
+        self.flush_range(address, 2);
+        self.inner.atomic_modify_half(address, operand, op)
+    }
+    fn atomic_modify_byte(
+        &mut self,
+        address: u32,
+        operand: u8,
+        op: fn(u8, u8) -> u8,
+    ) -> Result<u8, MemoryAccessFailure> {
+        self.flush_range(address, 1);
+        self.inner.atomic_modify_byte(address, operand, op)
+    }
+    fn compare_and_swap_double(
+        &mut self,
+        address: u32,
+        expected: u64,
+        new: u64,
+    ) -> Result<u64, MemoryAccessFailure> {
+        let old =
+            self.inner.compare_and_swap_double(address, expected, new)?;
+        if old == expected {
+            self.flush_range(address, 8);
+        }
+        Ok(old)
+    }
+    fn compare_and_swap_half(
+        &mut self,
+        address: u32,
+        expected: u16,
+        new: u16,
+    ) -> Result<u16, MemoryAccessFailure> {
+        self.flush_range(address, 2);
+        self.inner.compare_and_swap_half(address, expected, new)
+    }
+    fn compare_and_swap_byte(
+        &mut self,
+        address: u32,
+        expected: u8,
+        new: u8,
+    ) -> Result<u8, MemoryAccessFailure> {
+        self.flush_range(address, 1);
+        self.inner.compare_and_swap_byte(address, expected, new)
+    }
+    fn cache_block_zero(
+        &mut self,
+        address: u32,
+    ) -> Result<(), MemoryAccessFailure> {
+        self.flush_range(address, Self::CACHE_BLOCK_SIZE);
+        self.inner.cache_block_zero(address)
+    }
+    fn is_io_address(&self, address: u32) -> bool {
+        self.inner.is_io_address(address)
+    }
+    fn is_io_csr(&self, csr_number: u32) -> bool {
+        self.inner.is_io_csr(csr_number)
+    }
+    fn perform_ecall<F: FloatBits, V: VectorBits, X: IntegerRegisters>(
+        &mut self,
+        cpu: &mut Cpu<F, V, X>,
+    ) -> Result<(), (ExceptionCause, u32)> {
+        self.inner.perform_ecall(cpu)
+    }
+    fn perform_ebreak<F: FloatBits, V: VectorBits, X: IntegerRegisters>(
+        &mut self,
+        cpu: &mut Cpu<F, V, X>,
+    ) -> Result<(), (ExceptionCause, u32)> {
+        self.inner.perform_ebreak(cpu)
+    }
+    fn perform_custom<F: FloatBits, V: VectorBits, X: IntegerRegisters>(
+        &mut self,
+        cpu: &mut Cpu<F, V, X>,
+        instruction: CustomInstruction,
+    ) -> Result<(), (ExceptionCause, u32)> {
+        self.inner.perform_custom(cpu, instruction)
+    }
+    fn read_csr(&mut self, csr_number: u32) -> Result<u32, ExceptionCause> {
+        self.inner.read_csr(csr_number)
+    }
+    fn write_csr(
+        &mut self,
+        csr_number: u32,
+        new_value: u32,
+    ) -> Result<(), ExceptionCause> {
+        self.inner.write_csr(csr_number, new_value)
+    }
+    fn poll_entropy(&mut self) -> Result<EntropyStatus, ExceptionCause> {
+        self.inner.poll_entropy()
+    }
+    fn fence_i(&mut self) {
+        self.flush();
+        self.inner.fence_i()
+    }
+    fn cache_block_op(
+        &mut self,
+        address: u32,
+        op: CacheBlockOp,
+    ) -> Result<(), MemoryAccessFailure> {
+        self.inner.cache_block_op(address, op)
+    }
+    fn prefetch(&mut self, address: u32, kind: PrefetchKind) {
+        self.inner.prefetch(address, kind)
+    }
+    fn pause(&mut self) {
+        self.inner.pause()
+    }
+    fn wait_on_reservation(
+        &mut self,
+        short_timeout: bool,
+    ) -> Result<(), ExceptionCause> {
+        self.inner.wait_on_reservation(short_timeout)
+    }
+    fn non_temporal_hint(&mut self, locality: NonTemporalLocality) {
+        self.inner.non_temporal_hint(locality)
+    }
+    fn read_mtime(&mut self) -> Result<u64, ExceptionCause> {
+        self.inner.read_mtime()
+    }
+    fn cycle_count(&self) -> Option<u64> {
+        self.inner.cycle_count()
+    }
+    fn read_vs(&self) -> ExtensionStatus {
+        self.inner.read_vs()
+    }
+    fn write_vs(&mut self, status: ExtensionStatus) {
+        self.inner.write_vs(status)
+    }
+    fn read_fs(&self) -> ExtensionStatus {
+        self.inner.read_fs()
+    }
+    fn write_fs(&mut self, status: ExtensionStatus) {
+        self.inner.write_fs(status)
+    }
+    fn use_accurate_single_sqrt(&self) -> bool {
+        self.inner.use_accurate_single_sqrt()
+    }
+    fn use_accurate_double_sqrt(&self) -> bool {
+        self.inner.use_accurate_double_sqrt()
+    }
+    fn use_accurate_quad_sqrt(&self) -> bool {
+        self.inner.use_accurate_quad_sqrt()
+    }
+    fn account_ifetch(&mut self, pc: u32) {
+        self.inner.account_ifetch(pc)
+    }
+    fn account_retire(&mut self) {
+        self.inner.account_retire()
+    }
+    fn account_generic_op(&mut self) {
+        self.inner.account_generic_op()
+    }
+    fn account_memory_load(&mut self, address: u32) {
+        self.inner.account_memory_load(address)
+    }
+    fn account_memory_store(&mut self, address: u32) {
+        self.inner.account_memory_store(address)
+    }
+    fn account_memory_double_load(&mut self, address: u32) {
+        self.inner.account_memory_double_load(address)
+    }
+    fn account_memory_double_store(&mut self, address: u32) {
+        self.inner.account_memory_double_store(address)
+    }
+    fn account_memory_quad_load(&mut self, address: u32) {
+        self.inner.account_memory_quad_load(address)
+    }
+    fn account_memory_quad_store(&mut self, address: u32) {
+        self.inner.account_memory_quad_store(address)
+    }
+    fn account_memory_op(&mut self, address: u32) {
+        self.inner.account_memory_op(address)
+    }
+    fn account_alu_op(&mut self) {
+        self.inner.account_alu_op()
+    }
+    fn account_mul_op(&mut self) {
+        self.inner.account_mul_op()
+    }
+    fn account_div_op(&mut self) {
+        self.inner.account_div_op()
+    }
+    fn account_clmul_op(&mut self) {
+        self.inner.account_clmul_op()
+    }
+    fn account_xperm_op(&mut self) {
+        self.inner.account_xperm_op()
+    }
+    fn account_crypto_op(&mut self) {
+        self.inner.account_crypto_op()
+    }
+    fn account_amo_op(&mut self) {
+        self.inner.account_amo_op()
+    }
+    fn account_jump_op(&mut self) {
+        self.inner.account_jump_op()
+    }
+    fn account_branch_op(&mut self, did_take: bool, was_forward: bool) {
+        self.inner.account_branch_op(did_take, was_forward)
+    }
+    fn account_float_op(&mut self, num_words: u32) {
+        self.inner.account_float_op(num_words)
+    }
+    fn account_float_divide(&mut self, num_words: u32) {
+        self.inner.account_float_divide(num_words)
+    }
+    fn account_float_ternop(&mut self, num_words: u32) {
+        self.inner.account_float_ternop(num_words)
+    }
+    fn account_fcvt_from_int(&mut self, num_words: u32) {
+        self.inner.account_fcvt_from_int(num_words)
+    }
+    fn account_fcvt_to_int(&mut self, num_words: u32) {
+        self.inner.account_fcvt_to_int(num_words)
+    }
+    fn account_sqrt(&mut self, num_words: u32, num_iterations: u32) {
+        self.inner.account_sqrt(num_words, num_iterations)
+    }
+    fn account_vector_op(&mut self, elements: u32) {
+        self.inner.account_vector_op(elements)
+    }
+    fn account_custom_op(&mut self) {
+        self.inner.account_custom_op()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    const PROGRAM: [u32; 5] = [
+        0x00200293, // li x5, 2
+        0x00138393, // 1: addi x7, x7, 1
+        0x00602223, // sw x6, 4(x0)
+        0xfff28293, // addi x5, x5, -1
+        0xfe029ae3, // bnez x5, 1b
+    ];
+    /// Every instruction that can skip the full decoder, and some that
+    /// can't, compressed where possible. Loops three times, then ends at 150.
+    const MIXED: [u32; 38] = [
+        0x10000513, 0x75fd428d, 0x00000617, 0xffb58693, 0x0006a713,
+        0x0076b793, 0x0556c813, 0x0016e893, 0x07f6f913, 0x00369993,
+        0x0036da13, 0x4036da93, 0x01498b33, 0x41498bb3, 0x00569c33,
+        0x0056acb3, 0x0056bd33, 0x0056cdb3, 0x0056de33, 0x4056deb3,
+        0x0056ef33, 0x0056ffb3, 0x02568333, 0x00d50023, 0x00d51123,
+        0x0403c154, 0x44830005, 0x11830005, 0x52030025, 0x23830025,
+        0x20090045, 0x0056c263, 0x0056d463, 0x0056e263, 0x0056f263,
+        0x00528263, 0x9ae312fd, 0x0000f602,
+    ];
+    struct Ram {
+        words: Vec<u32>,
+        fetches: u32,
+        disable_cm: bool,
+    }
+    impl ExecutionEnvironment for Ram {
+        const SUPPORT_ZCB: bool = true;
+        fn enable_c(&self) -> bool {
+            !self.disable_cm
+        }
+        fn enable_m(&self) -> bool {
+            !self.disable_cm
+        }
+        fn read_word(
+            &mut self,
+            address: u32,
+            _mask: u32,
+        ) -> Result<u32, MemoryAccessFailure> {
+            self.words
+                .get(address as usize / 4)
+                .copied()
+                .ok_or(MemoryAccessFailure::AccessFault)
+        }
+        fn read_instruction(
+            &mut self,
+            address: u32,
+        ) -> Result<u32, MemoryAccessFailure> {
+            self.fetches += 1;
+            let low = self.read_word(address & !3, !0)?;
+            if address & 2 == 0 {
+                Ok(low)
+            } else {
+                let high = self.read_word((address & !3) + 4, !0)?;
+                Ok(low >> 16 | high << 16)
+            }
+        }
+        fn write_word(
+            &mut self,
+            address: u32,
+            data: u32,
+            mask: u32,
+        ) -> Result<(), MemoryAccessFailure> {
+            let word = self
+                .words
+                .get_mut(address as usize / 4)
+                .ok_or(MemoryAccessFailure::AccessFault)?;
+            *word = (*word & !mask) | (data & mask);
+            Ok(())
+        }
+        fn load_reserved_word(
+            &mut self,
+            _address: u32,
+        ) -> Result<u32, MemoryAccessFailure> {
+            unreachable!()
+        }
+        fn store_reserved_word(
+            &mut self,
+            _address: u32,
+            _data: u32,
+        ) -> Result<bool, MemoryAccessFailure> {
+            unreachable!()
+        }
+        fn is_io_address(&self, address: u32) -> bool {
+            address >= 0x1000
+        }
+    }
+    fn ram(program: &[u32]) -> Ram {
+        let mut words = vec![0; 128];
+        words[..program.len()].copy_from_slice(program);
+        Ram {
+            words,
+            fetches: 0,
+            disable_cm: false,
+        }
+    }
+    #[test]
+    fn self_modifying_code() {
+        let mut env = DecodeCache::new(ram(&PROGRAM));
+        let mut cpu = Cpu::<()>::new();
+        // addi x7, x7, 16
+        cpu.set_register(6, 0x01038393);
+        while cpu.get_pc() != 20 {
+            cpu.step(&mut env).unwrap();
+        }
+        // the second time around, the patched instruction ran
+        assert_eq!(cpu.get_register(7), 17);
+        // every instruction was fetched once, plus the patched one again
+        assert_eq!(env.inner().fetches, 6);
+    }
+    #[test]
+    fn invalidation() {
+        let mut env = DecodeCache::with_size(ram(&PROGRAM), 4);
+        let decoded = DecodedInstruction {
+            original: PROGRAM[1],
+            expanded: PROGRAM[1],
+            ..EMPTY.decoded
+        };
+        env.cache_instruction(4, decoded);
+        assert_eq!(env.cached_instruction(4), Some(decoded));
+        // a store to the upper half of the instruction
+        env.write_half(6, 0).unwrap();
+        assert_eq!(env.cached_instruction(4), None);
+        // a store to the next word over
+        env.cache_instruction(4, decoded);
+        env.write_word(8, 0, !0).unwrap();
+        assert_eq!(env.cached_instruction(4), Some(decoded));
+        env.fence_i();
+        assert_eq!(env.cached_instruction(4), None);
+        // a doubleword CAS only writes if it succeeds
+        env.cache_instruction(4, decoded);
+        env.compare_and_swap_double(0, !0, 0).unwrap();
+        assert_eq!(env.cached_instruction(4), Some(decoded));
+        let words = &env.inner().words;
+        let old = (words[1] as u64) << 32 | words[0] as u64;
+        env.compare_and_swap_double(0, old, old).unwrap();
+        assert_eq!(env.cached_instruction(4), None);
+        env.cache_instruction(0x1000, decoded);
+        assert_eq!(env.cached_instruction(0x1000), None);
+    }
+    #[test]
+    fn matches_full_decoder() {
+        fn run<Env: ExecutionEnvironment>(env: &mut Counters<Env>) -> Cpu<()> {
+            // count every event
+            for event in 0..29 {
+                env.write_csr(0x323 + event, 1 << event).unwrap();
+            }
+            let mut cpu = Cpu::<()>::new();
+            while cpu.get_pc() != 150 {
+                cpu.step(env).unwrap();
+            }
+            cpu
+        }
+        let mut uncached = Counters::new(ram(&MIXED));
+        let mut cached = Counters::new(DecodeCache::new(ram(&MIXED)));
+        let expected = run(&mut uncached);
+        let actual = run(&mut cached);
+        for register in 1..32 {
+            assert_eq!(
+                actual.get_register(register),
+                expected.get_register(register),
+                "x{register}"
+            );
+        }
+        assert_eq!(cached.inner().inner().words, uncached.inner().words);
+        for counter in 3..32 {
+            assert_eq!(
+                cached.hpmcounter(counter),
+                uncached.hpmcounter(counter),
+                "hpmcounter{counter}"
+            );
+        }
+        // every instruction was only fetched the first time around
+        assert_eq!(uncached.inner().fetches, 116);
+        assert_eq!(cached.inner().inner().fetches, 40);
+    }
+    #[test]
+    fn extension_changes() {
+        // mul x5, x6, x7; c.addi x5, 1
+        let mut env = DecodeCache::new(ram(&[0x027302b3, 0x00010285]));
+        let mut cpu = Cpu::<()>::new();
+        cpu.step(&mut env).unwrap();
+        cpu.step(&mut env).unwrap();
+        assert!(env.cached_instruction(0).is_some());
+        assert!(env.cached_instruction(4).is_some());
+        // both are illegal once M and C are turned off
+        env.inner_mut().disable_cm = true;
+        for pc in [0, 4] {
+            cpu.set_pc(pc);
+            let e = cpu.step(&mut env).unwrap_err();
+            assert_eq!(e.mcause, ExceptionCause::IllegalInstruction);
+        }
+        // and legal again once they're back
+        env.inner_mut().disable_cm = false;
+        cpu.set_register(6, 3);
+        cpu.set_register(7, 5);
+        cpu.set_pc(0);
+        cpu.step(&mut env).unwrap();
+        cpu.step(&mut env).unwrap();
+        assert_eq!(cpu.get_register(5), 16);
+    }
+    #[test]
+    fn every_compressed_instruction() {
+        fn counted<Env: ExecutionEnvironment>(env: Env) -> Counters<Env> {
+            let mut env = Counters::new(env);
+            for event in 0..29 {
+                env.write_csr(0x323 + event, 1 << event).unwrap();
+            }
+            env
+        }
+        let mut start = Cpu::<()>::new();
+        // a mix of zero, negative, and valid addresses
+        for register in 1..32 {
+            let value = register * 0x2C % 0x180;
+            let value = [0, !value, value][register as usize % 3];
+            start.set_register(register, value);
+        }
+        start.set_pc(0x100);
+        for instruction in 0..=0xFFFF {
+            if instruction & 0b11 == 0b11 {
+                continue;
+            }
+            let mut program = vec![0; 0x100 / 4];
+            program.push(instruction);
+            // cache it...
+            let mut cache = DecodeCache::with_size(ram(&program), 4);
+            if start.clone().step(&mut cache).is_err() {
+                continue;
+            }
+            let Some(decoded) = cache.cached_instruction(0x100) else {
+                continue;
+            };
+            // ...then run it again from the cache, and without one
+            let mut cached = counted(DecodeCache::with_size(ram(&program), 4));
+            cached.cache_instruction(0x100, decoded);
+            let mut uncached = counted(ram(&program));
+            let (mut actual, mut expected) = (start.clone(), start.clone());
+            assert_eq!(
+                actual.step(&mut cached).map_err(|e| e.mcause),
+                expected.step(&mut uncached).map_err(|e| e.mcause),
+                "{instruction:04x}"
+            );
+            assert_eq!(
+                actual.get_pc(),
+                expected.get_pc(),
+                "{instruction:04x}"
+            );
+            for register in 1..32 {
+                assert_eq!(
+                    actual.get_register(register),
+                    expected.get_register(register),
+                    "{instruction:04x} x{register}"
+                );
+            }
+            assert_eq!(cached.inner().inner().words, uncached.inner().words);
+            for counter in 3..32 {
+                assert_eq!(
+                    cached.hpmcounter(counter),
+                    uncached.hpmcounter(counter),
+                    "{instruction:04x} hpmcounter{counter}"
+                );
+            }
+        }
+    }
+}
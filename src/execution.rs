@@ -1,8 +1,10 @@
-use super::{Cpu, ExceptionCause, FloatBits};
+use super::{
+    Cpu, ExceptionCause, FastOp, FloatBits, IntegerRegisters, VectorBits,
+};
 
 /// Reasons that a memory access can fail.
 #[repr(i32)]
-#[derive(Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum MemoryAccessFailure {
     /// The address was misaligned, and this system doesn't support misaligned
     /// access.
@@ -54,6 +56,166 @@ impl ExtensionStatus {
     }
 }
 
+/// The state of the entropy source, as read from the `seed` CSR (Zkr). See
+/// [`poll_entropy`](ExecutionEnvironment::poll_entropy).
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum EntropyStatus {
+    /// The source is running its built-in self test. Try again later.
+    Bist,
+    /// The source has no entropy ready yet. Try again later.
+    Wait,
+    /// Here are sixteen bits of fresh entropy.
+    Es16(u16),
+    /// The source has failed, and won't be giving out any more entropy.
+    Dead,
+}
+
+impl EntropyStatus {
+    /// Convert to the value read from the `seed` CSR. OPST goes in the top
+    /// two bits (0b00 = BIST, 0b01 = WAIT, 0b10 = ES16, 0b11 = DEAD), and the
+    /// entropy, if any, in the bottom sixteen.
+    pub fn to_seed(&self) -> u32 {
+        match *self {
+            EntropyStatus::Bist => 0b00 << 30,
+            EntropyStatus::Wait => 0b01 << 30,
+            EntropyStatus::Es16(entropy) => 0b10 << 30 | entropy as u32,
+            EntropyStatus::Dead => 0b11 << 30,
+        }
+    }
+    /// Convert from a value read from the `seed` CSR. Bits 29 through 16 are
+    /// ignored, as is the entropy field if OPST isn't ES16.
+    pub fn from_seed(seed: u32) -> EntropyStatus {
+        match seed >> 30 {
+            0b00 => EntropyStatus::Bist,
+            0b01 => EntropyStatus::Wait,
+            0b10 => EntropyStatus::Es16(seed as u16),
+            0b11 => EntropyStatus::Dead,
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// A cache block management operation (Zicbom). See
+/// [`cache_block_op`](ExecutionEnvironment::cache_block_op).
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum CacheBlockOp {
+    /// `CBO.CLEAN`: write the block back to memory if it's dirty, and keep
+    /// it in the cache.
+    Clean,
+    /// `CBO.FLUSH`: write the block back to memory if it's dirty, and evict
+    /// it from the cache.
+    Flush,
+    /// `CBO.INVAL`: evict the block from the cache *without* writing it
+    /// back. (The privileged spec lets the OS turn this into a flush.)
+    Invalidate,
+}
+
+/// What a prefetch hint (Zicbop) is getting ready for. See
+/// [`prefetch`](ExecutionEnvironment::prefetch).
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum PrefetchKind {
+    /// `PREFETCH.I`: the block will be executed soon.
+    Instruction,
+    /// `PREFETCH.R`: the block will be read soon.
+    Read,
+    /// `PREFETCH.W`: the block will be written soon.
+    Write,
+}
+
+/// Which levels of the memory hierarchy a non-temporal locality hint
+/// (Zihintntl) says the next memory access won't benefit from. See
+/// [`non_temporal_hint`](ExecutionEnvironment::non_temporal_hint).
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum NonTemporalLocality {
+    /// `NTL.P1`: the innermost private cache.
+    InnermostPrivate,
+    /// `NTL.PALL`: all private caches.
+    AllPrivate,
+    /// `NTL.S1`: the innermost shared cache.
+    InnermostShared,
+    /// `NTL.ALL`: all caches.
+    All,
+}
+
+/// An instruction in one of the four major opcodes the base ISA reserves for
+/// custom extensions, with its fields already pulled out. See
+/// [`perform_custom`](ExecutionEnvironment::perform_custom).
+///
+/// Every field is extracted as if the instruction were R-type or I-type;
+/// which of them actually mean anything is up to your extension.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CustomInstruction {
+    /// Which of `custom-0` through `custom-3` this is, 0 through 3.
+    pub opcode: u32,
+    /// Bits 11 through 7.
+    pub rd: u32,
+    /// Bits 14 through 12.
+    pub funct3: u32,
+    /// Bits 19 through 15.
+    pub rs1: u32,
+    /// Bits 24 through 20.
+    pub rs2: u32,
+    /// Bits 31 through 25.
+    pub funct7: u32,
+    /// Bits 31 through 20, sign extended.
+    pub imm: i32,
+    /// The whole instruction.
+    pub instruction: u32,
+}
+
+impl CustomInstruction {
+    /// Pull the fields out of an instruction known to be in one of the
+    /// custom major opcodes.
+    pub(crate) fn decode(instruction: u32) -> CustomInstruction {
+        CustomInstruction {
+            opcode: match (instruction >> 2) & 0b11111 {
+                0b00010 => 0,
+                0b01010 => 1,
+                0b10110 => 2,
+                _ => 3,
+            },
+            rd: (instruction >> 7) & 0b11111,
+            funct3: (instruction >> 12) & 0b111,
+            rs1: (instruction >> 15) & 0b11111,
+            rs2: (instruction >> 20) & 0b11111,
+            funct7: instruction >> 25,
+            imm: (instruction as i32) >> 20,
+            instruction,
+        }
+    }
+}
+
+/// An instruction that has already been fetched and decoded, as far as it
+/// can be without knowing the state of the registers. See
+/// [`cached_instruction`](ExecutionEnvironment::cached_instruction).
+///
+/// What's inside is none of your business, and may change even on patch
+/// releases. Store these exactly as you got them.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct DecodedInstruction {
+    /// The instruction as fetched, with a compressed instruction's upper
+    /// half masked off.
+    pub(crate) original: u32,
+    /// The 32-bit instruction a compressed instruction expands to. Equal to
+    /// `original` for 32-bit instructions, and for compressed instructions
+    /// simple enough to execute without expanding.
+    pub(crate) expanded: u32,
+    /// Whether `expanded` can skip the full decoder, and if so, what it
+    /// does.
+    pub(crate) op: FastOp,
+    /// The operand fields of `expanded`, with the ones it doesn't use
+    /// zeroed.
+    pub(crate) rd: u8,
+    pub(crate) rs1: u8,
+    pub(crate) rs2: u8,
+    /// The immediate of `expanded`, already extracted.
+    pub(crate) imm: u32,
+    /// Which of the extensions that decoding depends on were enabled when
+    /// this was decoded. If that's changed since, it has to be decoded
+    /// again.
+    pub(crate) gates: u8,
+}
+
 /// Everything *outside* of the core CPU: memory space, CSRs, extension state,
 /// cycle accounting. `rrv32` provides the core CPU, you provide one of these,
 /// together they make a whole system.
@@ -68,10 +230,209 @@ pub trait ExecutionEnvironment {
     const SUPPORT_A: bool = true;
     /// Set to true (default) if the C extension should ever be supported. See
     /// also [`enable_c`](Self::enable_c).
+    ///
+    /// On its own, this is Zca, the compressed integer instructions. The
+    /// compressed float loads and stores are Zcf and Zcd, see
+    /// [`SUPPORT_ZCF`](Self::SUPPORT_ZCF) and
+    /// [`SUPPORT_ZCD`](Self::SUPPORT_ZCD).
     const SUPPORT_C: bool = true;
     /// Set to true (default) if the M extension should ever be supported. See
     /// also [`enable_m`](Self::enable_m).
     const SUPPORT_M: bool = true;
+    /// Set to true if the Zba extension (address generation) should ever be
+    /// supported. Default is false. See also [`enable_zba`](Self::enable_zba).
+    const SUPPORT_ZBA: bool = false;
+    /// Set to true if the Zbb extension (basic bit manipulation) should ever
+    /// be supported. Default is false. See also
+    /// [`enable_zbb`](Self::enable_zbb).
+    const SUPPORT_ZBB: bool = false;
+    /// Set to true if the Zbc extension (carry-less multiplication) should
+    /// ever be supported. Default is false. See also
+    /// [`enable_zbc`](Self::enable_zbc).
+    const SUPPORT_ZBC: bool = false;
+    /// Set to true if the Zbs extension (single-bit instructions) should ever
+    /// be supported. Default is false. See also
+    /// [`enable_zbs`](Self::enable_zbs).
+    const SUPPORT_ZBS: bool = false;
+    /// Set to true if the Zbkb extension (bit manipulation for cryptography)
+    /// should ever be supported. Default is false. See also
+    /// [`enable_zbkb`](Self::enable_zbkb).
+    const SUPPORT_ZBKB: bool = false;
+    /// Set to true if the Zbkc extension (carry-less multiplication for
+    /// cryptography) should ever be supported. Default is false. See also
+    /// [`enable_zbkc`](Self::enable_zbkc).
+    const SUPPORT_ZBKC: bool = false;
+    /// Set to true if the Zbkx extension (crossbar permutations) should ever
+    /// be supported. Default is false. See also
+    /// [`enable_zbkx`](Self::enable_zbkx).
+    const SUPPORT_ZBKX: bool = false;
+    /// Set to true if the Zknd extension (AES decryption) should ever be
+    /// supported. Default is false. See also
+    /// [`enable_zknd`](Self::enable_zknd).
+    const SUPPORT_ZKND: bool = false;
+    /// Set to true if the Zkne extension (AES encryption) should ever be
+    /// supported. Default is false. See also
+    /// [`enable_zkne`](Self::enable_zkne).
+    const SUPPORT_ZKNE: bool = false;
+    /// Set to true if the Zknh extension (SHA-2 hash functions) should ever
+    /// be supported. Default is false. See also
+    /// [`enable_zknh`](Self::enable_zknh).
+    const SUPPORT_ZKNH: bool = false;
+    /// Set to true if the Zksed extension (SM4 block cipher) should ever be
+    /// supported. Default is false. See also
+    /// [`enable_zksed`](Self::enable_zksed).
+    const SUPPORT_ZKSED: bool = false;
+    /// Set to true if the Zksh extension (SM3 hash function) should ever be
+    /// supported. Default is false. See also
+    /// [`enable_zksh`](Self::enable_zksh).
+    const SUPPORT_ZKSH: bool = false;
+    /// Set to true if the Zkr extension (entropy source) should ever be
+    /// supported. Default is false. See also
+    /// [`enable_zkr`](Self::enable_zkr) and
+    /// [`poll_entropy`](Self::poll_entropy).
+    const SUPPORT_ZKR: bool = false;
+    /// Set to true if the Zicond extension (conditional zeroing) should ever
+    /// be supported. Default is false. See also
+    /// [`enable_zicond`](Self::enable_zicond).
+    const SUPPORT_ZICOND: bool = false;
+    /// Set to true if the Zfhmin extension (minimal half-precision floating
+    /// point: loads, stores, moves, and conversions) should ever be
+    /// supported. Default is false. See also
+    /// [`enable_zfhmin`](Self::enable_zfhmin).
+    ///
+    /// Requires F.
+    const SUPPORT_ZFHMIN: bool = false;
+    /// Set to true if the Zfh extension (full half-precision floating point)
+    /// should ever be supported. Default is false. See also
+    /// [`enable_zfh`](Self::enable_zfh).
+    ///
+    /// Requires F. Zfh includes everything in Zfhmin.
+    const SUPPORT_ZFH: bool = false;
+    /// Set to true if the Zfa extension (additional floating point
+    /// instructions) should ever be supported. Default is false. See also
+    /// [`enable_zfa`](Self::enable_zfa).
+    ///
+    /// Requires F or Zfinx. Under Zfinx, `FLI`, `FMVH.X.D`, and `FMVP.D.X`
+    /// are not available.
+    const SUPPORT_ZFA: bool = false;
+    /// Set to true if the Zfinx extension (single-precision floating point
+    /// in the integer registers) should ever be supported. Default is false.
+    /// See also [`enable_zfinx`](Self::enable_zfinx).
+    ///
+    /// While Zfinx is enabled, the F and D instructions read and write the
+    /// integer registers instead of the float registers, `FLW`, `FSW`,
+    /// `FMV.X.W`, and `FMV.W.X` (and the D equivalents) are illegal, and
+    /// `mstatus.FS` is not consulted. Only has an effect if the chosen
+    /// [`FloatBits`] has an `fcsr`; use [`Zfinx`](crate::Zfinx) if you don't
+    /// want to pay for float registers at all.
+    const SUPPORT_ZFINX: bool = false;
+    /// Set to true if the Zdinx extension (double-precision floating point
+    /// in even/odd pairs of integer registers) should ever be supported.
+    /// Default is false. See also [`enable_zdinx`](Self::enable_zdinx).
+    ///
+    /// Requires Zfinx.
+    const SUPPORT_ZDINX: bool = false;
+    /// Set to true if the Zfbfmin extension (BFloat16 conversions) should
+    /// ever be supported. Default is false. See also
+    /// [`enable_zfbfmin`](Self::enable_zfbfmin).
+    ///
+    /// Requires F. BFloat16 values are loaded, stored, and moved with the
+    /// half-precision instructions, which Zfbfmin enables even without
+    /// Zfhmin.
+    const SUPPORT_ZFBFMIN: bool = false;
+    /// Set to true if the Zve32x extension (embedded vector, integer only)
+    /// should ever be supported. Default is false. See also
+    /// [`enable_zve32x`](Self::enable_zve32x).
+    ///
+    /// Only has an effect if the chosen [`VectorBits`] has vector registers.
+    /// `ELEN` is 32, and `VLEN` is set by the `VectorBits`.
+    const SUPPORT_ZVE32X: bool = false;
+    /// Set to true if the Zve32f extension (embedded vector, with single
+    /// precision floats) should ever be supported. Default is false. See also
+    /// [`enable_zve32f`](Self::enable_zve32f).
+    ///
+    /// Requires Zve32x and F, and does nothing under Zfinx.
+    const SUPPORT_ZVE32F: bool = false;
+    /// Set to true if the P extension (packed SIMD, 0.9.x draft) should ever
+    /// be supported. Default is false. See also [`enable_p`](Self::enable_p).
+    ///
+    /// Requires the `P` feature flag.
+    const SUPPORT_P: bool = false;
+    /// Set to true (default) if the Zcf extension (compressed single
+    /// precision loads and stores) should ever be supported, when C is. See
+    /// also [`enable_zcf`](Self::enable_zcf).
+    const SUPPORT_ZCF: bool = true;
+    /// Set to true (default) if the Zcd extension (compressed double
+    /// precision loads and stores) should ever be supported, when C is. See
+    /// also [`enable_zcd`](Self::enable_zcd).
+    ///
+    /// Zcmp and Zcmt reuse Zcd's encodings, so Zcd is unavailable while
+    /// either of them is enabled.
+    const SUPPORT_ZCD: bool = true;
+    /// Set to true if the Zcb extension (more compressed integer
+    /// instructions) should ever be supported. Default is false. See also
+    /// [`enable_zcb`](Self::enable_zcb).
+    const SUPPORT_ZCB: bool = false;
+    /// Set to true if the Zcmp extension (compressed push, pop, and register
+    /// pair moves) should ever be supported. Default is false. See also
+    /// [`enable_zcmp`](Self::enable_zcmp).
+    const SUPPORT_ZCMP: bool = false;
+    /// Set to true if the Zcmt extension (compressed table jumps) should ever
+    /// be supported. Default is false. See also
+    /// [`enable_zcmt`](Self::enable_zcmt).
+    ///
+    /// Requires the `C` feature flag, which is where the `jvt` CSR lives.
+    const SUPPORT_ZCMT: bool = false;
+    /// Set to true if the Zicbom extension (cache block management) should
+    /// ever be supported. Default is false. See also
+    /// [`enable_zicbom`](Self::enable_zicbom).
+    const SUPPORT_ZICBOM: bool = false;
+    /// Set to true if the Zicboz extension (cache block zeroing) should ever
+    /// be supported. Default is false. See also
+    /// [`enable_zicboz`](Self::enable_zicboz).
+    const SUPPORT_ZICBOZ: bool = false;
+    /// Set to true if the Zicbop extension (cache block prefetch hints)
+    /// should ever be supported. Default is false. See also
+    /// [`enable_zicbop`](Self::enable_zicbop).
+    ///
+    /// The prefetch instructions are `ORI`s that write to `x0`, so they
+    /// execute as no-ops whether or not this is set; this only decides
+    /// whether [`prefetch`](Self::prefetch) gets called.
+    const SUPPORT_ZICBOP: bool = false;
+    /// Set to true if the Zawrs extension (wait on reservation set) should
+    /// ever be supported. Default is false. See also
+    /// [`enable_zawrs`](Self::enable_zawrs).
+    ///
+    /// Requires A, for the reservations to wait on.
+    const SUPPORT_ZAWRS: bool = false;
+    /// Set to true if the Zihintpause extension (`PAUSE` hint) should ever be
+    /// supported. Default is false.
+    ///
+    /// `PAUSE` is a `FENCE`, so it executes as a no-op whether or not this is
+    /// set; this only decides whether [`pause`](Self::pause) gets called.
+    const SUPPORT_ZIHINTPAUSE: bool = false;
+    /// Set to true if the Zihintntl extension (non-temporal locality hints)
+    /// should ever be supported. Default is false.
+    ///
+    /// The hints are `ADD`s that write to `x0`, so they execute as no-ops
+    /// whether or not this is set; this only decides whether
+    /// [`non_temporal_hint`](Self::non_temporal_hint) gets called.
+    const SUPPORT_ZIHINTNTL: bool = false;
+    /// Set to true if the Zacas extension (atomic compare-and-swap) should
+    /// ever be supported. Default is false. See also
+    /// [`enable_zacas`](Self::enable_zacas).
+    ///
+    /// Requires A.
+    const SUPPORT_ZACAS: bool = false;
+    /// Set to true if the Zabha extension (byte and halfword atomic memory
+    /// operations) should ever be supported. Default is false. See also
+    /// [`enable_zabha`](Self::enable_zabha).
+    ///
+    /// Requires A.
+    const SUPPORT_ZABHA: bool = false;
+    /// The size of a cache block in bytes, as operated on by Zicbom, Zicboz,
+    /// and Zicbop. Must be a power of two, and at least 4. Default is 64.
+    const CACHE_BLOCK_SIZE: u32 = 64;
     /// Return true if the A extension should be enabled right now, allowing
     /// atomic memory accesses.
     ///
@@ -128,6 +489,265 @@ pub trait ExecutionEnvironment {
     fn enable_zifence(&self) -> bool {
         true
     }
+    /// Return true if the Zba extension should be enabled right now, allowing
+    /// the `SHnADD` instructions.
+    ///
+    /// Only checked if [`SUPPORT_ZBA`](Self::SUPPORT_ZBA) is true.
+    fn enable_zba(&self) -> bool {
+        true
+    }
+    /// Return true if the Zbb extension should be enabled right now, allowing
+    /// basic bit manipulation instructions (`ANDN`, `CLZ`, `MAX`, `ROL`,
+    /// `REV8`, etc.).
+    ///
+    /// Only checked if [`SUPPORT_ZBB`](Self::SUPPORT_ZBB) is true.
+    fn enable_zbb(&self) -> bool {
+        true
+    }
+    /// Return true if the Zbs extension should be enabled right now, allowing
+    /// the single-bit instructions (`BSET`, `BCLR`, `BINV`, `BEXT`).
+    ///
+    /// Only checked if [`SUPPORT_ZBS`](Self::SUPPORT_ZBS) is true.
+    fn enable_zbs(&self) -> bool {
+        true
+    }
+    /// Return true if the Zbc extension should be enabled right now, allowing
+    /// the `CLMUL`, `CLMULH`, and `CLMULR` instructions.
+    ///
+    /// Only checked if [`SUPPORT_ZBC`](Self::SUPPORT_ZBC) is true.
+    fn enable_zbc(&self) -> bool {
+        true
+    }
+    /// Return true if the Zbkb extension should be enabled right now, allowing
+    /// `PACK`, `PACKH`, `BREV8`, `ZIP`, and `UNZIP`, as well as the rotates,
+    /// `ANDN`/`ORN`/`XNOR`, and `REV8` from Zbb.
+    ///
+    /// Only checked if [`SUPPORT_ZBKB`](Self::SUPPORT_ZBKB) is true.
+    fn enable_zbkb(&self) -> bool {
+        true
+    }
+    /// Return true if the Zbkc extension should be enabled right now, allowing
+    /// the `CLMUL` and `CLMULH` instructions.
+    ///
+    /// Only checked if [`SUPPORT_ZBKC`](Self::SUPPORT_ZBKC) is true.
+    fn enable_zbkc(&self) -> bool {
+        true
+    }
+    /// Return true if the Zbkx extension should be enabled right now, allowing
+    /// the `XPERM4` and `XPERM8` instructions.
+    ///
+    /// Only checked if [`SUPPORT_ZBKX`](Self::SUPPORT_ZBKX) is true.
+    fn enable_zbkx(&self) -> bool {
+        true
+    }
+    /// Return true if the Zknd extension should be enabled right now, allowing
+    /// the `AES32DSI` and `AES32DSMI` instructions.
+    ///
+    /// Only checked if [`SUPPORT_ZKND`](Self::SUPPORT_ZKND) is true.
+    fn enable_zknd(&self) -> bool {
+        true
+    }
+    /// Return true if the Zkne extension should be enabled right now, allowing
+    /// the `AES32ESI` and `AES32ESMI` instructions.
+    ///
+    /// Only checked if [`SUPPORT_ZKNE`](Self::SUPPORT_ZKNE) is true.
+    fn enable_zkne(&self) -> bool {
+        true
+    }
+    /// Return true if the Zknh extension should be enabled right now, allowing
+    /// the `SHA256*` and `SHA512*` instructions.
+    ///
+    /// Only checked if [`SUPPORT_ZKNH`](Self::SUPPORT_ZKNH) is true.
+    fn enable_zknh(&self) -> bool {
+        true
+    }
+    /// Return true if the Zksed extension should be enabled right now,
+    /// allowing the `SM4ED` and `SM4KS` instructions.
+    ///
+    /// Only checked if [`SUPPORT_ZKSED`](Self::SUPPORT_ZKSED) is true.
+    fn enable_zksed(&self) -> bool {
+        true
+    }
+    /// Return true if the Zksh extension should be enabled right now, allowing
+    /// the `SM3P0` and `SM3P1` instructions.
+    ///
+    /// Only checked if [`SUPPORT_ZKSH`](Self::SUPPORT_ZKSH) is true.
+    fn enable_zksh(&self) -> bool {
+        true
+    }
+    /// Return true if the Zkr extension should be enabled right now, making
+    /// the CPU implement the `seed` CSR.
+    ///
+    /// Only checked if [`SUPPORT_ZKR`](Self::SUPPORT_ZKR) is true.
+    fn enable_zkr(&self) -> bool {
+        true
+    }
+    /// Return true if the Zicond extension should be enabled right now,
+    /// allowing the `CZERO.EQZ` and `CZERO.NEZ` instructions.
+    ///
+    /// Only checked if [`SUPPORT_ZICOND`](Self::SUPPORT_ZICOND) is true.
+    fn enable_zicond(&self) -> bool {
+        true
+    }
+    /// Return true if the Zfhmin extension should be enabled right now,
+    /// allowing half-precision loads, stores, moves, and conversions.
+    ///
+    /// Only checked if [`SUPPORT_ZFHMIN`](Self::SUPPORT_ZFHMIN) is true.
+    fn enable_zfhmin(&self) -> bool {
+        true
+    }
+    /// Return true if the Zfh extension should be enabled right now,
+    /// allowing all half-precision floating point instructions.
+    ///
+    /// Only checked if [`SUPPORT_ZFH`](Self::SUPPORT_ZFH) is true.
+    fn enable_zfh(&self) -> bool {
+        true
+    }
+    /// Return true if the Zfa extension should be enabled right now,
+    /// allowing `FLI`, `FMINM`, `FMAXM`, `FROUND`, `FROUNDNX`, `FLEQ`,
+    /// `FLTQ`, `FCVTMOD.W.D`, `FMVH.X.D`, and `FMVP.D.X`.
+    ///
+    /// Only checked if [`SUPPORT_ZFA`](Self::SUPPORT_ZFA) is true.
+    fn enable_zfa(&self) -> bool {
+        true
+    }
+    /// Return true if the Zfinx extension should be enabled right now,
+    /// making the float instructions operate on the integer registers. While
+    /// this returns true, the float registers are not used at all.
+    ///
+    /// Only checked if [`SUPPORT_ZFINX`](Self::SUPPORT_ZFINX) is true.
+    fn enable_zfinx(&self) -> bool {
+        true
+    }
+    /// Return true if the Zdinx extension should be enabled right now,
+    /// allowing double-precision float instructions on register pairs.
+    ///
+    /// Only checked if [`SUPPORT_ZDINX`](Self::SUPPORT_ZDINX) is true, and
+    /// Zfinx is enabled.
+    fn enable_zdinx(&self) -> bool {
+        true
+    }
+    /// Return true if the Zfbfmin extension should be enabled right now,
+    /// allowing `FCVT.BF16.S` and `FCVT.S.BF16`, plus `FLH`, `FSH`,
+    /// `FMV.X.H`, and `FMV.H.X`.
+    ///
+    /// Only checked if [`SUPPORT_ZFBFMIN`](Self::SUPPORT_ZFBFMIN) is true.
+    fn enable_zfbfmin(&self) -> bool {
+        true
+    }
+    /// Return true if the Zve32x extension should be enabled right now,
+    /// allowing vector configuration, loads, stores, and integer arithmetic.
+    ///
+    /// Only checked if [`SUPPORT_ZVE32X`](Self::SUPPORT_ZVE32X) is true.
+    fn enable_zve32x(&self) -> bool {
+        true
+    }
+    /// Return true if the Zve32f extension should be enabled right now,
+    /// allowing single-precision vector float arithmetic.
+    ///
+    /// Only checked if [`SUPPORT_ZVE32F`](Self::SUPPORT_ZVE32F) is true, and
+    /// Zve32x and F are enabled.
+    fn enable_zve32f(&self) -> bool {
+        true
+    }
+    /// Return true if the P extension should be enabled right now, allowing
+    /// the packed SIMD instructions and the `vxsat` CSR.
+    ///
+    /// Only checked if [`SUPPORT_P`](Self::SUPPORT_P) is true.
+    fn enable_p(&self) -> bool {
+        true
+    }
+    /// Return true if the Zcf extension should be enabled right now, allowing
+    /// `C.FLW`, `C.FSW`, `C.FLWSP`, and `C.FSWSP`.
+    ///
+    /// Only checked if [`SUPPORT_ZCF`](Self::SUPPORT_ZCF) is true and C is
+    /// enabled.
+    fn enable_zcf(&self) -> bool {
+        true
+    }
+    /// Return true if the Zcd extension should be enabled right now, allowing
+    /// `C.FLD`, `C.FSD`, `C.FLDSP`, and `C.FSDSP`.
+    ///
+    /// Only checked if [`SUPPORT_ZCD`](Self::SUPPORT_ZCD) is true, C is
+    /// enabled, and neither Zcmp nor Zcmt is.
+    fn enable_zcd(&self) -> bool {
+        true
+    }
+    /// Return true if the Zcb extension should be enabled right now, allowing
+    /// the compressed byte and halfword loads and stores, zero and sign
+    /// extensions, `C.NOT`, and `C.MUL`.
+    ///
+    /// Only checked if [`SUPPORT_ZCB`](Self::SUPPORT_ZCB) is true and C is
+    /// enabled. The sign extensions and `C.ZEXT.H` also need Zbb, and
+    /// `C.MUL` needs M.
+    fn enable_zcb(&self) -> bool {
+        true
+    }
+    /// Return true if the Zcmp extension should be enabled right now,
+    /// allowing `CM.PUSH`, `CM.POP`, `CM.POPRET`, `CM.POPRETZ`,
+    /// `CM.MVSA01`, and `CM.MVA01S`.
+    ///
+    /// Only checked if [`SUPPORT_ZCMP`](Self::SUPPORT_ZCMP) is true and C is
+    /// enabled.
+    fn enable_zcmp(&self) -> bool {
+        true
+    }
+    /// Return true if the Zcmt extension should be enabled right now,
+    /// allowing `CM.JT`, `CM.JALT`, and the `jvt` CSR.
+    ///
+    /// Only checked if [`SUPPORT_ZCMT`](Self::SUPPORT_ZCMT) is true. (The
+    /// instructions also need C to be enabled, but `jvt` doesn't.)
+    fn enable_zcmt(&self) -> bool {
+        true
+    }
+    /// Return true if the Zicbom extension should be enabled right now,
+    /// allowing `CBO.CLEAN`, `CBO.FLUSH`, and `CBO.INVAL`. (This is where
+    /// you'd check `menvcfg.CBCFE` and friends.)
+    ///
+    /// Only checked if [`SUPPORT_ZICBOM`](Self::SUPPORT_ZICBOM) is true.
+    fn enable_zicbom(&self) -> bool {
+        true
+    }
+    /// Return true if the Zicboz extension should be enabled right now,
+    /// allowing `CBO.ZERO`. (This is where you'd check `menvcfg.CBZE`.)
+    ///
+    /// Only checked if [`SUPPORT_ZICBOZ`](Self::SUPPORT_ZICBOZ) is true.
+    fn enable_zicboz(&self) -> bool {
+        true
+    }
+    /// Return true if the Zicbop extension should be enabled right now,
+    /// passing prefetch hints on to [`prefetch`](Self::prefetch).
+    ///
+    /// Only checked if [`SUPPORT_ZICBOP`](Self::SUPPORT_ZICBOP) is true.
+    fn enable_zicbop(&self) -> bool {
+        true
+    }
+    /// Return true if the Zawrs extension should be enabled right now,
+    /// allowing `WRS.NTO` and `WRS.STO`.
+    ///
+    /// Only checked if [`SUPPORT_ZAWRS`](Self::SUPPORT_ZAWRS) is true and A
+    /// is enabled.
+    fn enable_zawrs(&self) -> bool {
+        true
+    }
+    /// Return true if the Zacas extension should be enabled right now,
+    /// allowing `AMOCAS.W` and `AMOCAS.D` (and `AMOCAS.B` and `AMOCAS.H`, if
+    /// Zabha is enabled too).
+    ///
+    /// Only checked if [`SUPPORT_ZACAS`](Self::SUPPORT_ZACAS) is true and A
+    /// is enabled.
+    fn enable_zacas(&self) -> bool {
+        true
+    }
+    /// Return true if the Zabha extension should be enabled right now,
+    /// allowing the byte and halfword forms of the AMOs (`AMOADD.B`,
+    /// `AMOSWAP.H`, etc.).
+    ///
+    /// Only checked if [`SUPPORT_ZABHA`](Self::SUPPORT_ZABHA) is true and A
+    /// is enabled.
+    fn enable_zabha(&self) -> bool {
+        true
+    }
     /// Read an entire word from memory. Return `Err(Unaligned)` if address
     /// is not aligned to a four-byte boundary, **OR** determine and implement
     /// unaligned memory access logic yourself. (See section 2.6 "Load and
@@ -165,7 +785,7 @@ pub trait ExecutionEnvironment {
         &mut self,
         address: u32,
     ) -> Result<u32, MemoryAccessFailure> {
-        debug_assert!(address % 2 == 0);
+        debug_assert!(address & 1 == 0);
         if address & 2 == 0 {
             // Full word aligned.
             self.read_word(address, !0)
@@ -181,6 +801,31 @@ pub trait ExecutionEnvironment {
             }
         }
     }
+    /// Look up the instruction at `address` in your decoded-instruction
+    /// cache, if you have one. If this returns `Some`, the CPU executes that
+    /// instead of calling [`read_instruction`](Self::read_instruction) and
+    /// decoding it all over again. Default implementation returns `None`.
+    ///
+    /// You shouldn't need to implement this and
+    /// [`cache_instruction`](Self::cache_instruction) yourself; wrap your
+    /// environment in a [`DecodeCache`](crate::DecodeCache) instead.
+    fn cached_instruction(
+        &mut self,
+        _address: u32,
+    ) -> Option<DecodedInstruction> {
+        None
+    }
+    /// The instruction at `address` was just fetched and decoded. If you
+    /// have a decoded-instruction cache, now's the time to put it in there.
+    /// It's up to you to forget it again if the memory it came from changes,
+    /// if it was fetched from an I/O address, or if you change which
+    /// extensions are enabled. Default implementation does nothing.
+    fn cache_instruction(
+        &mut self,
+        _address: u32,
+        _decoded: DecodedInstruction,
+    ) {
+    }
     /// Read a halfword from memory. Return `Err(Unaligned)` if address is not
     /// aligned to a two-byte boundary, **OR** determine and implement
     /// unaligned memory access logic yourself. Default implementation calls
@@ -292,24 +937,202 @@ pub trait ExecutionEnvironment {
         address: u32,
         data: u32,
     ) -> Result<bool, MemoryAccessFailure>;
+    /// Perform an atomic memory operation (`AMOADD.W`, `AMOSWAP.W`, etc.) on
+    /// an aligned word: read the word, write back `op(old, operand)`, and
+    /// return the old value. If the read fails, nothing is written. Faults
+    /// are reported as store faults regardless of which access failed.
+    ///
+    /// Default implementation calls `read_word` and then `write_word`, which
+    /// is perfectly atomic as long as only one hart touches your memory at a
+    /// time. If harts on other threads can access the same memory, you must
+    /// override this to do the whole operation atomically (e.g. with a
+    /// compare-and-swap loop). See [`SharedRam`](crate::SharedRam).
+    fn atomic_modify_word(
+        &mut self,
+        address: u32,
+        operand: u32,
+        op: fn(u32, u32) -> u32,
+    ) -> Result<u32, MemoryAccessFailure> {
+        let old = self.read_word(address, !0)?;
+        self.write_word(address, op(old, operand), !0)?;
+        Ok(old)
+    }
+    /// As [`atomic_modify_word`](Self::atomic_modify_word), but on an
+    /// aligned halfword, for Zabha.
+    ///
+    /// Default implementation splices the new halfword in with
+    /// [`compare_and_swap_word`](Self::compare_and_swap_word), retrying if
+    /// the rest of the word changed underneath it, so it's atomic exactly
+    /// when that is.
+    fn atomic_modify_half(
+        &mut self,
+        address: u32,
+        operand: u16,
+        op: fn(u16, u16) -> u16,
+    ) -> Result<u16, MemoryAccessFailure> {
+        if address & 1 != 0 {
+            return Err(MemoryAccessFailure::Unaligned);
+        }
+        update_lane(self, address, 16, |old| {
+            Some(op(old as u16, operand) as u32)
+        })
+        .map(|old| old as u16)
+    }
+    /// As [`atomic_modify_word`](Self::atomic_modify_word), but on a byte,
+    /// for Zabha.
+    ///
+    /// Default implementation splices the new byte in with
+    /// [`compare_and_swap_word`](Self::compare_and_swap_word), retrying if
+    /// the rest of the word changed underneath it, so it's atomic exactly
+    /// when that is.
+    fn atomic_modify_byte(
+        &mut self,
+        address: u32,
+        operand: u8,
+        op: fn(u8, u8) -> u8,
+    ) -> Result<u8, MemoryAccessFailure> {
+        update_lane(
+            self,
+            address,
+            8,
+            |old| Some(op(old as u8, operand) as u32),
+        )
+        .map(|old| old as u8)
+    }
+    /// Atomically compare the aligned word at `address` to `expected`, and
+    /// if they're equal, replace it with `new`. Return the old value either
+    /// way. Faults are reported as store faults, even if the comparison
+    /// failed.
+    ///
+    /// Default implementation calls `read_word` and then, if the comparison
+    /// succeeded, `write_word`, which is perfectly atomic as long as only
+    /// one hart touches your memory at a time. If harts on other threads can
+    /// access the same memory, you must override this to do the whole
+    /// operation atomically. See [`SharedRam`](crate::SharedRam).
+    fn compare_and_swap_word(
+        &mut self,
+        address: u32,
+        expected: u32,
+        new: u32,
+    ) -> Result<u32, MemoryAccessFailure> {
+        let old = self.read_word(address, !0)?;
+        if old == expected {
+            self.write_word(address, new, !0)?;
+        }
+        Ok(old)
+    }
+    /// As [`compare_and_swap_word`](Self::compare_and_swap_word), but on an
+    /// 8-byte aligned doubleword (low word first), for `AMOCAS.D`.
+    ///
+    /// Default implementation calls `read_word` twice and then, if the
+    /// comparison succeeded, `write_word` twice. The same caveat about
+    /// atomicity applies.
+    fn compare_and_swap_double(
+        &mut self,
+        address: u32,
+        expected: u64,
+        new: u64,
+    ) -> Result<u64, MemoryAccessFailure> {
+        if address & 7 != 0 {
+            return Err(MemoryAccessFailure::Unaligned);
+        }
+        let low = self.read_word(address, !0)?;
+        let high = self.read_word(address.wrapping_add(4), !0)?;
+        let old = (high as u64) << 32 | low as u64;
+        if old == expected {
+            self.write_word(address, new as u32, !0)?;
+            self.write_word(address.wrapping_add(4), (new >> 32) as u32, !0)?;
+        }
+        Ok(old)
+    }
+    /// As [`compare_and_swap_word`](Self::compare_and_swap_word), but on an
+    /// aligned halfword, for `AMOCAS.H`.
+    ///
+    /// Default implementation is built on `compare_and_swap_word`, like
+    /// [`atomic_modify_half`](Self::atomic_modify_half).
+    fn compare_and_swap_half(
+        &mut self,
+        address: u32,
+        expected: u16,
+        new: u16,
+    ) -> Result<u16, MemoryAccessFailure> {
+        if address & 1 != 0 {
+            return Err(MemoryAccessFailure::Unaligned);
+        }
+        update_lane(self, address, 16, |old| {
+            (old == expected as u32).then_some(new as u32)
+        })
+        .map(|old| old as u16)
+    }
+    /// As [`compare_and_swap_word`](Self::compare_and_swap_word), but on a
+    /// byte, for `AMOCAS.B`.
+    ///
+    /// Default implementation is built on `compare_and_swap_word`, like
+    /// [`atomic_modify_byte`](Self::atomic_modify_byte).
+    fn compare_and_swap_byte(
+        &mut self,
+        address: u32,
+        expected: u8,
+        new: u8,
+    ) -> Result<u8, MemoryAccessFailure> {
+        update_lane(self, address, 8, |old| {
+            (old == expected as u32).then_some(new as u32)
+        })
+        .map(|old| old as u8)
+    }
+    /// Return true if reading from `address` might not give the same result
+    /// every time the emulated machine is put into the same state, e.g.
+    /// because the address belongs to a memory-mapped input device. Default
+    /// implementation returns false, meaning all of memory is just memory.
+    ///
+    /// The CPU itself doesn't care about this. It's used by tools that need
+    /// to reproduce a run, such as [`Recorder`](crate::Recorder) and
+    /// [`TimeTravel`](crate::TimeTravel), which log the values read from
+    /// these addresses and feed them back later.
+    fn is_io_address(&self, _address: u32) -> bool {
+        false
+    }
+    /// Return true if reading from the given CSR might not give the same
+    /// result every time the emulated machine is put into the same state,
+    /// e.g. because it reads a real-time clock. Default implementation
+    /// returns false. See [`is_io_address`](Self::is_io_address).
+    fn is_io_csr(&self, _csr_number: u32) -> bool {
+        false
+    }
     /// Respond to an `ECALL` instruction. Default implementation raises an
     /// exception appropriate for `ECALL` in M mode. You may override this to
     /// accelerate operating environment emulation, if you like.
-    fn perform_ecall<F: FloatBits>(
+    fn perform_ecall<F: FloatBits, V: VectorBits, X: IntegerRegisters>(
         &mut self,
-        _cpu: &mut Cpu<F>,
+        _cpu: &mut Cpu<F, V, X>,
     ) -> Result<(), (ExceptionCause, u32)> {
         Err((ExceptionCause::EcallFromMmode, 0))
     }
     /// Respond to an `EBREAK` instruction. Default implementation raises an
     /// exception appropriate for `EBREAK`. You may override this to... do
     /// something else?
-    fn perform_ebreak<F: FloatBits>(
+    fn perform_ebreak<F: FloatBits, V: VectorBits, X: IntegerRegisters>(
         &mut self,
-        _cpu: &mut Cpu<F>,
+        _cpu: &mut Cpu<F, V, X>,
     ) -> Result<(), (ExceptionCause, u32)> {
         Err((ExceptionCause::Breakpoint, 0))
     }
+    /// Execute an instruction in one of the `custom-0` through `custom-3`
+    /// major opcodes. Default implementation raises an illegal instruction
+    /// exception, as if there were no custom extensions.
+    ///
+    /// Use `cpu`'s accessors to read operands and write results. Don't touch
+    /// the PC; on success, it advances to the next instruction as usual, and
+    /// the instruction is accounted with
+    /// [`account_custom_op`](Self::account_custom_op). On RV32E, it's up to
+    /// you to reject register fields above 15.
+    fn perform_custom<F: FloatBits, V: VectorBits, X: IntegerRegisters>(
+        &mut self,
+        _cpu: &mut Cpu<F, V, X>,
+        instruction: CustomInstruction,
+    ) -> Result<(), (ExceptionCause, u32)> {
+        Err((ExceptionCause::IllegalInstruction, instruction.instruction))
+    }
     /// Read from a CSR. Return `Err(IllegalInstruction)` if the CSR number is
     /// not recognized.
     ///
@@ -332,6 +1155,121 @@ pub trait ExecutionEnvironment {
     ) -> Result<(), ExceptionCause> {
         Err(ExceptionCause::IllegalInstruction)
     }
+    /// Poll the entropy source, on behalf of an access to the `seed` CSR.
+    /// Every access to `seed` polls exactly once, even `CSRRW x0, seed, ...`,
+    /// and any entropy returned is considered used up. Return
+    /// `Err(IllegalInstruction)` if `seed` can't be accessed at the current
+    /// privilege level (see `mseccfg.SSEED` and `mseccfg.USEED`).
+    ///
+    /// Default implementation returns `DEAD`. If you want runs to be
+    /// reproducible, use a seeded deterministic generator here; either way,
+    /// [`Recorder`](crate::Recorder) logs what you return.
+    ///
+    /// Only called if [`SUPPORT_ZKR`](Self::SUPPORT_ZKR) is true.
+    fn poll_entropy(&mut self) -> Result<EntropyStatus, ExceptionCause> {
+        Ok(EntropyStatus::Dead)
+    }
+    /// A `FENCE.I` (Zifencei) was executed: any instructions fetched from
+    /// here on must reflect every store made before it. Default
+    /// implementation does nothing, which is correct if you don't cache
+    /// instructions.
+    fn fence_i(&mut self) {}
+    /// Perform a cache block management operation (Zicbom) on the block
+    /// starting at `address`, which is aligned to
+    /// [`CACHE_BLOCK_SIZE`](Self::CACHE_BLOCK_SIZE). Failures are reported
+    /// as store faults.
+    ///
+    /// Default implementation does nothing, which is correct if you don't
+    /// emulate any caches. If you emulate a device that does DMA, this is
+    /// where you'd make its view of memory agree with the hart's.
+    ///
+    /// Only called if [`SUPPORT_ZICBOM`](Self::SUPPORT_ZICBOM) is true.
+    fn cache_block_op(
+        &mut self,
+        _address: u32,
+        _op: CacheBlockOp,
+    ) -> Result<(), MemoryAccessFailure> {
+        Ok(())
+    }
+    /// Write zeroes to the whole cache block starting at `address`, which is
+    /// aligned to [`CACHE_BLOCK_SIZE`](Self::CACHE_BLOCK_SIZE), as from
+    /// `CBO.ZERO` (Zicboz). Failures are reported as store faults.
+    ///
+    /// Default implementation calls `write_word` once for each word of the
+    /// block. (The wrappers in this crate that watch stores, like
+    /// [`Recorder`](crate::Recorder), keep using this default rather than
+    /// passing the call through.)
+    ///
+    /// Only called if [`SUPPORT_ZICBOZ`](Self::SUPPORT_ZICBOZ) is true.
+    fn cache_block_zero(
+        &mut self,
+        address: u32,
+    ) -> Result<(), MemoryAccessFailure> {
+        for offset in (0..Self::CACHE_BLOCK_SIZE).step_by(4) {
+            self.write_word(address.wrapping_add(offset), 0, !0)?;
+        }
+        Ok(())
+    }
+    /// The cache block starting at `address`, which is aligned to
+    /// [`CACHE_BLOCK_SIZE`](Self::CACHE_BLOCK_SIZE), is about to be used, as
+    /// hinted by a Zicbop prefetch. Prefetches never fault.
+    ///
+    /// Default implementation does nothing.
+    ///
+    /// Only called if [`SUPPORT_ZICBOP`](Self::SUPPORT_ZICBOP) is true.
+    fn prefetch(&mut self, _address: u32, _kind: PrefetchKind) {}
+    /// A `PAUSE` hint (Zihintpause) was executed. The hart is almost
+    /// certainly spinning, waiting for another hart or a device, so this is a
+    /// good time to let something else run, or to skip ahead to the next
+    /// interesting event.
+    ///
+    /// Default implementation does nothing.
+    ///
+    /// Only called if [`SUPPORT_ZIHINTPAUSE`](Self::SUPPORT_ZIHINTPAUSE) is
+    /// true.
+    fn pause(&mut self) {}
+    /// Respond to a `WRS.NTO` (`short_timeout` is false) or `WRS.STO` (it's
+    /// true) instruction from Zawrs. The hart wants to stall until the
+    /// reservation set by its last `LR.W` is broken, an interrupt is pending,
+    /// or, for `WRS.STO`, a short while has passed. Return `Ok(())` to resume
+    /// execution, after yielding or fast-forwarding time as you see fit, or
+    /// `Err(IllegalInstruction)` if the instruction should trap instead (see
+    /// `mstatus.TW`).
+    ///
+    /// Resuming at any time is always correct, since the hart just goes back
+    /// to checking the lock. Default implementation returns `Ok(())`
+    /// straight away.
+    ///
+    /// Only called if [`SUPPORT_ZAWRS`](Self::SUPPORT_ZAWRS) is true.
+    fn wait_on_reservation(
+        &mut self,
+        _short_timeout: bool,
+    ) -> Result<(), ExceptionCause> {
+        Ok(())
+    }
+    /// A non-temporal locality hint (Zihintntl) was executed: the memory
+    /// access made by the next instruction isn't likely to be repeated soon,
+    /// at the given level of the memory hierarchy.
+    ///
+    /// Default implementation does nothing.
+    ///
+    /// Only called if [`SUPPORT_ZIHINTNTL`](Self::SUPPORT_ZIHINTNTL) is true.
+    fn non_temporal_hint(&mut self, _locality: NonTemporalLocality) {}
+    /// Read the real-time counter, `mtime`, for the `time` and `timeh` CSRs.
+    /// Only called by [`Counters`](crate::Counters). Default implementation
+    /// returns `Err(IllegalInstruction)`, so that reading `time` traps and
+    /// can be emulated.
+    fn read_mtime(&mut self) -> Result<u64, ExceptionCause> {
+        Err(ExceptionCause::IllegalInstruction)
+    }
+    /// Return the number of cycles that have elapsed, according to your
+    /// `account_*` hooks, for the `cycle` CSR. Only the differences between
+    /// successive values matter. Only called by
+    /// [`Counters`](crate::Counters). Default implementation returns `None`,
+    /// meaning every instruction takes one cycle.
+    fn cycle_count(&self) -> Option<u64> {
+        None
+    }
     /// Return the status of the floating point registers. The default
     /// implementation just returns Dirty all the time, which makes context
     /// switches potentially a little less efficient in an OS environment.
@@ -343,6 +1281,16 @@ pub trait ExecutionEnvironment {
     /// hardware may set the status to Dirty in response to a floating point
     /// operation.
     fn write_fs(&mut self, _status: ExtensionStatus) {}
+    /// Return the status of the vector registers. As with
+    /// [`read_fs`](Self::read_fs), the default implementation just returns
+    /// Dirty all the time.
+    fn read_vs(&self) -> ExtensionStatus {
+        ExtensionStatus::Dirty
+    }
+    /// Set the status of the vector registers. The hardware may set the
+    /// status to Dirty in response to any vector instruction that changes
+    /// vector state, including `vsetvl` and the vector CSRs.
+    fn write_vs(&mut self, _status: ExtensionStatus) {}
     /// Return true if we should use the slow, exact square root for single
     /// precision at the moment, false to use the fast, not completely exact
     /// square root. Default is true (slow, exact).
@@ -370,6 +1318,10 @@ pub trait ExecutionEnvironment {
     }
     /// An instruction word has been fetched. Called once per instruction.
     fn account_ifetch(&mut self, _pc: u32) {}
+    /// An instruction has retired, i.e. finished without raising an
+    /// exception. Called once per retired instruction, after all of its
+    /// other accounting.
+    fn account_retire(&mut self) {}
     /// A generic "operation" has been performed.
     fn account_generic_op(&mut self) {}
     /// A memory load was performed. Default implementation calls
@@ -429,6 +1381,23 @@ pub trait ExecutionEnvironment {
     fn account_div_op(&mut self) {
         self.account_alu_op()
     }
+    /// A carry-less multiplication (`CLMUL`, `CLMULH`, `CLMULR`) has been
+    /// performed. Default implementation calls
+    /// [`mul_op`](Self::account_mul_op).
+    fn account_clmul_op(&mut self) {
+        self.account_mul_op()
+    }
+    /// A crossbar permutation (`XPERM4`, `XPERM8`) has been performed.
+    /// Default implementation calls [`alu_op`](Self::account_alu_op).
+    fn account_xperm_op(&mut self) {
+        self.account_alu_op()
+    }
+    /// A scalar cryptography instruction (AES, SHA-2, SM3, SM4) has been
+    /// performed. Default implementation calls
+    /// [`alu_op`](Self::account_alu_op).
+    fn account_crypto_op(&mut self) {
+        self.account_alu_op()
+    }
     /// An atomic memory access has been performed. Default implementation
     /// calls [`generic_op`](Self::account_generic_op).
     fn account_amo_op(&mut self) {
@@ -480,4 +1449,45 @@ pub trait ExecutionEnvironment {
             self.account_float_divide(num_words)
         }
     }
+    /// A vector arithmetic instruction has been performed, on the given
+    /// number of elements. (Vector loads and stores are accounted element by
+    /// element, as ordinary loads and stores.) Default implementation calls
+    /// [`generic_op`](Self::account_generic_op).
+    fn account_vector_op(&mut self, _elements: u32) {
+        self.account_generic_op()
+    }
+    /// A custom instruction has been performed by
+    /// [`perform_custom`](Self::perform_custom). Default implementation calls
+    /// [`generic_op`](Self::account_generic_op).
+    fn account_custom_op(&mut self) {
+        self.account_generic_op()
+    }
+}
+
+/// Atomically replace the `bits` wide lane at `address` with `update(old)`,
+/// unless that's `None`, by swapping the whole word around it with
+/// `compare_and_swap_word` until nobody else has changed it in the meantime.
+/// Returns the old lane.
+pub(crate) fn update_lane<Env: ExecutionEnvironment + ?Sized>(
+    env: &mut Env,
+    address: u32,
+    bits: u32,
+    update: impl Fn(u32) -> Option<u32>,
+) -> Result<u32, MemoryAccessFailure> {
+    let word_address = address & !3;
+    let shift = (address & 3) * 8;
+    let mask = (u32::MAX >> (32 - bits)) << shift;
+    loop {
+        let old_word = env.read_word(word_address, mask)?;
+        let old = (old_word & mask) >> shift;
+        let Some(new) = update(old) else {
+            return Ok(old);
+        };
+        let new_word = (old_word & !mask) | ((new << shift) & mask);
+        if env.compare_and_swap_word(word_address, old_word, new_word)?
+            == old_word
+        {
+            return Ok(old);
+        }
+    }
 }
@@ -1,18 +1,41 @@
 #![doc=include_str!("../README.md")]
 
+mod counters;
+pub use counters::*;
 mod cpu;
 pub use cpu::*;
+mod decode_cache;
+pub use decode_cache::*;
 mod execution;
 pub use execution::*;
+mod machine;
+pub use machine::*;
+mod parallel;
+pub use parallel::*;
+mod replay;
+pub use replay::*;
+mod timetravel;
+pub use timetravel::*;
 
 /// 32-bit RISC-V CPU with no float support.
 pub type Rv32I = Cpu<()>;
+/// 32-bit RISC-V CPU with only 16 integer registers (RV32E), and no float
+/// support.
+pub type Rv32E = Cpu<(), (), [u32; 16]>;
 /// 32-bit RISC-V CPU with 32-bit float support.
 pub type Rv32IF = Cpu<u32>;
 /// 32-bit RISC-V CPU with 64-bit float support.
 pub type Rv32G = Cpu<u64>;
 /// 32-bit RISC-V CPU with 128-bit float support.
 pub type Rv32GQ = Cpu<u128>;
+/// 32-bit RISC-V CPU whose floats live in the integer registers (Zfinx, and
+/// optionally Zdinx).
+#[cfg(feature = "float")]
+pub type Rv32IZfinx = Cpu<Zfinx>;
+/// 32-bit RISC-V CPU with 64-bit float support and 128-bit vector registers
+/// (Zve32x and Zve32f).
+#[cfg(feature = "float")]
+pub type Rv32GZve32f = Cpu<u64, [u8; 16]>;
 
 /// The value that should be returned when the `mvendorid` CSR is read.
 ///
@@ -42,7 +65,7 @@ pub const ARCH_ID: u32 = 45;
 /// the value that was stored at power-on instead of letting the emulated
 /// machine witness `mimpid` changing. (Specifically, applications
 /// where determinism is absolutely essential, such as during replay playback
-/// or lockstep replication.)
+/// or lockstep replication. See also [`Recorder`].)
 pub const IMPLEMENTATION_ID: u32 = u32::from_be_bytes([
     0,
     parse_const_u8(env!("CARGO_PKG_VERSION_MAJOR").as_bytes()),
@@ -0,0 +1,1006 @@
+//! Several harts sharing one environment.
+
+use super::*;
+
+/// The `mhartid` CSR.
+const CSR_MHARTID: u32 = 0xF14;
+
+/// An [`ExecutionEnvironment`] that can be shared between several harts in a
+/// [`Machine`].
+///
+/// Memory, devices, and everything else go through the one environment, no
+/// matter which hart is running. If you keep any per-hart state (such as
+/// the privileged CSRs, or per-hart accounting), `switch_hart` tells you
+/// which hart is about to run. The `Machine` takes care of `mhartid` and of
+/// reservations, so your `load_reserved_word` and `store_reserved_word` will
+/// never be called.
+pub trait SmpEnvironment: ExecutionEnvironment {
+    /// The given hart is about to run, and will keep running until the next
+    /// call to `switch_hart`. The default implementation does nothing.
+    fn switch_hart(&mut self, _hart: usize) {}
+}
+
+/// How a [`Machine`] divides its time between harts.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Schedule {
+    /// Each hart executes one instruction, then it's the next hart's turn.
+    /// This interleaves the harts as finely as possible, which is good for
+    /// shaking out concurrency bugs.
+    RoundRobin,
+    /// Each hart executes this many instructions before it's the next hart's
+    /// turn. Coarser interleaving means fewer calls to
+    /// [`switch_hart`](SmpEnvironment::switch_hart), which may be cheaper.
+    Quantum(u32),
+}
+
+/// An exception that occurred on a particular hart of a [`Machine`].
+#[derive(Debug)]
+pub struct HartException {
+    /// The hart on which the exception occurred.
+    pub hart: usize,
+    /// The exception itself.
+    pub exception: Exception,
+}
+
+#[derive(Clone, Default)]
+struct HartState {
+    /// Word address of the current reservation, if any.
+    reservation: Option<u32>,
+    /// Machine-mode software interrupt pending, i.e. someone sent an IPI.
+    msip: bool,
+    running: bool,
+}
+
+/// Several harts (hardware threads, i.e. `Cpu`s) sharing one environment:
+/// a simulated multicore computer.
+///
+/// Each hart has a distinct `mhartid`, counting up from 0. Reservations
+/// (`LR.W`/`SC.W`) are tracked per hart, and any store by any hart to a
+/// reserved word breaks every hart's reservation on that word. The harts
+/// take turns according to the [`Schedule`], all within the calling thread.
+/// A hart that spins politely, with `PAUSE` (Zihintpause) or with `WRS.*`
+/// (Zawrs) while it holds a reservation, gives up the rest of its turn.
+///
+/// Inter-processor interrupts use a CLINT-style `msip` region, if you set
+/// one with [`set_msip_base`](Self::set_msip_base): one word per hart, where
+/// writing 1 (or 0) to the low bit sets (or clears) that hart's pending
+/// software interrupt. The `Machine` doesn't take interrupts itself—that's
+/// privileged spec territory—but you can check
+/// [`ipi_pending`](Self::ipi_pending) between steps and act accordingly.
+///
+/// `V` and `X` pick the harts' vector and integer register files, as they do
+/// for [`Cpu`]. The defaults are no vector registers and the full RV32I
+/// register file.
+pub struct Machine<
+    F: FloatBits,
+    Env: SmpEnvironment,
+    V: VectorBits = (),
+    X: IntegerRegisters = [u32; 32],
+> {
+    cpus: Vec<Cpu<F, V, X>>,
+    harts: Vec<HartState>,
+    env: Env,
+    schedule: Schedule,
+    msip_base: Option<u32>,
+    current_hart: usize,
+    /// Instructions left in the current hart's quantum.
+    remaining: u32,
+    /// Hart whose state `env` was last told about.
+    switched_to: Option<usize>,
+}
+
+impl<
+        F: FloatBits,
+        Env: SmpEnvironment,
+        V: VectorBits,
+        X: IntegerRegisters,
+    > Machine<F, Env, V, X>
+{
+    /// Create a machine with `hart_count` harts, all freshly reset and
+    /// running, sharing the given environment.
+    ///
+    /// PANICS if `hart_count` is zero.
+    pub fn new(env: Env, hart_count: usize) -> Machine<F, Env, V, X> {
+        assert!(hart_count > 0, "a Machine needs at least one hart");
+        Machine {
+            cpus: vec![Cpu::new(); hart_count],
+            harts: vec![
+                HartState {
+                    running: true,
+                    ..HartState::default()
+                };
+                hart_count
+            ],
+            env,
+            schedule: Schedule::RoundRobin,
+            msip_base: None,
+            current_hart: 0,
+            remaining: 1,
+            switched_to: None,
+        }
+    }
+    /// Number of harts in this machine.
+    pub fn hart_count(&self) -> usize {
+        self.cpus.len()
+    }
+    /// The CPU state of the given hart.
+    pub fn cpu(&self, hart: usize) -> &Cpu<F, V, X> {
+        &self.cpus[hart]
+    }
+    /// The CPU state of the given hart.
+    pub fn cpu_mut(&mut self, hart: usize) -> &mut Cpu<F, V, X> {
+        &mut self.cpus[hart]
+    }
+    /// The shared environment.
+    pub fn env(&self) -> &Env {
+        &self.env
+    }
+    /// The shared environment.
+    pub fn env_mut(&mut self) -> &mut Env {
+        &mut self.env
+    }
+    /// Tear down the machine, giving back the environment.
+    pub fn into_env(self) -> Env {
+        self.env
+    }
+    /// Change how harts take turns. Takes effect at the next turn.
+    pub fn set_schedule(&mut self, schedule: Schedule) {
+        self.schedule = schedule;
+    }
+    /// Place the `msip` registers at the given (word-aligned) base address,
+    /// or remove them.
+    pub fn set_msip_base(&mut self, base: Option<u32>) {
+        self.msip_base = base.map(|x| x & !3);
+    }
+    /// Mark an IPI as pending for the given hart, as if another hart had
+    /// written to its `msip` register.
+    pub fn send_ipi(&mut self, hart: usize) {
+        self.harts[hart].msip = true;
+    }
+    /// Clear any pending IPI for the given hart.
+    pub fn clear_ipi(&mut self, hart: usize) {
+        self.harts[hart].msip = false;
+    }
+    /// True if the given hart has an IPI pending.
+    pub fn ipi_pending(&self, hart: usize) -> bool {
+        self.harts[hart].msip
+    }
+    /// Start or stop the given hart. Stopped harts are skipped by
+    /// [`step`](Self::step). (Useful for e.g. `WFI`, or for holding
+    /// secondary harts in reset.)
+    pub fn set_running(&mut self, hart: usize, running: bool) {
+        self.harts[hart].running = running;
+    }
+    /// True if the given hart is running.
+    pub fn is_running(&self, hart: usize) -> bool {
+        self.harts[hart].running
+    }
+    /// Throw away the given hart's reservation, if it has one.
+    pub fn clear_reservation(&mut self, hart: usize) {
+        self.harts[hart].reservation = None;
+    }
+    /// Execute one instruction on whichever hart's turn it is. Returns the
+    /// hart that ran, or `None` if no harts are running.
+    pub fn step(&mut self) -> Result<Option<usize>, HartException> {
+        if self.remaining == 0 || !self.harts[self.current_hart].running {
+            let count = self.hart_count();
+            let Some(next) = (1..=count)
+                .map(|n| (self.current_hart + n) % count)
+                .find(|&hart| self.harts[hart].running)
+            else {
+                return Ok(None);
+            };
+            self.current_hart = next;
+            self.remaining = match self.schedule {
+                Schedule::RoundRobin => 1,
+                Schedule::Quantum(n) => n.max(1),
+            };
+        }
+        self.remaining -= 1;
+        let hart = self.current_hart;
+        self.step_hart(hart).map(|_| Some(hart))
+    }
+    /// Execute one instruction on the given hart, regardless of the
+    /// schedule or whether it's running. Useful if you want to do your own
+    /// scheduling.
+    pub fn step_hart(&mut self, hart: usize) -> Result<(), HartException> {
+        if self.switched_to != Some(hart) {
+            self.env.switch_hart(hart);
+            self.switched_to = Some(hart);
+        }
+        let mut view = HartView {
+            env: &mut self.env,
+            hart,
+            harts: &mut self.harts[..],
+            msip_base: self.msip_base,
+            yielded: false,
+        };
+        let result = self.cpus[hart]
+            .step(&mut view)
+            .map_err(|exception| HartException { hart, exception });
+        if view.yielded {
+            self.remaining = 0;
+        }
+        result
+    }
+}
+
+/// What a `Cpu` sees while it's running as part of a `Machine`.
+struct HartView<'a, Env: SmpEnvironment> {
+    env: &'a mut Env,
+    hart: usize,
+    harts: &'a mut [HartState],
+    msip_base: Option<u32>,
+    /// Set if the hart executed `PAUSE` or `WRS.*`, and so would like to
+    /// give up the rest of its quantum.
+    yielded: bool,
+}
+
+impl<Env: SmpEnvironment> HartView<'_, Env> {
+    /// If the address is one of the `msip` registers, which hart's?
+    fn msip_hart(&self, address: u32) -> Option<usize> {
+        let base = self.msip_base?;
+        let index = (address.wrapping_sub(base) / 4) as usize;
+        (address >= base && index < self.harts.len()).then_some(index)
+    }
+    /// Break every hart's reservation that overlaps the given store.
+    fn store(&mut self, address: u32, length: u32) {
+        for hart in self.harts.iter_mut() {
+            if let Some(reserved) = hart.reservation {
+                if address.wrapping_sub(reserved) < 4
+                    || reserved.wrapping_sub(address) < length
+                {
+                    hart.reservation = None;
+                }
+            }
+        }
+    }
+}
+
+impl<Env: SmpEnvironment> ExecutionEnvironment for HartView<'_, Env> {
+    const SUPPORT_A: bool = Env::SUPPORT_A;
+    const SUPPORT_C: bool = Env::SUPPORT_C;
+    const SUPPORT_M: bool = Env::SUPPORT_M;
+    const SUPPORT_ZBA: bool = Env::SUPPORT_ZBA;
+    const SUPPORT_ZBB: bool = Env::SUPPORT_ZBB;
+    const SUPPORT_ZBS: bool = Env::SUPPORT_ZBS;
+    const SUPPORT_ZBC: bool = Env::SUPPORT_ZBC;
+    const SUPPORT_ZBKB: bool = Env::SUPPORT_ZBKB;
+    const SUPPORT_ZBKC: bool = Env::SUPPORT_ZBKC;
+    const SUPPORT_ZBKX: bool = Env::SUPPORT_ZBKX;
+    const SUPPORT_ZKND: bool = Env::SUPPORT_ZKND;
+    const SUPPORT_ZKNE: bool = Env::SUPPORT_ZKNE;
+    const SUPPORT_ZKNH: bool = Env::SUPPORT_ZKNH;
+    const SUPPORT_ZKSED: bool = Env::SUPPORT_ZKSED;
+    const SUPPORT_ZKSH: bool = Env::SUPPORT_ZKSH;
+    const SUPPORT_ZKR: bool = Env::SUPPORT_ZKR;
+    const SUPPORT_ZICOND: bool = Env::SUPPORT_ZICOND;
+    const SUPPORT_ZFHMIN: bool = Env::SUPPORT_ZFHMIN;
+    const SUPPORT_ZFH: bool = Env::SUPPORT_ZFH;
+    const SUPPORT_ZFA: bool = Env::SUPPORT_ZFA;
+    const SUPPORT_ZFINX: bool = Env::SUPPORT_ZFINX;
+    const SUPPORT_ZDINX: bool = Env::SUPPORT_ZDINX;
+    const SUPPORT_ZFBFMIN: bool = Env::SUPPORT_ZFBFMIN;
+    const SUPPORT_ZVE32X: bool = Env::SUPPORT_ZVE32X;
+    const SUPPORT_ZVE32F: bool = Env::SUPPORT_ZVE32F;
+    const SUPPORT_P: bool = Env::SUPPORT_P;
+    const SUPPORT_ZCF: bool = Env::SUPPORT_ZCF;
+    const SUPPORT_ZCD: bool = Env::SUPPORT_ZCD;
+    const SUPPORT_ZCB: bool = Env::SUPPORT_ZCB;
+    const SUPPORT_ZCMP: bool = Env::SUPPORT_ZCMP;
+    const SUPPORT_ZCMT: bool = Env::SUPPORT_ZCMT;
+    const SUPPORT_ZICBOM: bool = Env::SUPPORT_ZICBOM;
+    const SUPPORT_ZICBOZ: bool = Env::SUPPORT_ZICBOZ;
+    const SUPPORT_ZICBOP: bool = Env::SUPPORT_ZICBOP;
+    const SUPPORT_ZAWRS: bool = Env::SUPPORT_ZAWRS;
+    const SUPPORT_ZIHINTPAUSE: bool = Env::SUPPORT_ZIHINTPAUSE;
+    const SUPPORT_ZIHINTNTL: bool = Env::SUPPORT_ZIHINTNTL;
+    const SUPPORT_ZACAS: bool = Env::SUPPORT_ZACAS;
+    const SUPPORT_ZABHA: bool = Env::SUPPORT_ZABHA;
+    const CACHE_BLOCK_SIZE: u32 = Env::CACHE_BLOCK_SIZE;
+    fn enable_a(&self) -> bool {
+        self.env.enable_a()
+    }
+    fn enable_c(&self) -> bool {
+        self.env.enable_c()
+    }
+    fn enable_m(&self) -> bool {
+        self.env.enable_m()
+    }
+    fn enable_f(&self) -> bool {
+        self.env.enable_f()
+    }
+    fn enable_d(&self) -> bool {
+        self.env.enable_d()
+    }
+    fn enable_q(&self) -> bool {
+        self.env.enable_q()
+    }
+    fn enable_zicsr(&self) -> bool {
+        self.env.enable_zicsr()
+    }
+    fn enable_zifence(&self) -> bool {
+        self.env.enable_zifence()
+    }
+    fn enable_zba(&self) -> bool {
+        self.env.enable_zba()
+    }
+    fn enable_zbb(&self) -> bool {
+        self.env.enable_zbb()
+    }
+    fn enable_zbs(&self) -> bool {
+        self.env.enable_zbs()
+    }
+    fn enable_zbc(&self) -> bool {
+        self.env.enable_zbc()
+    }
+    fn enable_zbkb(&self) -> bool {
+        self.env.enable_zbkb()
+    }
+    fn enable_zbkc(&self) -> bool {
+        self.env.enable_zbkc()
+    }
+    fn enable_zbkx(&self) -> bool {
+        self.env.enable_zbkx()
+    }
+    fn enable_zknd(&self) -> bool {
+        self.env.enable_zknd()
+    }
+    fn enable_zkne(&self) -> bool {
+        self.env.enable_zkne()
+    }
+    fn enable_zknh(&self) -> bool {
+        self.env.enable_zknh()
+    }
+    fn enable_zksed(&self) -> bool {
+        self.env.enable_zksed()
+    }
+    fn enable_zksh(&self) -> bool {
+        self.env.enable_zksh()
+    }
+    fn enable_zkr(&self) -> bool {
+        self.env.enable_zkr()
+    }
+    fn enable_zicond(&self) -> bool {
+        self.env.enable_zicond()
+    }
+    fn enable_zfhmin(&self) -> bool {
+        self.env.enable_zfhmin()
+    }
+    fn enable_zfh(&self) -> bool {
+        self.env.enable_zfh()
+    }
+    fn enable_zfa(&self) -> bool {
+        self.env.enable_zfa()
+    }
+    fn enable_zfinx(&self) -> bool {
+        self.env.enable_zfinx()
+    }
+    fn enable_zdinx(&self) -> bool {
+        self.env.enable_zdinx()
+    }
+    fn enable_zfbfmin(&self) -> bool {
+        self.env.enable_zfbfmin()
+    }
+    fn enable_zve32x(&self) -> bool {
+        self.env.enable_zve32x()
+    }
+    fn enable_zve32f(&self) -> bool {
+        self.env.enable_zve32f()
+    }
+    fn enable_p(&self) -> bool {
+        self.env.enable_p()
+    }
+    fn enable_zcf(&self) -> bool {
+        self.env.enable_zcf()
+    }
+    fn enable_zcd(&self) -> bool {
+        self.env.enable_zcd()
+    }
+    fn enable_zcb(&self) -> bool {
+        self.env.enable_zcb()
+    }
+    fn enable_zcmp(&self) -> bool {
+        self.env.enable_zcmp()
+    }
+    fn enable_zcmt(&self) -> bool {
+        self.env.enable_zcmt()
+    }
+    fn enable_zicbom(&self) -> bool {
+        self.env.enable_zicbom()
+    }
+    fn enable_zicboz(&self) -> bool {
+        self.env.enable_zicboz()
+    }
+    fn enable_zicbop(&self) -> bool {
+        self.env.enable_zicbop()
+    }
+    fn enable_zawrs(&self) -> bool {
+        self.env.enable_zawrs()
+    }
+    fn enable_zacas(&self) -> bool {
+        self.env.enable_zacas()
+    }
+    fn enable_zabha(&self) -> bool {
+        self.env.enable_zabha()
+    }
+    fn read_word(
+        &mut self,
+        address: u32,
+        mask: u32,
+    ) -> Result<u32, MemoryAccessFailure> {
+        match self.msip_hart(address) {
+            Some(hart) => Ok(self.harts[hart].msip as u32),
+            None => self.env.read_word(address, mask),
+        }
+    }
+    fn read_instruction(
+        &mut self,
+        address: u32,
+    ) -> Result<u32, MemoryAccessFailure> {
+        self.env.read_instruction(address)
+    }
+    fn cached_instruction(
+        &mut self,
+        address: u32,
+    ) -> Option<DecodedInstruction> {
+        self.env.cached_instruction(address)
+    }
+    fn cache_instruction(
+        &mut self,
+        address: u32,
+        decoded: DecodedInstruction,
+    ) {
+        self.env.cache_instruction(address, decoded)
+    }
+    fn read_half(&mut self, address: u32) -> Result<u16, MemoryAccessFailure> {
+        match self.msip_hart(address) {
+            Some(hart) => {
+                Ok((address & 2 == 0 && self.harts[hart].msip) as u16)
+            }
+            None => self.env.read_half(address),
+        }
+    }
+    fn read_byte(&mut self, address: u32) -> Result<u8, MemoryAccessFailure> {
+        match self.msip_hart(address) {
+            Some(hart) => {
+                Ok((address & 3 == 0 && self.harts[hart].msip) as u8)
+            }
+            None => self.env.read_byte(address),
+        }
+    }
+    fn write_word(
+        &mut self,
+        address: u32,
+        data: u32,
+        mask: u32,
+    ) -> Result<(), MemoryAccessFailure> {
+        if let Some(hart) = self.msip_hart(address) {
+            if mask & 1 != 0 {
+                self.harts[hart].msip = data & 1 != 0;
+            }
+            return Ok(());
+        }
+        self.store(address, 4);
+        self.env.write_word(address, data, mask)
+    }
+    fn write_half(
+        &mut self,
+        address: u32,
+        data: u16,
+    ) -> Result<(), MemoryAccessFailure> {
+        if let Some(hart) = self.msip_hart(address) {
+            if address & 2 == 0 {
+                self.harts[hart].msip = data & 1 != 0;
+            }
+            return Ok(());
+        }
+        self.store(address, 2);
+        self.env.write_half(address, data)
+    }
+    fn write_byte(
+        &mut self,
+        address: u32,
+        data: u8,
+    ) -> Result<(), MemoryAccessFailure> {
+        if let Some(hart) = self.msip_hart(address) {
+            if address & 3 == 0 {
+                self.harts[hart].msip = data & 1 != 0;
+            }
+            return Ok(());
+        }
+        self.store(address, 1);
+        self.env.write_byte(address, data)
+    }
+    fn load_reserved_word(
+        &mut self,
+        address: u32,
+    ) -> Result<u32, MemoryAccessFailure> {
+        if address & 3 != 0 {
+            return Err(MemoryAccessFailure::Unaligned);
+        }
+        let ret = self.read_word(address, !0)?;
+        self.harts[self.hart].reservation = Some(address);
+        Ok(ret)
+    }
+    fn store_reserved_word(
+        &mut self,
+        address: u32,
+        data: u32,
+    ) -> Result<bool, MemoryAccessFailure> {
+        if address & 3 != 0 {
+            return Err(MemoryAccessFailure::Unaligned);
+        }
+        if self.harts[self.hart].reservation.take() != Some(address) {
+            return Ok(false);
+        }
+        self.write_word(address, data, !0)?;
+        Ok(true)
+    }
+    fn atomic_modify_word(
+        &mut self,
+        address: u32,
+        operand: u32,
+        op: fn(u32, u32) -> u32,
+    ) -> Result<u32, MemoryAccessFailure> {
+        if let Some(hart) = self.msip_hart(address) {
+            let old = self.harts[hart].msip as u32;
+            self.harts[hart].msip = op(old, operand) & 1 != 0;
+            return Ok(old);
+        }
+        self.store(address, 4);
+        self.env.atomic_modify_word(address, operand, op)
+    }
+    fn compare_and_swap_word(
+        &mut self,
+        address: u32,
+        expected: u32,
+        new: u32,
+    ) -> Result<u32, MemoryAccessFailure> {
+        if let Some(hart) = self.msip_hart(address) {
+            let old = self.harts[hart].msip as u32;
+            if old == expected {
+                self.harts[hart].msip = new & 1 != 0;
+            }
+            return Ok(old);
+        }
+        self.store(address, 4);
+        self.env.compare_and_swap_word(address, expected, new)
+    }
+    fn atomic_modify_half(
+        &mut self,
+        address: u32,
+        operand: u16,
+        op: fn(u16, u16) -> u16,
+    ) -> Result<u16, MemoryAccessFailure> {
+        if self.msip_hart(address).is_some() {
+            if address & 1 != 0 {
+                return Err(MemoryAccessFailure::Unaligned);
+            }
+            return update_lane(self, address, 16, |old| {
+                Some(op(old as u16, operand) as u32)
+            })
+            .map(|old| old as u16);
+        }
+        self.store(address, 2);
+        self.env.atomic_modify_half(address, operand, op)
+    }
+    fn atomic_modify_byte(
+        &mut self,
+        address: u32,
+        operand: u8,
+        op: fn(u8, u8) -> u8,
+    ) -> Result<u8, MemoryAccessFailure> {
+        if self.msip_hart(address).is_some() {
+            return update_lane(self, address, 8, |old| {
+                Some(op(old as u8, operand) as u32)
+            })
+            .map(|old| old as u8);
+        }
+        self.store(address, 1);
+        self.env.atomic_modify_byte(address, operand, op)
+    }
+    fn compare_and_swap_double(
+        &mut self,
+        address: u32,
+        expected: u64,
+        new: u64,
+    ) -> Result<u64, MemoryAccessFailure> {
+        if address & 7 != 0 {
+            return Err(MemoryAccessFailure::Unaligned);
+        }
+        let high_address = address.wrapping_add(4);
+        if self.msip_hart(address).is_some()
+            || self.msip_hart(high_address).is_some()
+        {
+            // only one hart runs at a time, so a word at a time is still
+            // atomic as far as the others can tell
+            let low = self.read_word(address, !0)?;
+            let high = self.read_word(high_address, !0)?;
+            let old = (high as u64) << 32 | low as u64;
+            if old == expected {
+                self.write_word(address, new as u32, !0)?;
+                self.write_word(high_address, (new >> 32) as u32, !0)?;
+            }
+            return Ok(old);
+        }
+        self.store(address, 8);
+        self.env.compare_and_swap_double(address, expected, new)
+    }
+    fn compare_and_swap_half(
+        &mut self,
+        address: u32,
+        expected: u16,
+        new: u16,
+    ) -> Result<u16, MemoryAccessFailure> {
+        if self.msip_hart(address).is_some() {
+            if address & 1 != 0 {
+                return Err(MemoryAccessFailure::Unaligned);
+            }
+            return update_lane(self, address, 16, |old| {
+                (old == expected as u32).then_some(new as u32)
+            })
+            .map(|old| old as u16);
+        }
+        self.store(address, 2);
+        self.env.compare_and_swap_half(address, expected, new)
+    }
+    fn compare_and_swap_byte(
+        &mut self,
+        address: u32,
+        expected: u8,
+        new: u8,
+    ) -> Result<u8, MemoryAccessFailure> {
+        if self.msip_hart(address).is_some() {
+            return update_lane(self, address, 8, |old| {
+                (old == expected as u32).then_some(new as u32)
+            })
+            .map(|old| old as u8);
+        }
+        self.store(address, 1);
+        self.env.compare_and_swap_byte(address, expected, new)
+    }
+    fn is_io_address(&self, address: u32) -> bool {
+        self.msip_hart(address).is_some() || self.env.is_io_address(address)
+    }
+    fn is_io_csr(&self, csr_number: u32) -> bool {
+        self.env.is_io_csr(csr_number)
+    }
+    fn perform_ecall<F: FloatBits, V: VectorBits, X: IntegerRegisters>(
+        &mut self,
+        cpu: &mut Cpu<F, V, X>,
+    ) -> Result<(), (ExceptionCause, u32)> {
+        self.env.perform_ecall(cpu)
+    }
+    fn perform_ebreak<F: FloatBits, V: VectorBits, X: IntegerRegisters>(
+        &mut self,
+        cpu: &mut Cpu<F, V, X>,
+    ) -> Result<(), (ExceptionCause, u32)> {
+        self.env.perform_ebreak(cpu)
+    }
+    fn perform_custom<F: FloatBits, V: VectorBits, X: IntegerRegisters>(
+        &mut self,
+        cpu: &mut Cpu<F, V, X>,
+        instruction: CustomInstruction,
+    ) -> Result<(), (ExceptionCause, u32)> {
+        self.env.perform_custom(cpu, instruction)
+    }
+    fn read_csr(&mut self, csr_number: u32) -> Result<u32, ExceptionCause> {
+        if csr_number == CSR_MHARTID {
+            Ok(self.hart as u32)
+        } else {
+            self.env.read_csr(csr_number)
+        }
+    }
+    fn write_csr(
+        &mut self,
+        csr_number: u32,
+        new_value: u32,
+    ) -> Result<(), ExceptionCause> {
+        if csr_number == CSR_MHARTID {
+            Err(ExceptionCause::IllegalInstruction)
+        } else {
+            self.env.write_csr(csr_number, new_value)
+        }
+    }
+    fn poll_entropy(&mut self) -> Result<EntropyStatus, ExceptionCause> {
+        self.env.poll_entropy()
+    }
+    fn fence_i(&mut self) {
+        self.env.fence_i()
+    }
+    fn cache_block_op(
+        &mut self,
+        address: u32,
+        op: CacheBlockOp,
+    ) -> Result<(), MemoryAccessFailure> {
+        self.env.cache_block_op(address, op)
+    }
+    // cache_block_zero keeps the default, so the zeroes go through our
+    // write_word
+    fn prefetch(&mut self, address: u32, kind: PrefetchKind) {
+        self.env.prefetch(address, kind)
+    }
+    fn pause(&mut self) {
+        self.yielded = true;
+        self.env.pause()
+    }
+    fn wait_on_reservation(
+        &mut self,
+        short_timeout: bool,
+    ) -> Result<(), ExceptionCause> {
+        // nothing to wait for without a reservation
+        if self.harts[self.hart].reservation.is_none() {
+            return Ok(());
+        }
+        self.yielded = true;
+        self.env.wait_on_reservation(short_timeout)
+    }
+    fn non_temporal_hint(&mut self, locality: NonTemporalLocality) {
+        self.env.non_temporal_hint(locality)
+    }
+    fn read_mtime(&mut self) -> Result<u64, ExceptionCause> {
+        self.env.read_mtime()
+    }
+    fn cycle_count(&self) -> Option<u64> {
+        self.env.cycle_count()
+    }
+    fn read_vs(&self) -> ExtensionStatus {
+        self.env.read_vs()
+    }
+    fn write_vs(&mut self, status: ExtensionStatus) {
+        self.env.write_vs(status)
+    }
+    fn read_fs(&self) -> ExtensionStatus {
+        self.env.read_fs()
+    }
+    fn write_fs(&mut self, status: ExtensionStatus) {
+        self.env.write_fs(status)
+    }
+    fn use_accurate_single_sqrt(&self) -> bool {
+        self.env.use_accurate_single_sqrt()
+    }
+    fn use_accurate_double_sqrt(&self) -> bool {
+        self.env.use_accurate_double_sqrt()
+    }
+    fn use_accurate_quad_sqrt(&self) -> bool {
+        self.env.use_accurate_quad_sqrt()
+    }
+    fn account_ifetch(&mut self, pc: u32) {
+        self.env.account_ifetch(pc)
+    }
+    fn account_retire(&mut self) {
+        self.env.account_retire()
+    }
+    fn account_generic_op(&mut self) {
+        self.env.account_generic_op()
+    }
+    fn account_memory_load(&mut self, address: u32) {
+        self.env.account_memory_load(address)
+    }
+    fn account_memory_store(&mut self, address: u32) {
+        self.env.account_memory_store(address)
+    }
+    fn account_memory_double_load(&mut self, address: u32) {
+        self.env.account_memory_double_load(address)
+    }
+    fn account_memory_double_store(&mut self, address: u32) {
+        self.env.account_memory_double_store(address)
+    }
+    fn account_memory_quad_load(&mut self, address: u32) {
+        self.env.account_memory_quad_load(address)
+    }
+    fn account_memory_quad_store(&mut self, address: u32) {
+        self.env.account_memory_quad_store(address)
+    }
+    fn account_memory_op(&mut self, address: u32) {
+        self.env.account_memory_op(address)
+    }
+    fn account_alu_op(&mut self) {
+        self.env.account_alu_op()
+    }
+    fn account_mul_op(&mut self) {
+        self.env.account_mul_op()
+    }
+    fn account_div_op(&mut self) {
+        self.env.account_div_op()
+    }
+    fn account_clmul_op(&mut self) {
+        self.env.account_clmul_op()
+    }
+    fn account_xperm_op(&mut self) {
+        self.env.account_xperm_op()
+    }
+    fn account_crypto_op(&mut self) {
+        self.env.account_crypto_op()
+    }
+    fn account_amo_op(&mut self) {
+        self.env.account_amo_op()
+    }
+    fn account_jump_op(&mut self) {
+        self.env.account_jump_op()
+    }
+    fn account_branch_op(&mut self, did_take: bool, was_forward: bool) {
+        self.env.account_branch_op(did_take, was_forward)
+    }
+    fn account_float_op(&mut self, num_words: u32) {
+        self.env.account_float_op(num_words)
+    }
+    fn account_float_divide(&mut self, num_words: u32) {
+        self.env.account_float_divide(num_words)
+    }
+    fn account_float_ternop(&mut self, num_words: u32) {
+        self.env.account_float_ternop(num_words)
+    }
+    fn account_fcvt_from_int(&mut self, num_words: u32) {
+        self.env.account_fcvt_from_int(num_words)
+    }
+    fn account_fcvt_to_int(&mut self, num_words: u32) {
+        self.env.account_fcvt_to_int(num_words)
+    }
+    fn account_sqrt(&mut self, num_words: u32, num_iterations: u32) {
+        self.env.account_sqrt(num_words, num_iterations)
+    }
+    fn account_vector_op(&mut self, elements: u32) {
+        self.env.account_vector_op(elements)
+    }
+    fn account_custom_op(&mut self) {
+        self.env.account_custom_op()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    const PROGRAM: [u32; 9] = [
+        0xf1402673, // csrr x12, mhartid
+        0x06400293, // li x5, 100
+        0x1005232f, // 1: lr.w x6, (x10)
+        0x00130313, // addi x6, x6, 1
+        0x186523af, // sc.w x7, x6, (x10)
+        0xfe039ae3, // bnez x7, 1b
+        0xfff28293, // addi x5, x5, -1
+        0xfe0296e3, // bnez x5, 1b
+        0x0000006f, // j .
+    ];
+    struct Ram([u32; 128]);
+    impl ExecutionEnvironment for Ram {
+        const SUPPORT_ZAWRS: bool = true;
+        const SUPPORT_ZACAS: bool = true;
+        const SUPPORT_ZIHINTPAUSE: bool = true;
+        fn read_word(
+            &mut self,
+            address: u32,
+            _mask: u32,
+        ) -> Result<u32, MemoryAccessFailure> {
+            Ok(self.0[(address / 4) as usize % 128])
+        }
+        fn write_word(
+            &mut self,
+            address: u32,
+            data: u32,
+            mask: u32,
+        ) -> Result<(), MemoryAccessFailure> {
+            let word = &mut self.0[(address / 4) as usize % 128];
+            *word = (*word & !mask) | (data & mask);
+            Ok(())
+        }
+        fn load_reserved_word(
+            &mut self,
+            _address: u32,
+        ) -> Result<u32, MemoryAccessFailure> {
+            unreachable!()
+        }
+        fn store_reserved_word(
+            &mut self,
+            _address: u32,
+            _data: u32,
+        ) -> Result<bool, MemoryAccessFailure> {
+            unreachable!()
+        }
+    }
+    impl SmpEnvironment for Ram {}
+    #[test]
+    fn contended_increment() {
+        let mut ram = Ram([0; 128]);
+        ram.0[..PROGRAM.len()].copy_from_slice(&PROGRAM);
+        for schedule in [Schedule::RoundRobin, Schedule::Quantum(3)] {
+            let mut machine = Machine::<(), _>::new(ram, 4);
+            machine.set_schedule(schedule);
+            for hart in 0..4 {
+                machine.cpu_mut(hart).set_register(10, 0x100);
+            }
+            for _ in 0..10000 {
+                machine.step().unwrap();
+            }
+            for hart in 0..4 {
+                assert_eq!(machine.cpu(hart).get_pc(), 32);
+                assert_eq!(machine.cpu(hart).get_register(12), hart as u32);
+            }
+            assert_eq!(machine.env().0[0x40], 400);
+            ram = machine.into_env();
+            ram.0[0x40] = 0;
+        }
+    }
+    #[test]
+    fn contended_amocas_double() {
+        let mut ram = Ram([0; 128]);
+        ram.0[..14].copy_from_slice(&[
+            0x06400293, // li x5, 100
+            0x00052303, // 1: lw x6, 0(x10)
+            0x00452383, // lw x7, 4(x10)
+            0x00130413, // addi x8, x6, 1
+            0x006434b3, // sltu x9, x8, x6
+            0x009384b3, // add x9, x7, x9
+            0x00030e13, // mv x28, x6
+            0x00038e93, // mv x29, x7
+            0x2885332f, // amocas.d x6, x8, (x10)
+            0xffc310e3, // bne x6, x28, 1b
+            0xfdd39ee3, // bne x7, x29, 1b
+            0xfff28293, // addi x5, x5, -1
+            0xfc029ae3, // bnez x5, 1b
+            0x0000006f, // j .
+        ]);
+        // the low word carries into the high one partway through
+        ram.0[0x40] = 0xFFFF_FF80;
+        let mut machine = Machine::<(), _>::new(ram, 2);
+        machine.set_schedule(Schedule::Quantum(5));
+        for hart in 0..2 {
+            machine.cpu_mut(hart).set_register(10, 0x100);
+        }
+        for _ in 0..10000 {
+            machine.step().unwrap();
+        }
+        for hart in 0..2 {
+            assert_eq!(machine.cpu(hart).get_pc(), 52);
+        }
+        assert_eq!(machine.env().0[0x40..0x42], [0x48, 1]);
+    }
+    #[test]
+    fn ipi() {
+        let mut ram = Ram([0; 128]);
+        ram.0[0] = 0x00b52023; // sw x11, 0(x10)
+        let mut machine = Machine::<(), _>::new(ram, 2);
+        machine.set_msip_base(Some(0x1000));
+        machine.cpu_mut(0).set_register(10, 0x1004);
+        machine.cpu_mut(0).set_register(11, 1);
+        assert!(!machine.ipi_pending(1));
+        machine.step_hart(0).unwrap();
+        assert!(machine.ipi_pending(1));
+        assert!(!machine.ipi_pending(0));
+    }
+    #[test]
+    fn spinning_yields() {
+        let mut ram = Ram([0; 128]);
+        ram.0[..4].copy_from_slice(&[
+            0x0100000f, // pause
+            0x1005232f, // lr.w x6, (x10)
+            0x01d00073, // wrs.sto
+            0x0000006f, // j .
+        ]);
+        let mut machine = Machine::<(), _>::new(ram, 2);
+        machine.set_schedule(Schedule::Quantum(10));
+        let harts = (0..7)
+            .map(|_| machine.step().unwrap().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(harts, [0, 1, 0, 0, 1, 1, 0]);
+    }
+    #[test]
+    fn rv32e() {
+        let mut ram = Ram([0; 128]);
+        ram.0[..PROGRAM.len()].copy_from_slice(&PROGRAM);
+        let mut machine = Machine::<(), _, (), [u32; 16]>::new(ram, 2);
+        machine.set_schedule(Schedule::Quantum(3));
+        for hart in 0..2 {
+            machine.cpu_mut(hart).set_register(10, 0x100);
+        }
+        for _ in 0..5000 {
+            machine.step().unwrap();
+        }
+        for hart in 0..2 {
+            assert_eq!(machine.cpu(hart).get_pc(), 32);
+            assert_eq!(machine.cpu(hart).get_register(12), hart as u32);
+        }
+        assert_eq!(machine.env().0[0x40], 200);
+    }
+}
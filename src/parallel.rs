@@ -0,0 +1,509 @@
+//! Several harts running on several host threads.
+
+use super::*;
+
+use std::{
+    hint::spin_loop,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+    thread,
+};
+
+/// Number of reservation stripes in a `SharedRam`. Words that share a
+/// stripe can break each other's reservations, which is allowed (SC may fail
+/// spuriously) but slows things down, so this should be comfortably more
+/// than the number of words anyone is likely to be spinning on at once.
+const STRIPE_COUNT: usize = 4096;
+
+/// RAM that can be shared between harts running on different threads,
+/// starting at address 0.
+///
+/// Every access is single-copy atomic, `LR.W`/`SC.W` and the AMOs are truly
+/// atomic with respect to every other hart (including Zacas and Zabha's, as
+/// long as you forward `compare_and_swap_word` and `compare_and_swap_double`
+/// along with `atomic_modify_word`), and all accesses are sequentially
+/// consistent (which is stronger than RVWMO requires, so correct programs
+/// will run correctly, but some incorrect programs will too).
+///
+/// This is just the memory. Each hart needs its own `ExecutionEnvironment`,
+/// which holds a reference to the `SharedRam` (e.g. an `Arc`) and a
+/// [`Reservation`], and forwards RAM accesses to the methods here. That's
+/// also where you put any devices, CSRs, etc.
+pub struct SharedRam {
+    words: Box<[AtomicU32]>,
+    /// Even: the generation of this stripe, bumped by every store. Odd: a
+    /// store is in progress.
+    stripes: Box<[AtomicU32]>,
+}
+
+/// A single hart's reservation on a [`SharedRam`] word. Keep one of these
+/// in each hart's environment.
+#[derive(Clone, Debug, Default)]
+pub struct Reservation(Option<(u32, u32)>);
+
+impl Reservation {
+    /// Throw away the reservation, if any. (e.g. on a trap, or a context
+    /// switch)
+    pub fn clear(&mut self) {
+        self.0 = None;
+    }
+}
+
+impl SharedRam {
+    /// Create a zero-filled RAM of the given size in bytes, rounded up to a
+    /// whole number of words.
+    pub fn new(size: u32) -> SharedRam {
+        SharedRam {
+            words: (0..size.div_ceil(4)).map(|_| AtomicU32::new(0)).collect(),
+            stripes: (0..STRIPE_COUNT).map(|_| AtomicU32::new(0)).collect(),
+        }
+    }
+    /// The size of the RAM, in bytes.
+    pub fn size(&self) -> u32 {
+        self.words.len() as u32 * 4
+    }
+    fn word(
+        &self,
+        address: u32,
+    ) -> Result<(&AtomicU32, &AtomicU32), MemoryAccessFailure> {
+        if address & 3 != 0 {
+            return Err(MemoryAccessFailure::Unaligned);
+        }
+        let index = (address / 4) as usize;
+        let word = self
+            .words
+            .get(index)
+            .ok_or(MemoryAccessFailure::AccessFault)?;
+        Ok((word, &self.stripes[index % STRIPE_COUNT]))
+    }
+    /// Wait for any store in progress on this stripe, then start our own.
+    /// Returns the generation to pass to `unlock`.
+    fn lock(stripe: &AtomicU32) -> u32 {
+        loop {
+            let generation = stripe.load(Ordering::Relaxed);
+            if generation & 1 == 0
+                && stripe
+                    .compare_exchange_weak(
+                        generation,
+                        generation | 1,
+                        Ordering::SeqCst,
+                        Ordering::Relaxed,
+                    )
+                    .is_ok()
+            {
+                return generation;
+            }
+            spin_loop();
+        }
+    }
+    fn unlock(stripe: &AtomicU32, generation: u32) {
+        stripe.store(generation.wrapping_add(2), Ordering::SeqCst);
+    }
+    /// As [`ExecutionEnvironment::read_word`].
+    pub fn read_word(
+        &self,
+        address: u32,
+        _mask: u32,
+    ) -> Result<u32, MemoryAccessFailure> {
+        let (word, _) = self.word(address)?;
+        Ok(word.load(Ordering::SeqCst))
+    }
+    /// As [`ExecutionEnvironment::write_word`]. Breaks every hart's
+    /// reservation on this word.
+    pub fn write_word(
+        &self,
+        address: u32,
+        data: u32,
+        mask: u32,
+    ) -> Result<(), MemoryAccessFailure> {
+        let (word, stripe) = self.word(address)?;
+        let generation = Self::lock(stripe);
+        // only stores hold the lock, so nobody can sneak in between the load
+        // and the store
+        let old = word.load(Ordering::SeqCst);
+        word.store((old & !mask) | (data & mask), Ordering::SeqCst);
+        Self::unlock(stripe, generation);
+        Ok(())
+    }
+    /// As [`ExecutionEnvironment::atomic_modify_word`]. Breaks every hart's
+    /// reservation on this word.
+    pub fn atomic_modify_word(
+        &self,
+        address: u32,
+        operand: u32,
+        op: fn(u32, u32) -> u32,
+    ) -> Result<u32, MemoryAccessFailure> {
+        let (word, stripe) = self.word(address)?;
+        let generation = Self::lock(stripe);
+        let old = word.load(Ordering::SeqCst);
+        word.store(op(old, operand), Ordering::SeqCst);
+        Self::unlock(stripe, generation);
+        Ok(old)
+    }
+    /// As [`ExecutionEnvironment::compare_and_swap_word`]. Breaks every
+    /// hart's reservation on this word, even if the comparison fails.
+    pub fn compare_and_swap_word(
+        &self,
+        address: u32,
+        expected: u32,
+        new: u32,
+    ) -> Result<u32, MemoryAccessFailure> {
+        let (word, stripe) = self.word(address)?;
+        let generation = Self::lock(stripe);
+        let old = word.load(Ordering::SeqCst);
+        if old == expected {
+            word.store(new, Ordering::SeqCst);
+        }
+        Self::unlock(stripe, generation);
+        Ok(old)
+    }
+    /// As [`ExecutionEnvironment::compare_and_swap_double`]. Breaks every
+    /// hart's reservation on both words, even if the comparison fails.
+    pub fn compare_and_swap_double(
+        &self,
+        address: u32,
+        expected: u64,
+        new: u64,
+    ) -> Result<u64, MemoryAccessFailure> {
+        if address & 7 != 0 {
+            return Err(MemoryAccessFailure::Unaligned);
+        }
+        let (low, low_stripe) = self.word(address)?;
+        let (high, high_stripe) = self.word(address.wrapping_add(4))?;
+        // always lock the lower numbered stripe first, so that two of these
+        // can't deadlock
+        let (first, second) =
+            if (address / 4) as usize % STRIPE_COUNT < STRIPE_COUNT - 1 {
+                (low_stripe, high_stripe)
+            } else {
+                (high_stripe, low_stripe)
+            };
+        let first_generation = Self::lock(first);
+        let second_generation = Self::lock(second);
+        let old = (high.load(Ordering::SeqCst) as u64) << 32
+            | low.load(Ordering::SeqCst) as u64;
+        if old == expected {
+            low.store(new as u32, Ordering::SeqCst);
+            high.store((new >> 32) as u32, Ordering::SeqCst);
+        }
+        Self::unlock(second, second_generation);
+        Self::unlock(first, first_generation);
+        Ok(old)
+    }
+    /// As [`ExecutionEnvironment::load_reserved_word`], reserving the word
+    /// for the hart that owns `reservation`.
+    pub fn load_reserved_word(
+        &self,
+        address: u32,
+        reservation: &mut Reservation,
+    ) -> Result<u32, MemoryAccessFailure> {
+        let (word, stripe) = self.word(address)?;
+        loop {
+            let generation = stripe.load(Ordering::SeqCst);
+            if generation & 1 == 0 {
+                let value = word.load(Ordering::SeqCst);
+                if stripe.load(Ordering::SeqCst) == generation {
+                    reservation.0 = Some((address, generation));
+                    return Ok(value);
+                }
+            }
+            spin_loop();
+        }
+    }
+    /// As [`ExecutionEnvironment::store_reserved_word`]. Succeeds only if no
+    /// hart has stored to the reserved word (or another word in the same
+    /// stripe) since `load_reserved_word`. Always consumes the reservation.
+    pub fn store_reserved_word(
+        &self,
+        address: u32,
+        data: u32,
+        reservation: &mut Reservation,
+    ) -> Result<bool, MemoryAccessFailure> {
+        let (word, stripe) = self.word(address)?;
+        match reservation.0.take() {
+            Some((reserved, generation)) if reserved == address => {
+                if stripe
+                    .compare_exchange(
+                        generation,
+                        generation | 1,
+                        Ordering::SeqCst,
+                        Ordering::Relaxed,
+                    )
+                    .is_err()
+                {
+                    return Ok(false);
+                }
+                word.store(data, Ordering::SeqCst);
+                Self::unlock(stripe, generation);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+}
+
+/// Several harts, each with its own environment, each running on its own
+/// host thread.
+///
+/// The harts only share what their environments share, typically a
+/// [`SharedRam`]. Unlike [`Machine`], nothing here knows about `mhartid` or
+/// IPIs; your environments are in charge of all of that.
+///
+/// As with [`Machine`], `V` and `X` are the harts' vector and integer
+/// register files.
+pub struct ParallelMachine<
+    F: FloatBits,
+    Env: ExecutionEnvironment + Send,
+    V: VectorBits = (),
+    X: IntegerRegisters = [u32; 32],
+> {
+    harts: Vec<(Cpu<F, V, X>, Env)>,
+}
+
+impl<
+        F: FloatBits,
+        Env: ExecutionEnvironment + Send,
+        V: VectorBits,
+        X: IntegerRegisters,
+    > ParallelMachine<F, Env, V, X>
+where
+    Cpu<F, V, X>: Send,
+{
+    /// Create a machine with one freshly reset hart per environment given.
+    pub fn new(
+        envs: impl IntoIterator<Item = Env>,
+    ) -> ParallelMachine<F, Env, V, X> {
+        ParallelMachine {
+            harts: envs.into_iter().map(|env| (Cpu::new(), env)).collect(),
+        }
+    }
+    /// Number of harts in this machine.
+    pub fn hart_count(&self) -> usize {
+        self.harts.len()
+    }
+    /// The CPU state of the given hart.
+    pub fn cpu(&self, hart: usize) -> &Cpu<F, V, X> {
+        &self.harts[hart].0
+    }
+    /// The CPU state of the given hart.
+    pub fn cpu_mut(&mut self, hart: usize) -> &mut Cpu<F, V, X> {
+        &mut self.harts[hart].0
+    }
+    /// The given hart's environment.
+    pub fn env(&self, hart: usize) -> &Env {
+        &self.harts[hart].1
+    }
+    /// The given hart's environment.
+    pub fn env_mut(&mut self, hart: usize) -> &mut Env {
+        &mut self.harts[hart].1
+    }
+    /// Tear down the machine, giving back each hart's CPU and environment.
+    pub fn into_harts(self) -> Vec<(Cpu<F, V, X>, Env)> {
+        self.harts
+    }
+    /// Run every hart on its own thread for up to `steps` instructions each,
+    /// and wait for them all to finish. If any hart hits an exception, every
+    /// hart stops at its next instruction boundary, and you get back all the
+    /// exceptions that occurred (in hart order). Handle them and call `run`
+    /// again.
+    ///
+    /// Threads are spawned anew on every call, so make `steps` large.
+    pub fn run(&mut self, steps: u64) -> Result<(), Vec<HartException>> {
+        let stop = AtomicBool::new(false);
+        let exceptions: Vec<HartException> = thread::scope(|scope| {
+            let threads: Vec<_> = self
+                .harts
+                .iter_mut()
+                .enumerate()
+                .map(|(hart, (cpu, env))| {
+                    let stop = &stop;
+                    scope.spawn(move || {
+                        for _ in 0..steps {
+                            if stop.load(Ordering::Relaxed) {
+                                break;
+                            }
+                            if let Err(exception) = cpu.step(env) {
+                                stop.store(true, Ordering::Relaxed);
+                                return Some(HartException {
+                                    hart,
+                                    exception,
+                                });
+                            }
+                        }
+                        None
+                    })
+                })
+                .collect();
+            threads
+                .into_iter()
+                .filter_map(|thread| thread.join().unwrap())
+                .collect()
+        });
+        if exceptions.is_empty() {
+            Ok(())
+        } else {
+            Err(exceptions)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Arc;
+    const PROGRAM: [u32; 9] = [
+        0x06400293, // li x5, 100
+        0x1005232f, // 1: lr.w x6, (x10)
+        0x00130313, // addi x6, x6, 1
+        0x186523af, // sc.w x7, x6, (x10)
+        0xfe039ae3, // bnez x7, 1b
+        0x00d5a02f, // amoadd.w x0, x13, (x11)
+        0xfff28293, // addi x5, x5, -1
+        0xfe0294e3, // bnez x5, 1b
+        0x0000006f, // j .
+    ];
+    struct Hart {
+        ram: Arc<SharedRam>,
+        reservation: Reservation,
+    }
+    impl ExecutionEnvironment for Hart {
+        const SUPPORT_ZACAS: bool = true;
+        const SUPPORT_ZABHA: bool = true;
+        fn read_word(
+            &mut self,
+            address: u32,
+            mask: u32,
+        ) -> Result<u32, MemoryAccessFailure> {
+            self.ram.read_word(address, mask)
+        }
+        fn write_word(
+            &mut self,
+            address: u32,
+            data: u32,
+            mask: u32,
+        ) -> Result<(), MemoryAccessFailure> {
+            self.ram.write_word(address, data, mask)
+        }
+        fn atomic_modify_word(
+            &mut self,
+            address: u32,
+            operand: u32,
+            op: fn(u32, u32) -> u32,
+        ) -> Result<u32, MemoryAccessFailure> {
+            self.ram.atomic_modify_word(address, operand, op)
+        }
+        fn compare_and_swap_word(
+            &mut self,
+            address: u32,
+            expected: u32,
+            new: u32,
+        ) -> Result<u32, MemoryAccessFailure> {
+            self.ram.compare_and_swap_word(address, expected, new)
+        }
+        fn compare_and_swap_double(
+            &mut self,
+            address: u32,
+            expected: u64,
+            new: u64,
+        ) -> Result<u64, MemoryAccessFailure> {
+            self.ram.compare_and_swap_double(address, expected, new)
+        }
+        fn load_reserved_word(
+            &mut self,
+            address: u32,
+        ) -> Result<u32, MemoryAccessFailure> {
+            self.ram.load_reserved_word(address, &mut self.reservation)
+        }
+        fn store_reserved_word(
+            &mut self,
+            address: u32,
+            data: u32,
+        ) -> Result<bool, MemoryAccessFailure> {
+            self.ram
+                .store_reserved_word(address, data, &mut self.reservation)
+        }
+    }
+    #[test]
+    fn contended_increment() {
+        let ram = Arc::new(SharedRam::new(512));
+        for (n, &word) in PROGRAM.iter().enumerate() {
+            ram.write_word(n as u32 * 4, word, !0).unwrap();
+        }
+        let mut machine =
+            ParallelMachine::<(), _>::new((0..4).map(|_| Hart {
+                ram: ram.clone(),
+                reservation: Reservation::default(),
+            }));
+        for hart in 0..4 {
+            let cpu = machine.cpu_mut(hart);
+            cpu.set_register(10, 0x100);
+            cpu.set_register(11, 0x104);
+            cpu.set_register(13, 1);
+        }
+        machine.run(100000).unwrap();
+        for hart in 0..4 {
+            assert_eq!(machine.cpu(hart).get_pc(), 32);
+        }
+        assert_eq!(ram.read_word(0x100, !0), Ok(400));
+        assert_eq!(ram.read_word(0x104, !0), Ok(400));
+    }
+    #[test]
+    fn contended_cas() {
+        let program = [
+            0x06400293, // li x5, 100
+            0x00052303, // 1: lw x6, (x10)
+            0x00130393, // addi x7, x6, 1
+            0x00030413, // mv x8, x6
+            0x2875242f, // amocas.w x8, x7, (x10)
+            0xfe6418e3, // bne x8, x6, 1b
+            0x00d5902f, // amoadd.h x0, x13, (x11)
+            0xfff28293, // addi x5, x5, -1
+            0xfe0292e3, // bnez x5, 1b
+            0x0000006f, // j .
+        ];
+        let ram = Arc::new(SharedRam::new(512));
+        for (n, &word) in program.iter().enumerate() {
+            ram.write_word(n as u32 * 4, word, !0).unwrap();
+        }
+        let mut machine =
+            ParallelMachine::<(), _>::new((0..4).map(|_| Hart {
+                ram: ram.clone(),
+                reservation: Reservation::default(),
+            }));
+        for hart in 0..4 {
+            let cpu = machine.cpu_mut(hart);
+            cpu.set_register(10, 0x100);
+            cpu.set_register(11, 0x106);
+            cpu.set_register(13, 1);
+        }
+        machine.run(100000).unwrap();
+        for hart in 0..4 {
+            assert_eq!(machine.cpu(hart).get_pc(), 36);
+        }
+        assert_eq!(ram.read_word(0x100, !0), Ok(400));
+        // only the high half of this word was added to
+        assert_eq!(ram.read_word(0x104, !0), Ok(400 << 16));
+    }
+    #[test]
+    fn rv32e() {
+        let ram = Arc::new(SharedRam::new(512));
+        for (n, &word) in PROGRAM.iter().enumerate() {
+            ram.write_word(n as u32 * 4, word, !0).unwrap();
+        }
+        let mut machine =
+            ParallelMachine::<(), _, (), [u32; 16]>::new((0..2).map(|_| {
+                Hart {
+                    ram: ram.clone(),
+                    reservation: Reservation::default(),
+                }
+            }));
+        for hart in 0..2 {
+            let cpu = machine.cpu_mut(hart);
+            cpu.set_register(10, 0x100);
+            cpu.set_register(11, 0x104);
+            cpu.set_register(13, 1);
+        }
+        machine.run(100000).unwrap();
+        assert_eq!(ram.read_word(0x100, !0), Ok(200));
+        assert_eq!(ram.read_word(0x104, !0), Ok(200));
+    }
+}
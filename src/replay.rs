@@ -0,0 +1,1121 @@
+//! Deterministic record and replay of everything an `ExecutionEnvironment`
+//! feeds into the CPU that might not be the same next time.
+
+use std::io::{self, Read, Write};
+
+use super::*;
+
+/// One nondeterministic input that the environment gave to the CPU.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Input {
+    /// The outcome of a read from an I/O address. (See
+    /// [`is_io_address`](ExecutionEnvironment::is_io_address).) Byte and
+    /// halfword reads are zero extended.
+    Memory(Result<u32, MemoryAccessFailure>),
+    /// The outcome of a read from an I/O CSR (see
+    /// [`is_io_csr`](ExecutionEnvironment::is_io_csr)), of polling the
+    /// entropy source (see
+    /// [`poll_entropy`](ExecutionEnvironment::poll_entropy)), or of half of
+    /// a [`read_mtime`](ExecutionEnvironment::read_mtime).
+    Csr(Result<u32, ExceptionCause>),
+    /// The outcome of an `ECALL`: what it returned, and which integer
+    /// registers it changed. (Register 0 stands in for the PC.)
+    Ecall {
+        result: Result<(), (ExceptionCause, u32)>,
+        registers: Vec<(u8, u32)>,
+    },
+}
+
+/// A sequence of [`Input`]s, in the order they were consumed.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct InputLog {
+    entries: Vec<Input>,
+}
+
+const LOG_MAGIC: &[u8; 8] = b"rrv32in\x01";
+
+const TAG_MEMORY_OK: u8 = 0;
+const TAG_MEMORY_ERR: u8 = 1;
+const TAG_CSR_OK: u8 = 2;
+const TAG_CSR_ERR: u8 = 3;
+const TAG_ECALL_OK: u8 = 4;
+const TAG_ECALL_ERR: u8 = 5;
+
+fn write_varint<W: Write>(w: &mut W, mut value: u32) -> io::Result<()> {
+    let mut buf = [0u8; 5];
+    let mut n = 0;
+    loop {
+        buf[n] = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            n += 1;
+            break;
+        }
+        buf[n] |= 0x80;
+        n += 1;
+    }
+    w.write_all(&buf[..n])
+}
+
+fn read_u8<R: Read>(r: &mut R) -> io::Result<u8> {
+    let mut buf = [0u8];
+    r.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_varint<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut ret = 0u32;
+    for shift in (0..35).step_by(7) {
+        let b = read_u8(r)?;
+        ret |= ((b & 0x7F) as u32) << shift;
+        if b & 0x80 == 0 {
+            return Ok(ret);
+        }
+    }
+    Err(invalid_data("overlong number in input log"))
+}
+
+fn invalid_data(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, what)
+}
+
+fn failure_from_code(code: u8) -> io::Result<MemoryAccessFailure> {
+    Ok(match code {
+        0 => MemoryAccessFailure::Unaligned,
+        1 => MemoryAccessFailure::AccessFault,
+        2 => MemoryAccessFailure::PageFault,
+        _ => return Err(invalid_data("unknown memory failure in input log")),
+    })
+}
+
+fn cause_from_code(code: u8) -> io::Result<ExceptionCause> {
+    use ExceptionCause::*;
+    Ok(match code {
+        0 => MisalignedPC,
+        1 => InstructionAccessFault,
+        2 => IllegalInstruction,
+        3 => Breakpoint,
+        4 => MisalignedLoad,
+        5 => LoadAccessFault,
+        6 => MisalignedStore,
+        7 => StoreAccessFault,
+        8 => EcallFromUmode,
+        9 => EcallFromSmode,
+        11 => EcallFromMmode,
+        12 => InstructionPageFault,
+        13 => LoadPageFault,
+        15 => StorePageFault,
+        _ => return Err(invalid_data("unknown exception cause in input log")),
+    })
+}
+
+impl InputLog {
+    /// Creates a new, empty log.
+    pub fn new() -> InputLog {
+        InputLog::default()
+    }
+    /// Number of inputs in the log.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    /// True if the log has no inputs in it.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    /// All of the inputs in the log, in order.
+    pub fn entries(&self) -> &[Input] {
+        &self.entries[..]
+    }
+    pub(crate) fn truncate(&mut self, len: usize) {
+        self.entries.truncate(len)
+    }
+    /// Write the log in a compact binary format, suitable for reading back
+    /// with [`read_from`](Self::read_from). Most inputs take two to six
+    /// bytes.
+    pub fn write_to<W: Write>(&self, mut w: W) -> io::Result<()> {
+        w.write_all(LOG_MAGIC)?;
+        write_varint(&mut w, self.entries.len() as u32)?;
+        for entry in self.entries.iter() {
+            match entry {
+                Input::Memory(Ok(value)) => {
+                    w.write_all(&[TAG_MEMORY_OK])?;
+                    write_varint(&mut w, *value)?;
+                }
+                Input::Memory(Err(failure)) => {
+                    w.write_all(&[TAG_MEMORY_ERR, *failure as u8])?;
+                }
+                Input::Csr(Ok(value)) => {
+                    w.write_all(&[TAG_CSR_OK])?;
+                    write_varint(&mut w, *value)?;
+                }
+                Input::Csr(Err(cause)) => {
+                    w.write_all(&[TAG_CSR_ERR, *cause as u8])?;
+                }
+                Input::Ecall { result, registers } => {
+                    match result {
+                        Ok(()) => w.write_all(&[TAG_ECALL_OK])?,
+                        Err((cause, mtval)) => {
+                            w.write_all(&[TAG_ECALL_ERR, *cause as u8])?;
+                            write_varint(&mut w, *mtval)?;
+                        }
+                    }
+                    w.write_all(&[registers.len() as u8])?;
+                    for (index, value) in registers.iter() {
+                        w.write_all(&[*index])?;
+                        write_varint(&mut w, *value)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Read a log previously written with [`write_to`](Self::write_to).
+    pub fn read_from<R: Read>(mut r: R) -> io::Result<InputLog> {
+        let mut magic = [0u8; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != LOG_MAGIC {
+            return Err(invalid_data("not an rrv32 input log"));
+        }
+        let count = read_varint(&mut r)? as usize;
+        let mut entries = Vec::with_capacity(count.min(1 << 20));
+        for _ in 0..count {
+            entries.push(match read_u8(&mut r)? {
+                TAG_MEMORY_OK => Input::Memory(Ok(read_varint(&mut r)?)),
+                TAG_MEMORY_ERR => {
+                    Input::Memory(Err(failure_from_code(read_u8(&mut r)?)?))
+                }
+                TAG_CSR_OK => Input::Csr(Ok(read_varint(&mut r)?)),
+                TAG_CSR_ERR => {
+                    Input::Csr(Err(cause_from_code(read_u8(&mut r)?)?))
+                }
+                tag @ (TAG_ECALL_OK | TAG_ECALL_ERR) => {
+                    let result = if tag == TAG_ECALL_OK {
+                        Ok(())
+                    } else {
+                        let cause = cause_from_code(read_u8(&mut r)?)?;
+                        Err((cause, read_varint(&mut r)?))
+                    };
+                    let count = read_u8(&mut r)?;
+                    let mut registers = Vec::with_capacity(count as usize);
+                    for _ in 0..count {
+                        let index = read_u8(&mut r)?;
+                        if index >= 32 {
+                            return Err(invalid_data(
+                                "bad register number in input log",
+                            ));
+                        }
+                        registers.push((index, read_varint(&mut r)?));
+                    }
+                    Input::Ecall { result, registers }
+                }
+                _ => return Err(invalid_data("unknown entry in input log")),
+            });
+        }
+        Ok(InputLog { entries })
+    }
+}
+
+/// Wraps an `ExecutionEnvironment`, passing everything through to it, except
+/// for the things that might not come out the same way twice:
+///
+/// - Reads from I/O addresses (see
+///   [`is_io_address`](ExecutionEnvironment::is_io_address))
+/// - Reads from I/O CSRs (see
+///   [`is_io_csr`](ExecutionEnvironment::is_io_csr))
+/// - `ECALL`s
+///
+/// While recording, these are performed as normal, and their outcomes are
+/// appended to an [`InputLog`]. While replaying, they are **not** performed;
+/// instead, their outcomes are taken from the log. Writes to I/O addresses
+/// are also dropped on the floor while replaying, so that the outside world
+/// doesn't see them twice.
+///
+/// Given the same starting state and the same log, a replay will be
+/// bit-identical to the recorded run, as long as everything else your
+/// environment does is deterministic. One thing to watch out for: an
+/// `ECALL`'s changes to the integer registers are logged, but any changes
+/// your `perform_ecall` makes to memory or to its own state are not. If
+/// your `ECALL`s need to feed data into memory, have the guest read it from
+/// an I/O address instead.
+///
+/// Replaying past the end of the log will PANIC. Check
+/// [`is_exhausted`](Self::is_exhausted) and call
+/// [`stop_replaying`](Self::stop_replaying) if you want to take over where
+/// the recording left off.
+pub struct Recorder<Env: ExecutionEnvironment> {
+    pub(crate) inner: Env,
+    pub(crate) log: InputLog,
+    pub(crate) cursor: usize,
+    pub(crate) replaying: bool,
+    /// If set, any store that touches this word will set `watch_hit`.
+    pub(crate) watch: Option<u32>,
+    pub(crate) watch_hit: bool,
+}
+
+impl<Env: ExecutionEnvironment> Recorder<Env> {
+    /// Wrap an environment, and start recording.
+    pub fn new(inner: Env) -> Recorder<Env> {
+        Recorder {
+            inner,
+            log: InputLog::new(),
+            cursor: 0,
+            replaying: false,
+            watch: None,
+            watch_hit: false,
+        }
+    }
+    /// Wrap an environment, and start replaying the given log.
+    pub fn replay(inner: Env, log: InputLog) -> Recorder<Env> {
+        Recorder {
+            replaying: true,
+            log,
+            ..Recorder::new(inner)
+        }
+    }
+    /// The wrapped environment.
+    pub fn inner(&self) -> &Env {
+        &self.inner
+    }
+    /// The wrapped environment. Be careful not to make any changes that
+    /// would throw off a replay!
+    pub fn inner_mut(&mut self) -> &mut Env {
+        &mut self.inner
+    }
+    /// Everything recorded so far (or everything being replayed).
+    pub fn log(&self) -> &InputLog {
+        &self.log
+    }
+    /// Take apart the `Recorder`, returning the wrapped environment and the
+    /// log.
+    pub fn into_parts(self) -> (Env, InputLog) {
+        (self.inner, self.log)
+    }
+    /// True if we are replaying, false if we are recording.
+    pub fn is_replaying(&self) -> bool {
+        self.replaying
+    }
+    /// True if every input in the log has been replayed.
+    pub fn is_exhausted(&self) -> bool {
+        self.cursor >= self.log.len()
+    }
+    /// Stop replaying, and start recording. Anything in the log that hasn't
+    /// been replayed yet is discarded.
+    pub fn stop_replaying(&mut self) {
+        self.log.truncate(self.cursor);
+        self.replaying = false;
+    }
+    fn next_replayed(&mut self) -> &Input {
+        let ret = self.log.entries.get(self.cursor).expect(
+            "replay ran off the end of the log (is the environment \
+             deterministic?)",
+        );
+        self.cursor += 1;
+        ret
+    }
+    fn record(&mut self, input: Input) {
+        self.log.truncate(self.cursor);
+        self.log.entries.push(input);
+        self.cursor += 1;
+    }
+    /// Replay a CSR-like input. `what` is for the panic message.
+    fn replayed_csr(&mut self, what: &str) -> Result<u32, ExceptionCause> {
+        match *self.next_replayed() {
+            Input::Csr(result) => result,
+            ref x => panic!(
+                "replay expected {what} input, but the log had {x:?} (is the \
+                 environment deterministic?)"
+            ),
+        }
+    }
+    /// Perform (or replay) a read from an I/O address.
+    fn input<T: Copy + Into<u32> + TryFrom<u32>>(
+        &mut self,
+        address: u32,
+        live: impl FnOnce(&mut Env) -> Result<T, MemoryAccessFailure>,
+    ) -> Result<T, MemoryAccessFailure> {
+        if !self.inner.is_io_address(address) {
+            return live(&mut self.inner);
+        }
+        if self.replaying {
+            match *self.next_replayed() {
+                Input::Memory(Ok(value)) => Ok(T::try_from(value)
+                    .unwrap_or_else(|_| {
+                        panic!(
+                            "replayed memory input doesn't fit the access \
+                             (is the environment deterministic?)"
+                        )
+                    })),
+                Input::Memory(Err(failure)) => Err(failure),
+                ref x => panic!(
+                    "replay expected a memory input, but the log had {x:?} \
+                     (is the environment deterministic?)"
+                ),
+            }
+        } else {
+            let result = live(&mut self.inner);
+            self.record(Input::Memory(result.map(Into::into)));
+            result
+        }
+    }
+    /// Returns true if this store should actually be performed.
+    fn output(&mut self, address: u32, length: u32) -> bool {
+        if let Some(watch) = self.watch {
+            let watch = watch & !3;
+            if address.wrapping_sub(watch) < 4
+                || watch.wrapping_sub(address) < length
+            {
+                self.watch_hit = true;
+            }
+        }
+        !(self.replaying && self.inner.is_io_address(address))
+    }
+}
+
+impl<Env: ExecutionEnvironment> ExecutionEnvironment for Recorder<Env> {
+    const SUPPORT_A: bool = Env::SUPPORT_A;
+    const SUPPORT_C: bool = Env::SUPPORT_C;
+    const SUPPORT_M: bool = Env::SUPPORT_M;
+    const SUPPORT_ZBA: bool = Env::SUPPORT_ZBA;
+    const SUPPORT_ZBB: bool = Env::SUPPORT_ZBB;
+    const SUPPORT_ZBS: bool = Env::SUPPORT_ZBS;
+    const SUPPORT_ZBC: bool = Env::SUPPORT_ZBC;
+    const SUPPORT_ZBKB: bool = Env::SUPPORT_ZBKB;
+    const SUPPORT_ZBKC: bool = Env::SUPPORT_ZBKC;
+    const SUPPORT_ZBKX: bool = Env::SUPPORT_ZBKX;
+    const SUPPORT_ZKND: bool = Env::SUPPORT_ZKND;
+    const SUPPORT_ZKNE: bool = Env::SUPPORT_ZKNE;
+    const SUPPORT_ZKNH: bool = Env::SUPPORT_ZKNH;
+    const SUPPORT_ZKSED: bool = Env::SUPPORT_ZKSED;
+    const SUPPORT_ZKSH: bool = Env::SUPPORT_ZKSH;
+    const SUPPORT_ZKR: bool = Env::SUPPORT_ZKR;
+    const SUPPORT_ZICOND: bool = Env::SUPPORT_ZICOND;
+    const SUPPORT_ZFHMIN: bool = Env::SUPPORT_ZFHMIN;
+    const SUPPORT_ZFH: bool = Env::SUPPORT_ZFH;
+    const SUPPORT_ZFA: bool = Env::SUPPORT_ZFA;
+    const SUPPORT_ZFINX: bool = Env::SUPPORT_ZFINX;
+    const SUPPORT_ZDINX: bool = Env::SUPPORT_ZDINX;
+    const SUPPORT_ZFBFMIN: bool = Env::SUPPORT_ZFBFMIN;
+    const SUPPORT_ZVE32X: bool = Env::SUPPORT_ZVE32X;
+    const SUPPORT_ZVE32F: bool = Env::SUPPORT_ZVE32F;
+    const SUPPORT_P: bool = Env::SUPPORT_P;
+    const SUPPORT_ZCF: bool = Env::SUPPORT_ZCF;
+    const SUPPORT_ZCD: bool = Env::SUPPORT_ZCD;
+    const SUPPORT_ZCB: bool = Env::SUPPORT_ZCB;
+    const SUPPORT_ZCMP: bool = Env::SUPPORT_ZCMP;
+    const SUPPORT_ZCMT: bool = Env::SUPPORT_ZCMT;
+    const SUPPORT_ZICBOM: bool = Env::SUPPORT_ZICBOM;
+    const SUPPORT_ZICBOZ: bool = Env::SUPPORT_ZICBOZ;
+    const SUPPORT_ZICBOP: bool = Env::SUPPORT_ZICBOP;
+    const SUPPORT_ZAWRS: bool = Env::SUPPORT_ZAWRS;
+    const SUPPORT_ZIHINTPAUSE: bool = Env::SUPPORT_ZIHINTPAUSE;
+    const SUPPORT_ZIHINTNTL: bool = Env::SUPPORT_ZIHINTNTL;
+    const SUPPORT_ZACAS: bool = Env::SUPPORT_ZACAS;
+    const SUPPORT_ZABHA: bool = Env::SUPPORT_ZABHA;
+    const CACHE_BLOCK_SIZE: u32 = Env::CACHE_BLOCK_SIZE;
+    fn enable_a(&self) -> bool {
+        self.inner.enable_a()
+    }
+    fn enable_c(&self) -> bool {
+        self.inner.enable_c()
+    }
+    fn enable_m(&self) -> bool {
+        self.inner.enable_m()
+    }
+    fn enable_f(&self) -> bool {
+        self.inner.enable_f()
+    }
+    fn enable_d(&self) -> bool {
+        self.inner.enable_d()
+    }
+    fn enable_q(&self) -> bool {
+        self.inner.enable_q()
+    }
+    fn enable_zicsr(&self) -> bool {
+        self.inner.enable_zicsr()
+    }
+    fn enable_zifence(&self) -> bool {
+        self.inner.enable_zifence()
+    }
+    fn enable_zba(&self) -> bool {
+        self.inner.enable_zba()
+    }
+    fn enable_zbb(&self) -> bool {
+        self.inner.enable_zbb()
+    }
+    fn enable_zbs(&self) -> bool {
+        self.inner.enable_zbs()
+    }
+    fn enable_zbc(&self) -> bool {
+        self.inner.enable_zbc()
+    }
+    fn enable_zbkb(&self) -> bool {
+        self.inner.enable_zbkb()
+    }
+    fn enable_zbkc(&self) -> bool {
+        self.inner.enable_zbkc()
+    }
+    fn enable_zbkx(&self) -> bool {
+        self.inner.enable_zbkx()
+    }
+    fn enable_zknd(&self) -> bool {
+        self.inner.enable_zknd()
+    }
+    fn enable_zkne(&self) -> bool {
+        self.inner.enable_zkne()
+    }
+    fn enable_zknh(&self) -> bool {
+        self.inner.enable_zknh()
+    }
+    fn enable_zksed(&self) -> bool {
+        self.inner.enable_zksed()
+    }
+    fn enable_zksh(&self) -> bool {
+        self.inner.enable_zksh()
+    }
+    fn enable_zkr(&self) -> bool {
+        self.inner.enable_zkr()
+    }
+    fn enable_zicond(&self) -> bool {
+        self.inner.enable_zicond()
+    }
+    fn enable_zfhmin(&self) -> bool {
+        self.inner.enable_zfhmin()
+    }
+    fn enable_zfh(&self) -> bool {
+        self.inner.enable_zfh()
+    }
+    fn enable_zfa(&self) -> bool {
+        self.inner.enable_zfa()
+    }
+    fn enable_zfinx(&self) -> bool {
+        self.inner.enable_zfinx()
+    }
+    fn enable_zdinx(&self) -> bool {
+        self.inner.enable_zdinx()
+    }
+    fn enable_zfbfmin(&self) -> bool {
+        self.inner.enable_zfbfmin()
+    }
+    fn enable_zve32x(&self) -> bool {
+        self.inner.enable_zve32x()
+    }
+    fn enable_zve32f(&self) -> bool {
+        self.inner.enable_zve32f()
+    }
+    fn enable_p(&self) -> bool {
+        self.inner.enable_p()
+    }
+    fn enable_zcf(&self) -> bool {
+        self.inner.enable_zcf()
+    }
+    fn enable_zcd(&self) -> bool {
+        self.inner.enable_zcd()
+    }
+    fn enable_zcb(&self) -> bool {
+        self.inner.enable_zcb()
+    }
+    fn enable_zcmp(&self) -> bool {
+        self.inner.enable_zcmp()
+    }
+    fn enable_zcmt(&self) -> bool {
+        self.inner.enable_zcmt()
+    }
+    fn enable_zicbom(&self) -> bool {
+        self.inner.enable_zicbom()
+    }
+    fn enable_zicboz(&self) -> bool {
+        self.inner.enable_zicboz()
+    }
+    fn enable_zicbop(&self) -> bool {
+        self.inner.enable_zicbop()
+    }
+    fn enable_zawrs(&self) -> bool {
+        self.inner.enable_zawrs()
+    }
+    fn enable_zacas(&self) -> bool {
+        self.inner.enable_zacas()
+    }
+    fn enable_zabha(&self) -> bool {
+        self.inner.enable_zabha()
+    }
+    fn read_word(
+        &mut self,
+        address: u32,
+        mask: u32,
+    ) -> Result<u32, MemoryAccessFailure> {
+        self.input(address, |env| env.read_word(address, mask))
+    }
+    fn read_instruction(
+        &mut self,
+        address: u32,
+    ) -> Result<u32, MemoryAccessFailure> {
+        self.input(address, |env| env.read_instruction(address))
+    }
+    fn cached_instruction(
+        &mut self,
+        address: u32,
+    ) -> Option<DecodedInstruction> {
+        self.inner.cached_instruction(address)
+    }
+    fn cache_instruction(
+        &mut self,
+        address: u32,
+        decoded: DecodedInstruction,
+    ) {
+        self.inner.cache_instruction(address, decoded)
+    }
+    fn read_half(&mut self, address: u32) -> Result<u16, MemoryAccessFailure> {
+        self.input(address, |env| env.read_half(address))
+    }
+    fn read_byte(&mut self, address: u32) -> Result<u8, MemoryAccessFailure> {
+        self.input(address, |env| env.read_byte(address))
+    }
+    fn write_word(
+        &mut self,
+        address: u32,
+        data: u32,
+        mask: u32,
+    ) -> Result<(), MemoryAccessFailure> {
+        if self.output(address, 4) {
+            self.inner.write_word(address, data, mask)
+        } else {
+            Ok(())
+        }
+    }
+    fn write_half(
+        &mut self,
+        address: u32,
+        data: u16,
+    ) -> Result<(), MemoryAccessFailure> {
+        if self.output(address, 2) {
+            self.inner.write_half(address, data)
+        } else {
+            Ok(())
+        }
+    }
+    fn write_byte(
+        &mut self,
+        address: u32,
+        data: u8,
+    ) -> Result<(), MemoryAccessFailure> {
+        if self.output(address, 1) {
+            self.inner.write_byte(address, data)
+        } else {
+            Ok(())
+        }
+    }
+    fn load_reserved_word(
+        &mut self,
+        address: u32,
+    ) -> Result<u32, MemoryAccessFailure> {
+        self.input(address, |env| env.load_reserved_word(address))
+    }
+    fn store_reserved_word(
+        &mut self,
+        address: u32,
+        data: u32,
+    ) -> Result<bool, MemoryAccessFailure> {
+        let watch_hit = self.watch_hit;
+        if self.output(address, 4) {
+            let result = self.inner.store_reserved_word(address, data);
+            if !matches!(result, Ok(true)) {
+                // a failed store conditional didn't write anything
+                self.watch_hit = watch_hit;
+            }
+            result
+        } else {
+            Ok(true)
+        }
+    }
+    fn atomic_modify_word(
+        &mut self,
+        address: u32,
+        operand: u32,
+        op: fn(u32, u32) -> u32,
+    ) -> Result<u32, MemoryAccessFailure> {
+        let watch_hit = self.watch_hit;
+        self.output(address, 4);
+        // logged like a read; while replaying, I/O addresses don't get the
+        // write, same as any other store
+        let result = self.input(address, |env| {
+            env.atomic_modify_word(address, operand, op)
+        });
+        if result.is_err() {
+            self.watch_hit = watch_hit;
+        }
+        result
+    }
+    fn compare_and_swap_word(
+        &mut self,
+        address: u32,
+        expected: u32,
+        new: u32,
+    ) -> Result<u32, MemoryAccessFailure> {
+        let watch_hit = self.watch_hit;
+        self.output(address, 4);
+        // logged like atomic_modify_word
+        let result = self.input(address, |env| {
+            env.compare_and_swap_word(address, expected, new)
+        });
+        if result.is_err() {
+            self.watch_hit = watch_hit;
+        }
+        result
+    }
+    fn atomic_modify_half(
+        &mut self,
+        address: u32,
+        operand: u16,
+        op: fn(u16, u16) -> u16,
+    ) -> Result<u16, MemoryAccessFailure> {
+        let watch_hit = self.watch_hit;
+        self.output(address, 2);
+        let result = self.input(address, |env| {
+            env.atomic_modify_half(address, operand, op)
+        });
+        if result.is_err() {
+            self.watch_hit = watch_hit;
+        }
+        result
+    }
+    fn atomic_modify_byte(
+        &mut self,
+        address: u32,
+        operand: u8,
+        op: fn(u8, u8) -> u8,
+    ) -> Result<u8, MemoryAccessFailure> {
+        let watch_hit = self.watch_hit;
+        self.output(address, 1);
+        let result = self.input(address, |env| {
+            env.atomic_modify_byte(address, operand, op)
+        });
+        if result.is_err() {
+            self.watch_hit = watch_hit;
+        }
+        result
+    }
+    fn compare_and_swap_double(
+        &mut self,
+        address: u32,
+        expected: u64,
+        new: u64,
+    ) -> Result<u64, MemoryAccessFailure> {
+        let watch_hit = self.watch_hit;
+        self.output(address, 8);
+        // logged as two word inputs, low half first; there's no high half if
+        // the low one is an error
+        let mut high = 0;
+        let low = self.input(address, |env| {
+            env.compare_and_swap_double(address, expected, new)
+                .map(|old| {
+                    high = (old >> 32) as u32;
+                    old as u32
+                })
+        });
+        let result = low.and_then(|low| {
+            let high = self.input(address, |_| Ok(high))?;
+            Ok((high as u64) << 32 | low as u64)
+        });
+        if result.is_err() {
+            self.watch_hit = watch_hit;
+        }
+        result
+    }
+    fn compare_and_swap_half(
+        &mut self,
+        address: u32,
+        expected: u16,
+        new: u16,
+    ) -> Result<u16, MemoryAccessFailure> {
+        let watch_hit = self.watch_hit;
+        self.output(address, 2);
+        let result = self.input(address, |env| {
+            env.compare_and_swap_half(address, expected, new)
+        });
+        if result.is_err() {
+            self.watch_hit = watch_hit;
+        }
+        result
+    }
+    fn compare_and_swap_byte(
+        &mut self,
+        address: u32,
+        expected: u8,
+        new: u8,
+    ) -> Result<u8, MemoryAccessFailure> {
+        let watch_hit = self.watch_hit;
+        self.output(address, 1);
+        let result = self.input(address, |env| {
+            env.compare_and_swap_byte(address, expected, new)
+        });
+        if result.is_err() {
+            self.watch_hit = watch_hit;
+        }
+        result
+    }
+    fn is_io_address(&self, address: u32) -> bool {
+        self.inner.is_io_address(address)
+    }
+    fn is_io_csr(&self, csr_number: u32) -> bool {
+        self.inner.is_io_csr(csr_number)
+    }
+    fn perform_ecall<F: FloatBits, V: VectorBits, X: IntegerRegisters>(
+        &mut self,
+        cpu: &mut Cpu<F, V, X>,
+    ) -> Result<(), (ExceptionCause, u32)> {
+        if self.replaying {
+            match self.next_replayed() {
+                Input::Ecall { result, registers } => {
+                    for &(index, value) in registers.iter() {
+                        if index == 0 {
+                            cpu.set_pc(value);
+                        } else {
+                            cpu.set_register(index as u32, value);
+                        }
+                    }
+                    *result
+                }
+                x => panic!(
+                    "replay expected an ECALL, but the log had {x:?} (is \
+                     the environment deterministic?)"
+                ),
+            }
+        } else {
+            let before = cpu.clone();
+            let result = self.inner.perform_ecall(cpu);
+            let mut registers = vec![];
+            if cpu.get_pc() != before.get_pc() {
+                registers.push((0, cpu.get_pc()));
+            }
+            for index in 1..X::COUNT {
+                let value = cpu.get_register(index);
+                if value != before.get_register(index) {
+                    registers.push((index as u8, value));
+                }
+            }
+            self.record(Input::Ecall { result, registers });
+            result
+        }
+    }
+    fn perform_ebreak<F: FloatBits, V: VectorBits, X: IntegerRegisters>(
+        &mut self,
+        cpu: &mut Cpu<F, V, X>,
+    ) -> Result<(), (ExceptionCause, u32)> {
+        self.inner.perform_ebreak(cpu)
+    }
+    fn perform_custom<F: FloatBits, V: VectorBits, X: IntegerRegisters>(
+        &mut self,
+        cpu: &mut Cpu<F, V, X>,
+        instruction: CustomInstruction,
+    ) -> Result<(), (ExceptionCause, u32)> {
+        self.inner.perform_custom(cpu, instruction)
+    }
+    fn read_csr(&mut self, csr_number: u32) -> Result<u32, ExceptionCause> {
+        if !self.inner.is_io_csr(csr_number) {
+            self.inner.read_csr(csr_number)
+        } else if self.replaying {
+            self.replayed_csr("a CSR")
+        } else {
+            let result = self.inner.read_csr(csr_number);
+            self.record(Input::Csr(result));
+            result
+        }
+    }
+    fn write_csr(
+        &mut self,
+        csr_number: u32,
+        new_value: u32,
+    ) -> Result<(), ExceptionCause> {
+        self.inner.write_csr(csr_number, new_value)
+    }
+    fn poll_entropy(&mut self) -> Result<EntropyStatus, ExceptionCause> {
+        if self.replaying {
+            self.replayed_csr("an entropy")
+                .map(EntropyStatus::from_seed)
+        } else {
+            let result = self.inner.poll_entropy();
+            self.record(Input::Csr(result.map(|status| status.to_seed())));
+            result
+        }
+    }
+    fn fence_i(&mut self) {
+        self.inner.fence_i()
+    }
+    fn cache_block_op(
+        &mut self,
+        address: u32,
+        op: CacheBlockOp,
+    ) -> Result<(), MemoryAccessFailure> {
+        self.inner.cache_block_op(address, op)
+    }
+    // cache_block_zero keeps the default, so the zeroes go through our
+    // write_word
+    fn prefetch(&mut self, address: u32, kind: PrefetchKind) {
+        self.inner.prefetch(address, kind)
+    }
+    fn pause(&mut self) {
+        self.inner.pause()
+    }
+    fn wait_on_reservation(
+        &mut self,
+        short_timeout: bool,
+    ) -> Result<(), ExceptionCause> {
+        self.inner.wait_on_reservation(short_timeout)
+    }
+    fn non_temporal_hint(&mut self, locality: NonTemporalLocality) {
+        self.inner.non_temporal_hint(locality)
+    }
+    fn read_mtime(&mut self) -> Result<u64, ExceptionCause> {
+        // logged as two CSR inputs, low half first, or one if it failed
+        if self.replaying {
+            let low = self.replayed_csr("a time")?;
+            let high = self.replayed_csr("a time")?;
+            Ok((high as u64) << 32 | low as u64)
+        } else {
+            let result = self.inner.read_mtime();
+            match result {
+                Ok(time) => {
+                    self.record(Input::Csr(Ok(time as u32)));
+                    self.record(Input::Csr(Ok((time >> 32) as u32)));
+                }
+                Err(cause) => self.record(Input::Csr(Err(cause))),
+            }
+            result
+        }
+    }
+    fn cycle_count(&self) -> Option<u64> {
+        self.inner.cycle_count()
+    }
+    fn read_vs(&self) -> ExtensionStatus {
+        self.inner.read_vs()
+    }
+    fn write_vs(&mut self, status: ExtensionStatus) {
+        self.inner.write_vs(status)
+    }
+    fn read_fs(&self) -> ExtensionStatus {
+        self.inner.read_fs()
+    }
+    fn write_fs(&mut self, status: ExtensionStatus) {
+        self.inner.write_fs(status)
+    }
+    fn use_accurate_single_sqrt(&self) -> bool {
+        self.inner.use_accurate_single_sqrt()
+    }
+    fn use_accurate_double_sqrt(&self) -> bool {
+        self.inner.use_accurate_double_sqrt()
+    }
+    fn use_accurate_quad_sqrt(&self) -> bool {
+        self.inner.use_accurate_quad_sqrt()
+    }
+    fn account_ifetch(&mut self, pc: u32) {
+        self.inner.account_ifetch(pc)
+    }
+    fn account_retire(&mut self) {
+        self.inner.account_retire()
+    }
+    fn account_generic_op(&mut self) {
+        self.inner.account_generic_op()
+    }
+    fn account_memory_load(&mut self, address: u32) {
+        self.inner.account_memory_load(address)
+    }
+    fn account_memory_store(&mut self, address: u32) {
+        self.inner.account_memory_store(address)
+    }
+    fn account_memory_double_load(&mut self, address: u32) {
+        self.inner.account_memory_double_load(address)
+    }
+    fn account_memory_double_store(&mut self, address: u32) {
+        self.inner.account_memory_double_store(address)
+    }
+    fn account_memory_quad_load(&mut self, address: u32) {
+        self.inner.account_memory_quad_load(address)
+    }
+    fn account_memory_quad_store(&mut self, address: u32) {
+        self.inner.account_memory_quad_store(address)
+    }
+    fn account_memory_op(&mut self, address: u32) {
+        self.inner.account_memory_op(address)
+    }
+    fn account_alu_op(&mut self) {
+        self.inner.account_alu_op()
+    }
+    fn account_mul_op(&mut self) {
+        self.inner.account_mul_op()
+    }
+    fn account_div_op(&mut self) {
+        self.inner.account_div_op()
+    }
+    fn account_clmul_op(&mut self) {
+        self.inner.account_clmul_op()
+    }
+    fn account_xperm_op(&mut self) {
+        self.inner.account_xperm_op()
+    }
+    fn account_crypto_op(&mut self) {
+        self.inner.account_crypto_op()
+    }
+    fn account_amo_op(&mut self) {
+        self.inner.account_amo_op()
+    }
+    fn account_jump_op(&mut self) {
+        self.inner.account_jump_op()
+    }
+    fn account_branch_op(&mut self, did_take: bool, was_forward: bool) {
+        self.inner.account_branch_op(did_take, was_forward)
+    }
+    fn account_float_op(&mut self, num_words: u32) {
+        self.inner.account_float_op(num_words)
+    }
+    fn account_float_divide(&mut self, num_words: u32) {
+        self.inner.account_float_divide(num_words)
+    }
+    fn account_float_ternop(&mut self, num_words: u32) {
+        self.inner.account_float_ternop(num_words)
+    }
+    fn account_fcvt_from_int(&mut self, num_words: u32) {
+        self.inner.account_fcvt_from_int(num_words)
+    }
+    fn account_fcvt_to_int(&mut self, num_words: u32) {
+        self.inner.account_fcvt_to_int(num_words)
+    }
+    fn account_sqrt(&mut self, num_words: u32, num_iterations: u32) {
+        self.inner.account_sqrt(num_words, num_iterations)
+    }
+    fn account_vector_op(&mut self, elements: u32) {
+        self.inner.account_vector_op(elements)
+    }
+    fn account_custom_op(&mut self) {
+        self.inner.account_custom_op()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    const PROGRAM: [u32; 4] = [
+        0x20002103, // lw x2, 0x200(x0)
+        0x002080b3, // add x1, x1, x2
+        0x00000073, // ecall
+        0xff5ff06f, // j -12
+    ];
+    struct Noisy {
+        ram: [u32; 16],
+        noise: u32,
+    }
+    impl Noisy {
+        fn noise(&mut self) -> u32 {
+            self.noise = self.noise.wrapping_mul(69069).wrapping_add(1);
+            self.noise
+        }
+    }
+    impl ExecutionEnvironment for Noisy {
+        fn read_word(
+            &mut self,
+            address: u32,
+            _mask: u32,
+        ) -> Result<u32, MemoryAccessFailure> {
+            if address == 0x200 {
+                Ok(self.noise())
+            } else {
+                Ok(self.ram[(address / 4) as usize % 16])
+            }
+        }
+        fn write_word(
+            &mut self,
+            _address: u32,
+            _data: u32,
+            _mask: u32,
+        ) -> Result<(), MemoryAccessFailure> {
+            unreachable!()
+        }
+        fn load_reserved_word(
+            &mut self,
+            _address: u32,
+        ) -> Result<u32, MemoryAccessFailure> {
+            unreachable!()
+        }
+        fn store_reserved_word(
+            &mut self,
+            _address: u32,
+            _data: u32,
+        ) -> Result<bool, MemoryAccessFailure> {
+            unreachable!()
+        }
+        fn compare_and_swap_double(
+            &mut self,
+            _address: u32,
+            _expected: u64,
+            _new: u64,
+        ) -> Result<u64, MemoryAccessFailure> {
+            Ok((self.noise() as u64) << 32 | self.noise() as u64)
+        }
+        fn is_io_address(&self, address: u32) -> bool {
+            address == 0x200
+        }
+        fn perform_ecall<F: FloatBits, V: VectorBits, X: IntegerRegisters>(
+            &mut self,
+            cpu: &mut Cpu<F, V, X>,
+        ) -> Result<(), (ExceptionCause, u32)> {
+            let noise = self.noise();
+            cpu.set_register(REGISTER_A0, noise);
+            Ok(())
+        }
+    }
+    fn run<X: IntegerRegisters>(env: &mut Recorder<Noisy>) -> [u32; 3] {
+        let mut cpu = Cpu::<(), (), X>::new();
+        for _ in 0..40 {
+            cpu.step(env).unwrap();
+        }
+        [
+            cpu.get_register(1),
+            cpu.get_register(2),
+            cpu.get_register(10),
+        ]
+    }
+    #[test]
+    fn record_and_replay() {
+        let mut ram = [0; 16];
+        ram[..4].copy_from_slice(&PROGRAM);
+        let mut recorder = Recorder::new(Noisy { ram, noise: 1 });
+        let recorded = run::<[u32; 32]>(&mut recorder);
+        let (_, log) = recorder.into_parts();
+        assert_eq!(log.len(), 20);
+        let mut file = vec![];
+        log.write_to(&mut file).unwrap();
+        let log = InputLog::read_from(&file[..]).unwrap();
+        let mut replayer = Recorder::replay(Noisy { ram, noise: 42 }, log);
+        assert_eq!(run::<[u32; 32]>(&mut replayer), recorded);
+        assert!(replayer.is_exhausted());
+        // the replay must not have touched the "real" inputs
+        assert_eq!(replayer.inner().noise, 42);
+    }
+    #[test]
+    fn rv32e_ecall() {
+        let mut ram = [0; 16];
+        ram[..4].copy_from_slice(&PROGRAM);
+        let mut recorder = Recorder::new(Noisy { ram, noise: 1 });
+        let recorded = run::<[u32; 16]>(&mut recorder);
+        let (_, log) = recorder.into_parts();
+        let mut replayer = Recorder::replay(Noisy { ram, noise: 42 }, log);
+        assert_eq!(run::<[u32; 16]>(&mut replayer), recorded);
+        assert!(replayer.is_exhausted());
+    }
+    #[test]
+    fn amocas_double() {
+        let ram = [0; 16];
+        let mut recorder = Recorder::new(Noisy { ram, noise: 1 });
+        let recorded = (0..4)
+            .map(|_| recorder.compare_and_swap_double(0x200, 0, 0).unwrap())
+            .collect::<Vec<_>>();
+        let (_, log) = recorder.into_parts();
+        assert_eq!(log.len(), 8);
+        let mut replayer = Recorder::replay(Noisy { ram, noise: 42 }, log);
+        let replayed = (0..4)
+            .map(|_| replayer.compare_and_swap_double(0x200, 0, 0).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(replayed, recorded);
+        assert!(replayer.is_exhausted());
+        assert_eq!(replayer.inner().noise, 42);
+    }
+}
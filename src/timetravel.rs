@@ -0,0 +1,434 @@
+//! Reverse execution, by way of checkpoints and deterministic replay.
+
+use super::*;
+use replay::Recorder;
+
+struct Checkpoint<F: FloatBits, V: VectorBits, X: IntegerRegisters, Env> {
+    position: u64,
+    cpu: Cpu<F, V, X>,
+    env: Env,
+    cursor: usize,
+}
+
+/// A `Cpu` and `ExecutionEnvironment`, bundled together with enough history
+/// to run them backwards.
+///
+/// Every `checkpoint_interval` instructions, `TimeTravel` clones the CPU and
+/// the environment. Meanwhile, a [`Recorder`] logs every nondeterministic
+/// input: I/O reads, I/O CSR reads, and `ECALL` outcomes. Going back in time
+/// is then a matter of restoring the nearest earlier checkpoint and replaying
+/// forward from it, feeding the logged inputs back in. Writes to I/O
+/// addresses are suppressed during replay, so e.g. a terminal won't print the
+/// same text twice.
+///
+/// Stepping forward after going back will replay the recorded history until
+/// it catches back up to the present, at which point execution goes "live"
+/// again, exactly where it left off.
+///
+/// The one thing `TimeTravel` can't see is what *you* do to the CPU and the
+/// environment between steps—taking an interrupt, handling an exception,
+/// poking memory from a debugger. All such changes must be made through
+/// [`modify`](Self::modify), which discards any history after the current
+/// position (it's now an alternate timeline) and takes a fresh checkpoint.
+///
+/// Every checkpoint is a full clone of your environment, so pick
+/// `checkpoint_interval` with the size of your memory space in mind. Larger
+/// intervals use less memory but make going back slower, since up to
+/// `checkpoint_interval` instructions may need to be replayed.
+///
+/// `V` and `X` are passed through to the [`Cpu`], so vector state and RV32E
+/// register files are checkpointed and restored like everything else.
+pub struct TimeTravel<
+    F: FloatBits,
+    Env: ExecutionEnvironment + Clone,
+    V: VectorBits = (),
+    X: IntegerRegisters = [u32; 32],
+> {
+    cpu: Cpu<F, V, X>,
+    env: Recorder<Env>,
+    position: u64,
+    frontier: u64,
+    checkpoint_interval: u64,
+    checkpoints: Vec<Checkpoint<F, V, X, Env>>,
+    /// The real, live state, stashed while we're off visiting the past.
+    live: Option<(Cpu<F, V, X>, Env)>,
+}
+
+impl<
+        F: FloatBits,
+        Env: ExecutionEnvironment + Clone,
+        V: VectorBits,
+        X: IntegerRegisters,
+    > TimeTravel<F, Env, V, X>
+{
+    /// Start recording history from the given state. This state will be
+    /// position 0, the earliest point we can go back to.
+    ///
+    /// PANICS if `checkpoint_interval` is zero.
+    pub fn new(
+        cpu: Cpu<F, V, X>,
+        env: Env,
+        checkpoint_interval: u64,
+    ) -> TimeTravel<F, Env, V, X> {
+        assert!(checkpoint_interval > 0, "checkpoint_interval must not be 0");
+        let mut ret = TimeTravel {
+            cpu,
+            env: Recorder::new(env),
+            position: 0,
+            frontier: 0,
+            checkpoint_interval,
+            checkpoints: vec![],
+            live: None,
+        };
+        ret.take_checkpoint();
+        ret
+    }
+    /// The CPU, as of the current position.
+    pub fn cpu(&self) -> &Cpu<F, V, X> {
+        &self.cpu
+    }
+    /// The environment, as of the current position.
+    pub fn env(&self) -> &Env {
+        &self.env.inner
+    }
+    /// Number of instructions that were retired between the beginning of
+    /// history and the current position.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+    /// The latest position that has been reached. Any position from 0 to this
+    /// one, inclusive, can be revisited with [`seek`](Self::seek).
+    pub fn frontier(&self) -> u64 {
+        self.frontier
+    }
+    /// Give up on time travel, and get back the CPU and environment as of the
+    /// current position.
+    pub fn into_inner(self) -> (Cpu<F, V, X>, Env) {
+        (self.cpu, self.env.inner)
+    }
+    /// Make changes to the CPU and/or environment. If we weren't at the
+    /// frontier, everything after the current position is forgotten.
+    pub fn modify<R>(
+        &mut self,
+        handler: impl FnOnce(&mut Cpu<F, V, X>, &mut Env) -> R,
+    ) -> R {
+        self.live = None;
+        self.frontier = self.position;
+        self.env.log.truncate(self.env.cursor);
+        let position = self.position;
+        self.checkpoints.retain(|x| x.position < position);
+        let ret = handler(&mut self.cpu, &mut self.env.inner);
+        self.take_checkpoint();
+        ret
+    }
+    /// Execute one instruction. If we are behind the frontier, this replays
+    /// the instruction that was executed last time. Otherwise, it's like
+    /// calling [`Cpu::step`] directly.
+    ///
+    /// An exception leaves the position where it was. If you want to handle
+    /// the exception (e.g. by jumping to a trap handler), do so using
+    /// [`modify`](Self::modify).
+    pub fn step(&mut self) -> Result<(), Exception> {
+        if self.position < self.frontier {
+            self.replay_step();
+            if self.position == self.frontier {
+                self.go_live();
+            }
+            return Ok(());
+        }
+        self.go_live();
+        let cursor = self.env.cursor;
+        self.env.replaying = false;
+        match self.cpu.step(&mut self.env) {
+            Ok(()) => {
+                self.position += 1;
+                self.frontier = self.position;
+                if self.position.is_multiple_of(self.checkpoint_interval) {
+                    self.take_checkpoint();
+                }
+                Ok(())
+            }
+            Err(x) => {
+                // Forget any inputs the failed instruction consumed.
+                self.env.log.truncate(cursor);
+                self.env.cursor = cursor;
+                Err(x)
+            }
+        }
+    }
+    /// Go to the given position, which must not be after the frontier.
+    ///
+    /// PANICS if `position` is after the frontier, or if the replay diverges
+    /// from the recording (which would mean that your environment is not
+    /// deterministic, or that some change was made without going through
+    /// [`modify`](Self::modify)).
+    pub fn seek(&mut self, position: u64) {
+        assert!(
+            position <= self.frontier,
+            "can't seek to {position}, which is after the frontier"
+        );
+        if position == self.frontier {
+            if self.live.is_some() {
+                self.go_live();
+                self.position = position;
+                return;
+            }
+        } else if position < self.position
+            || self.checkpoint_index(position)
+                != self.checkpoint_index(self.position)
+        {
+            self.restore(self.checkpoint_index(position));
+        }
+        while self.position < position {
+            self.replay_step();
+        }
+    }
+    /// Go back one instruction. Returns false (and does nothing) if we are
+    /// already at the beginning of history.
+    pub fn reverse_step(&mut self) -> bool {
+        if self.position == 0 {
+            false
+        } else {
+            self.seek(self.position - 1);
+            true
+        }
+    }
+    /// Go back until reaching a state for which `stop` returns true, e.g.
+    /// because the PC is at a breakpoint. The current state is not
+    /// considered. Returns false if no such state was found, in which case
+    /// we are left at the beginning of history.
+    pub fn reverse_continue(
+        &mut self,
+        stop: impl FnMut(&Cpu<F, V, X>) -> bool,
+    ) -> bool {
+        self.search_back(None, stop)
+    }
+    /// Go back to just before the most recent instruction that stored to the
+    /// word containing `address`. Stepping forward once from there will
+    /// perform that store. Returns false if no such store happened, in which
+    /// case we are left at the beginning of history.
+    pub fn reverse_to_write(&mut self, address: u32) -> bool {
+        self.search_back(Some(address), |_| false)
+    }
+    fn search_back(
+        &mut self,
+        watch: Option<u32>,
+        mut stop: impl FnMut(&Cpu<F, V, X>) -> bool,
+    ) -> bool {
+        let mut end = self.position;
+        let mut index = self.checkpoint_index(end);
+        loop {
+            // Skip a checkpoint that has nothing before `end`.
+            if self.checkpoints[index].position == end {
+                if index == 0 {
+                    break;
+                }
+                index -= 1;
+            }
+            self.restore(index);
+            let mut found = None;
+            self.env.watch = watch;
+            while self.position < end {
+                if stop(&self.cpu) {
+                    found = Some(self.position);
+                }
+                self.env.watch_hit = false;
+                let position = self.position;
+                self.replay_step();
+                if self.env.watch_hit {
+                    found = Some(position);
+                }
+            }
+            self.env.watch = None;
+            if let Some(found) = found {
+                self.seek(found);
+                return true;
+            }
+            if index == 0 {
+                break;
+            }
+            end = self.checkpoints[index].position;
+        }
+        self.seek(0);
+        false
+    }
+    /// Index of the latest checkpoint at or before the given position.
+    fn checkpoint_index(&self, position: u64) -> usize {
+        self.checkpoints.partition_point(|x| x.position <= position) - 1
+    }
+    fn take_checkpoint(&mut self) {
+        self.checkpoints.push(Checkpoint {
+            position: self.position,
+            cpu: self.cpu.clone(),
+            env: self.env.inner.clone(),
+            cursor: self.env.cursor,
+        });
+    }
+    fn restore(&mut self, index: usize) {
+        let checkpoint = &self.checkpoints[index];
+        let cpu = std::mem::replace(&mut self.cpu, checkpoint.cpu.clone());
+        let env =
+            std::mem::replace(&mut self.env.inner, checkpoint.env.clone());
+        if self.position == self.frontier && self.live.is_none() {
+            self.live = Some((cpu, env));
+        }
+        self.position = checkpoint.position;
+        self.env.cursor = checkpoint.cursor;
+    }
+    fn go_live(&mut self) {
+        if let Some((cpu, env)) = self.live.take() {
+            self.cpu = cpu;
+            self.env.inner = env;
+            self.env.cursor = self.env.log.len();
+        }
+    }
+    fn replay_step(&mut self) {
+        self.env.replaying = true;
+        if let Err(x) = self.cpu.step(&mut self.env) {
+            panic!(
+                "replay diverged from the recording at position {}: {x:?} \
+                 (is the environment deterministic?)",
+                self.position
+            );
+        }
+        self.position += 1;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    const PROGRAM: [u32; 4] = [
+        0x00108093, // addi x1, x1, 1
+        0x10102023, // sw x1, 0x100(x0)
+        0x20002103, // lw x2, 0x200(x0)
+        0xff5ff06f, // j -12
+    ];
+    #[derive(Clone)]
+    struct Counter {
+        ram: [u32; 128],
+        next_input: u32,
+    }
+    impl ExecutionEnvironment for Counter {
+        const SUPPORT_ZVE32X: bool = true;
+        fn read_word(
+            &mut self,
+            address: u32,
+            _mask: u32,
+        ) -> Result<u32, MemoryAccessFailure> {
+            if address == 0x200 {
+                self.next_input = self.next_input.wrapping_mul(69069) + 1;
+                Ok(self.next_input)
+            } else {
+                Ok(self.ram[(address / 4) as usize % 128])
+            }
+        }
+        fn write_word(
+            &mut self,
+            address: u32,
+            data: u32,
+            _mask: u32,
+        ) -> Result<(), MemoryAccessFailure> {
+            self.ram[(address / 4) as usize % 128] = data;
+            Ok(())
+        }
+        fn load_reserved_word(
+            &mut self,
+            _address: u32,
+        ) -> Result<u32, MemoryAccessFailure> {
+            unreachable!()
+        }
+        fn store_reserved_word(
+            &mut self,
+            _address: u32,
+            _data: u32,
+        ) -> Result<bool, MemoryAccessFailure> {
+            unreachable!()
+        }
+        fn is_io_address(&self, address: u32) -> bool {
+            address == 0x200
+        }
+    }
+    fn registers(cpu: &Cpu<()>) -> [u32; 3] {
+        [cpu.get_pc(), cpu.get_register(1), cpu.get_register(2)]
+    }
+    #[test]
+    fn reverse_and_replay() {
+        let mut env = Counter {
+            ram: [0; 128],
+            next_input: 1,
+        };
+        env.ram[..4].copy_from_slice(&PROGRAM);
+        let mut tt = TimeTravel::new(Cpu::<()>::new(), env, 7);
+        let mut history = vec![registers(tt.cpu())];
+        for _ in 0..50 {
+            tt.step().unwrap();
+            history.push(registers(tt.cpu()));
+        }
+        for position in (0..50).rev() {
+            assert!(tt.reverse_step());
+            assert_eq!(registers(tt.cpu()), history[position]);
+        }
+        assert!(!tt.reverse_step());
+        tt.seek(33);
+        assert_eq!(registers(tt.cpu()), history[33]);
+        for expected in &history[34..] {
+            tt.step().unwrap();
+            assert_eq!(registers(tt.cpu()), *expected);
+        }
+        // back at the frontier, the live input device must pick up where it
+        // left off
+        let live_input = tt.env().next_input;
+        tt.step().unwrap();
+        tt.step().unwrap();
+        tt.step().unwrap();
+        assert_ne!(tt.env().next_input, live_input);
+    }
+    #[test]
+    fn reverse_to_write() {
+        let mut env = Counter {
+            ram: [0; 128],
+            next_input: 1,
+        };
+        env.ram[..4].copy_from_slice(&PROGRAM);
+        let mut tt = TimeTravel::new(Cpu::<()>::new(), env, 5);
+        for _ in 0..30 {
+            tt.step().unwrap();
+        }
+        assert!(tt.reverse_to_write(0x100));
+        assert_eq!(tt.cpu().get_pc(), 4);
+        assert_eq!(tt.position(), 29);
+        let x1 = tt.cpu().get_register(1);
+        assert!(tt.reverse_to_write(0x102));
+        assert_eq!(tt.cpu().get_pc(), 4);
+        assert_eq!(tt.cpu().get_register(1), x1 - 1);
+        assert!(tt.reverse_continue(|cpu| cpu.get_register(1) == 2));
+        assert_eq!(tt.position(), 8);
+        assert!(!tt.reverse_to_write(0x104));
+        assert_eq!(tt.position(), 0);
+    }
+    #[test]
+    fn vector_state() {
+        let mut env = Counter {
+            ram: [0; 128],
+            next_input: 1,
+        };
+        env.ram[..3].copy_from_slice(&[
+            0xc1027057, // vsetivli x0, 4, e32, m1, tu, mu
+            0x0210b0d7, // 1: vadd.vi v1, v1, 1
+            0xffdff06f, // j 1b
+        ]);
+        let cpu = Cpu::<(), [u8; 16]>::new();
+        let mut tt = TimeTravel::new(cpu, env, 4);
+        let mut history = vec![tt.cpu().get_vector_register(1).to_vec()];
+        for _ in 0..20 {
+            tt.step().unwrap();
+            history.push(tt.cpu().get_vector_register(1).to_vec());
+        }
+        assert_eq!(history[20][12], 10);
+        for position in (0..20).rev() {
+            assert!(tt.reverse_step());
+            assert_eq!(tt.cpu().get_vector_register(1), &history[position]);
+        }
+    }
+}